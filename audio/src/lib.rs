@@ -1,15 +1,90 @@
-use sdl2::mixer::{Chunk, Music, Channel, DEFAULT_CHANNELS};
-use wad::WadFile;
+use sdl2::mixer::{Chunk, Music, Channel, LoaderRWops, DEFAULT_CHANNELS, MAX_VOLUME};
+use sdl2::rwops::RWops;
+use wad::{WadFile, WadLump};
+
+const DOOM_SOUND_HEADER_LEN: usize = 8;
+// DMX sounds are format 3 and pad the samples with 16 bytes at each end
+const DMX_FORMAT: u16 = 3;
+const DMX_PADDING: usize = 16;
+
+// In map units per second, taking one unit as roughly an inch
+const SPEED_OF_SOUND: f64 = 13500.0;
+// Doppler shifts outside this range sound broken rather than fast
+const MIN_PITCH: f64 = 0.5;
+const MAX_PITCH: f64 = 2.0;
 
 pub struct AudioManager {
     _mixer_context: sdl2::mixer::Sdl2MixerContext,
     sound_effects: std::collections::HashMap<String, Chunk>,
+    // The original lumps, for building pitch-shifted copies of a sound
+    sound_lumps: std::collections::HashMap<String, Vec<u8>>,
+    // Pitch-shifted chunks have to live as long as their channel is playing them
+    channel_chunks: std::collections::HashMap<i32, Chunk>,
+    looping: LoopingChannels,
     current_music: Option<Music<'static>>,
+    // SDL_mixer streams music from its source for as long as it plays, so each track
+    // is written here and loaded from the file, which the next track overwrites
+    music_file: std::path::PathBuf,
+    // Volume settings from 0 to 1. Muting leaves them alone so unmuting restores them.
+    master_volume: f64,
+    sfx_volume: f64,
+    music_volume: f64,
+    muted: bool,
+}
+
+impl Drop for AudioManager {
+    fn drop(&mut self) {
+        // Nothing is left to clean up if no music was ever played
+        let _ = std::fs::remove_file(&self.music_file);
+    }
+}
+
+struct LoopingSound {
+    channel: Channel,
+    sound: String,
+}
+
+// Looping sounds by the id of whatever is making them, each holding a channel until
+// it's stopped. Starting or stopping one hands back the channel it no longer needs, for
+// the caller to halt.
+#[derive(Default)]
+struct LoopingChannels {
+    sounds: std::collections::HashMap<u64, LoopingSound>,
+}
+
+impl LoopingChannels {
+    fn start(&mut self, owner: u64, channel: Channel, sound: &str) -> Option<Channel> {
+        let sound = LoopingSound { channel, sound: sound.to_string() };
+        self.sounds.insert(owner, sound).map(|replaced| replaced.channel)
+    }
+
+    fn stop(&mut self, owner: u64) -> Option<Channel> {
+        self.sounds.remove(&owner).map(|stopped| stopped.channel)
+    }
+
+    fn get(&self, owner: u64) -> Option<&LoopingSound> {
+        self.sounds.get(&owner)
+    }
+
+    fn owners(&self) -> impl Iterator<Item = u64> + '_ {
+        self.sounds.keys().copied()
+    }
+
+    fn len(&self) -> usize {
+        self.sounds.len()
+    }
+}
+
+// Velocities of the listener and the sound's source in map units per second
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Doppler {
+    pub listener_velocity: (f64, f64),
+    pub source_velocity: (f64, f64),
 }
 
 impl AudioManager {
     pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        let mixer_context = sdl2::mixer::init(sdl2::mixer::InitFlag::OGG)?;
+        let mixer_context = sdl2::mixer::init(sdl2::mixer::InitFlag::OGG | sdl2::mixer::InitFlag::MID)?;
 
         // Initialize mixer with reasonable defaults
         sdl2::mixer::open_audio(44100, sdl2::mixer::AUDIO_S16LSB, DEFAULT_CHANNELS, 1024)?;
@@ -18,52 +93,350 @@ impl AudioManager {
         Ok(AudioManager {
             _mixer_context: mixer_context,
             sound_effects: std::collections::HashMap::new(),
+            sound_lumps: std::collections::HashMap::new(),
+            channel_chunks: std::collections::HashMap::new(),
+            looping: LoopingChannels::default(),
             current_music: None,
+            music_file: std::env::temp_dir().join(format!("room-music-{}.mid", std::process::id())),
+            master_volume: 1.0,
+            sfx_volume: 1.0,
+            music_volume: 1.0,
+            muted: false,
         })
     }
 
     pub fn load_sound_effects(&mut self, wad: &WadFile) -> Result<(), Box<dyn std::error::Error>> {
-        let sound_names = ["DSPISTOL", "DSSHOTGN", "DSPLASMA", "DSBFG", "DSRLAUNC"];
+        for lump in sound_effect_lumps(wad) {
+            let sound_data = self.convert_doom_sound_to_wav(&lump.data, 1.0)?;
+            let chunk = Self::load_chunk(&sound_data)?;
+            self.sound_effects.insert(lump.name.clone(), chunk);
+            self.sound_lumps.insert(lump.name.clone(), lump.data.clone());
+        }
+
+        Ok(())
+    }
+
+    pub fn set_master_volume(&mut self, volume: f64) {
+        self.master_volume = volume.clamp(0.0, 1.0);
+        self.apply_music_volume();
+    }
+
+    // Applies to sounds started from now on, ones already playing keep their level
+    pub fn set_sfx_volume(&mut self, volume: f64) {
+        self.sfx_volume = volume.clamp(0.0, 1.0);
+    }
+
+    pub fn set_music_volume(&mut self, volume: f64) {
+        self.music_volume = volume.clamp(0.0, 1.0);
+        self.apply_music_volume();
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+        self.apply_music_volume();
+    }
+
+    pub fn master_volume(&self) -> f64 {
+        self.master_volume
+    }
+
+    pub fn sfx_volume(&self) -> f64 {
+        self.sfx_volume
+    }
+
+    pub fn music_volume(&self) -> f64 {
+        self.music_volume
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    // Holds every playing sound and the music where they are, to carry on from there
+    pub fn set_paused(&mut self, paused: bool) {
+        if paused {
+            Channel::all().pause();
+            Music::pause();
+        } else {
+            Channel::all().resume();
+            Music::resume();
+        }
+    }
+
+    fn effective_master(&self) -> f64 {
+        if self.muted { 0.0 } else { self.master_volume }
+    }
+
+    fn apply_music_volume(&self) {
+        Music::set_volume(scale_volume(MAX_VOLUME, self.effective_master() * self.music_volume));
+    }
+
+    // SDL_mixer decodes the WAV into the output format and keeps its own copy
+    fn load_chunk(wav_data: &[u8]) -> Result<Chunk, Box<dyn std::error::Error>> {
+        Ok(RWops::from_bytes(wav_data)?.load_wav()?)
+    }
+
+    // Plays a sound panned relative to where the player is facing. With `doppler`, the
+    // pitch rises as the player and source close on each other and drops as they part.
+    pub fn play_sound_3d(
+        &mut self,
+        sound_name: &str,
+        player_pos: (f64, f64),
+        player_angle: f64,
+        sound_pos: (f64, f64),
+        doppler: Option<Doppler>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let pitch = doppler.map_or(1.0, |doppler| doppler_pitch(player_pos, sound_pos, doppler));
+
+        // Only rebuild the sound when the shift is big enough to hear
+        let pitched = match self.sound_lumps.get(sound_name) {
+            Some(lump) if (pitch - 1.0).abs() > 0.01 => Some(Self::load_chunk(&self.convert_doom_sound_to_wav(lump, pitch)?)?),
+            _ => None,
+        };
+        let Some(chunk) = pitched.as_ref().or_else(|| self.sound_effects.get(sound_name)) else {
+            return Ok(());
+        };
 
-        for sound_name in &sound_names {
-            if let Some(lump) = wad.find_lump(sound_name) {
-                let sound_data = self.convert_doom_sound_to_wav(&lump.data)?;
-                let chunk = Chunk::from_raw_buffer(sound_data.into_boxed_slice())?;
-                self.sound_effects.insert(sound_name.to_string(), chunk);
+        let distance = ((sound_pos.0 - player_pos.0).powi(2) + (sound_pos.1 - player_pos.1).powi(2)).sqrt();
+        let (left, right) = stereo_panning(player_pos, player_angle, sound_pos);
+
+        let channel = Channel::all().play(chunk, 0)?;
+        let volume = self.effective_master() * self.sfx_volume;
+        channel.set_volume(scale_volume(distance_volume(distance), volume));
+        channel.set_panning(left, right)?;
+
+        // Whatever chunk the channel held before has finished, since it was free to play this
+        match pitched {
+            Some(chunk) => {
+                self.channel_chunks.insert(channel.0, chunk);
+            }
+            None => {
+                self.channel_chunks.remove(&channel.0);
             }
         }
 
         Ok(())
     }
 
-    pub fn play_sound_3d(&self, sound_name: &str, player_pos: (f64, f64), sound_pos: (f64, f64)) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some(chunk) = self.sound_effects.get(sound_name) {
-            let distance = ((sound_pos.0 - player_pos.0).powi(2) + (sound_pos.1 - player_pos.1).powi(2)).sqrt();
+    // Keeps `sound_name` playing on a loop for `owner`, such as an entity's id. The first
+    // call starts it on a channel of its own and later ones follow the listener and
+    // source around, updating its volume and panning. A new sound for the same owner
+    // replaces the old one.
+    pub fn play_looping_3d(
+        &mut self,
+        owner: u64,
+        sound_name: &str,
+        listener_pos: (f64, f64),
+        listener_angle: f64,
+        source_pos: (f64, f64),
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let playing = self
+            .looping
+            .get(owner)
+            .is_some_and(|looping| looping.sound == sound_name && looping.channel.is_playing());
+        if !playing {
+            self.stop_looping(owner);
+            let Some(chunk) = self.sound_effects.get(sound_name) else {
+                return Ok(());
+            };
+            let channel = Channel::all().play(chunk, -1)?;
+            self.channel_chunks.remove(&channel.0);
+            self.looping.start(owner, channel, sound_name);
+        }
+
+        let Some(looping) = self.looping.get(owner) else {
+            return Ok(());
+        };
+        let distance = (source_pos.0 - listener_pos.0).hypot(source_pos.1 - listener_pos.1);
+        let (left, right) = stereo_panning(listener_pos, listener_angle, source_pos);
+        let volume = self.effective_master() * self.sfx_volume;
+        looping.channel.set_volume(scale_volume(distance_volume(distance), volume));
+        looping.channel.set_panning(left, right)?;
+        Ok(())
+    }
+
+    // Stops an owner's looping sound and frees its channel
+    pub fn stop_looping(&mut self, owner: u64) {
+        if let Some(channel) = self.looping.stop(owner) {
+            channel.halt();
+        }
+    }
+
+    // Stops the looping sounds of every owner `keep` turns down, such as ones that
+    // have despawned
+    pub fn retain_looping(&mut self, keep: impl Fn(u64) -> bool) {
+        let stopped: Vec<u64> = self.looping.owners().filter(|&owner| !keep(owner)).collect();
+        for owner in stopped {
+            self.stop_looping(owner);
+        }
+    }
+
+    // The channel an owner's looping sound holds, None when it has none
+    pub fn looping_channel(&self, owner: u64) -> Option<i32> {
+        self.looping.get(owner).map(|looping| looping.channel.0)
+    }
+
+    pub fn looping_channel_count(&self) -> usize {
+        self.looping.len()
+    }
 
-            // Calculate volume based on distance
-            let volume = (255.0 / (1.0 + distance / 100.0)) as i32;
-            let volume = volume.max(0).min(255);
+    pub fn play_music(&mut self, wad: &WadFile, lump_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let lump = wad.find_lump(lump_name)
+            .ok_or_else(|| format!("Music lump {} not found", lump_name))?;
 
-            // Calculate panning based on relative position
-            let angle = (sound_pos.1 - player_pos.1).atan2(sound_pos.0 - player_pos.0);
-            let pan = ((angle.sin() + 1.0) * 127.0) as u8;
+        let midi_data = if lump.data.starts_with(b"MThd") {
+            lump.data.clone()
+        } else {
+            Self::convert_mus_to_midi(&lump.data)?
+        };
 
-            let channel = Channel::all().play(chunk, 0)?;
-            channel.set_volume(volume);
-            channel.set_panning(255 - pan, pan)?;
+        // The playing track still reads its file, so it's stopped before the file is
+        // replaced
+        if self.current_music.is_some() {
+            Music::halt();
+            self.current_music = None;
         }
 
+        std::fs::write(&self.music_file, &midi_data)?;
+        let music = Music::from_file(&self.music_file)?;
+        music.play(-1)?;
+        self.apply_music_volume();
+        self.current_music = Some(music);
+
         Ok(())
     }
 
-    fn convert_doom_sound_to_wav(&self, doom_data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        // Doom sounds have a simple header format
-        if doom_data.len() < 8 {
-            return Err("Invalid Doom sound data".into());
+    pub fn convert_mus_to_midi(mus_data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        if mus_data.len() < 16 || &mus_data[0..4] != b"MUS\x1A" {
+            return Err("Invalid MUS data".into());
+        }
+
+        let score_start = u16::from_le_bytes([mus_data[6], mus_data[7]]) as usize;
+        if score_start >= mus_data.len() {
+            return Err("MUS score starts past the end of the lump".into());
+        }
+
+        let mut track = Vec::new();
+
+        // MUS runs at 140 ticks per second: 70 ticks per quarter note at 120 bpm
+        track.extend_from_slice(&[0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20]);
+
+        let mut channel_volumes = [127u8; 16];
+        let mut pending_delay = 0u32;
+        let mut position = score_start;
+
+        let mut next_byte = || -> Result<u8, Box<dyn std::error::Error>> {
+            let byte = *mus_data.get(position).ok_or("MUS score ended unexpectedly")?;
+            position += 1;
+            Ok(byte)
+        };
+
+        loop {
+            let descriptor = next_byte()?;
+            let mus_channel = descriptor & 0x0F;
+            let event_type = (descriptor >> 4) & 0x07;
+            let last_in_group = descriptor & 0x80 != 0;
+
+            // MUS channel 15 is percussion, which MIDI keeps on channel 9
+            let channel = match mus_channel {
+                15 => 9,
+                9..=14 => mus_channel + 1,
+                _ => mus_channel,
+            };
+
+            let event: Option<Vec<u8>> = match event_type {
+                // Release note
+                0 => {
+                    let note = next_byte()? & 0x7F;
+                    Some(vec![0x80 | channel, note, 0x40])
+                }
+                // Play note, optionally carrying a new volume
+                1 => {
+                    let note = next_byte()?;
+                    if note & 0x80 != 0 {
+                        channel_volumes[channel as usize] = next_byte()? & 0x7F;
+                    }
+                    Some(vec![0x90 | channel, note & 0x7F, channel_volumes[channel as usize]])
+                }
+                // Pitch bend, 0-255 with 128 centered, widened to 14 bits
+                2 => {
+                    let bend = (next_byte()? as u16) << 6;
+                    Some(vec![0xE0 | channel, (bend & 0x7F) as u8, ((bend >> 7) & 0x7F) as u8])
+                }
+                // System event
+                3 => {
+                    let controller = match next_byte()? {
+                        10 => 120, // all sounds off
+                        11 => 123, // all notes off
+                        12 => 126, // mono
+                        13 => 127, // poly
+                        14 => 121, // reset all controllers
+                        _ => 0xFF,
+                    };
+                    (controller != 0xFF).then(|| vec![0xB0 | channel, controller, 0])
+                }
+                // Controller change, where controller 0 is a program change
+                4 => {
+                    let controller = next_byte()?;
+                    let value = next_byte()?.min(127);
+                    match controller {
+                        0 => Some(vec![0xC0 | channel, value]),
+                        1..=9 => {
+                            let midi_controller = [0, 1, 7, 10, 11, 91, 93, 64, 67][controller as usize - 1];
+                            Some(vec![0xB0 | channel, midi_controller, value])
+                        }
+                        _ => None,
+                    }
+                }
+                // End of measure carries no data
+                5 => None,
+                // Score end
+                6 => break,
+                _ => return Err(format!("Unknown MUS event type {}", event_type).into()),
+            };
+
+            if let Some(event) = event {
+                write_variable_length(&mut track, pending_delay);
+                track.extend_from_slice(&event);
+                pending_delay = 0;
+            }
+
+            if last_in_group {
+                // Delay is a big-endian base-128 number with a continuation bit
+                let mut delay = 0u32;
+                loop {
+                    let byte = next_byte()?;
+                    delay = (delay << 7) | (byte & 0x7F) as u32;
+                    if byte & 0x80 == 0 {
+                        break;
+                    }
+                }
+                pending_delay += delay;
+            }
         }
 
-        let sample_rate = u16::from_le_bytes([doom_data[2], doom_data[3]]);
-        let sample_count = u32::from_le_bytes([doom_data[4], doom_data[5], doom_data[6], doom_data[7]]);
+        // End of track
+        write_variable_length(&mut track, pending_delay);
+        track.extend_from_slice(&[0xFF, 0x2F, 0x00]);
+
+        let mut midi_data = Vec::with_capacity(track.len() + 22);
+        midi_data.extend_from_slice(b"MThd");
+        midi_data.extend_from_slice(&6u32.to_be_bytes());
+        midi_data.extend_from_slice(&0u16.to_be_bytes()); // Single track
+        midi_data.extend_from_slice(&1u16.to_be_bytes());
+        midi_data.extend_from_slice(&70u16.to_be_bytes()); // Ticks per quarter note
+        midi_data.extend_from_slice(b"MTrk");
+        midi_data.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        midi_data.extend_from_slice(&track);
+
+        Ok(midi_data)
+    }
+
+    // Playing the samples back at a scaled sample rate shifts their pitch by `pitch`
+    fn convert_doom_sound_to_wav(&self, doom_data: &[u8], pitch: f64) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let (sample_rate, samples) = parse_dmx_sound(doom_data)?;
+        let sample_rate = (sample_rate as f64 * pitch).round() as u16;
+        let sample_count = samples.len() as u32;
 
         // Convert to standard WAV format for SDL2
         let mut wav_data = Vec::new();
@@ -83,9 +456,205 @@ impl AudioManager {
         wav_data.extend_from_slice(b"data");
         wav_data.extend_from_slice(&sample_count.to_le_bytes());
 
-        // Sound data (skip header)
-        wav_data.extend_from_slice(&doom_data[8..]);
+        wav_data.extend_from_slice(samples);
 
         Ok(wav_data)
     }
-}
\ No newline at end of file
+}
+// Every "DS" lump is a sound effect, including ones added by PWADs. Ones too short or
+// otherwise not DMX sounds are skipped rather than failing the whole load.
+pub fn sound_effect_lumps(wad: &WadFile) -> impl Iterator<Item = &WadLump> {
+    wad.lumps
+        .iter()
+        .filter(|lump| lump.name.starts_with("DS") && parse_dmx_sound(&lump.data).is_ok())
+}
+
+// Reads a DMX sound lump: u16 format, u16 sample rate and u32 sample count, then the
+// 8-bit unsigned samples. The count includes the padding bytes on either side, which are
+// dropped. A count running past the end of the lump is cut short to what's there.
+pub fn parse_dmx_sound(data: &[u8]) -> Result<(u16, &[u8]), Box<dyn std::error::Error>> {
+    if data.len() < DOOM_SOUND_HEADER_LEN {
+        return Err("Invalid Doom sound data".into());
+    }
+
+    let format = u16::from_le_bytes([data[0], data[1]]);
+    if format != DMX_FORMAT {
+        return Err(format!("Unsupported Doom sound format {}", format).into());
+    }
+
+    let sample_rate = u16::from_le_bytes([data[2], data[3]]);
+    let sample_count = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+    let samples = &data[DOOM_SOUND_HEADER_LEN..];
+    let samples = &samples[..sample_count.min(samples.len())];
+
+    if samples.len() < DMX_PADDING * 2 {
+        return Ok((sample_rate, &[]));
+    }
+    Ok((sample_rate, &samples[DMX_PADDING..samples.len() - DMX_PADDING]))
+}
+
+// Volume fades with distance from full at the listener, clamped to SDL_mixer's range
+pub fn distance_volume(distance: f64) -> i32 {
+    let volume = MAX_VOLUME as f64 / (1.0 + distance.max(0.0) / 100.0);
+    (volume as i32).clamp(0, MAX_VOLUME)
+}
+
+// Scales an SDL_mixer volume by a 0 to 1 setting
+pub fn scale_volume(volume: i32, scale: f64) -> i32 {
+    ((volume as f64 * scale.clamp(0.0, 1.0)).round() as i32).clamp(0, MAX_VOLUME)
+}
+
+// Left and right channel levels for a sound heard by a player facing `player_angle`.
+// A sound straight to the left plays only from the left, one in front or behind from
+// both equally.
+pub fn stereo_panning(player_pos: (f64, f64), player_angle: f64, sound_pos: (f64, f64)) -> (u8, u8) {
+    let (dx, dy) = (sound_pos.0 - player_pos.0, sound_pos.1 - player_pos.1);
+    if dx == 0.0 && dy == 0.0 {
+        return (255, 255);
+    }
+
+    // Angles run counterclockwise, so a positive sine is to the player's left
+    let separation = (dy.atan2(dx) - player_angle).sin().clamp(-1.0, 1.0);
+    let left = ((1.0 + separation) / 2.0 * 255.0).round() as u8;
+    let right = ((1.0 - separation) / 2.0 * 255.0).round() as u8;
+    (left, right)
+}
+
+// Pitch factor from the listener's and source's speeds along the line between them
+pub fn doppler_pitch(listener_pos: (f64, f64), source_pos: (f64, f64), doppler: Doppler) -> f64 {
+    let (dx, dy) = (source_pos.0 - listener_pos.0, source_pos.1 - listener_pos.1);
+    let distance = dx.hypot(dy);
+    if distance == 0.0 {
+        return 1.0;
+    }
+    let (to_source_x, to_source_y) = (dx / distance, dy / distance);
+
+    let listener_closing = doppler.listener_velocity.0 * to_source_x + doppler.listener_velocity.1 * to_source_y;
+    let source_closing = -(doppler.source_velocity.0 * to_source_x + doppler.source_velocity.1 * to_source_y);
+    let pitch = (SPEED_OF_SOUND + listener_closing) / (SPEED_OF_SOUND - source_closing).max(1.0);
+    pitch.clamp(MIN_PITCH, MAX_PITCH)
+}
+
+fn write_variable_length(buffer: &mut Vec<u8>, mut value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    value >>= 7;
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.reverse();
+    buffer.extend_from_slice(&bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mus_converts_to_a_midi_file() {
+        // The 16 byte header then a note on MUS channel 15 at volume 100, held for 10
+        // ticks, its release and the end of the score
+        let mut mus = b"MUS\x1A".to_vec();
+        for field in [7u16, 16, 1, 0, 0, 0] {
+            mus.extend_from_slice(&field.to_le_bytes());
+        }
+        mus.extend_from_slice(&[0x9F, 0x80 | 60, 100, 10, 0x0F, 60, 0x60]);
+
+        let midi = AudioManager::convert_mus_to_midi(&mus).unwrap();
+        assert_eq!(&midi[0..4], b"MThd");
+        assert_eq!(u32::from_be_bytes(midi[4..8].try_into().unwrap()), 6);
+        assert_eq!(u16::from_be_bytes([midi[10], midi[11]]), 1);
+        assert_eq!(&midi[14..18], b"MTrk");
+        let track = &midi[22..];
+        assert_eq!(u32::from_be_bytes(midi[18..22].try_into().unwrap()) as usize, track.len());
+
+        // The percussion note lands on MIDI channel 9, and the track ends properly
+        assert!(track.windows(3).any(|event| event == [0x99, 60, 100]));
+        assert!(track.ends_with(&[0xFF, 0x2F, 0x00]));
+    }
+
+    // A DMX sound of `samples` between the 16 bytes of padding at each end
+    fn dmx_sound(samples: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&DMX_FORMAT.to_le_bytes());
+        data.extend_from_slice(&11025u16.to_le_bytes());
+        data.extend_from_slice(&((samples.len() + DMX_PADDING * 2) as u32).to_le_bytes());
+        data.extend_from_slice(&[0x80; DMX_PADDING]);
+        data.extend_from_slice(samples);
+        data.extend_from_slice(&[0x80; DMX_PADDING]);
+        data
+    }
+
+    #[test]
+    fn only_valid_ds_lumps_are_sound_effects() {
+        let lump = |name: &str, data: Vec<u8>| WadLump { name: name.to_string(), data };
+        let wad = WadFile {
+            lumps: vec![
+                lump("DSPISTOL", dmx_sound(&[1, 2, 3])),
+                lump("DSBOGUS", vec![3, 0, 0x11]),
+                lump("PLAYPAL", dmx_sound(&[4])),
+                lump("DSSHOTGN", dmx_sound(&[5, 6])),
+            ],
+        };
+
+        let names: Vec<&str> = sound_effect_lumps(&wad).map(|lump| lump.name.as_str()).collect();
+        assert_eq!(names, ["DSPISTOL", "DSSHOTGN"]);
+    }
+
+    #[test]
+    fn sound_to_the_left_pans_fully_left_whichever_way_the_player_faces() {
+        for player_angle in [0.0, 1.0, std::f64::consts::PI, -2.5, 5.0] {
+            // A quarter turn counterclockwise from the view direction is to the left
+            let left = player_angle + std::f64::consts::FRAC_PI_2;
+            let sound_pos = (100.0 + 64.0 * left.cos(), -30.0 + 64.0 * left.sin());
+            assert_eq!(stereo_panning((100.0, -30.0), player_angle, sound_pos), (255, 0), "facing {}", player_angle);
+        }
+    }
+
+    #[test]
+    fn half_sfx_volume_halves_the_distance_volume() {
+        for distance in [0.0, 100.0, 450.0] {
+            let full = distance_volume(distance);
+            assert_eq!(scale_volume(full, 0.5), (full as f64 / 2.0).round() as i32, "at {}", distance);
+        }
+        assert_eq!(scale_volume(distance_volume(0.0), 0.0), 0);
+    }
+
+    #[test]
+    fn dmx_padding_is_stripped_from_the_samples() {
+        let samples = [0x10, 0x7F, 0x80, 0xFF, 0x00];
+        let lump = dmx_sound(&samples);
+        let (sample_rate, pcm) = parse_dmx_sound(&lump).unwrap();
+        assert_eq!(sample_rate, 11025);
+        assert_eq!(pcm, samples);
+
+        // Trailing bytes past the sample count aren't sound
+        let mut long = lump.clone();
+        long.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(parse_dmx_sound(&long).unwrap().1, samples);
+
+        let mut wrong_format = lump;
+        wrong_format[0] = 2;
+        assert!(parse_dmx_sound(&wrong_format).is_err());
+        assert!(parse_dmx_sound(&[3, 0, 0x11]).is_err());
+    }
+
+    #[test]
+    fn looping_sound_holds_its_channel_until_stopped() {
+        let mut looping = LoopingChannels::default();
+        assert!(looping.start(7, Channel(3), "DSSAWIDL").is_none());
+        assert!(looping.start(8, Channel(4), "DSSAWFUL").is_none());
+        assert_eq!(looping.get(7).map(|sound| (sound.channel.0, sound.sound.as_str())), Some((3, "DSSAWIDL")));
+        assert_eq!(looping.len(), 2);
+
+        // A new sound for the same owner gives back the channel of the one it replaces
+        assert_eq!(looping.start(7, Channel(5), "DSSAWHIT").map(|channel| channel.0), Some(3));
+        assert_eq!(looping.len(), 2);
+
+        // Stopping frees only that owner's channel, and stopping again does nothing
+        assert_eq!(looping.stop(7).map(|channel| channel.0), Some(5));
+        assert!(looping.get(7).is_none());
+        assert!(looping.stop(7).is_none());
+        assert_eq!(looping.owners().collect::<Vec<_>>(), [8]);
+    }
+}
@@ -1,27 +1,98 @@
 use engine::*;
 
-use std::fs::File;
-use std::io::BufReader;
-use wad::*;
-
-fn main() {
-    match read_wad("./game/Doom1.WAD") {
-        Ok(_) => println!("Success!"),
-        Err(e) => println!("Error: {}", e),
+use std::process::ExitCode;
+
+const USAGE: &str = "Usage: doom <WAD file> [map name] [-playdemo <demo lump>] [-record <file>] [-deathmatch]\n\nExample: doom DOOM.WAD E1M1";
+
+// What to play, from the command line
+#[derive(Debug, Default)]
+struct Options {
+    wad_path: String,
+    map_name: Option<String>,
+    play_demo: Option<String>,
+    record: Option<String>,
+    deathmatch: bool,
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match run(&options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            eprintln!("{}", USAGE);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_args(
+    mut args: impl Iterator<Item = String>,
+) -> Result<Options, Box<dyn std::error::Error>> {
+    let mut options = Options {
+        wad_path: args.next().ok_or("No WAD file given")?,
+        ..Options::default()
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-playdemo" => {
+                let demo = args
+                    .next()
+                    .ok_or("-playdemo needs the name of a demo lump, such as DEMO1")?;
+                options.play_demo = Some(demo.to_uppercase());
+            }
+            "-record" => {
+                options.record = Some(
+                    args.next()
+                        .ok_or("-record needs a file to write the demo to")?,
+                );
+            }
+            "-deathmatch" => options.deathmatch = true,
+            _ if options.map_name.is_none() => options.map_name = Some(arg.to_uppercase()),
+            _ => return Err(format!("Unexpected argument '{}'", arg).into()),
+        }
     }
 
-    Engine::draw_testing();
+    Ok(options)
 }
 
-fn read_wad(filename: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let file = File::open(filename)?;
-    let reader = BufReader::new(file);
+fn run(options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    let wad_path = &options.wad_path;
+    if !std::path::Path::new(wad_path).is_file() {
+        return Err(format!("Could not open WAD file '{}'", wad_path).into());
+    }
 
-    let wad = WadFile::load(reader)?;
+    let mut engine = Engine::new(wad_path)?;
+    engine.set_deathmatch(options.deathmatch);
 
-    for lump in wad.lumps {
-        println!("Lump: {} ({} bytes)", lump.name, lump.data.len());
+    if let Some(demo) = &options.play_demo {
+        engine.play_demo(demo)?;
+        return engine.run();
     }
 
-    Ok(())
+    // Default to the first map in the WAD
+    let map_name = match &options.map_name {
+        Some(name) => name.clone(),
+        None => engine
+            .wad()
+            .map_names()
+            .first()
+            .map(|name| name.to_string())
+            .ok_or_else(|| format!("No maps found in '{}'", wad_path))?,
+    };
+
+    engine.load_map(&map_name)?;
+    if let Some(path) = &options.record {
+        engine.record_demo(path)?;
+    }
+    engine.run()
 }
@@ -1,53 +1,943 @@
+use audio::AudioManager;
+use bevy_ecs::prelude::*;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use sdl2::Sdl;
 
+use entity::Player as PlayerMarker;
 use entity::*;
 use input::*;
 use map::*;
-use math::*;
+use player::Player;
 use player::*;
 use renderer::*;
 use wad::WadFile;
 
-use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
+use std::collections::{HashSet, VecDeque};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
+// How the main loop paces frames: at most target_fps of them a second, where None
+// draws as fast as it can, and optionally in step with the display's refresh
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EngineConfig {
+    pub target_fps: Option<u32>,
+    pub vsync: bool,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            target_fps: Some(60),
+            vsync: false,
+        }
+    }
+}
+
+// How long to wait after a frame that took `frame_time` so frames come no faster than
+// `target_fps`. Frames already over their share of the second don't wait at all.
+pub fn frame_sleep(target_fps: Option<u32>, frame_time: Duration) -> Duration {
+    match target_fps {
+        Some(fps) if fps > 0 => (Duration::from_secs(1) / fps).saturating_sub(frame_time),
+        _ => Duration::ZERO,
+    }
+}
+
+// Length of the window the rolling frame rate is measured over
+const FPS_WINDOW: Duration = Duration::from_secs(1);
+
+// Saved games start with these, and the version is bumped whenever the layout changes
+const SAVE_MAGIC: &[u8; 4] = b"RMSV";
+const SAVE_VERSION: u32 = 2;
+const QUICKSAVE_PATH: &str = "quicksave.sav";
+
+// The game advances in fixed steps at Doom's 35 tics a second, and frames drawn
+// between steps are interpolated. Long stalls only catch up this much game time.
+const TICK: Duration = Duration::from_nanos(1_000_000_000 / 35);
+const MAX_CATCH_UP: Duration = Duration::from_millis(250);
+
+// The console opens and closes on the key left of 1, keeps this many lines printed and
+// shows the latest few of them. Things it spawns appear this far ahead of the player.
+const CONSOLE_KEY: Keycode = Keycode::Backquote;
+const CONSOLE_SCROLLBACK: usize = 64;
+const CONSOLE_VISIBLE_LINES: usize = 8;
+const CONSOLE_SPAWN_DISTANCE: f64 = 64.0;
+
 pub struct Engine {
     sdl_context: Sdl,
     wad: WadFile,
     renderer: Renderer,
+    // None when there's no sound device, and the game plays silently
+    audio: Option<AudioManager>,
     game_state: GameState,
     input_handler: Input,
+    schedule: Schedule,
+    config: EngineConfig,
     last_frame_time: Instant,
+    // Real time not yet simulated, always less than a tick after each frame's ticks run
+    accumulator: Duration,
+    // The player before the latest tick, None straight after a level starts
+    previous_player: Option<Player>,
+    frame_stats: FrameStats,
+    // Shows the frame rate in the window title, refreshed once per FPS_WINDOW
+    show_fps: bool,
+    fps_shown_at: Duration,
+    // Tic commands left of a demo being played back, used instead of live input
+    demo: Option<std::vec::IntoIter<TicCmd>>,
+    // The last tic's buttons, so use only triggers when first pressed
+    previous_buttons: u8,
+    // A demo being recorded from live input and the file it's written to on quitting
+    recording: Option<(Demo, PathBuf)>,
+    console: Console,
+}
+
+// Frame times since the engine started, plus the frames from the last second
+// for a rolling frame rate
+#[derive(Debug, Clone, Default)]
+pub struct FrameStats {
+    frame_count: u64,
+    total_time: Duration,
+    min_frame_time: Option<Duration>,
+    max_frame_time: Option<Duration>,
+    recent: VecDeque<Duration>,
+    recent_time: Duration,
+}
+
+impl FrameStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, frame_time: Duration) {
+        self.frame_count += 1;
+        self.total_time += frame_time;
+        self.min_frame_time = Some(
+            self.min_frame_time
+                .map_or(frame_time, |min| min.min(frame_time)),
+        );
+        self.max_frame_time = Some(
+            self.max_frame_time
+                .map_or(frame_time, |max| max.max(frame_time)),
+        );
+
+        self.recent.push_back(frame_time);
+        self.recent_time += frame_time;
+        // Keep at least the newest frame so a single slow frame still gives a rate
+        while self.recent.len() > 1 && self.recent_time - self.recent[0] >= FPS_WINDOW {
+            if let Some(oldest) = self.recent.pop_front() {
+                self.recent_time -= oldest;
+            }
+        }
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn min_frame_time(&self) -> Duration {
+        self.min_frame_time.unwrap_or(Duration::ZERO)
+    }
+
+    pub fn max_frame_time(&self) -> Duration {
+        self.max_frame_time.unwrap_or(Duration::ZERO)
+    }
+
+    pub fn average_frame_time(&self) -> Duration {
+        if self.frame_count == 0 {
+            return Duration::ZERO;
+        }
+        self.total_time.div_f64(self.frame_count as f64)
+    }
+
+    // Frames per second over roughly the last second, 0 before any frame is timed
+    pub fn fps(&self) -> f64 {
+        if self.recent_time.is_zero() {
+            return 0.0;
+        }
+        self.recent.len() as f64 / self.recent_time.as_secs_f64()
+    }
 }
 
 pub struct GameState {
+    pub map_name: Option<String>,
     pub current_map: Option<Map>,
     pub player: Player,
+    pub world: World,
     pub entities: Vec<Entity>,
     pub game_time: Duration,
+    pub skill: SkillLevel,
+    pub multiplayer: bool,
+    // Deathmatch players start each level at the next of the map's deathmatch starts
+    pub deathmatch: bool,
+    next_deathmatch_start: usize,
+    pub automap: bool,
+    // Nothing moves and game time stands still while paused
+    pub paused: bool,
+    // Walking, running and turning speeds, kept when a saved game is loaded
+    pub movement: MovementConfig,
+}
+
+impl GameState {
+    pub fn new() -> Self {
+        let mut world = World::new();
+        world.insert_resource(Time::default());
+        world.insert_resource(PlayerInput::default());
+        world.insert_resource(SoundEvents::default());
+        world.insert_resource(GameRng::default());
+
+        GameState {
+            map_name: None,
+            current_map: None,
+            player: Player::new(0.0, 0.0, 0.0),
+            world,
+            entities: Vec::new(),
+            game_time: Duration::ZERO,
+            skill: SkillLevel::HurtMePlenty,
+            multiplayer: false,
+            deathmatch: false,
+            next_deathmatch_start: 0,
+            automap: false,
+            paused: false,
+            movement: MovementConfig::default(),
+        }
+    }
+
+    // Replaces the current level, spawning its things and moving the player to the
+    // start. A map without a start for player 1 is refused and the current level kept.
+    pub fn start_level(
+        &mut self,
+        map_name: &str,
+        map: Map,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let start = self
+            .deathmatch_start(&map)
+            .or_else(|| map.player_start(1))
+            .ok_or_else(|| format!("Map {} has no player 1 start", map_name))?;
+        self.player = Player::new(start.x as f64, start.y as f64, start.angle_radians() as f64);
+        self.player.snap_to_floor(&map);
+
+        self.world.clear_entities();
+        self.world.insert_resource(LevelMap(map.clone()));
+        self.world.insert_resource(GameRng::default());
+
+        self.entities = spawn_things(
+            &mut self.world,
+            &map.things,
+            self.skill,
+            self.multiplayer || self.deathmatch,
+        );
+
+        let mut commands = self.world.commands();
+        let player = spawn_player(
+            &mut commands,
+            self.player.x,
+            self.player.y,
+            self.player.angle,
+        );
+        self.world.flush();
+        self.entities.push(player);
+
+        self.map_name = Some(map_name.to_string());
+        self.current_map = Some(map);
+        Ok(())
+    }
+
+    // Takes the next deathmatch start in turn, None outside deathmatch or on maps
+    // without any
+    fn deathmatch_start<'a>(&mut self, map: &'a Map) -> Option<&'a Thing> {
+        if !self.deathmatch {
+            return None;
+        }
+        let starts = map.deathmatch_starts();
+        let start = starts.get(self.next_deathmatch_start % starts.len().max(1))?;
+        self.next_deathmatch_start += 1;
+        Some(start)
+    }
+
+    // Writes the level, the player and every entity spawned from a map thing.
+    // Projectiles in flight and items waiting to respawn aren't kept.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+        let (Some(map_name), Some(map)) = (self.map_name.as_ref(), self.current_map.as_ref())
+        else {
+            return Err("No level is loaded, so there is nothing to save".into());
+        };
+        let status = self
+            .player_status()
+            .ok_or("The level has no player entity")?;
+
+        let mut writer = BufWriter::new(std::fs::File::create(path)?);
+        writer.write_all(SAVE_MAGIC)?;
+        writer.write_u32::<LittleEndian>(SAVE_VERSION)?;
+
+        write_string(&mut writer, map_name)?;
+        writer.write_u8(skill_index(self.skill))?;
+        writer.write_u8(self.multiplayer as u8)?;
+        writer.write_f64::<LittleEndian>(self.game_time.as_secs_f64())?;
+
+        writer.write_f64::<LittleEndian>(self.player.x)?;
+        writer.write_f64::<LittleEndian>(self.player.y)?;
+        writer.write_f64::<LittleEndian>(self.player.angle)?;
+        writer.write_f64::<LittleEndian>(self.player.z)?;
+        writer.write_f64::<LittleEndian>(self.player.height)?;
+
+        let PlayerStatus {
+            health,
+            armor,
+            inventory,
+            weapon,
+        } = status;
+        writer.write_i32::<LittleEndian>(health.0)?;
+        writer.write_i32::<LittleEndian>(armor.points)?;
+        writer.write_u8(match armor.class {
+            None => 0,
+            Some(ArmorClass::Green) => 1,
+            Some(ArmorClass::Blue) => 2,
+        })?;
+        for ammo_type in AmmoType::ALL {
+            writer.write_u32::<LittleEndian>(inventory.ammo(ammo_type))?;
+        }
+        writer.write_u8(bit_set(&WeaponType::ALL, |weapon| {
+            inventory.weapons.contains(weapon)
+        }))?;
+        writer.write_u8(bit_set(&KeyType::ALL, |key| inventory.keys.contains(key)))?;
+        writer.write_u8(index_of(&WeaponType::ALL, &weapon.selected))?;
+
+        // Doors and lifts move sector planes, so the heights are part of the level state
+        let sectors = self
+            .world
+            .get_resource::<LevelMap>()
+            .map_or(&map.sectors, |level| &level.0.sectors);
+        writer.write_u32::<LittleEndian>(sectors.len() as u32)?;
+        for sector in sectors {
+            writer.write_i16::<LittleEndian>(sector.floor_height)?;
+            writer.write_i16::<LittleEndian>(sector.ceiling_height)?;
+        }
+
+        let things: Vec<SavedThing> = self
+            .world
+            .iter_entities()
+            .filter(|entity| !entity.contains::<Respawning>())
+            .filter_map(|entity| {
+                let thing_type = entity.get::<ThingType>()?;
+                let transform = entity.get::<entity::Transform>()?;
+                let health = match entity.get::<EntityType>()? {
+                    EntityType::Monster { health, .. } => *health,
+                    _ => 0,
+                };
+                Some(SavedThing {
+                    thing_type: thing_type.0,
+                    x: transform.x,
+                    y: transform.y,
+                    angle: transform.angle,
+                    health,
+                    deaf: entity.contains::<Deaf>(),
+                })
+            })
+            .collect();
+        writer.write_u32::<LittleEndian>(things.len() as u32)?;
+        for thing in &things {
+            writer.write_u16::<LittleEndian>(thing.thing_type)?;
+            writer.write_f64::<LittleEndian>(thing.x)?;
+            writer.write_f64::<LittleEndian>(thing.y)?;
+            writer.write_f64::<LittleEndian>(thing.angle)?;
+            writer.write_i32::<LittleEndian>(thing.health)?;
+            writer.write_u8(thing.deaf as u8)?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    // Reads a game written by `save`, loading its level from the WAD but taking the
+    // things from the save rather than the map
+    pub fn load(
+        path: impl AsRef<Path>,
+        wad: &WadFile,
+    ) -> Result<GameState, Box<dyn std::error::Error>> {
+        let mut reader = BufReader::new(std::fs::File::open(path)?);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != SAVE_MAGIC {
+            return Err("Not a saved game".into());
+        }
+        let version = reader.read_u32::<LittleEndian>()?;
+        if version != SAVE_VERSION {
+            return Err(format!(
+                "Saved game version {} is not supported, expected version {}",
+                version, SAVE_VERSION
+            )
+            .into());
+        }
+
+        let map_name = read_string(&mut reader)?;
+        let mut map = Map::load_from_wad(wad, &map_name)?;
+
+        let mut state = GameState::new();
+        state.skill = skill_from_index(reader.read_u8()?)?;
+        state.multiplayer = reader.read_u8()? != 0;
+        state.game_time = Duration::try_from_secs_f64(reader.read_f64::<LittleEndian>()?)?;
+
+        let (x, y, angle) = (
+            reader.read_f64::<LittleEndian>()?,
+            reader.read_f64::<LittleEndian>()?,
+            reader.read_f64::<LittleEndian>()?,
+        );
+        state.player = Player::new(x, y, angle);
+        state.player.z = reader.read_f64::<LittleEndian>()?;
+        state.player.height = reader.read_f64::<LittleEndian>()?;
+
+        let health = Health(reader.read_i32::<LittleEndian>()?);
+        let points = reader.read_i32::<LittleEndian>()?;
+        let class = match reader.read_u8()? {
+            0 => None,
+            1 => Some(ArmorClass::Green),
+            2 => Some(ArmorClass::Blue),
+            class => return Err(format!("Unknown armor class {} in saved game", class).into()),
+        };
+        let mut inventory = Inventory::default();
+        for ammo_type in AmmoType::ALL {
+            inventory
+                .ammo
+                .insert(ammo_type, reader.read_u32::<LittleEndian>()?);
+        }
+        let weapons = reader.read_u8()?;
+        inventory.weapons = WeaponType::ALL
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| weapons & (1 << i) != 0)
+            .map(|(_, weapon)| weapon)
+            .collect();
+        let keys = reader.read_u8()?;
+        inventory.keys = KeyType::ALL
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| keys & (1 << i) != 0)
+            .map(|(_, key)| key)
+            .collect();
+        let selected = *WeaponType::ALL
+            .get(reader.read_u8()? as usize)
+            .ok_or("Unknown weapon in saved game")?;
+
+        let sector_count = reader.read_u32::<LittleEndian>()? as usize;
+        if sector_count != map.sectors.len() {
+            return Err(format!(
+                "Saved game has {} sectors but {} in the WAD has {}",
+                sector_count,
+                map_name,
+                map.sectors.len()
+            )
+            .into());
+        }
+        for sector in &mut map.sectors {
+            sector.floor_height = reader.read_i16::<LittleEndian>()?;
+            sector.ceiling_height = reader.read_i16::<LittleEndian>()?;
+        }
+
+        let thing_count = reader.read_u32::<LittleEndian>()?;
+        let mut things = Vec::new();
+        for _ in 0..thing_count {
+            things.push(SavedThing {
+                thing_type: reader.read_u16::<LittleEndian>()?,
+                x: reader.read_f64::<LittleEndian>()?,
+                y: reader.read_f64::<LittleEndian>()?,
+                angle: reader.read_f64::<LittleEndian>()?,
+                health: reader.read_i32::<LittleEndian>()?,
+                deaf: reader.read_u8()? != 0,
+            });
+        }
+
+        state.world.insert_resource(LevelMap(map.clone()));
+        state.entities = spawn_saved_things(&mut state.world, &things);
+
+        let mut commands = state.world.commands();
+        let player = spawn_player(&mut commands, x, y, angle);
+        state.world.flush();
+        state.entities.push(player);
+        state.set_player_status(PlayerStatus {
+            health,
+            armor: entity::Armor { points, class },
+            inventory,
+            weapon: Weapon {
+                selected,
+                cooldown: 0.0,
+            },
+        });
+
+        state.map_name = Some(map_name);
+        state.current_map = Some(map);
+        Ok(state)
+    }
+
+    // Pauses or resumes the game, returning whether it's now paused
+    pub fn toggle_pause(&mut self) -> bool {
+        self.paused = !self.paused;
+        self.paused
+    }
+
+    // Moves game time on by a tick unless the game is paused, returning whether the
+    // tick should be simulated
+    pub fn advance_time(&mut self, delta_time: Duration) -> bool {
+        if !self.paused {
+            self.game_time += delta_time;
+        }
+        !self.paused
+    }
+
+    // The tinted palette for the player's current damage or pickup flash
+    pub fn flash_palette(&self) -> usize {
+        self.world
+            .iter_entities()
+            .find(|entity| entity.contains::<PlayerMarker>())
+            .and_then(|player| player.get::<PaletteFlash>())
+            .map_or(0, |flash| {
+                renderer::flash_palette(flash.damage, flash.bonus)
+            })
+    }
+
+    // The colormap the view is drawn with while the player is invulnerable
+    pub fn fixed_colormap(&self) -> Option<usize> {
+        self.world
+            .iter_entities()
+            .find(|entity| entity.contains::<PlayerMarker>())
+            .and_then(|player| player.get::<Powerups>())
+            .and_then(|powerups| {
+                renderer::fixed_colormap(powerups.invulnerability / TICK.as_secs_f64())
+            })
+    }
+
+    // Every thing with a sprite other than the player, where it is alpha of the way from
+    // the previous tick to this one
+    pub fn sprites(&self, alpha: f64) -> Vec<renderer::Sprite> {
+        self.world
+            .iter_entities()
+            .filter(|entity| !entity.contains::<PlayerMarker>())
+            .filter_map(|entity| {
+                let sprite = entity.get::<entity::Sprite>()?;
+                if sprite.name.is_empty() {
+                    return None;
+                }
+                let transform = entity.get::<Transform>()?;
+                let position = match entity.get::<PreviousTransform>() {
+                    Some(previous) => previous.0.lerp(transform, alpha),
+                    None => transform.clone(),
+                };
+                let spectre = matches!(
+                    entity.get::<EntityType>(),
+                    Some(EntityType::Monster {
+                        monster_type: MonsterType::Spectre,
+                        ..
+                    })
+                );
+                Some(renderer::Sprite {
+                    name: sprite.name.clone(),
+                    frame: entity
+                        .get::<Animation>()
+                        .and_then(Animation::frame)
+                        .unwrap_or('A'),
+                    x: position.x,
+                    y: position.y,
+                    z: position.z,
+                    angle: position.angle,
+                    fuzz: spectre,
+                })
+            })
+            .collect()
+    }
+
+    // The player's weapon as drawn over the view, none once they've died
+    pub fn weapon_overlay(&self) -> Option<WeaponOverlay> {
+        let player = self
+            .world
+            .iter_entities()
+            .find(|entity| entity.contains::<PlayerMarker>() && !entity.contains::<Dead>())?;
+        let weapon = player.get::<Weapon>()?;
+        Some(WeaponOverlay {
+            sprite: weapon.selected.view_sprite(),
+            frame: weapon.view_frame(),
+            ready: weapon.is_ready(),
+            time: self.game_time.as_secs_f64(),
+            fuzz: player
+                .get::<Powerups>()
+                .is_some_and(|powerups| powerups.is_active(PowerupType::PartialInvisibility)),
+        })
+    }
+
+    pub fn player_status(&self) -> Option<PlayerStatus> {
+        let player = self
+            .world
+            .iter_entities()
+            .find(|entity| entity.contains::<PlayerMarker>())?;
+        Some(PlayerStatus {
+            health: *player.get::<Health>()?,
+            armor: player.get::<entity::Armor>().copied().unwrap_or_default(),
+            inventory: player.get::<Inventory>().cloned().unwrap_or_default(),
+            weapon: player.get::<Weapon>().cloned().unwrap_or_default(),
+        })
+    }
+
+    // Gives the ECS player the status, marking them dead if it has no health left
+    pub fn set_player_status(&mut self, status: PlayerStatus) {
+        let Some(player) = self
+            .world
+            .query_filtered::<Entity, With<PlayerMarker>>()
+            .iter(&self.world)
+            .next()
+        else {
+            return;
+        };
+        let dead = status.health.0 <= 0;
+        let mut player = self.world.entity_mut(player);
+        player.insert((status.health, status.armor, status.inventory, status.weapon));
+        if dead {
+            player.insert(Dead);
+        } else {
+            player.remove::<Dead>();
+        }
+    }
+}
+
+// The parts of the player that last beyond a level: saved games keep all of it
+// and finishing a level keeps everything but the keys
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerStatus {
+    pub health: Health,
+    pub armor: entity::Armor,
+    pub inventory: Inventory,
+    pub weapon: Weapon,
+}
+
+impl Default for GameState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Turns map things into live entities. Unknown thing types are reported and spawned as
+// generic decorations. Things not flagged for the current skill and multiplayer-only
+// things in single player are skipped.
+pub fn spawn_things(
+    world: &mut World,
+    things: &[Thing],
+    skill: SkillLevel,
+    multiplayer: bool,
+) -> Vec<Entity> {
+    let mut entities = Vec::new();
+
+    let mut commands = world.commands();
+    for thing in things {
+        let info = ThingInfo::lookup(thing.thing_type).unwrap_or_else(|| {
+            eprintln!(
+                "Unknown thing type {} at ({}, {})",
+                thing.thing_type, thing.x, thing.y
+            );
+            ThingInfo::lookup_or_unknown(thing.thing_type)
+        });
+        if !thing.appears_on(skill, multiplayer) {
+            continue;
+        }
+        let Some(entity_type) = info.entity_type() else {
+            continue;
+        };
+
+        let entity = spawn_thing(
+            &mut commands,
+            thing.x as f64,
+            thing.y as f64,
+            thing.angle_radians() as f64,
+            entity_type,
+            info,
+        );
+        commands.entity(entity).insert(ThingType(thing.thing_type));
+        if thing.is_ambush() {
+            commands.entity(entity).insert(Deaf);
+        }
+        entities.push(entity);
+    }
+    world.flush();
+
+    entities
+}
+
+// An entity as written to a saved game. Health is only meaningful for monsters,
+// where zero or less means the monster is a corpse.
+struct SavedThing {
+    thing_type: u16,
+    x: f64,
+    y: f64,
+    angle: f64,
+    health: i32,
+    deaf: bool,
+}
+
+fn spawn_saved_things(world: &mut World, things: &[SavedThing]) -> Vec<Entity> {
+    let mut entities = Vec::new();
+
+    let mut commands = world.commands();
+    for thing in things {
+        let info = ThingInfo::lookup_or_unknown(thing.thing_type);
+        let Some(mut entity_type) = info.entity_type() else {
+            continue;
+        };
+        let corpse = match &mut entity_type {
+            EntityType::Monster { health, .. } => {
+                *health = thing.health;
+                thing.health <= 0
+            }
+            _ => false,
+        };
+
+        let entity = spawn_thing(
+            &mut commands,
+            thing.x,
+            thing.y,
+            thing.angle,
+            entity_type,
+            info,
+        );
+        commands.entity(entity).insert(ThingType(thing.thing_type));
+        if thing.deaf {
+            commands.entity(entity).insert(Deaf);
+        }
+        // Dead monsters come back as the same non-solid decorations they died as
+        if corpse {
+            let mut ai = MonsterAi::default();
+            ai.enter(AiState::Death, 0.0);
+            commands
+                .entity(entity)
+                .insert((EntityType::Decoration, ai))
+                .remove::<Collider>();
+        }
+        entities.push(entity);
+    }
+    world.flush();
+
+    entities
+}
+
+fn skill_index(skill: SkillLevel) -> u8 {
+    match skill {
+        SkillLevel::ImTooYoungToDie => 0,
+        SkillLevel::HeyNotTooRough => 1,
+        SkillLevel::HurtMePlenty => 2,
+        SkillLevel::UltraViolence => 3,
+        SkillLevel::Nightmare => 4,
+    }
+}
+
+fn skill_from_index(index: u8) -> Result<SkillLevel, Box<dyn std::error::Error>> {
+    Ok(match index {
+        0 => SkillLevel::ImTooYoungToDie,
+        1 => SkillLevel::HeyNotTooRough,
+        2 => SkillLevel::HurtMePlenty,
+        3 => SkillLevel::UltraViolence,
+        4 => SkillLevel::Nightmare,
+        _ => return Err(format!("Unknown skill level {}", index).into()),
+    })
+}
+
+// One bit per entry of `all`, in order
+fn bit_set<T>(all: &[T], contains: impl Fn(&T) -> bool) -> u8 {
+    all.iter()
+        .enumerate()
+        .filter(|(_, item)| contains(item))
+        .fold(0, |bits, (i, _)| bits | 1 << i)
+}
+
+fn index_of<T: PartialEq>(all: &[T], item: &T) -> u8 {
+    all.iter().position(|other| other == item).unwrap_or(0) as u8
+}
+
+fn write_string(writer: &mut impl Write, text: &str) -> std::io::Result<()> {
+    writer.write_u16::<LittleEndian>(text.len() as u16)?;
+    writer.write_all(text.as_bytes())
+}
+
+fn read_string(reader: &mut impl Read) -> Result<String, Box<dyn std::error::Error>> {
+    let len = reader.read_u16::<LittleEndian>()? as usize;
+    let mut bytes = vec![0u8; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(String::from_utf8(bytes)?)
 }
 
 impl Engine {
     pub fn new(wad_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::from_wad(WadFile::load(std::fs::File::open(wad_path)?)?)
+    }
+
+    // Starts the engine on a WAD that's already loaded, from memory or elsewhere
+    pub fn from_wad(wad: WadFile) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::with_config(wad, EngineConfig::default())
+    }
+
+    pub fn with_config(
+        wad: WadFile,
+        config: EngineConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
         let sdl_context = sdl2::init()?;
-        let wad = WadFile::load(std::fs::File::open(wad_path)?)?;
 
-        let renderer = Renderer::new(&sdl_context)?;
+        let render_config = RenderConfig {
+            vsync: config.vsync,
+            ..RenderConfig::default()
+        };
+        let mut renderer = Renderer::new(&sdl_context, render_config)?;
+        renderer.load_textures(&wad)?;
+        let audio = start_audio(&wad);
         let game_state = GameState::new();
         let input_handler = Input::new(&sdl_context)?;
 
+        let mut schedule = Schedule::default();
+        EntityPlugin.build(&mut schedule);
+
         Ok(Engine {
             sdl_context,
             wad,
             renderer,
+            audio,
             game_state,
             input_handler,
+            schedule,
+            config,
             last_frame_time: Instant::now(),
+            accumulator: Duration::ZERO,
+            previous_player: None,
+            frame_stats: FrameStats::new(),
+            show_fps: false,
+            fps_shown_at: Duration::ZERO,
+            demo: None,
+            previous_buttons: 0,
+            recording: None,
+            console: Console::default(),
         })
     }
 
+    pub fn wad(&self) -> &WadFile {
+        &self.wad
+    }
+
+    pub fn frame_stats(&self) -> &FrameStats {
+        &self.frame_stats
+    }
+
+    pub fn set_movement(&mut self, movement: MovementConfig) {
+        self.game_state.movement = movement;
+    }
+
+    // Starts levels from now on at the deathmatch starts
+    pub fn set_deathmatch(&mut self, deathmatch: bool) {
+        self.game_state.deathmatch = deathmatch;
+    }
+
+    pub fn set_show_fps(&mut self, show: bool) -> Result<(), Box<dyn std::error::Error>> {
+        self.show_fps = show;
+        if !show {
+            self.renderer.set_title_status(None)?;
+        }
+        Ok(())
+    }
+
+    // Loads a map from the WAD and starts it, replacing the level being played along
+    // with its entities and any doors or lifts in motion
+    pub fn load_map(&mut self, map_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let map = match Map::load_from_wad(&self.wad, map_name) {
+            Ok(map) => map,
+            // Name the maps there are, as the map was likely mistyped
+            Err(MapError::MapNotFound(name)) => {
+                return Err(format!(
+                    "Map {} not found, the WAD has {}",
+                    name,
+                    self.wad.map_names().join(", ")
+                )
+                .into());
+            }
+            Err(e) => return Err(format!("Map {} can't be loaded: {}", map_name, e).into()),
+        };
+        self.game_state.start_level(map_name, map.clone())?;
+        self.show_map(map_name, &map);
+        Ok(())
+    }
+
+    // Starts the session a demo lump records and plays its tic commands back, one per
+    // tick. Live control returns when it runs out.
+    pub fn play_demo(&mut self, lump_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let lump = self
+            .wad
+            .find_lump(lump_name)
+            .ok_or_else(|| format!("Demo lump {} not found", lump_name))?;
+        let demo = Demo::parse(&lump.data)?;
+
+        // Episode and map for Doom, just the map for a WAD of MAPxx levels
+        let episode_map = format!("E{}M{}", demo.episode, demo.map);
+        let map_name = if self.wad.find_lump(&episode_map).is_some() {
+            episode_map
+        } else {
+            format!("MAP{:02}", demo.map)
+        };
+
+        self.game_state.skill = skill_from_index(demo.skill)?;
+        self.load_map(&map_name)?;
+        self.demo = Some(demo.tics.into_iter());
+        self.previous_buttons = 0;
+        Ok(())
+    }
+
+    // Records live play from the start of the current level into a demo, written to
+    // `path` when the engine stops running
+    pub fn record_demo(
+        &mut self,
+        path: impl Into<PathBuf>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let map_name = self
+            .game_state
+            .map_name
+            .as_deref()
+            .ok_or("A level has to be loaded to record a demo")?;
+        let (episode, map) = map_episode_and_number(map_name)
+            .ok_or_else(|| format!("{} isn't a map a demo can record", map_name))?;
+
+        let demo = Demo::new(skill_index(self.game_state.skill), episode, map);
+        self.recording = Some((demo, path.into()));
+        Ok(())
+    }
+
+    // Writes out the demo being recorded, if any, and stops recording
+    pub fn finish_recording(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some((demo, path)) = self.recording.take() {
+            std::fs::write(&path, demo.to_bytes())?;
+            println!("Recorded {} tics to {}", demo.tics.len(), path.display());
+        }
+        Ok(())
+    }
+
+    pub fn save_game(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+        self.game_state.save(path)
+    }
+
+    // Replaces the running game with a saved one. The current game is left alone if
+    // the save can't be read.
+    pub fn load_game(&mut self, path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+        let mut game_state = GameState::load(path, &self.wad)?;
+        game_state.movement = self.game_state.movement;
+        game_state.deathmatch = self.game_state.deathmatch;
+        if let (Some(map_name), Some(map)) = (&game_state.map_name, &game_state.current_map) {
+            self.show_map(map_name, map);
+        }
+        self.game_state = game_state;
+        Ok(())
+    }
+
+    // Hands a level's geometry to the renderer. The first frame isn't interpolated
+    // from wherever the player was before.
+    fn show_map(&mut self, map_name: &str, map: &Map) {
+        self.previous_player = None;
+        self.renderer.load_map(map);
+        match BspTree::load_from_wad(&self.wad, map_name) {
+            Ok(bsp) => self.renderer.load_bsp(bsp),
+            Err(e) => eprintln!("Falling back to raycasting for {}: {}", map_name, e),
+        }
+    }
+
     pub fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         let mut event_pump = self.sdl_context.event_pump()?;
 
@@ -55,41 +945,1132 @@ impl Engine {
             let current_time = Instant::now();
             let delta_time = current_time - self.last_frame_time;
             self.last_frame_time = current_time;
+            self.frame_stats.record(delta_time);
+            self.accumulator = (self.accumulator + delta_time).min(MAX_CATCH_UP);
 
-            // Handle input
-            if !self.input_handler.handle_events(&mut event_pump)? {
-                break 'running;
-            }
+            // Input is read once per tick, so each key press is seen by exactly one tick
+            while self.accumulator >= TICK {
+                if !self.input_handler.handle_events(&mut event_pump)? {
+                    break 'running;
+                }
 
-            // Update game state
-            self.update_game_state(delta_time)?;
+                self.previous_player = Some(self.game_state.player.clone());
+                entity::store_previous_transforms(&mut self.game_state.world);
+                self.update_game_state(TICK)?;
+                self.accumulator -= TICK;
+            }
 
             // Render frame
-            self.renderer.render_frame(&self.game_state)?;
+            self.renderer.animate_textures(self.game_state.game_time);
+            if let Some(map) = self.game_state.current_map.as_ref() {
+                self.renderer.animate_lights(map, self.game_state.game_time);
+            }
+            let console_lines = self.console.open.then(|| self.console.visible_lines());
+            let alpha = self.accumulator.as_secs_f64() / TICK.as_secs_f64();
+            let sprites = self.game_state.sprites(alpha);
+            self.renderer.render_frame(&FrameView {
+                map: self.game_state.current_map.as_ref(),
+                player: &self.game_state.player,
+                previous_player: self.previous_player.as_ref(),
+                alpha,
+                automap: self.game_state.automap,
+                paused: self.game_state.paused,
+                palette: self.game_state.flash_palette(),
+                fixed_colormap: self.game_state.fixed_colormap(),
+                hud: self.game_state.player_status().as_ref().map(hud_status),
+                weapon: self.game_state.weapon_overlay(),
+                console: console_lines.as_deref(),
+                sprites: &sprites,
+            })?;
 
-            // Cap frame rate
-            std::thread::sleep(Duration::from_millis(16)); // ~60 FPS
+            if self.show_fps && self.game_state.game_time >= self.fps_shown_at + FPS_WINDOW {
+                self.fps_shown_at = self.game_state.game_time;
+                let stats = &self.frame_stats;
+                let render = self.renderer.stats();
+                self.renderer.set_title_status(Some(&format!(
+                    "{:.0} FPS, {:.1} ms avg, {:.1} ms max, {} rays, {} subsectors, {} wall slices, {}/{} sprites",
+                    stats.fps(),
+                    stats.average_frame_time().as_secs_f64() * 1000.0,
+                    stats.max_frame_time().as_secs_f64() * 1000.0,
+                    render.rays_cast,
+                    render.subsectors_visited,
+                    render.wall_slices,
+                    render.sprites_drawn,
+                    render.sprites_drawn + render.sprites_culled,
+                )))?;
+            }
+
+            // Wait out whatever's left of this frame's share of the second
+            let sleep = frame_sleep(self.config.target_fps, current_time.elapsed());
+            if !sleep.is_zero() {
+                std::thread::sleep(sleep);
+            }
         }
 
-        Ok(())
+        self.finish_recording()
     }
 
     fn update_game_state(
         &mut self,
         delta_time: Duration,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        self.game_state.game_time += delta_time;
+        let keys = TickKeys {
+            console: self.input_handler.was_key_pressed(CONSOLE_KEY),
+            pause: self.input_handler.was_action_pressed(Action::Pause),
+        };
+        let was_paused = self.game_state.paused;
+        let tick = start_tick(&mut self.game_state, &mut self.console, keys, delta_time);
+        if self.game_state.paused != was_paused
+            && let Some(audio) = self.audio.as_mut()
+        {
+            audio.set_paused(self.game_state.paused);
+        }
+        match tick {
+            Tick::Console => {
+                self.update_console();
+                return Ok(());
+            }
+            Tick::Paused => return Ok(()),
+            Tick::Run => {}
+        }
+
+        if self.input_handler.was_key_pressed(Keycode::Tab) {
+            self.game_state.automap = !self.game_state.automap;
+        }
+        // Quicksave and quickload on Doom's keys. A failure is reported but doesn't end the game.
+        if self.input_handler.was_key_pressed(Keycode::F6)
+            && let Err(e) = self.save_game(QUICKSAVE_PATH)
+        {
+            eprintln!("Failed to save to {}: {}", QUICKSAVE_PATH, e);
+        }
+        if self.input_handler.was_key_pressed(Keycode::F9) {
+            if let Err(e) = self.load_game(QUICKSAVE_PATH) {
+                eprintln!("Failed to load {}: {}", QUICKSAVE_PATH, e);
+            }
+            return Ok(());
+        }
+        if self.input_handler.was_key_pressed(Keycode::PrintScreen) {
+            match next_screenshot_path() {
+                Some(path) => self.renderer.queue_screenshot(path),
+                None => eprintln!("No free screenshot name left"),
+            }
+        }
+        if self.input_handler.was_key_pressed(Keycode::F12) {
+            self.set_show_fps(!self.show_fps)?;
+        }
+        if self.game_state.automap {
+            let scale = self.renderer.automap_scale();
+            if self.input_handler.was_key_pressed(Keycode::Equals) {
+                self.renderer.set_automap_scale(scale * 1.25);
+            }
+            if self.input_handler.was_key_pressed(Keycode::Minus) {
+                self.renderer.set_automap_scale(scale / 1.25);
+            }
+        }
 
-        // Update player position based on input
-        self.game_state
-            .player
-            .update(delta_time, &self.input_handler);
+        // This tic's command comes from the demo while one is playing
+        let demo_cmd = self.demo.as_mut().map(|tics| tics.next());
+        let cmd = match demo_cmd {
+            Some(Some(cmd)) => cmd,
+            Some(None) => {
+                println!("Demo finished");
+                self.demo = None;
+                player::tic_cmd(
+                    &self.input_handler,
+                    delta_time,
+                    &self.game_state.movement,
+                    &mut self.game_state.player.turn_rate,
+                )
+            }
+            None => player::tic_cmd(
+                &self.input_handler,
+                delta_time,
+                &self.game_state.movement,
+                &mut self.game_state.player.turn_rate,
+            ),
+        };
+        let cmd = match self.recording.as_mut() {
+            Some((demo, _)) if self.demo.is_none() => demo.record(cmd),
+            _ => cmd,
+        };
+        let use_pressed = cmd.buttons & BT_USE != 0 && self.previous_buttons & BT_USE == 0;
+        self.previous_buttons = cmd.buttons;
+
+        // Update player position based on input, unless the player has died
+        let world = &mut self.game_state.world;
+        let dead = world
+            .query_filtered::<(), (With<PlayerMarker>, With<Dead>)>()
+            .iter(world)
+            .next()
+            .is_some();
+        if !dead {
+            if self.demo.is_none() {
+                self.game_state
+                    .player
+                    .update_debug(delta_time, &self.input_handler);
+            }
+            self.game_state.player.run_tic_cmd(
+                delta_time,
+                &cmd,
+                self.game_state.current_map.as_ref(),
+                &self.game_state.movement,
+            );
+        }
+
+        // Keep the ECS player in sync so monsters chase the real position
+        let player = &self.game_state.player;
+        let world = &mut self.game_state.world;
+        let mut player_query = world.query_filtered::<&mut entity::Transform, With<PlayerMarker>>();
+        for mut transform in player_query.iter_mut(world) {
+            transform.x = player.x;
+            transform.y = player.y;
+            transform.z = player.z;
+            transform.angle = player.angle;
+        }
 
         // Update entities
-        for entity in &mut self.game_state.entities {
-            entity.update(delta_time);
+        let input = &self.input_handler;
+        let weapon_cycle = input.was_button_pressed(Button::RightShoulder) as i32
+            - input.was_button_pressed(Button::LeftShoulder) as i32;
+        *world.resource_mut::<PlayerInput>() = PlayerInput {
+            fire: !dead && cmd.buttons & BT_ATTACK != 0,
+            use_pressed: !dead && use_pressed,
+            weapon_cycle,
+        };
+        world.resource_mut::<Time>().advance(delta_time);
+        self.schedule.run(world);
+
+        // Doors and lifts move the ECS copy of the level and switches change its
+        // textures, so copy the sector heights and any changed sides back for player
+        // movement and rendering
+        if let (Some(map), Some(level)) = (
+            self.game_state.current_map.as_mut(),
+            world.get_resource::<LevelMap>(),
+        ) {
+            map.sectors.clone_from(&level.0.sectors);
+            for (side, level_side) in map.sidedefs.iter_mut().zip(&level.0.sidedefs) {
+                if side.upper_texture != level_side.upper_texture
+                    || side.middle_texture != level_side.middle_texture
+                    || side.lower_texture != level_side.lower_texture
+                {
+                    side.clone_from(level_side);
+                }
+            }
+        }
+
+        // Teleports stop the player dead, as in Doom, and aren't interpolated across
+        if let Some(teleport) = world.remove_resource::<PlayerTeleport>() {
+            let player = &mut self.game_state.player;
+            player.x = teleport.x;
+            player.y = teleport.y;
+            player.angle = teleport.angle;
+            player.velocity = (0.0, 0.0);
+            if let Some(map) = self.game_state.current_map.as_ref() {
+                player.snap_to_floor(map);
+            }
+            self.previous_player = None;
+        }
+        self.play_level_sounds();
+
+        let world = &mut self.game_state.world;
+        if let Some(exit) = world.remove_resource::<LevelExit>() {
+            self.exit_level(exit.secret)?;
+        }
+
+        Ok(())
+    }
+
+    // Takes the tic's typing and runs the line when Return is pressed, printing what
+    // came of it
+    fn update_console(&mut self) {
+        let typed = self.input_handler.text_input();
+        self.console
+            .input
+            .extend(typed.chars().filter(|&character| character != '`'));
+        if self.input_handler.was_key_pressed(Keycode::Backspace) {
+            self.console.input.pop();
+        }
+        if !self.input_handler.was_key_pressed(Keycode::Return) {
+            return;
+        }
+
+        let line = std::mem::take(&mut self.console.input);
+        if line.trim().is_empty() {
+            return;
+        }
+        self.console.print(format!("> {}", line));
+        let result = parse_console_command(&line)
+            .map_err(|e| e.into())
+            .and_then(|command| self.run_console_command(command));
+        match result {
+            Ok(message) => self.console.print(message),
+            Err(e) => self.console.print(format!("Error: {}", e)),
+        }
+    }
+
+    // Carries out a console command, returning the line to print about it
+    pub fn run_console_command(
+        &mut self,
+        command: ConsoleCommand,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        match command {
+            ConsoleCommand::Map(name) => {
+                self.load_map(&name)?;
+                Ok(format!("Started {}", name))
+            }
+            ConsoleCommand::Give(items) => {
+                let mut status = self
+                    .game_state
+                    .player_status()
+                    .ok_or("There is no player to give to")?;
+                let all = items == GiveItems::All;
+                if all || items == GiveItems::Health {
+                    status.health.0 = status.health.0.max(100);
+                }
+                if all || items == GiveItems::Armor {
+                    status.armor = entity::Armor {
+                        points: 200,
+                        class: Some(ArmorClass::Blue),
+                    };
+                }
+                let inventory = &mut status.inventory;
+                if all || items == GiveItems::Weapons {
+                    inventory.weapons.extend(WeaponType::ALL);
+                }
+                if all || items == GiveItems::Ammo {
+                    for ammo_type in AmmoType::ALL {
+                        inventory.ammo.insert(ammo_type, ammo_type.max());
+                    }
+                }
+                if all || items == GiveItems::Keys {
+                    inventory.keys.extend(KeyType::ALL);
+                }
+                self.game_state.set_player_status(status);
+                Ok(format!("Given {}", items.name()))
+            }
+            ConsoleCommand::God => {
+                let world = &mut self.game_state.world;
+                let (player, god) = world
+                    .query_filtered::<(Entity, Has<GodMode>), With<PlayerMarker>>()
+                    .iter(world)
+                    .next()
+                    .ok_or("There is no player")?;
+                if god {
+                    world.entity_mut(player).remove::<GodMode>();
+                } else {
+                    world.entity_mut(player).insert(GodMode);
+                }
+                Ok(format!("God mode {}", if god { "off" } else { "on" }))
+            }
+            ConsoleCommand::Noclip => {
+                let player = &mut self.game_state.player;
+                player.noclip = !player.noclip;
+                Ok(format!(
+                    "No clipping {}",
+                    if player.noclip { "on" } else { "off" }
+                ))
+            }
+            ConsoleCommand::Spawn(thing_type) => {
+                if ThingInfo::lookup(thing_type).is_none() {
+                    return Err(format!("Unknown thing type {}", thing_type).into());
+                }
+                let player = &self.game_state.player;
+                let (x, y) = (
+                    player.x + player.angle.cos() * CONSOLE_SPAWN_DISTANCE,
+                    player.y + player.angle.sin() * CONSOLE_SPAWN_DISTANCE,
+                );
+                let thing = Thing {
+                    tid: 0,
+                    x: x.round() as i16,
+                    y: y.round() as i16,
+                    z: 0,
+                    angle: player.angle.to_degrees().rem_euclid(360.0) as u16,
+                    thing_type,
+                    flags: self.game_state.skill.thing_flag(),
+                    special: 0,
+                    args: [0; 5],
+                };
+                let state = &mut self.game_state;
+                let spawned = spawn_things(
+                    &mut state.world,
+                    std::slice::from_ref(&thing),
+                    state.skill,
+                    state.multiplayer,
+                );
+                if spawned.is_empty() {
+                    return Err(format!("Thing type {} can't be spawned", thing_type).into());
+                }
+                state.entities.extend(spawned);
+                Ok(format!(
+                    "Spawned thing {} at ({}, {})",
+                    thing_type, thing.x, thing.y
+                ))
+            }
+            ConsoleCommand::Teleport { x, y } => {
+                let player = &mut self.game_state.player;
+                player.x = x;
+                player.y = y;
+                player.velocity = (0.0, 0.0);
+                if let Some(map) = self.game_state.current_map.as_ref() {
+                    player.snap_to_floor(map);
+                }
+                self.previous_player = None;
+                Ok(format!("Teleported to ({}, {})", x, y))
+            }
+        }
+    }
+
+    // Plays the sounds made this tic where they were made, and keeps looping ones
+    // following the player until whatever makes them goes away
+    fn play_level_sounds(&mut self) {
+        let world = &mut self.game_state.world;
+        let events = world
+            .get_resource_mut::<SoundEvents>()
+            .map(|mut events| std::mem::take(&mut events.0))
+            .unwrap_or_default();
+        let looping = looping_sounds(world);
+        let Some(audio) = self.audio.as_mut() else {
+            return;
+        };
+
+        // Sounds that find every channel busy are dropped, as in Doom
+        let player = &self.game_state.player;
+        let listener = (player.x, player.y);
+        for event in events {
+            let _ = audio.play_sound_3d(
+                event.sound,
+                listener,
+                player.angle,
+                (event.x, event.y),
+                None,
+            );
+        }
+
+        let owners: HashSet<u64> = looping
+            .iter()
+            .map(|(entity, ..)| entity.to_bits())
+            .collect();
+        audio.retain_looping(|owner| owners.contains(&owner));
+        for (entity, sound, position) in looping {
+            let _ =
+                audio.play_looping_3d(entity.to_bits(), sound, listener, player.angle, position);
         }
+    }
+
+    // Moves on to the level after the current one. The player keeps their health,
+    // armor, weapons and ammo as in Doom, but not their keys.
+    fn exit_level(&mut self, secret: bool) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(current) = self.game_state.map_name.clone() else {
+            return Ok(());
+        };
+        let next = next_map_name(&current, secret)
+            .filter(|next| self.wad.map_names().contains(&next.as_str()));
+        let Some(next) = next else {
+            eprintln!("{} is the last level", current);
+            return Ok(());
+        };
 
+        let status = self.game_state.player_status();
+        self.load_map(&next)?;
+        if let Some(mut status) = status {
+            status.inventory.keys.clear();
+            status.weapon.cooldown = 0.0;
+            self.game_state.set_player_status(status);
+        }
         Ok(())
     }
 }
+
+// Opens the sound device and loads the WAD's sound effects. Failing either isn't fatal:
+// the game carries on without sound.
+fn start_audio(wad: &WadFile) -> Option<AudioManager> {
+    let audio = AudioManager::new().and_then(|mut audio| {
+        audio.load_sound_effects(wad)?;
+        Ok(audio)
+    });
+    match audio {
+        Ok(audio) => Some(audio),
+        Err(e) => {
+            eprintln!("Playing without sound: {}", e);
+            None
+        }
+    }
+}
+
+// Doom's numbered screenshot names, taking the first that isn't already used
+fn next_screenshot_path() -> Option<PathBuf> {
+    (0..100)
+        .map(|number| PathBuf::from(format!("ROOM{:02}.png", number)))
+        .find(|path| !path.exists())
+}
+
+// What the status bar shows of the player's status
+pub fn hud_status(status: &PlayerStatus) -> HudStatus {
+    let inventory = &status.inventory;
+    let ammo_type = status
+        .weapon
+        .selected
+        .ammo_use()
+        .map(|(ammo_type, _)| ammo_type);
+    let key = |card: KeyType, skull: KeyType| {
+        if inventory.keys.contains(&skull) {
+            HudKey::Skull
+        } else if inventory.keys.contains(&card) {
+            HudKey::Card
+        } else {
+            HudKey::None
+        }
+    };
+
+    HudStatus {
+        health: status.health.0,
+        armor: status.armor.points,
+        ammo: ammo_type.map(|ammo_type| inventory.ammo(ammo_type)),
+        ammo_max: ammo_type.map_or(0, |ammo_type| ammo_type.max()),
+        ammo_counts: AmmoType::ALL.map(|ammo_type| (inventory.ammo(ammo_type), ammo_type.max())),
+        keys: [
+            key(KeyType::BlueCard, KeyType::BlueSkull),
+            key(KeyType::YellowCard, KeyType::YellowSkull),
+            key(KeyType::RedCard, KeyType::RedSkull),
+        ],
+    }
+}
+
+// The episode and map numbers a demo header gives a level, with MAPxx levels in
+// episode 1 as Doom II records them
+pub fn map_episode_and_number(name: &str) -> Option<(u8, u8)> {
+    let name = name.to_ascii_uppercase();
+
+    if let Some(number) = name.strip_prefix("MAP") {
+        return Some((1, number.parse().ok()?));
+    }
+    let (episode, mission) = name.strip_prefix('E')?.split_once('M')?;
+    Some((episode.parse().ok()?, mission.parse().ok()?))
+}
+
+// The level an exit leads to, None after the last level of an episode or game.
+// Secret exits in Doom lead to each episode's ninth map, which returns to the map
+// after the one with the secret exit. Doom II's secret levels are MAP31 and MAP32.
+pub fn next_map_name(current: &str, secret: bool) -> Option<String> {
+    let current = current.to_ascii_uppercase();
+
+    if let Some(number) = current.strip_prefix("MAP") {
+        let next = match (number.parse::<u32>().ok()?, secret) {
+            (15, true) => 31,
+            (31, true) => 32,
+            (31, false) | (32, _) => 16,
+            (30, _) => return None,
+            (map, _) => map + 1,
+        };
+        return Some(format!("MAP{:02}", next));
+    }
+
+    let (episode, mission) = current.strip_prefix('E')?.split_once('M')?;
+    let (episode, mission) = (episode.parse::<u32>().ok()?, mission.parse::<u32>().ok()?);
+    let next = match mission {
+        8 => return None,
+        9 => match episode {
+            1 => 4,
+            2 => 6,
+            3 => 7,
+            4 => 3,
+            _ => return None,
+        },
+        _ if secret => 9,
+        mission => mission + 1,
+    };
+    Some(format!("E{}M{}", episode, next))
+}
+
+// The keys that decide whether a tick is played at all
+#[derive(Debug, Default, Clone, Copy)]
+struct TickKeys {
+    console: bool,
+    pause: bool,
+}
+
+// What a tick does once the console and pause keys are handled
+#[derive(Debug, PartialEq, Eq)]
+enum Tick {
+    Console,
+    Paused,
+    Run,
+}
+
+// Opens or closes the console and pauses or resumes for this tick's keys, then moves
+// game time on unless the game is stopped. The game stands still while the console
+// is open, and keys, the pause key included, type into it.
+fn start_tick(
+    game: &mut GameState,
+    console: &mut Console,
+    keys: TickKeys,
+    delta_time: Duration,
+) -> Tick {
+    if keys.console {
+        console.open = !console.open;
+    }
+    if console.open {
+        return Tick::Console;
+    }
+
+    if keys.pause {
+        game.toggle_pause();
+    }
+    if game.advance_time(delta_time) {
+        Tick::Run
+    } else {
+        Tick::Paused
+    }
+}
+
+// The debug console: the line being typed and what's been printed, oldest first
+#[derive(Debug, Default)]
+pub struct Console {
+    pub open: bool,
+    pub input: String,
+    scrollback: VecDeque<String>,
+}
+
+impl Console {
+    pub fn print(&mut self, line: impl Into<String>) {
+        if self.scrollback.len() == CONSOLE_SCROLLBACK {
+            self.scrollback.pop_front();
+        }
+        self.scrollback.push_back(line.into());
+    }
+
+    pub fn scrollback(&self) -> impl Iterator<Item = &String> {
+        self.scrollback.iter()
+    }
+
+    // The latest lines printed and then the line being typed, as drawn
+    pub fn visible_lines(&self) -> Vec<String> {
+        let skipped = self.scrollback.len().saturating_sub(CONSOLE_VISIBLE_LINES);
+        self.scrollback
+            .iter()
+            .skip(skipped)
+            .cloned()
+            .chain([format!("> {}_", self.input)])
+            .collect()
+    }
+}
+
+// A debug command typed into the console
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConsoleCommand {
+    Map(String),
+    Give(GiveItems),
+    God,
+    Noclip,
+    // Spawns a thing of a map thing type in front of the player
+    Spawn(u16),
+    Teleport { x: f64, y: f64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GiveItems {
+    All,
+    Health,
+    Armor,
+    Weapons,
+    Ammo,
+    Keys,
+}
+
+impl GiveItems {
+    pub const ALL: [GiveItems; 6] = [
+        GiveItems::All,
+        GiveItems::Health,
+        GiveItems::Armor,
+        GiveItems::Weapons,
+        GiveItems::Ammo,
+        GiveItems::Keys,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GiveItems::All => "all",
+            GiveItems::Health => "health",
+            GiveItems::Armor => "armor",
+            GiveItems::Weapons => "weapons",
+            GiveItems::Ammo => "ammo",
+            GiveItems::Keys => "keys",
+        }
+    }
+}
+
+// Reads a console line as a command and its arguments. Commands and map names aren't
+// case sensitive.
+pub fn parse_console_command(line: &str) -> Result<ConsoleCommand, String> {
+    let mut words = line.split_whitespace();
+    let command = words.next().ok_or("No command given")?.to_lowercase();
+    let arguments: Vec<&str> = words.collect();
+    let usage = |usage: &str| Err(format!("Usage: {}", usage));
+
+    match (command.as_str(), arguments.as_slice()) {
+        ("map", [name]) => Ok(ConsoleCommand::Map(name.to_uppercase())),
+        ("map", _) => usage("map <name>"),
+        ("give", [items]) => GiveItems::ALL
+            .into_iter()
+            .find(|give| give.name().eq_ignore_ascii_case(items))
+            .map(ConsoleCommand::Give)
+            .ok_or_else(|| format!("Can't give '{}'", items)),
+        ("give", _) => {
+            let names: Vec<&str> = GiveItems::ALL.iter().map(|give| give.name()).collect();
+            usage(&format!("give {}", names.join("|")))
+        }
+        ("god", []) => Ok(ConsoleCommand::God),
+        ("noclip", []) => Ok(ConsoleCommand::Noclip),
+        ("god" | "noclip", _) => usage(&command),
+        ("spawn", [thing_type]) => thing_type
+            .parse()
+            .map(ConsoleCommand::Spawn)
+            .map_err(|_| format!("'{}' isn't a thing type number", thing_type)),
+        ("spawn", _) => usage("spawn <thing type>"),
+        ("tp", [x, y]) => match (x.parse::<f64>(), y.parse::<f64>()) {
+            (Ok(x), Ok(y)) if x.is_finite() && y.is_finite() => {
+                Ok(ConsoleCommand::Teleport { x, y })
+            }
+            _ => Err(format!("'{} {}' isn't a position", x, y)),
+        },
+        ("tp", _) => usage("tp <x> <y>"),
+        _ => Err(format!("Unknown command '{}'", command)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::{FRAC_PI_2, PI};
+
+    fn thing(thing_type: u16, x: i16, y: i16, angle: u16, flags: u16) -> Thing {
+        Thing {
+            tid: 0,
+            x,
+            y,
+            z: 0,
+            angle,
+            thing_type,
+            flags,
+            special: 0,
+            args: [0; 5],
+        }
+    }
+
+    // A 512-unit square room holding the given things
+    fn room(things: Vec<Thing>) -> Map {
+        let corners = [(0, 0), (0, 512), (512, 512), (512, 0)];
+        let mut text = String::from("namespace = \"doom\";\n");
+        for (x, y) in corners {
+            text += &format!("vertex {{ x = {}; y = {}; }}\n", x, y);
+        }
+        for line in 0..corners.len() {
+            text += &format!(
+                "linedef {{ v1 = {}; v2 = {}; sidefront = {}; blocking = true; }}\n",
+                line,
+                (line + 1) % corners.len(),
+                line
+            );
+            text += "sidedef { sector = 0; texturemiddle = \"STARTAN3\"; }\n";
+        }
+        text += "sector { heightfloor = 0; heightceiling = 128; lightlevel = 255; }\n";
+        let mut map = Map::parse_udmf(&text).unwrap();
+        map.things = things;
+        map
+    }
+
+    const ALL_SKILLS: u16 = MTF_EASY | MTF_NORMAL | MTF_HARD;
+
+    #[test]
+    fn spawns_monsters_items_and_the_player_start() {
+        let things = vec![
+            thing(3001, 100, 120, 90, ALL_SKILLS),
+            thing(2012, 200, 220, 0, ALL_SKILLS),
+            thing(1, 300, 320, 180, ALL_SKILLS),
+        ];
+        let mut game = GameState::new();
+        game.start_level("TEST", room(things)).unwrap();
+
+        assert_eq!((game.player.x, game.player.y), (300.0, 320.0));
+        assert!((game.player.angle - PI).abs() < 1e-6);
+
+        let mut query = game.world.query::<(&EntityType, &Transform, &ThingType)>();
+        let mut spawned: Vec<_> = query
+            .iter(&game.world)
+            .map(|(entity_type, transform, thing_type)| {
+                (thing_type.0, entity_type.clone(), transform.clone())
+            })
+            .collect();
+        spawned.sort_by_key(|(thing_type, ..)| *thing_type);
+        assert_eq!(spawned.len(), 2);
+
+        let (_, medikit, transform) = &spawned[0];
+        assert!(matches!(
+            medikit,
+            EntityType::Item {
+                item_type: ItemType::Health { amount: 25, .. },
+                ..
+            }
+        ));
+        assert_eq!((transform.x, transform.y), (200.0, 220.0));
+
+        let (_, imp, transform) = &spawned[1];
+        assert!(matches!(
+            imp,
+            EntityType::Monster {
+                monster_type: MonsterType::Imp,
+                health: 60,
+            }
+        ));
+        assert_eq!((transform.x, transform.y), (100.0, 120.0));
+        assert!((transform.angle - FRAC_PI_2).abs() < 1e-6);
+
+        let players = game
+            .world
+            .query_filtered::<&Transform, With<PlayerMarker>>()
+            .iter(&game.world)
+            .map(|transform| (transform.x, transform.y))
+            .collect::<Vec<_>>();
+        assert_eq!(players, [(300.0, 320.0)]);
+    }
+
+    #[test]
+    fn a_map_without_a_player_start_is_refused() {
+        let mut game = GameState::new();
+        assert!(game.start_level("TEST", room(Vec::new())).is_err());
+        assert!(game.current_map.is_none());
+    }
+
+    #[test]
+    fn spawning_skips_things_off_this_skill_and_deafens_ambushers() {
+        let things = [
+            thing(3001, 0, 0, 0, MTF_EASY),
+            thing(3001, 10, 0, 0, MTF_NORMAL | MTF_AMBUSH),
+            thing(3001, 20, 0, 0, MTF_NORMAL | MTF_MULTIPLAYER),
+        ];
+        let mut world = World::new();
+        let spawned = spawn_things(&mut world, &things, SkillLevel::HurtMePlenty, false);
+
+        assert_eq!(spawned.len(), 1);
+        assert_eq!(world.get::<Transform>(spawned[0]).unwrap().x, 10.0);
+        assert!(world.get::<Deaf>(spawned[0]).is_some());
+
+        let mut world = World::new();
+        let spawned = spawn_things(&mut world, &things, SkillLevel::HurtMePlenty, true);
+        assert_eq!(spawned.len(), 2);
+    }
+
+    #[test]
+    fn sound_sources_loop_until_they_despawn() {
+        let mut world = World::new();
+        let things = [thing(14001, 64, 32, 0, MTF_NORMAL)];
+        let spawned = spawn_things(&mut world, &things, SkillLevel::HurtMePlenty, false);
+        assert_eq!(
+            looping_sounds(&mut world),
+            [(spawned[0], "DSSTNMOV", (64.0, 32.0))]
+        );
+        // Heard but not seen, and nothing bumps into them
+        assert!(world.get::<entity::Sprite>(spawned[0]).is_none());
+        assert!(world.get::<Collider>(spawned[0]).is_none());
+
+        world.despawn(spawned[0]);
+        assert!(looping_sounds(&mut world).is_empty());
+
+        // A saved game brings them back playing
+        let saved = SavedThing {
+            thing_type: 14002,
+            x: 10.0,
+            y: 20.0,
+            angle: 0.0,
+            health: 0,
+            deaf: false,
+        };
+        let spawned = spawn_saved_things(&mut world, &[saved]);
+        assert_eq!(
+            looping_sounds(&mut world),
+            [(spawned[0], "DSSAWIDL", (10.0, 20.0))]
+        );
+    }
+
+    #[test]
+    fn frame_stats_average_and_rolling_fps() {
+        let mut stats = FrameStats::new();
+        assert_eq!(stats.fps(), 0.0);
+        assert_eq!(stats.average_frame_time(), Duration::ZERO);
+
+        for millis in [10, 30, 20] {
+            stats.record(Duration::from_millis(millis));
+        }
+        assert_eq!(stats.frame_count(), 3);
+        assert_eq!(stats.average_frame_time(), Duration::from_millis(20));
+        assert_eq!(stats.min_frame_time(), Duration::from_millis(10));
+        assert_eq!(stats.max_frame_time(), Duration::from_millis(30));
+        assert!((stats.fps() - 50.0).abs() < 1e-9);
+
+        // A second and a half of 25ms frames pushes the early ones out of the window
+        for _ in 0..60 {
+            stats.record(Duration::from_millis(25));
+        }
+        assert!((stats.fps() - 40.0).abs() < 1e-9, "fps {}", stats.fps());
+        assert_eq!(stats.min_frame_time(), Duration::from_millis(10));
+    }
+
+    fn doom1() -> WadFile {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../game/Doom1.WAD");
+        WadFile::from_bytes(&std::fs::read(path).unwrap()).unwrap()
+    }
+
+    fn saved_things(game: &mut GameState) -> Vec<(u16, i64, i64, i32)> {
+        let mut query = game.world.query::<(&ThingType, &Transform, &EntityType)>();
+        let mut things: Vec<_> = query
+            .iter(&game.world)
+            .map(|(thing_type, transform, entity_type)| {
+                let health = match entity_type {
+                    EntityType::Monster { health, .. } => *health,
+                    _ => 0,
+                };
+                let (x, y) = (transform.x.round() as i64, transform.y.round() as i64);
+                (thing_type.0, x, y, health)
+            })
+            .collect();
+        things.sort();
+        things
+    }
+
+    #[test]
+    fn saved_game_loads_back_the_same_state() {
+        let wad = doom1();
+        let mut game = GameState::new();
+        game.skill = SkillLevel::UltraViolence;
+        game.start_level("E1M1", Map::load_from_wad(&wad, "E1M1").unwrap())
+            .unwrap();
+
+        game.player.x += 40.0;
+        game.player.angle = 1.25;
+        let mut status = game.player_status().unwrap();
+        status.health = Health(73);
+        status.armor = entity::Armor {
+            points: 40,
+            class: Some(ArmorClass::Green),
+        };
+        status.inventory.ammo.insert(AmmoType::Shells, 12);
+        status.inventory.weapons.insert(WeaponType::Shotgun);
+        status.inventory.keys.insert(KeyType::BlueCard);
+        status.weapon.selected = WeaponType::Shotgun;
+        game.set_player_status(status.clone());
+        for entity_type in game
+            .world
+            .query::<&mut EntityType>()
+            .iter_mut(&mut game.world)
+        {
+            if let EntityType::Monster { health, .. } = entity_type.into_inner() {
+                *health -= 5;
+                break;
+            }
+        }
+
+        let save = std::env::temp_dir().join(format!("room-save-{}.sav", std::process::id()));
+        game.save(&save).unwrap();
+        let mut loaded = GameState::load(&save, &wad).unwrap();
+        std::fs::remove_file(&save).unwrap();
+
+        assert_eq!(loaded.map_name.as_deref(), Some("E1M1"));
+        assert_eq!(loaded.skill, SkillLevel::UltraViolence);
+        assert_eq!(
+            (loaded.player.x, loaded.player.y, loaded.player.angle),
+            (game.player.x, game.player.y, game.player.angle)
+        );
+        // Ammo the player has none of is saved as a count of 0
+        let loaded_status = loaded.player_status().unwrap();
+        for ammo_type in AmmoType::ALL {
+            assert_eq!(
+                loaded_status.inventory.ammo(ammo_type),
+                status.inventory.ammo(ammo_type)
+            );
+        }
+        assert_eq!(
+            PlayerStatus {
+                inventory: Inventory {
+                    ammo: status.inventory.ammo.clone(),
+                    ..loaded_status.inventory
+                },
+                ..loaded_status
+            },
+            status
+        );
+        assert_eq!(saved_things(&mut loaded), saved_things(&mut game));
+    }
+
+    #[test]
+    fn saves_from_another_version_are_refused() {
+        let save = std::env::temp_dir().join(format!("room-old-{}.sav", std::process::id()));
+        let mut data = SAVE_MAGIC.to_vec();
+        data.extend_from_slice(&(SAVE_VERSION + 1).to_le_bytes());
+        std::fs::write(&save, data).unwrap();
+
+        let result = GameState::load(&save, &WadFile { lumps: Vec::new() });
+        std::fs::remove_file(&save).unwrap();
+        let error = result.err().unwrap().to_string();
+        assert!(error.contains("not supported"), "{}", error);
+    }
+
+    #[test]
+    fn starting_another_level_replaces_the_player_and_entities() {
+        let wad = doom1();
+        let mut game = GameState::new();
+        game.start_level("E1M1", Map::load_from_wad(&wad, "E1M1").unwrap())
+            .unwrap();
+        let first_level = saved_things(&mut game);
+
+        let e1m2 = Map::load_from_wad(&wad, "E1M2").unwrap();
+        let start = e1m2.player_start(1).unwrap().clone();
+        let mut fresh = GameState::new();
+        fresh.start_level("E1M2", e1m2.clone()).unwrap();
+        game.start_level("E1M2", e1m2).unwrap();
+
+        assert_eq!(game.map_name.as_deref(), Some("E1M2"));
+        assert_eq!(
+            (game.player.x, game.player.y),
+            (start.x as f64, start.y as f64)
+        );
+
+        // Nothing is left over from E1M1
+        let second_level = saved_things(&mut game);
+        assert_ne!(second_level, first_level);
+        assert_eq!(second_level, saved_things(&mut fresh));
+        assert_eq!(game.world.entities().len() as usize, game.entities.len());
+
+        let players = game
+            .world
+            .query_filtered::<&Transform, With<PlayerMarker>>()
+            .iter(&game.world)
+            .map(|transform| (transform.x, transform.y))
+            .collect::<Vec<_>>();
+        assert_eq!(players, [(start.x as f64, start.y as f64)]);
+    }
+
+    #[test]
+    fn game_time_stands_still_while_paused() {
+        let mut game = GameState::new();
+        let mut console = Console::default();
+        let mut tick = |game: &mut GameState, console_key: bool, pause: bool| {
+            let keys = TickKeys {
+                console: console_key,
+                pause,
+            };
+            start_tick(game, &mut console, keys, TICK)
+        };
+        assert_eq!(tick(&mut game, false, false), Tick::Run);
+        assert_eq!(game.game_time, TICK);
+
+        assert_eq!(tick(&mut game, false, true), Tick::Paused);
+        assert!(game.paused);
+        assert_eq!(tick(&mut game, false, false), Tick::Paused);
+        assert_eq!(game.game_time, TICK);
+
+        // The console still opens and closes while paused, and the game stays paused
+        assert_eq!(tick(&mut game, true, false), Tick::Console);
+        assert_eq!(tick(&mut game, false, true), Tick::Console);
+        assert_eq!(tick(&mut game, true, false), Tick::Paused);
+        assert_eq!(game.game_time, TICK);
+
+        // Resuming picks up from the same time rather than catching up
+        assert_eq!(tick(&mut game, false, true), Tick::Run);
+        assert!(!game.paused);
+        assert_eq!(game.game_time, TICK * 2);
+    }
+
+    #[test]
+    fn console_reads_commands_and_their_arguments() {
+        assert_eq!(
+            parse_console_command("tp 100 200"),
+            Ok(ConsoleCommand::Teleport { x: 100.0, y: 200.0 })
+        );
+        assert_eq!(
+            parse_console_command("  MAP e1m2 "),
+            Ok(ConsoleCommand::Map("E1M2".to_string()))
+        );
+        assert_eq!(
+            parse_console_command("give All"),
+            Ok(ConsoleCommand::Give(GiveItems::All))
+        );
+        assert_eq!(
+            parse_console_command("spawn 3001"),
+            Ok(ConsoleCommand::Spawn(3001))
+        );
+
+        // Malformed lines say what's wrong instead
+        for line in [
+            "",
+            "tp 100",
+            "tp 100 north",
+            "tp inf 0",
+            "god mode",
+            "spawn imp",
+            "give rockets",
+            "fly",
+        ] {
+            assert!(
+                parse_console_command(line).is_err(),
+                "'{}' parsed as a command",
+                line
+            );
+        }
+        assert_eq!(
+            parse_console_command("tp 1 2 3"),
+            Err("Usage: tp <x> <y>".to_string())
+        );
+    }
+
+    #[test]
+    fn frame_cap_sleeps_only_for_the_rest_of_the_frame() {
+        // A thirtieth of a second is about 33ms, so 10ms of work leaves about 23 to wait
+        let sleep = frame_sleep(Some(30), Duration::from_millis(10));
+        assert!(
+            (sleep.as_secs_f64() - (1.0 / 30.0 - 0.010)).abs() < 1e-6,
+            "slept {:?}",
+            sleep
+        );
+        assert_eq!(
+            frame_sleep(Some(30), Duration::from_millis(40)),
+            Duration::ZERO
+        );
+        assert_eq!(frame_sleep(None, Duration::from_millis(1)), Duration::ZERO);
+    }
+
+    #[test]
+    fn invulnerability_inverts_the_view_until_it_runs_out() {
+        use bevy_ecs::system::RunSystemOnce;
+
+        let wad = doom1();
+        let mut game = GameState::new();
+        game.start_level("E1M1", Map::load_from_wad(&wad, "E1M1").unwrap())
+            .unwrap();
+        assert_eq!(game.fixed_colormap(), None);
+
+        let mut players = game
+            .world
+            .query_filtered::<&mut Powerups, With<PlayerMarker>>();
+        players
+            .single_mut(&mut game.world)
+            .unwrap()
+            .give(PowerupType::Invulnerability);
+        assert_eq!(game.fixed_colormap(), Some(INVERSE_COLORMAP));
+
+        // Inverted all the way until the flicker near the end, then back to normal
+        let tics = (PowerupType::Invulnerability.duration() / TICK.as_secs_f64()).ceil() as u32;
+        let mut inverted = 0;
+        for _ in 0..tics + 1 {
+            game.world.resource_mut::<Time>().advance(TICK);
+            game.world.run_system_once(update_powerups).unwrap();
+            inverted += (game.fixed_colormap() == Some(INVERSE_COLORMAP)) as u32;
+        }
+        assert!(
+            inverted > tics - 128 && inverted < tics,
+            "inverted for {} of {} tics",
+            inverted,
+            tics
+        );
+        assert_eq!(game.fixed_colormap(), None);
+    }
+}
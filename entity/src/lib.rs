@@ -1,7 +1,72 @@
 use bevy_ecs::prelude::*;
+use map::{Map, Sector, Sidedef, Thing};
+use math::DoomRng;
+use std::collections::{HashMap, HashSet};
+use std::f64::consts::{FRAC_1_SQRT_2, FRAC_PI_2, FRAC_PI_4};
 use std::time::Duration;
 
-pub struct Entity;
+// Monster AI timings in seconds
+const REACTION_TIME: f64 = 0.25;
+const ATTACK_TIME: f64 = 0.5;
+const ATTACK_COOLDOWN: f64 = 1.0;
+const PAIN_TIME: f64 = 0.2;
+const CHASE_DIRECTION_TIME: f64 = 0.5;
+// Idle monsters further than this from the player lie dormant, only looking for them
+// every 10 tics as Doom's idle monsters do
+const DORMANT_DISTANCE: f64 = 1024.0;
+const DORMANT_LOOK_TIME: f64 = 10.0 / 35.0;
+
+// Reach of a melee attack beyond the target's radius, as in Doom's MELEERANGE
+const MELEE_RANGE: f64 = 64.0;
+// Chase directions are only taken along an axis when it's off by more than this
+const CHASE_DEADZONE: f64 = 10.0;
+const MISSILE_RADIUS: f64 = 6.0;
+// How far the player's bullets travel, Doom's MISSILERANGE
+const HITSCAN_RANGE: f64 = 2048.0;
+// Shots fly this far above the shooter's feet
+const SHOT_HEIGHT: f64 = 32.0;
+// Blood sprays and bullet puffs play these sprite frames for this long each, from
+// Doom's S_BLOOD and S_PUFF states. Puffs sit this far back from the wall they hit.
+const BLOOD_FRAMES: &str = "CBA";
+const BLOOD_FRAME_TIME: f64 = 8.0 / 35.0;
+const PUFF_FRAMES: &str = "ABCD";
+const PUFF_FRAME_TIME: f64 = 4.0 / 35.0;
+const PUFF_WALL_OFFSET: f64 = 4.0;
+// How far away the player can press a switch or open a door
+const USE_RANGE: f64 = 64.0;
+
+// Door and lift speeds in units per second and waits in seconds, from Doom's tic values
+const DOOR_SPEED: f64 = 70.0;
+const FAST_DOOR_SPEED: f64 = 280.0;
+const DOOR_WAIT: f64 = 150.0 / 35.0;
+const LIFT_SPEED: f64 = 140.0;
+const FAST_LIFT_SPEED: f64 = 280.0;
+const LIFT_WAIT: f64 = 3.0;
+// Floors and crushing ceilings move a unit per tic, stairs a quarter of that
+const FLOOR_SPEED: f64 = 35.0;
+const FAST_FLOOR_SPEED: f64 = 140.0;
+const STAIR_SPEED: f64 = 8.75;
+const CRUSHER_SPEED: f64 = 35.0;
+const FAST_CRUSHER_SPEED: f64 = 70.0;
+// Crushers come down to this far above the floor, hurting whatever they squeeze every
+// 4 tics
+const CRUSHER_GAP: f64 = 8.0;
+const CRUSH_DAMAGE: i32 = 10;
+const CRUSH_INTERVAL: f64 = 4.0 / 35.0;
+// Teleport destinations are things of this type. The fog left at both ends plays for
+// TELEPORT_FOG_TIME, the one at the destination this far in front of the arrival.
+const TELEPORT_DESTINATION: u16 = 14;
+const TELEPORT_FOG_TIME: f64 = 60.0 / 35.0;
+const TELEPORT_FOG_DISTANCE: f64 = 20.0;
+// Deathmatch starts are markers of this type
+const DEATHMATCH_START: u16 = 11;
+// How long a repeatable switch stays pressed before popping back out, Doom's BUTTONTIME
+const BUTTON_TIME: f64 = 1.0;
+
+// Screen flash lengths in tics: each point of damage adds one, up to a limit, and
+// each pickup adds a few
+const MAX_DAMAGE_FLASH: f64 = 100.0;
+const BONUS_FLASH: f64 = 6.0;
 
 // Components
 #[derive(Component, Debug, Clone)]
@@ -29,6 +94,22 @@ pub struct Transform {
     pub angle: f64,
 }
 
+impl Transform {
+    // Where a thing appears a fraction alpha of the way from this transform to the next
+    pub fn lerp(&self, next: &Transform, alpha: f64) -> Transform {
+        Transform {
+            x: math::lerp(self.x, next.x, alpha),
+            y: math::lerp(self.y, next.y, alpha),
+            z: math::lerp(self.z, next.z, alpha),
+            angle: math::lerp_angle(self.angle, next.angle, alpha),
+        }
+    }
+}
+
+// Transform at the start of the latest tick, for drawing frames between ticks
+#[derive(Component, Debug, Clone)]
+pub struct PreviousTransform(pub Transform);
+
 #[derive(Component, Debug, Clone)]
 pub struct Collider {
     pub radius: f64,
@@ -40,106 +121,4090 @@ pub struct Sprite {
     pub name: String,
 }
 
-#[derive(Component)]
-pub struct Active(bool);
+// Whether the entity is updated every tic. See set_active.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Active(pub bool);
+
+// Marks a monster update_dormancy has put to sleep. It's skipped like an inactive one,
+// but only dormancy wakes it, so it never undoes set_active.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Dormant;
+
+// The map thing type an entity was spawned from, so a saved game can respawn it
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThingType(pub u16);
+
+// Monsters placed with the ambush flag ignore sound until they see the player
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Deaf;
+
+// Marks the entity controlled by the local player
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Player;
+
+// A player with this takes no damage, like Doom's god mode cheat
+#[derive(Component, Debug, Clone, Copy)]
+pub struct GodMode;
+
+// Seconds left of each powerup the player has picked up, 0 once it's worn off
+#[derive(Component, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Powerups {
+    pub invulnerability: f64,
+    pub invisibility: f64,
+}
+
+impl Powerups {
+    // Picking up a powerup again starts its timer over
+    pub fn give(&mut self, powerup: PowerupType) {
+        *self.timer_mut(powerup) = powerup.duration();
+    }
+
+    pub fn remaining(&self, powerup: PowerupType) -> f64 {
+        match powerup {
+            PowerupType::Invulnerability => self.invulnerability,
+            PowerupType::PartialInvisibility => self.invisibility,
+        }
+    }
+
+    pub fn is_active(&self, powerup: PowerupType) -> bool {
+        self.remaining(powerup) > 0.0
+    }
+
+    pub fn tick(&mut self, dt: f64) {
+        for powerup in PowerupType::ALL {
+            let timer = self.timer_mut(powerup);
+            *timer = (*timer - dt).max(0.0);
+        }
+    }
+
+    fn timer_mut(&mut self, powerup: PowerupType) -> &mut f64 {
+        match powerup {
+            PowerupType::Invulnerability => &mut self.invulnerability,
+            PowerupType::PartialInvisibility => &mut self.invisibility,
+        }
+    }
+}
+
+// Whether a player with god mode `god` and these powerups is safe from harm
+fn shielded(god: bool, powerups: Option<&Powerups>) -> bool {
+    god || powerups.is_some_and(|powerups| powerups.is_active(PowerupType::Invulnerability))
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Health(pub i32);
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Armor {
+    pub points: i32,
+    pub class: Option<ArmorClass>,
+}
+
+// Green armor absorbs a third of incoming damage, blue armor half
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArmorClass {
+    Green,
+    Blue,
+}
+
+impl ArmorClass {
+    pub fn absorbed(self, damage: i32) -> i32 {
+        match self {
+            ArmorClass::Green => damage / 3,
+            ArmorClass::Blue => damage / 2,
+        }
+    }
+}
+
+// Added to the player when their health runs out
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Dead;
+
+// Doom's damagecount and bonuscount, the tics left of the red flash from being hurt
+// and the gold flash from picking something up. Both count down to zero.
+#[derive(Component, Debug, Clone, Default)]
+pub struct PaletteFlash {
+    pub damage: f64,
+    pub bonus: f64,
+    // Health when last checked, so any loss since can be added to the damage flash
+    last_health: Option<i32>,
+}
+
+#[derive(Component, Debug, Clone, PartialEq, Eq)]
+pub struct Inventory {
+    pub ammo: HashMap<AmmoType, u32>,
+    pub weapons: HashSet<WeaponType>,
+    pub keys: HashSet<KeyType>,
+}
+
+// Doom starts the player with a fist, a pistol and 50 bullets
+impl Default for Inventory {
+    fn default() -> Self {
+        Inventory {
+            ammo: HashMap::from([(AmmoType::Bullets, 50)]),
+            weapons: HashSet::from([WeaponType::Fist, WeaponType::Pistol]),
+            keys: HashSet::new(),
+        }
+    }
+}
+
+impl Inventory {
+    // Every weapon and key and full ammo, as the idkfa cheat gives
+    pub fn give_all(&mut self) {
+        self.weapons.extend(WeaponType::ALL);
+        self.keys.extend(KeyType::ALL);
+        for ammo_type in AmmoType::ALL {
+            self.ammo.insert(ammo_type, ammo_type.max());
+        }
+    }
+
+    pub fn ammo(&self, ammo_type: AmmoType) -> u32 {
+        self.ammo.get(&ammo_type).copied().unwrap_or(0)
+    }
+
+    // Adds ammo up to the type's maximum, returning false if it was already full
+    pub fn give_ammo(&mut self, ammo_type: AmmoType, amount: u32) -> bool {
+        let current = self.ammo(ammo_type);
+        if current >= ammo_type.max() {
+            return false;
+        }
+        self.ammo
+            .insert(ammo_type, (current + amount).min(ammo_type.max()));
+        true
+    }
+
+    // Takes one shot's worth of ammo for the weapon, returning false if there isn't enough
+    pub fn consume_ammo(&mut self, weapon: WeaponType) -> bool {
+        let Some((ammo_type, amount)) = weapon.ammo_use() else {
+            return true;
+        };
+        let current = self.ammo(ammo_type);
+        if current < amount {
+            return false;
+        }
+        self.ammo.insert(ammo_type, current - amount);
+        true
+    }
+}
+
+// The player's selected weapon and the time left before it can fire again
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct Weapon {
+    pub selected: WeaponType,
+    pub cooldown: f64,
+}
+
+impl Default for Weapon {
+    fn default() -> Self {
+        Weapon {
+            selected: WeaponType::Pistol,
+            cooldown: 0.0,
+        }
+    }
+}
+
+impl Weapon {
+    pub fn is_ready(&self) -> bool {
+        self.cooldown <= 0.0
+    }
+
+    // The frame of the weapon's view sprite: its firing frames in turn, spread over
+    // the time between shots, and its ready frame otherwise
+    pub fn view_frame(&self) -> char {
+        let (ready, firing) = self.selected.view_frames();
+        if self.is_ready() {
+            return ready;
+        }
+        let progress = 1.0 - self.cooldown / self.selected.refire_time();
+        firing[((progress * firing.len() as f64) as usize).min(firing.len() - 1)]
+    }
+}
+
+// Where the player was last frame, for spotting walk-over line triggers
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct LastPosition {
+    pub x: f64,
+    pub y: f64,
+}
+
+// Seconds until the entity is removed, for effects like teleport fog
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct Lifetime(pub f64);
+
+// Sprite frames played once in order, removing the entity after the last, for effects
+// like blood and bullet puffs
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct Animation {
+    pub frames: &'static str,
+    pub frame_time: f64,
+    pub elapsed: f64,
+}
+
+impl Animation {
+    pub fn new(frames: &'static str, frame_time: f64) -> Self {
+        Animation {
+            frames,
+            frame_time,
+            elapsed: 0.0,
+        }
+    }
+
+    // The frame showing now, None once every frame has played
+    pub fn frame(&self) -> Option<char> {
+        self.frames
+            .chars()
+            .nth((self.elapsed / self.frame_time) as usize)
+    }
+}
+
+// A sound played on a loop from wherever the entity is, for as long as it exists
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AmbientSound(pub &'static str);
+
+// A pressed repeatable switch, flipped back to its unpressed texture when the timer
+// runs out
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct SwitchReset {
+    pub sidedef: usize,
+    pub timer: f64,
+}
+
+// A picked-up item waiting to reappear. It can't be collected until this is removed.
+#[derive(Component, Debug, Clone)]
+pub struct Respawning {
+    pub remaining: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AiState {
+    // Idle until the player comes into view
+    Spawn,
+    // Alerted, waiting out the reaction time
+    See,
+    Chase,
+    Attack,
+    Pain,
+    Death,
+}
+
+#[derive(Component, Debug, Clone)]
+pub struct MonsterAi {
+    pub state: AiState,
+    // Seconds left before the current state may change
+    pub timer: f64,
+    pub direction: Option<MoveDirection>,
+    // Seconds left before picking a new chase direction
+    pub direction_timer: f64,
+}
+
+impl Default for MonsterAi {
+    fn default() -> Self {
+        MonsterAi {
+            state: AiState::Spawn,
+            timer: 0.0,
+            direction: None,
+            direction_timer: 0.0,
+        }
+    }
+}
+
+impl MonsterAi {
+    pub fn enter(&mut self, state: AiState, timer: f64) {
+        self.state = state;
+        self.timer = timer;
+    }
+}
+
+// The eight directions monsters walk in, counterclockwise from east
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveDirection {
+    East,
+    NorthEast,
+    North,
+    NorthWest,
+    West,
+    SouthWest,
+    South,
+    SouthEast,
+}
+
+impl MoveDirection {
+    pub const ALL: [MoveDirection; 8] = [
+        MoveDirection::East,
+        MoveDirection::NorthEast,
+        MoveDirection::North,
+        MoveDirection::NorthWest,
+        MoveDirection::West,
+        MoveDirection::SouthWest,
+        MoveDirection::South,
+        MoveDirection::SouthEast,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn opposite(self) -> Self {
+        Self::ALL[(self.index() + 4) % 8]
+    }
+
+    pub fn angle(self) -> f64 {
+        self.index() as f64 * FRAC_PI_4
+    }
+
+    // Unit step, diagonals included
+    pub fn vector(self) -> (f64, f64) {
+        match self {
+            MoveDirection::East => (1.0, 0.0),
+            MoveDirection::NorthEast => (FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+            MoveDirection::North => (0.0, 1.0),
+            MoveDirection::NorthWest => (-FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+            MoveDirection::West => (-1.0, 0.0),
+            MoveDirection::SouthWest => (-FRAC_1_SQRT_2, -FRAC_1_SQRT_2),
+            MoveDirection::South => (0.0, -1.0),
+            MoveDirection::SouthEast => (FRAC_1_SQRT_2, -FRAC_1_SQRT_2),
+        }
+    }
+
+    fn diagonal(horizontal: MoveDirection, vertical: MoveDirection) -> Option<Self> {
+        match (horizontal, vertical) {
+            (MoveDirection::East, MoveDirection::North) => Some(MoveDirection::NorthEast),
+            (MoveDirection::West, MoveDirection::North) => Some(MoveDirection::NorthWest),
+            (MoveDirection::West, MoveDirection::South) => Some(MoveDirection::SouthWest),
+            (MoveDirection::East, MoveDirection::South) => Some(MoveDirection::SouthEast),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MonsterAttack {
+    Melee { damage: i32 },
+    Hitscan { damage: i32 },
+    Missile { damage: i32, speed: f64 },
+}
 
 #[derive(Debug, Clone)]
 pub enum MonsterType {
+    ZombieMan,
+    ShotgunGuy,
     Imp,
     Demon,
+    Spectre,
+    LostSoul,
     Cacodemon,
     BaronOfHell,
+    SpiderMastermind,
+    Cyberdemon,
+}
+
+// Damage values are the averages of Doom's random rolls
+impl MonsterType {
+    // Walking speed in units per second
+    pub fn speed(&self) -> f64 {
+        match self {
+            MonsterType::ZombieMan | MonsterType::ShotgunGuy | MonsterType::Imp => 70.0,
+            MonsterType::Demon | MonsterType::Spectre => 175.0,
+            MonsterType::LostSoul => 100.0,
+            MonsterType::Cacodemon | MonsterType::BaronOfHell => 93.0,
+            MonsterType::SpiderMastermind => 140.0,
+            MonsterType::Cyberdemon => 187.0,
+        }
+    }
+
+    pub fn melee_attack(&self) -> Option<MonsterAttack> {
+        let damage = match self {
+            MonsterType::Imp => 12,
+            MonsterType::Demon | MonsterType::Spectre => 22,
+            MonsterType::LostSoul => 13,
+            MonsterType::Cacodemon => 35,
+            MonsterType::BaronOfHell => 45,
+            MonsterType::ZombieMan
+            | MonsterType::ShotgunGuy
+            | MonsterType::SpiderMastermind
+            | MonsterType::Cyberdemon => return None,
+        };
+        Some(MonsterAttack::Melee { damage })
+    }
+
+    pub fn ranged_attack(&self) -> Option<MonsterAttack> {
+        match self {
+            MonsterType::ZombieMan => Some(MonsterAttack::Hitscan { damage: 9 }),
+            MonsterType::ShotgunGuy => Some(MonsterAttack::Hitscan { damage: 27 }),
+            MonsterType::Imp => Some(MonsterAttack::Missile {
+                damage: 13,
+                speed: 350.0,
+            }),
+            MonsterType::Cacodemon => Some(MonsterAttack::Missile {
+                damage: 22,
+                speed: 350.0,
+            }),
+            MonsterType::BaronOfHell => Some(MonsterAttack::Missile {
+                damage: 36,
+                speed: 525.0,
+            }),
+            MonsterType::SpiderMastermind => Some(MonsterAttack::Hitscan { damage: 27 }),
+            MonsterType::Cyberdemon => Some(MonsterAttack::Missile {
+                damage: 90,
+                speed: 700.0,
+            }),
+            MonsterType::Demon | MonsterType::Spectre | MonsterType::LostSoul => None,
+        }
+    }
 }
 
+// What a map thing turns into when the level starts
 #[derive(Debug, Clone)]
-pub enum ItemType {
-    Health,
-    Armor,
+pub enum ThingCategory {
+    // Numbered from 1
+    PlayerStart(u8),
+    Monster(MonsterType),
+    Item(ItemType),
     Weapon(WeaponType),
-    Ammo(AmmoType),
-    Key(KeyType),
+    Decoration,
+    // Spots used by other code that spawn nothing, like deathmatch starts and
+    // teleport destinations
+    Marker,
+    // Unseen things that loop a sound from where they stand
+    SoundSource(&'static str),
 }
 
-// Systems
-pub fn update_monsters(
-    mut monsters: Query<(&mut Transform, &EntityType), With<Active>>,
-    player: Query<&Transform, With<Player>>,
-    time: Res<Time>,
-) {
-    let player_transform = if let Ok(transform) = player.get_single() {
-        transform
-    } else {
-        return;
-    };
+// The broad kinds of map thing, for picking one kind out of a map with things_of.
+// Deathmatch starts count as player starts, and markers that are neither a start nor
+// a teleport destination are just markers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ThingKind {
+    Monster,
+    Item,
+    Weapon,
+    Decoration,
+    PlayerStart,
+    Teleport,
+    Marker,
+}
+
+// Per-type details from Doom's mobjinfo table. Things that can't be hurt have
+// Doom's default spawn health of 1000.
+#[derive(Debug, Clone)]
+pub struct ThingInfo {
+    pub doomednum: u16,
+    pub sprite: &'static str,
+    pub radius: f64,
+    pub height: f64,
+    pub health: i32,
+    pub category: ThingCategory,
+}
+
+// Stand-in for editor numbers that aren't in THING_INFO
+static UNKNOWN_THING: ThingInfo = decoration(0, "UNKN", 20.0, 16.0);
+
+impl ThingInfo {
+    pub fn lookup(doomednum: u16) -> Option<&'static ThingInfo> {
+        THING_INFO.iter().find(|info| info.doomednum == doomednum)
+    }
 
-    for (mut transform, entity_type) in monsters.iter_mut() {
-        if let EntityType::Monster { .. } = entity_type {
-            let dx = player_transform.x - transform.x;
-            let dy = player_transform.y - transform.y;
-            let distance = (dx * dx + dy * dy).sqrt();
+    // Unknown numbers become a generic decoration
+    pub fn lookup_or_unknown(doomednum: u16) -> &'static ThingInfo {
+        Self::lookup(doomednum).unwrap_or(&UNKNOWN_THING)
+    }
 
-            if distance > 50.0 {
-                let move_speed = 50.0;
-                let dt = time.delta_seconds_f64();
+    pub fn kind(&self) -> ThingKind {
+        match self.category {
+            ThingCategory::PlayerStart(_) => ThingKind::PlayerStart,
+            ThingCategory::Monster(_) => ThingKind::Monster,
+            ThingCategory::Item(_) => ThingKind::Item,
+            ThingCategory::Weapon(_) => ThingKind::Weapon,
+            ThingCategory::Decoration => ThingKind::Decoration,
+            ThingCategory::Marker => match self.doomednum {
+                DEATHMATCH_START => ThingKind::PlayerStart,
+                TELEPORT_DESTINATION => ThingKind::Teleport,
+                _ => ThingKind::Marker,
+            },
+            ThingCategory::SoundSource(_) => ThingKind::Marker,
+        }
+    }
 
-                transform.x += (dx / distance) * move_speed * dt;
-                transform.y += (dy / distance) * move_speed * dt;
-                transform.angle = dy.atan2(dx);
+    // The entity to spawn, None for player starts and markers
+    pub fn entity_type(&self) -> Option<EntityType> {
+        let item = |item_type| EntityType::Item {
+            item_type,
+            respawn_time: None,
+        };
+        match &self.category {
+            ThingCategory::PlayerStart(_) | ThingCategory::Marker => None,
+            ThingCategory::Monster(monster_type) => Some(EntityType::Monster {
+                health: self.health,
+                monster_type: monster_type.clone(),
+            }),
+            ThingCategory::Item(item_type) => Some(item(item_type.clone())),
+            ThingCategory::Weapon(weapon) => Some(item(ItemType::Weapon(*weapon))),
+            ThingCategory::Decoration | ThingCategory::SoundSource(_) => {
+                Some(EntityType::Decoration)
             }
         }
     }
 }
 
-pub fn update_projectiles(
-    mut commands: Commands,
-    mut projectiles: Query<(Entity, &mut Transform, &EntityType), With<Active>>,
-    time: Res<Time>,
-) {
-    for (entity, mut transform, entity_type) in projectiles.iter_mut() {
-        if let EntityType::Projectile { velocity, .. } = entity_type {
-            transform.x += velocity.0 * time.delta_seconds_f64();
-            transform.y += velocity.1 * time.delta_seconds_f64();
+const fn player_start(doomednum: u16, player: u8) -> ThingInfo {
+    ThingInfo {
+        doomednum,
+        sprite: "PLAY",
+        radius: 16.0,
+        height: 56.0,
+        health: 100,
+        category: ThingCategory::PlayerStart(player),
+    }
+}
+
+const fn marker(doomednum: u16) -> ThingInfo {
+    ThingInfo {
+        doomednum,
+        sprite: "",
+        radius: 20.0,
+        height: 16.0,
+        health: 1000,
+        category: ThingCategory::Marker,
+    }
+}
+
+const fn monster(
+    doomednum: u16,
+    sprite: &'static str,
+    radius: f64,
+    height: f64,
+    health: i32,
+    monster_type: MonsterType,
+) -> ThingInfo {
+    ThingInfo {
+        doomednum,
+        sprite,
+        radius,
+        height,
+        health,
+        category: ThingCategory::Monster(monster_type),
+    }
+}
+
+// Every pickup is 20 units across and 16 high
+const fn item(doomednum: u16, sprite: &'static str, item_type: ItemType) -> ThingInfo {
+    ThingInfo {
+        doomednum,
+        sprite,
+        radius: 20.0,
+        height: 16.0,
+        health: 1000,
+        category: ThingCategory::Item(item_type),
+    }
+}
+
+const fn weapon(doomednum: u16, sprite: &'static str, weapon: WeaponType) -> ThingInfo {
+    ThingInfo {
+        doomednum,
+        sprite,
+        radius: 20.0,
+        height: 16.0,
+        health: 1000,
+        category: ThingCategory::Weapon(weapon),
+    }
+}
+
+const fn ammo(doomednum: u16, sprite: &'static str, ammo_type: AmmoType, amount: u32) -> ThingInfo {
+    item(doomednum, sprite, ItemType::Ammo { ammo_type, amount })
+}
+
+const fn sound_source(doomednum: u16, sound: &'static str) -> ThingInfo {
+    ThingInfo {
+        doomednum,
+        sprite: "",
+        radius: 0.0,
+        height: 0.0,
+        health: 1000,
+        category: ThingCategory::SoundSource(sound),
+    }
+}
+
+const fn decoration(doomednum: u16, sprite: &'static str, radius: f64, height: f64) -> ThingInfo {
+    ThingInfo {
+        doomednum,
+        sprite,
+        radius,
+        height,
+        health: 1000,
+        category: ThingCategory::Decoration,
+    }
+}
+
+// The things in the original Doom and Ultimate Doom. Doom II's additions aren't
+// listed yet and spawn as unknown things.
+pub static THING_INFO: &[ThingInfo] = &[
+    player_start(1, 1),
+    player_start(2, 2),
+    player_start(3, 3),
+    player_start(4, 4),
+    marker(DEATHMATCH_START),
+    marker(TELEPORT_DESTINATION),
+    // Monsters
+    monster(3004, "POSS", 20.0, 56.0, 20, MonsterType::ZombieMan),
+    monster(9, "SPOS", 20.0, 56.0, 30, MonsterType::ShotgunGuy),
+    monster(3001, "TROO", 20.0, 56.0, 60, MonsterType::Imp),
+    monster(3002, "SARG", 30.0, 56.0, 150, MonsterType::Demon),
+    monster(58, "SARG", 30.0, 56.0, 150, MonsterType::Spectre),
+    monster(3006, "SKUL", 16.0, 56.0, 100, MonsterType::LostSoul),
+    monster(3005, "HEAD", 31.0, 56.0, 400, MonsterType::Cacodemon),
+    monster(3003, "BOSS", 24.0, 64.0, 1000, MonsterType::BaronOfHell),
+    monster(7, "SPID", 128.0, 100.0, 3000, MonsterType::SpiderMastermind),
+    monster(16, "CYBR", 40.0, 110.0, 4000, MonsterType::Cyberdemon),
+    // Health and armor
+    item(
+        2011,
+        "STIM",
+        ItemType::Health {
+            amount: 10,
+            limit: 100,
+        },
+    ),
+    item(
+        2012,
+        "MEDI",
+        ItemType::Health {
+            amount: 25,
+            limit: 100,
+        },
+    ),
+    item(
+        2014,
+        "BON1",
+        ItemType::Health {
+            amount: 1,
+            limit: 200,
+        },
+    ),
+    item(
+        2013,
+        "SOUL",
+        ItemType::Health {
+            amount: 100,
+            limit: 200,
+        },
+    ),
+    item(
+        2015,
+        "BON2",
+        ItemType::Armor {
+            amount: 1,
+            limit: 200,
+            class: None,
+        },
+    ),
+    item(
+        2018,
+        "ARM1",
+        ItemType::Armor {
+            amount: 100,
+            limit: 100,
+            class: Some(ArmorClass::Green),
+        },
+    ),
+    item(
+        2019,
+        "ARM2",
+        ItemType::Armor {
+            amount: 200,
+            limit: 200,
+            class: Some(ArmorClass::Blue),
+        },
+    ),
+    // Weapons
+    weapon(2005, "CSAW", WeaponType::Chainsaw),
+    weapon(2001, "SHOT", WeaponType::Shotgun),
+    weapon(2002, "MGUN", WeaponType::Chaingun),
+    weapon(2003, "LAUN", WeaponType::RocketLauncher),
+    weapon(2004, "PLAS", WeaponType::PlasmaRifle),
+    weapon(2006, "BFUG", WeaponType::Bfg9000),
+    // Ammo
+    ammo(2007, "CLIP", AmmoType::Bullets, 10),
+    ammo(2048, "AMMO", AmmoType::Bullets, 50),
+    ammo(2008, "SHEL", AmmoType::Shells, 4),
+    ammo(2049, "SBOX", AmmoType::Shells, 20),
+    ammo(2010, "ROCK", AmmoType::Rockets, 1),
+    ammo(2046, "BROK", AmmoType::Rockets, 5),
+    ammo(2047, "CELL", AmmoType::Cells, 20),
+    ammo(17, "CELP", AmmoType::Cells, 100),
+    // Keys
+    item(5, "BKEY", ItemType::Key(KeyType::BlueCard)),
+    item(6, "YKEY", ItemType::Key(KeyType::YellowCard)),
+    item(13, "RKEY", ItemType::Key(KeyType::RedCard)),
+    item(40, "BSKU", ItemType::Key(KeyType::BlueSkull)),
+    item(39, "YSKU", ItemType::Key(KeyType::YellowSkull)),
+    item(38, "RSKU", ItemType::Key(KeyType::RedSkull)),
+    // Powerups
+    item(
+        2022,
+        "PINV",
+        ItemType::Powerup(PowerupType::Invulnerability),
+    ),
+    item(
+        2024,
+        "PINS",
+        ItemType::Powerup(PowerupType::PartialInvisibility),
+    ),
+    // Decorations. Barrels have 20 health in Doom, though nothing damages them yet.
+    ThingInfo {
+        health: 20,
+        ..decoration(2035, "BAR1", 10.0, 42.0)
+    },
+    decoration(2028, "COLU", 16.0, 16.0),
+    decoration(30, "COL1", 16.0, 16.0),
+    decoration(31, "COL2", 16.0, 16.0),
+    decoration(32, "COL3", 16.0, 16.0),
+    decoration(33, "COL4", 16.0, 16.0),
+    decoration(36, "COL5", 16.0, 16.0),
+    decoration(37, "COL6", 16.0, 16.0),
+    decoration(41, "CEYE", 16.0, 16.0),
+    decoration(42, "FSKU", 16.0, 16.0),
+    decoration(43, "TRE1", 16.0, 16.0),
+    decoration(54, "TRE2", 32.0, 16.0),
+    decoration(44, "TBLU", 16.0, 16.0),
+    decoration(45, "TGRN", 16.0, 16.0),
+    decoration(46, "TRED", 16.0, 16.0),
+    decoration(55, "SMBT", 16.0, 16.0),
+    decoration(56, "SMGT", 16.0, 16.0),
+    decoration(57, "SMRT", 16.0, 16.0),
+    decoration(47, "SMIT", 16.0, 16.0),
+    decoration(48, "ELEC", 16.0, 16.0),
+    decoration(34, "CAND", 20.0, 16.0),
+    decoration(35, "CBRA", 16.0, 16.0),
+    // Hanging bodies, the solid ones first
+    decoration(49, "GOR1", 16.0, 68.0),
+    decoration(50, "GOR2", 16.0, 84.0),
+    decoration(51, "GOR3", 16.0, 84.0),
+    decoration(52, "GOR4", 16.0, 68.0),
+    decoration(53, "GOR5", 16.0, 52.0),
+    decoration(59, "GOR2", 20.0, 84.0),
+    decoration(60, "GOR4", 20.0, 68.0),
+    decoration(61, "GOR3", 20.0, 52.0),
+    decoration(62, "GOR5", 20.0, 52.0),
+    decoration(63, "GOR1", 20.0, 68.0),
+    // Corpses and gore
+    decoration(10, "PLAY", 20.0, 16.0),
+    decoration(12, "PLAY", 20.0, 16.0),
+    decoration(15, "PLAY", 20.0, 16.0),
+    decoration(18, "POSS", 20.0, 16.0),
+    decoration(19, "SPOS", 20.0, 16.0),
+    decoration(20, "TROO", 20.0, 16.0),
+    decoration(21, "SARG", 20.0, 16.0),
+    decoration(22, "HEAD", 20.0, 16.0),
+    decoration(23, "SKUL", 20.0, 16.0),
+    decoration(24, "POL5", 20.0, 16.0),
+    decoration(25, "POL1", 16.0, 16.0),
+    decoration(26, "POL6", 16.0, 16.0),
+    decoration(27, "POL4", 16.0, 16.0),
+    decoration(28, "POL2", 16.0, 16.0),
+    decoration(29, "POL3", 16.0, 16.0),
+    // Sound sources, numbered from the start of ZDoom's ambient sounds. ZDoom picks
+    // their sounds from SNDINFO, which isn't read, so each plays a fixed Doom sound.
+    sound_source(14001, "DSSTNMOV"),
+    sound_source(14002, "DSSAWIDL"),
+];
+
+// Health and armor items add `amount` but never raise the total past `limit`,
+// so limits above 100 are the overfill items like the soulsphere
+#[derive(Debug, Clone)]
+pub enum ItemType {
+    Health {
+        amount: i32,
+        limit: i32,
+    },
+    Armor {
+        amount: i32,
+        limit: i32,
+        // Armor bonuses leave the class alone, suits replace it
+        class: Option<ArmorClass>,
+    },
+    Weapon(WeaponType),
+    Ammo {
+        ammo_type: AmmoType,
+        amount: u32,
+    },
+    Key(KeyType),
+    Powerup(PowerupType),
+}
 
-            // TODO: Add collision detection
-            // if check_projectile_collision(...) {
-            //     commands.entity(entity).despawn();
-            // }
+// Doom's invulnerability sphere lasts 30 seconds and its blur sphere 60
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PowerupType {
+    Invulnerability,
+    PartialInvisibility,
+}
+
+impl PowerupType {
+    pub const ALL: [PowerupType; 2] = [
+        PowerupType::Invulnerability,
+        PowerupType::PartialInvisibility,
+    ];
+
+    pub fn duration(self) -> f64 {
+        match self {
+            PowerupType::Invulnerability => 30.0,
+            PowerupType::PartialInvisibility => 60.0,
         }
     }
 }
 
-// Spawn helper functions
-pub fn spawn_entity(
-    commands: &mut Commands,
-    x: f64,
-    y: f64,
-    entity_type: EntityType,
-    sprite_name: String,
-) -> Entity {
-    commands
-        .spawn((
-            entity_type,
-            Transform {
-                x,
-                y,
-                z: 0.0,
-                angle: 0.0,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WeaponType {
+    Fist,
+    Chainsaw,
+    Pistol,
+    Shotgun,
+    Chaingun,
+    RocketLauncher,
+    PlasmaRifle,
+    Bfg9000,
+}
+
+// Spread is the total angle in radians that a weapon's pellets fan out over
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WeaponAttack {
+    Melee {
+        damage: i32,
+        range: f64,
+    },
+    Hitscan {
+        damage: i32,
+        pellets: u32,
+        spread: f64,
+    },
+    Projectile {
+        damage: i32,
+        speed: f64,
+    },
+}
+
+impl WeaponType {
+    // The sprite drawn over the player's view while the weapon is held
+    pub fn view_sprite(self) -> &'static str {
+        match self {
+            WeaponType::Fist => "PUNG",
+            WeaponType::Chainsaw => "SAWG",
+            WeaponType::Pistol => "PISG",
+            WeaponType::Shotgun => "SHTG",
+            WeaponType::Chaingun => "CHGG",
+            WeaponType::RocketLauncher => "MISG",
+            WeaponType::PlasmaRifle => "PLSG",
+            WeaponType::Bfg9000 => "BFGG",
+        }
+    }
+
+    // The view sprite's ready frame and its firing frames in order, as in Doom's
+    // weapon states
+    fn view_frames(self) -> (char, &'static [char]) {
+        match self {
+            WeaponType::Fist => ('A', &['B', 'C', 'D', 'C', 'B']),
+            WeaponType::Chainsaw => ('C', &['A', 'B']),
+            WeaponType::Pistol => ('A', &['A', 'B', 'C', 'B']),
+            WeaponType::Shotgun => ('A', &['A', 'B', 'C', 'D', 'C', 'B', 'A']),
+            WeaponType::Chaingun => ('A', &['A', 'B']),
+            WeaponType::RocketLauncher => ('A', &['B']),
+            WeaponType::PlasmaRifle => ('A', &['A']),
+            WeaponType::Bfg9000 => ('A', &['A', 'B']),
+        }
+    }
+
+    // In Doom's weapon slot order
+    pub const ALL: [WeaponType; 8] = [
+        WeaponType::Fist,
+        WeaponType::Chainsaw,
+        WeaponType::Pistol,
+        WeaponType::Shotgun,
+        WeaponType::Chaingun,
+        WeaponType::RocketLauncher,
+        WeaponType::PlasmaRifle,
+        WeaponType::Bfg9000,
+    ];
+
+    // Damage is roughly the average of Doom's random rolls, speeds are in units per second
+    pub fn attack(self) -> WeaponAttack {
+        match self {
+            WeaponType::Fist | WeaponType::Chainsaw => WeaponAttack::Melee {
+                damage: 10,
+                range: MELEE_RANGE,
             },
-            Collider {
-                radius: 20.0,
-                height: 56.0,
+            WeaponType::Pistol | WeaponType::Chaingun => WeaponAttack::Hitscan {
+                damage: 10,
+                pellets: 1,
+                spread: 0.0,
             },
-            Sprite { name: sprite_name },
-            Active(true),
-        ))
-        .id()
+            WeaponType::Shotgun => WeaponAttack::Hitscan {
+                damage: 10,
+                pellets: 7,
+                spread: 0.2,
+            },
+            WeaponType::RocketLauncher => WeaponAttack::Projectile {
+                damage: 90,
+                speed: 700.0,
+            },
+            WeaponType::PlasmaRifle => WeaponAttack::Projectile {
+                damage: 20,
+                speed: 875.0,
+            },
+            WeaponType::Bfg9000 => WeaponAttack::Projectile {
+                damage: 400,
+                speed: 875.0,
+            },
+        }
+    }
+
+    // Ammo taken by each shot, None for melee weapons
+    pub fn ammo_use(self) -> Option<(AmmoType, u32)> {
+        match self {
+            WeaponType::Fist | WeaponType::Chainsaw => None,
+            WeaponType::Pistol | WeaponType::Chaingun => Some((AmmoType::Bullets, 1)),
+            WeaponType::Shotgun => Some((AmmoType::Shells, 1)),
+            WeaponType::RocketLauncher => Some((AmmoType::Rockets, 1)),
+            WeaponType::PlasmaRifle => Some((AmmoType::Cells, 1)),
+            WeaponType::Bfg9000 => Some((AmmoType::Cells, 40)),
+        }
+    }
+
+    // Seconds between shots, from the length of each weapon's firing frames at 35 tics a second
+    pub fn refire_time(self) -> f64 {
+        let tics = match self {
+            WeaponType::Fist | WeaponType::Pistol => 14,
+            WeaponType::Chainsaw | WeaponType::Chaingun => 4,
+            WeaponType::Shotgun => 37,
+            WeaponType::RocketLauncher => 20,
+            WeaponType::PlasmaRifle => 3,
+            WeaponType::Bfg9000 => 60,
+        };
+        tics as f64 / 35.0
+    }
+
+    // Ammo that comes with the weapon when it's picked up
+    pub fn pickup_ammo(self) -> Option<(AmmoType, u32)> {
+        match self {
+            WeaponType::Fist | WeaponType::Chainsaw => None,
+            WeaponType::Pistol | WeaponType::Chaingun => Some((AmmoType::Bullets, 20)),
+            WeaponType::Shotgun => Some((AmmoType::Shells, 8)),
+            WeaponType::RocketLauncher => Some((AmmoType::Rockets, 2)),
+            WeaponType::PlasmaRifle | WeaponType::Bfg9000 => Some((AmmoType::Cells, 40)),
+        }
+    }
 }
 
-// Plugin to organize the systems
-pub struct EntityPlugin;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AmmoType {
+    Bullets,
+    Shells,
+    Rockets,
+    Cells,
+}
+
+impl AmmoType {
+    pub const ALL: [AmmoType; 4] = [
+        AmmoType::Bullets,
+        AmmoType::Shells,
+        AmmoType::Rockets,
+        AmmoType::Cells,
+    ];
+
+    // Carrying capacity without a backpack
+    pub fn max(self) -> u32 {
+        match self {
+            AmmoType::Bullets => 200,
+            AmmoType::Shells => 50,
+            AmmoType::Rockets => 50,
+            AmmoType::Cells => 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyType {
+    BlueCard,
+    YellowCard,
+    RedCard,
+    BlueSkull,
+    YellowSkull,
+    RedSkull,
+}
+
+// Sector specials
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Plane {
+    Floor,
+    Ceiling,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoverPhase {
+    Moving,
+    Waiting,
+    Returning,
+}
+
+// Moves one plane of a sector to `destination`, then waits and returns to `rest`
+// if there is one. Doors and crushers move the ceiling, lifts, floors and stairs the
+// floor.
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct SectorMover {
+    pub sector: usize,
+    pub plane: Plane,
+    pub height: f64,
+    pub destination: f64,
+    pub rest: Option<f64>,
+    pub speed: f64,
+    pub wait: f64,
+    pub phase: MoverPhase,
+    pub timer: f64,
+    // Crushers keep moving into whatever is in the way, hurting it. This is the time
+    // until they next do.
+    pub crush: Option<f64>,
+    // Goes back to the destination after returning to rest, until stopped
+    pub repeat: bool,
+}
+
+impl SectorMover {
+    pub fn new(sector: usize, map: &Map, action: SectorAction) -> Option<Self> {
+        let current = map.sectors.get(sector)?;
+        let (floor, ceiling) = (current.floor_height as f64, current.ceiling_height as f64);
+
+        let mut crush = None;
+        let mut repeat = false;
+        let (plane, height, destination, rest, speed, wait) = match action {
+            SectorAction::Door { kind, fast } => {
+                let speed = if fast { FAST_DOOR_SPEED } else { DOOR_SPEED };
+                let open = map.lowest_neighbor_ceiling(sector)? as f64 - 4.0;
+                match kind {
+                    DoorKind::Normal => (
+                        Plane::Ceiling,
+                        ceiling,
+                        open,
+                        Some(ceiling),
+                        speed,
+                        DOOR_WAIT,
+                    ),
+                    DoorKind::Open => (Plane::Ceiling, ceiling, open, None, speed, 0.0),
+                    DoorKind::Close => (Plane::Ceiling, ceiling, floor, None, speed, 0.0),
+                }
+            }
+            SectorAction::Lift { fast } => {
+                let speed = if fast { FAST_LIFT_SPEED } else { LIFT_SPEED };
+                let low = map
+                    .lowest_neighbor_floor(sector)
+                    .map_or(floor, |low| (low as f64).min(floor));
+                (Plane::Floor, floor, low, Some(floor), speed, LIFT_WAIT)
+            }
+            SectorAction::Floor { target, fast } => {
+                let speed = if fast { FAST_FLOOR_SPEED } else { FLOOR_SPEED };
+                let destination = match target {
+                    FloorTarget::LowestCeiling => map
+                        .lowest_neighbor_ceiling(sector)
+                        .map_or(ceiling, |low| (low as f64).min(ceiling)),
+                    FloorTarget::NextHigher => map
+                        .next_higher_neighbor_floor(sector, current.floor_height)
+                        .map_or(floor, f64::from),
+                    FloorTarget::Raise24 => floor + 24.0,
+                    FloorTarget::Lowest => map
+                        .lowest_neighbor_floor(sector)
+                        .map_or(floor, |low| (low as f64).min(floor)),
+                    FloorTarget::Highest => map
+                        .highest_neighbor_floor(sector)
+                        .map_or(floor, |high| (high as f64).min(floor)),
+                    FloorTarget::AboveHighest => map
+                        .highest_neighbor_floor(sector)
+                        .map_or(floor, |high| (high as f64 + 8.0).min(floor)),
+                };
+                (Plane::Floor, floor, destination, None, speed, 0.0)
+            }
+            SectorAction::Crusher { fast } => {
+                let speed = if fast {
+                    FAST_CRUSHER_SPEED
+                } else {
+                    CRUSHER_SPEED
+                };
+                crush = Some(0.0);
+                repeat = true;
+                let low = floor + CRUSHER_GAP;
+                (Plane::Ceiling, ceiling, low, Some(ceiling), speed, 0.0)
+            }
+            SectorAction::Stairs { step, fast } => {
+                let speed = if fast { FAST_FLOOR_SPEED } else { STAIR_SPEED };
+                let destination = floor + step as f64;
+                (Plane::Floor, floor, destination, None, speed, 0.0)
+            }
+            SectorAction::StopCrusher => return None,
+        };
+
+        Some(SectorMover {
+            sector,
+            plane,
+            height,
+            destination,
+            rest,
+            speed,
+            wait,
+            phase: MoverPhase::Moving,
+            timer: 0.0,
+            crush,
+            repeat,
+        })
+    }
+
+    // Advances the mover by `dt` seconds and writes its height into the sector. `blocked`
+    // is asked whether a floor/ceiling gap would crush something, in which case a returning
+    // mover goes back the way it came and any other holds still. Returns true when finished.
+    pub fn step(
+        &mut self,
+        sector: &mut Sector,
+        dt: f64,
+        blocked: impl Fn(f64, f64) -> bool,
+    ) -> bool {
+        if self.phase == MoverPhase::Waiting {
+            self.timer -= dt;
+            if self.timer <= 0.0 {
+                self.phase = MoverPhase::Returning;
+            }
+            return false;
+        }
+
+        let target = match (self.phase, self.rest) {
+            (MoverPhase::Returning, Some(rest)) => rest,
+            _ => self.destination,
+        };
+        let step = self.speed * dt;
+        let next = if target > self.height {
+            (self.height + step).min(target)
+        } else {
+            (self.height - step).max(target)
+        };
+
+        let (floor, ceiling) = match self.plane {
+            Plane::Floor => (next, sector.ceiling_height as f64),
+            Plane::Ceiling => (sector.floor_height as f64, next),
+        };
+        let closing = match self.plane {
+            Plane::Floor => next > self.height,
+            Plane::Ceiling => next < self.height,
+        };
+        if closing && self.crush.is_none() && blocked(floor, ceiling) {
+            if self.phase == MoverPhase::Returning {
+                self.phase = MoverPhase::Moving;
+            }
+            return false;
+        }
+
+        self.height = next;
+        match self.plane {
+            Plane::Floor => sector.floor_height = next.round() as i16,
+            Plane::Ceiling => sector.ceiling_height = next.round() as i16,
+        }
+
+        if self.height != target {
+            return false;
+        }
+        match (self.phase, self.rest) {
+            (MoverPhase::Moving, Some(_)) => {
+                self.phase = MoverPhase::Waiting;
+                self.timer = self.wait;
+                false
+            }
+            (MoverPhase::Returning, _) if self.repeat => {
+                self.phase = MoverPhase::Moving;
+                false
+            }
+            _ => true,
+        }
+    }
+
+    fn rising(&self) -> bool {
+        let target = match (self.phase, self.rest) {
+            (MoverPhase::Returning, Some(rest)) => rest,
+            _ => self.destination,
+        };
+        target > self.height
+    }
+
+    // The sound made setting off towards the current target. Doors open and close
+    // with a sound each way and lifts start with a hum.
+    pub fn start_sound(&self) -> Option<&'static str> {
+        let fast = self.speed >= FAST_DOOR_SPEED;
+        match (self.plane, self.rest) {
+            (Plane::Ceiling, _) if self.crush.is_some() => None,
+            (Plane::Ceiling, _) => Some(match (self.rising(), fast) {
+                (true, false) => "DSDOROPN",
+                (true, true) => "DSBDOPN",
+                (false, false) => "DSDORCLS",
+                (false, true) => "DSBDCLS",
+            }),
+            (Plane::Floor, Some(_)) => Some("DSPSTART"),
+            (Plane::Floor, None) => None,
+        }
+    }
+
+    // The clunk of a lift or floor coming to a stop
+    pub fn stop_sound(&self) -> Option<&'static str> {
+        (self.plane == Plane::Floor).then_some("DSPSTOP")
+    }
+
+    // Floors and crushers grind all the while they move
+    pub fn looping_sound(&self) -> Option<&'static str> {
+        let grinds = match self.plane {
+            Plane::Floor => self.rest.is_none(),
+            Plane::Ceiling => self.crush.is_some(),
+        };
+        (grinds && self.phase != MoverPhase::Waiting).then_some("DSSTNMOV")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DoorKind {
+    // Opens, waits and closes again
+    Normal,
+    Open,
+    Close,
+}
+
+// Where a moving floor stops, from the sector's neighbours
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloorTarget {
+    LowestCeiling,
+    NextHigher,
+    Raise24,
+    Lowest,
+    Highest,
+    AboveHighest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectorAction {
+    Door { kind: DoorKind, fast: bool },
+    Lift { fast: bool },
+    Floor { target: FloorTarget, fast: bool },
+    Crusher { fast: bool },
+    StopCrusher,
+    // Raises the sector and the run of sectors after it, each `step` above the last
+    Stairs { step: i16, fast: bool },
+}
+
+// The sectors stairs raise starting from `sector` with the heights they go to. Each
+// next step is across a two-sided line in front of the last, with the same floor
+// texture, as in Doom's EV_BuildStairs.
+pub fn stair_steps(map: &Map, sector: usize, step: i16) -> Vec<(usize, f64)> {
+    let Some(first) = map.sectors.get(sector) else {
+        return Vec::new();
+    };
+    let texture = &first.floor_texture;
+    let mut height = first.floor_height as f64 + step as f64;
+    let mut steps = vec![(sector, height)];
+
+    loop {
+        let current = steps[steps.len() - 1].0;
+        let next = map.linedefs.iter().find_map(|linedef| {
+            let back = map.side_sector(linedef.back_sidedef)?;
+            let continues = map.side_sector(linedef.front_sidedef) == Some(current)
+                && map.sectors.get(back)?.floor_texture == *texture
+                && steps.iter().all(|&(stepped, _)| stepped != back);
+            continues.then_some(back)
+        });
+        let Some(next) = next else {
+            return steps;
+        };
+        height += step as f64;
+        steps.push((next, height));
+    }
+}
+
+// The movers one activation of an action starts in a sector: one for most, one per
+// step for stairs and none for stopping crushers
+pub fn sector_movers(map: &Map, sector: usize, action: SectorAction) -> Vec<SectorMover> {
+    let SectorAction::Stairs { step, .. } = action else {
+        return SectorMover::new(sector, map, action).into_iter().collect();
+    };
+    stair_steps(map, sector, step)
+        .into_iter()
+        .filter_map(|(sector, height)| {
+            let mut mover = SectorMover::new(sector, map, action)?;
+            mover.destination = height;
+            Some(mover)
+        })
+        .collect()
+}
+
+// Manual specials act on the sector behind the line that was used, switches and
+// walk-over lines act on every sector with the line's tag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineTrigger {
+    Manual,
+    Switch,
+    Walk,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineSpecial {
+    pub trigger: LineTrigger,
+    pub repeatable: bool,
+    pub action: SectorAction,
+    // Either the keycard or the skull key of this colour opens it
+    pub key: Option<KeyType>,
+}
+
+impl LineSpecial {
+    // The door and lift linedef types from Doom's specials
+    pub fn from_type(special_type: u16) -> Option<Self> {
+        use DoorKind::*;
+        use LineTrigger::*;
+
+        let door = |trigger, repeatable, kind, fast, key| LineSpecial {
+            trigger,
+            repeatable,
+            action: SectorAction::Door { kind, fast },
+            key,
+        };
+        let lift = |trigger, repeatable, fast| LineSpecial {
+            trigger,
+            repeatable,
+            action: SectorAction::Lift { fast },
+            key: None,
+        };
+        let floor = |trigger, repeatable, target, fast| LineSpecial {
+            trigger,
+            repeatable,
+            action: SectorAction::Floor { target, fast },
+            key: None,
+        };
+        let crusher = |trigger, repeatable, fast| LineSpecial {
+            trigger,
+            repeatable,
+            action: SectorAction::Crusher { fast },
+            key: None,
+        };
+        let stairs = |trigger, step, fast| LineSpecial {
+            trigger,
+            repeatable: false,
+            action: SectorAction::Stairs { step, fast },
+            key: None,
+        };
+
+        let special = match special_type {
+            1 => door(Manual, true, Normal, false, None),
+            26 => door(Manual, true, Normal, false, Some(KeyType::BlueCard)),
+            27 => door(Manual, true, Normal, false, Some(KeyType::YellowCard)),
+            28 => door(Manual, true, Normal, false, Some(KeyType::RedCard)),
+            31 => door(Manual, false, Open, false, None),
+            32 => door(Manual, false, Open, false, Some(KeyType::BlueCard)),
+            33 => door(Manual, false, Open, false, Some(KeyType::RedCard)),
+            34 => door(Manual, false, Open, false, Some(KeyType::YellowCard)),
+            117 => door(Manual, true, Normal, true, None),
+            118 => door(Manual, false, Open, true, None),
+
+            2 => door(Walk, false, Open, false, None),
+            3 => door(Walk, false, Close, false, None),
+            4 => door(Walk, false, Normal, false, None),
+            75 => door(Walk, true, Close, false, None),
+            86 => door(Walk, true, Open, false, None),
+            90 => door(Walk, true, Normal, false, None),
+
+            29 => door(Switch, false, Normal, false, None),
+            42 => door(Switch, true, Close, false, None),
+            50 => door(Switch, false, Close, false, None),
+            61 => door(Switch, true, Open, false, None),
+            63 => door(Switch, true, Normal, false, None),
+            103 => door(Switch, false, Open, false, None),
+
+            10 => lift(Walk, false, false),
+            88 => lift(Walk, true, false),
+            120 => lift(Walk, true, true),
+            121 => lift(Walk, false, true),
+            21 => lift(Switch, false, false),
+            62 => lift(Switch, true, false),
+            122 => lift(Switch, false, true),
+            123 => lift(Switch, true, true),
+
+            5 => floor(Walk, false, FloorTarget::LowestCeiling, false),
+            91 => floor(Walk, true, FloorTarget::LowestCeiling, false),
+            101 => floor(Switch, false, FloorTarget::LowestCeiling, false),
+            64 => floor(Switch, true, FloorTarget::LowestCeiling, false),
+            119 => floor(Walk, false, FloorTarget::NextHigher, false),
+            128 => floor(Walk, true, FloorTarget::NextHigher, false),
+            18 => floor(Switch, false, FloorTarget::NextHigher, false),
+            69 => floor(Switch, true, FloorTarget::NextHigher, false),
+            58 => floor(Walk, false, FloorTarget::Raise24, false),
+            92 => floor(Walk, true, FloorTarget::Raise24, false),
+            38 => floor(Walk, false, FloorTarget::Lowest, false),
+            82 => floor(Walk, true, FloorTarget::Lowest, false),
+            23 => floor(Switch, false, FloorTarget::Lowest, false),
+            60 => floor(Switch, true, FloorTarget::Lowest, false),
+            19 => floor(Walk, false, FloorTarget::Highest, false),
+            83 => floor(Walk, true, FloorTarget::Highest, false),
+            102 => floor(Switch, false, FloorTarget::Highest, false),
+            45 => floor(Switch, true, FloorTarget::Highest, false),
+            36 => floor(Walk, false, FloorTarget::AboveHighest, true),
+            98 => floor(Walk, true, FloorTarget::AboveHighest, true),
+            71 => floor(Switch, false, FloorTarget::AboveHighest, true),
+            70 => floor(Switch, true, FloorTarget::AboveHighest, true),
+
+            6 => crusher(Walk, false, true),
+            25 => crusher(Walk, false, false),
+            73 => crusher(Walk, true, false),
+            77 => crusher(Walk, true, true),
+            141 => crusher(Walk, false, false),
+            49 => crusher(Switch, false, false),
+            57 => LineSpecial {
+                trigger: Walk,
+                repeatable: false,
+                action: SectorAction::StopCrusher,
+                key: None,
+            },
+            74 => LineSpecial {
+                trigger: Walk,
+                repeatable: true,
+                action: SectorAction::StopCrusher,
+                key: None,
+            },
+
+            8 => stairs(Walk, 8, false),
+            7 => stairs(Switch, 8, false),
+            100 => stairs(Walk, 16, true),
+            127 => stairs(Switch, 16, true),
+
+            _ => return None,
+        };
+        Some(special)
+    }
+}
+
+// Exit switches and walk-over exits, with whether they lead to the secret level
+pub fn exit_special(special_type: u16) -> Option<(LineTrigger, bool)> {
+    match special_type {
+        11 => Some((LineTrigger::Switch, false)),
+        51 => Some((LineTrigger::Switch, true)),
+        52 => Some((LineTrigger::Walk, false)),
+        124 => Some((LineTrigger::Walk, true)),
+        _ => None,
+    }
+}
+
+impl KeyType {
+    pub const ALL: [KeyType; 6] = [
+        KeyType::BlueCard,
+        KeyType::YellowCard,
+        KeyType::RedCard,
+        KeyType::BlueSkull,
+        KeyType::YellowSkull,
+        KeyType::RedSkull,
+    ];
+
+    // Whether holding this key opens a door locked with `lock`, a card colour
+    pub fn opens(self, lock: KeyType) -> bool {
+        let colour = |key| match key {
+            KeyType::BlueCard | KeyType::BlueSkull => 0,
+            KeyType::YellowCard | KeyType::YellowSkull => 1,
+            KeyType::RedCard | KeyType::RedSkull => 2,
+        };
+        colour(self) == colour(lock)
+    }
+}
+
+// Resources
+
+// Frame timing, advanced by the engine before running the schedule
+#[derive(Resource, Debug, Clone, Default)]
+pub struct Time {
+    pub delta: Duration,
+    pub elapsed: Duration,
+}
+
+impl Time {
+    pub fn advance(&mut self, delta: Duration) {
+        self.delta = delta;
+        self.elapsed += delta;
+    }
+
+    pub fn delta_seconds_f64(&self) -> f64 {
+        self.delta.as_secs_f64()
+    }
+}
+
+// Player actions for this frame, filled in by the engine from its input
+#[derive(Resource, Debug, Clone, Default)]
+pub struct PlayerInput {
+    pub fire: bool,
+    // Only set on the frame the use key goes down
+    pub use_pressed: bool,
+    // Steps through owned weapons, positive for the next and negative for the previous
+    pub weapon_cycle: i32,
+}
+
+// The currently loaded level, shared with systems that need geometry
+#[derive(Resource)]
+pub struct LevelMap(pub Map);
+
+// Inserted when the player uses an exit, for the engine to move on to the next level
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelExit {
+    pub secret: bool,
+}
+
+// The game's random numbers. Each level starts the table from the beginning, so
+// playing a demo back rolls the same numbers it was recorded with.
+#[derive(Resource, Debug, Clone, Copy, Default)]
+pub struct GameRng(pub DoomRng);
+
+// Inserted when the player makes a noise, such as firing a gun, for alert_monsters to
+// wake the monsters that hear it
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct Noise {
+    pub x: f64,
+    pub y: f64,
+}
+
+// A one-shot sound and where it was made. Systems add them to SoundEvents each tick
+// and the engine plays and clears them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SoundEvent {
+    pub sound: &'static str,
+    pub x: f64,
+    pub y: f64,
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct SoundEvents(pub Vec<SoundEvent>);
+
+// Inserted when the player crosses a teleport line, for the engine to move its copy of
+// the player to the destination
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct PlayerTeleport {
+    pub x: f64,
+    pub y: f64,
+    pub angle: f64,
+}
+
+// Systems
+// Runs each monster's state machine: idle until it sees the player, react, then chase
+// in one of eight directions and attack when in range with a clear line of fire
+#[allow(clippy::type_complexity)]
+pub fn update_monsters(
+    mut commands: Commands,
+    mut monsters: Query<
+        (
+            &mut Transform,
+            &EntityType,
+            &Collider,
+            &mut MonsterAi,
+            &Active,
+            Has<Dormant>,
+            Has<Deaf>,
+        ),
+        Without<Player>,
+    >,
+    mut player: Query<
+        (
+            &Transform,
+            &Collider,
+            Option<(&mut Health, &mut Armor)>,
+            Has<Dead>,
+            Has<GodMode>,
+            Option<&Powerups>,
+        ),
+        With<Player>,
+    >,
+    level: Option<Res<LevelMap>>,
+    mut rng: ResMut<GameRng>,
+    time: Res<Time>,
+) {
+    let Ok((player_transform, player_collider, mut player_health, dead, god, powerups)) =
+        player.single_mut()
+    else {
+        return;
+    };
+    let god = shielded(god, powerups);
+    // Nothing left to hunt
+    if dead {
+        return;
+    }
+    let target = (player_transform.x, player_transform.y);
+    let map = level.as_ref().map(|level| &level.0);
+    let dt = time.delta_seconds_f64();
+
+    for (mut transform, entity_type, collider, mut ai, active, dormant, deaf) in monsters.iter_mut()
+    {
+        let EntityType::Monster { monster_type, .. } = entity_type else {
+            continue;
+        };
+        if !active.0 || dormant {
+            continue;
+        }
+
+        ai.timer -= dt;
+        let position = (transform.x, transform.y);
+        let (dx, dy) = (target.0 - position.0, target.1 - position.1);
+        let distance = dx.hypot(dy);
+
+        match ai.state {
+            AiState::Spawn => {
+                if notices(&transform, deaf, map, target) {
+                    ai.enter(AiState::See, REACTION_TIME);
+                }
+            }
+            AiState::See => {
+                if ai.timer <= 0.0 {
+                    ai.enter(AiState::Chase, 0.0);
+                }
+            }
+            AiState::Chase => {
+                let in_melee_range = distance < MELEE_RANGE + player_collider.radius;
+                let attack = if in_melee_range {
+                    monster_type.melee_attack().or(monster_type.ranged_attack())
+                } else {
+                    monster_type.ranged_attack()
+                };
+
+                if ai.timer <= 0.0
+                    && let Some(attack) = attack
+                    && can_see(map, position, target)
+                {
+                    transform.angle = dy.atan2(dx);
+                    ai.enter(AiState::Attack, ATTACK_TIME);
+                    perform_attack(
+                        &mut commands,
+                        &mut rng.0,
+                        attack,
+                        &transform,
+                        collider.radius,
+                        player_health
+                            .as_mut()
+                            .filter(|_| !god)
+                            .map(|(health, armor)| (health.as_mut(), armor.as_mut())),
+                    );
+                    continue;
+                }
+
+                if !in_melee_range {
+                    chase(
+                        &mut transform,
+                        &mut ai,
+                        monster_type.speed(),
+                        collider.radius,
+                        (dx, dy),
+                        map,
+                        dt,
+                    );
+                }
+            }
+            AiState::Attack => {
+                if ai.timer <= 0.0 {
+                    ai.enter(AiState::Chase, ATTACK_COOLDOWN);
+                }
+            }
+            AiState::Pain => {
+                if ai.timer <= 0.0 {
+                    ai.enter(AiState::Chase, 0.0);
+                }
+            }
+            AiState::Death => {}
+        }
+    }
+}
+
+// Whether an idle monster sees the player. Ambush monsters only notice what is in
+// front of them.
+fn notices(transform: &Transform, deaf: bool, map: Option<&Map>, target: (f64, f64)) -> bool {
+    let (dx, dy) = (target.0 - transform.x, target.1 - transform.y);
+    let facing = angle_difference(dy.atan2(dx), transform.angle).abs() <= FRAC_PI_2;
+    (!deaf || facing) && can_see(map, (transform.x, transform.y), target)
+}
+
+// An idle monster far from the player, skipped by update_monsters
+pub fn is_dormant(ai: &MonsterAi, distance: f64) -> bool {
+    ai.state == AiState::Spawn && distance > DORMANT_DISTANCE
+}
+
+// Puts idle monsters far from the player to sleep and wakes the rest. Dormant monsters
+// look for the player every DORMANT_LOOK_TIME, and noise and damage wake them too.
+#[allow(clippy::type_complexity)]
+pub fn update_dormancy(
+    mut commands: Commands,
+    mut monsters: Query<
+        (Entity, &Transform, &mut MonsterAi, Has<Dormant>, Has<Deaf>),
+        Without<Player>,
+    >,
+    player: Query<&Transform, With<Player>>,
+    level: Option<Res<LevelMap>>,
+    time: Res<Time>,
+) {
+    let Ok(player) = player.single() else {
+        return;
+    };
+    let target = (player.x, player.y);
+    let map = level.as_ref().map(|level| &level.0);
+    let dt = time.delta_seconds_f64();
+
+    for (entity, transform, mut ai, dormant, deaf) in monsters.iter_mut() {
+        let distance = (target.0 - transform.x).hypot(target.1 - transform.y);
+        if !is_dormant(&ai, distance) {
+            if dormant {
+                commands.entity(entity).remove::<Dormant>();
+            }
+            continue;
+        }
+        if !dormant {
+            commands.entity(entity).insert(Dormant);
+        }
+
+        ai.timer -= dt;
+        if ai.timer > 0.0 {
+            continue;
+        }
+        ai.timer = DORMANT_LOOK_TIME;
+        if notices(transform, deaf, map, target) {
+            ai.enter(AiState::See, REACTION_TIME);
+            commands.entity(entity).remove::<Dormant>();
+        }
+    }
+}
+
+fn perform_attack(
+    commands: &mut Commands,
+    rng: &mut DoomRng,
+    attack: MonsterAttack,
+    attacker: &Transform,
+    attacker_radius: f64,
+    target: Option<(&mut Health, &mut Armor)>,
+) {
+    match attack {
+        MonsterAttack::Melee { damage } | MonsterAttack::Hitscan { damage } => {
+            if let Some((health, armor)) = target {
+                damage_player(health, armor, rng.random_damage(damage));
+            }
+        }
+        MonsterAttack::Missile { damage, speed } => {
+            spawn_missile(commands, attacker, attacker_radius, damage, speed);
+        }
+    }
+}
+
+fn spawn_missile(
+    commands: &mut Commands,
+    attacker: &Transform,
+    attacker_radius: f64,
+    damage: i32,
+    speed: f64,
+) {
+    // Start outside the attacker so the missile doesn't hit its owner
+    let (cos, sin) = (attacker.angle.cos(), attacker.angle.sin());
+    let offset = attacker_radius + MISSILE_RADIUS + 1.0;
+    commands.spawn((
+        EntityType::Projectile {
+            damage,
+            velocity: (cos * speed, sin * speed),
+        },
+        Transform {
+            x: attacker.x + cos * offset,
+            y: attacker.y + sin * offset,
+            z: attacker.z,
+            angle: attacker.angle,
+        },
+        Collider {
+            radius: MISSILE_RADIUS,
+            height: 8.0,
+        },
+        Active(true),
+    ));
+}
+
+// Walks along the current chase direction, picking a new one when it runs out or is blocked
+fn chase(
+    transform: &mut Transform,
+    ai: &mut MonsterAi,
+    speed: f64,
+    radius: f64,
+    to_target: (f64, f64),
+    map: Option<&Map>,
+    dt: f64,
+) {
+    let step = speed * dt;
+    let position = (transform.x, transform.y);
+    let blocked = |direction: MoveDirection| {
+        let (vx, vy) = direction.vector();
+        let to = (position.0 + vx * step, position.1 + vy * step);
+        map.is_some_and(|map| hits_solid_wall(map, position, to, radius))
+    };
+
+    ai.direction_timer -= dt;
+    let needs_direction = match ai.direction {
+        Some(direction) => ai.direction_timer <= 0.0 || blocked(direction),
+        None => true,
+    };
+    if needs_direction {
+        ai.direction = new_chase_direction(ai.direction, to_target, blocked);
+        ai.direction_timer = CHASE_DIRECTION_TIME;
+    }
+
+    if let Some(direction) = ai.direction
+        && !blocked(direction)
+    {
+        let (vx, vy) = direction.vector();
+        transform.x += vx * step;
+        transform.y += vy * step;
+        transform.angle = direction.angle();
+    }
+}
+
+// Doom's P_NewChaseDir: head diagonally at the target, then along the dominant axis, then
+// keep the old direction, then anything else. Turning straight around is the last resort.
+pub fn new_chase_direction(
+    current: Option<MoveDirection>,
+    to_target: (f64, f64),
+    blocked: impl Fn(MoveDirection) -> bool,
+) -> Option<MoveDirection> {
+    let turnaround = current.map(MoveDirection::opposite);
+    let usable = |direction: MoveDirection| Some(direction) != turnaround && !blocked(direction);
+
+    let (dx, dy) = to_target;
+    let mut horizontal = if dx > CHASE_DEADZONE {
+        Some(MoveDirection::East)
+    } else if dx < -CHASE_DEADZONE {
+        Some(MoveDirection::West)
+    } else {
+        None
+    };
+    let mut vertical = if dy > CHASE_DEADZONE {
+        Some(MoveDirection::North)
+    } else if dy < -CHASE_DEADZONE {
+        Some(MoveDirection::South)
+    } else {
+        None
+    };
+
+    if let (Some(h), Some(v)) = (horizontal, vertical)
+        && let Some(diagonal) = MoveDirection::diagonal(h, v)
+        && usable(diagonal)
+    {
+        return Some(diagonal);
+    }
+
+    if dy.abs() > dx.abs() {
+        std::mem::swap(&mut horizontal, &mut vertical);
+    }
+    for direction in [horizontal, vertical, current].into_iter().flatten() {
+        if usable(direction) {
+            return Some(direction);
+        }
+    }
+
+    if let Some(direction) = MoveDirection::ALL
+        .into_iter()
+        .find(|&direction| usable(direction))
+    {
+        return Some(direction);
+    }
+
+    turnaround.filter(|&direction| !blocked(direction))
+}
+
+fn can_see(map: Option<&Map>, from: (f64, f64), to: (f64, f64)) -> bool {
+    map.is_none_or(|map| map.line_of_sight(from, to))
+}
+
+// Signed difference between two angles, wrapped into -pi..pi
+fn angle_difference(a: f64, b: f64) -> f64 {
+    let difference = (a - b).rem_euclid(std::f64::consts::TAU);
+    if difference > std::f64::consts::PI {
+        difference - std::f64::consts::TAU
+    } else {
+        difference
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn update_projectiles(
+    mut commands: Commands,
+    mut queries: ParamSet<(
+        Query<(Entity, &mut Transform, &EntityType, &Collider, &Active)>,
+        // Inactive monsters, such as dormant ones, can still be hit
+        Query<
+            (
+                Entity,
+                &Transform,
+                &Collider,
+                &mut EntityType,
+                Option<&mut MonsterAi>,
+            ),
+            With<Active>,
+        >,
+        Query<
+            (
+                &Transform,
+                &Collider,
+                &mut Health,
+                &mut Armor,
+                Has<GodMode>,
+                Option<&Powerups>,
+            ),
+            With<Player>,
+        >,
+    )>,
+    level: Option<Res<LevelMap>>,
+    time: Res<Time>,
+) {
+    // Move every projectile first, remembering where it travelled from
+    let mut moved = Vec::new();
+    for (entity, mut transform, entity_type, collider, active) in queries.p0().iter_mut() {
+        if !active.0 {
+            continue;
+        }
+        if let EntityType::Projectile { damage, velocity } = entity_type {
+            let from = (transform.x, transform.y);
+            transform.x += velocity.0 * time.delta_seconds_f64();
+            transform.y += velocity.1 * time.delta_seconds_f64();
+            moved.push((
+                entity,
+                from,
+                (transform.x, transform.y),
+                transform.z,
+                *damage,
+                collider.radius,
+            ));
+        }
+    }
+
+    let mut monsters: Vec<(Entity, f64, f64, f64)> = queries
+        .p1()
+        .iter()
+        .filter(|(_, _, _, entity_type, _)| matches!(entity_type, EntityType::Monster { .. }))
+        .map(|(entity, transform, collider, ..)| {
+            (entity, transform.x, transform.y, collider.radius)
+        })
+        .collect();
+    let player = queries
+        .p2()
+        .single()
+        .ok()
+        .map(|(transform, collider, ..)| (transform.x, transform.y, collider.radius));
+
+    for (projectile, from, to, z, damage, radius) in moved {
+        let hit_monster = monsters.iter().find(|(_, x, y, monster_radius)| {
+            point_segment_distance((*x, *y), from, to) < radius + monster_radius
+        });
+
+        if let Some(&(monster, ..)) = hit_monster {
+            commands.entity(projectile).despawn();
+            spawn_blood(&mut commands, to.0, to.1, z);
+
+            let mut monster_query = queries.p1();
+            if let Ok((_, _, _, mut entity_type, ai)) = monster_query.get_mut(monster)
+                && damage_monster(&mut commands, monster, &mut entity_type, ai, damage)
+            {
+                monsters.retain(|(entity, ..)| *entity != monster);
+            }
+            continue;
+        }
+
+        if let Some((x, y, player_radius)) = player
+            && point_segment_distance((x, y), from, to) < radius + player_radius
+        {
+            commands.entity(projectile).despawn();
+            if let Ok((_, _, mut health, mut armor, god, powerups)) = queries.p2().single_mut()
+                && !shielded(god, powerups)
+            {
+                damage_player(&mut health, &mut armor, damage);
+            }
+            continue;
+        }
+
+        if let Some(level) = &level
+            && hits_solid_wall(&level.0, from, to, radius)
+        {
+            commands.entity(projectile).despawn();
+            spawn_puff(&mut commands, to.0, to.1, z);
+        }
+    }
+}
+
+// Fires the player's selected weapon while the fire input is held and the weapon is ready
+#[allow(clippy::type_complexity)]
+pub fn fire_weapons(
+    mut commands: Commands,
+    mut player: Query<
+        (&Transform, &Collider, &mut Weapon, &mut Inventory),
+        (With<Player>, Without<Dead>),
+    >,
+    mut monsters: Query<
+        (
+            Entity,
+            &Transform,
+            &Collider,
+            &mut EntityType,
+            Option<&mut MonsterAi>,
+        ),
+        Without<Player>,
+    >,
+    input: Option<Res<PlayerInput>>,
+    level: Option<Res<LevelMap>>,
+    mut rng: ResMut<GameRng>,
+    time: Res<Time>,
+) {
+    let Ok((transform, collider, mut weapon, mut inventory)) = player.single_mut() else {
+        return;
+    };
+
+    weapon.cooldown = (weapon.cooldown - time.delta_seconds_f64()).max(0.0);
+    let firing = input.is_some_and(|input| input.fire);
+    if !firing || weapon.cooldown > 0.0 || !inventory.consume_ammo(weapon.selected) {
+        return;
+    }
+    weapon.cooldown = weapon.selected.refire_time();
+
+    let map = level.as_ref().map(|level| &level.0);
+    let origin = (transform.x, transform.y);
+    // Punching and sawing are quiet, every other weapon is heard
+    if !matches!(weapon.selected.attack(), WeaponAttack::Melee { .. }) {
+        commands.insert_resource(Noise {
+            x: origin.0,
+            y: origin.1,
+        });
+    }
+    let (damage, range, angles) = match weapon.selected.attack() {
+        WeaponAttack::Projectile { damage, speed } => {
+            spawn_missile(&mut commands, transform, collider.radius, damage, speed);
+            return;
+        }
+        WeaponAttack::Melee { damage, range } => (damage, range, vec![transform.angle]),
+        // Pellets scatter at random across the spread
+        WeaponAttack::Hitscan {
+            damage,
+            pellets,
+            spread,
+        } => (
+            damage,
+            HITSCAN_RANGE,
+            (0..pellets)
+                .map(|_| transform.angle + rng.0.random_spread(spread / 2.0))
+                .collect(),
+        ),
+    };
+
+    let mut targets: Vec<(Entity, f64, f64, f64)> = monsters
+        .iter()
+        .filter(|(_, _, _, entity_type, _)| matches!(entity_type, EntityType::Monster { .. }))
+        .map(|(entity, target, target_collider, ..)| {
+            (entity, target.x, target.y, target_collider.radius)
+        })
+        .collect();
+
+    let shot_z = transform.z + SHOT_HEIGHT;
+    for angle in angles {
+        let target = match hitscan(map, origin, angle, range, &targets) {
+            Some(ShotHit::Target(target, (x, y))) => {
+                spawn_blood(&mut commands, x, y, shot_z);
+                target
+            }
+            Some(ShotHit::Wall((x, y))) => {
+                spawn_puff(&mut commands, x, y, shot_z);
+                continue;
+            }
+            None => continue,
+        };
+        let damage = rng.0.random_damage(damage);
+        if let Ok((_, _, _, mut entity_type, ai)) = monsters.get_mut(target)
+            && damage_monster(&mut commands, target, &mut entity_type, ai, damage)
+        {
+            targets.retain(|(entity, ..)| *entity != target);
+        }
+    }
+}
+
+// Wakes the idle monsters in every sector a noise reaches. Ambush monsters ignore it
+// and wait to see the player.
+pub fn alert_monsters(
+    mut commands: Commands,
+    mut monsters: Query<(&Transform, &mut MonsterAi), Without<Deaf>>,
+    noise: Option<Res<Noise>>,
+    level: Option<Res<LevelMap>>,
+) {
+    let Some(noise) = noise else {
+        return;
+    };
+    commands.remove_resource::<Noise>();
+    let Some(map) = level.as_ref().map(|level| &level.0) else {
+        return;
+    };
+    let Some(origin) = map.sector_at(noise.x, noise.y) else {
+        return;
+    };
+
+    // Doom's noise goes as far as the level lets it
+    let heard = map.flood_sound(origin as u16, u32::MAX);
+    for (transform, mut ai) in monsters.iter_mut() {
+        if ai.state == AiState::Spawn
+            && map
+                .sector_at(transform.x, transform.y)
+                .is_some_and(|sector| heard.contains(&(sector as u16)))
+        {
+            ai.enter(AiState::See, REACTION_TIME);
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn switch_weapons(
+    mut player: Query<(&mut Weapon, &Inventory), (With<Player>, Without<Dead>)>,
+    input: Option<Res<PlayerInput>>,
+) {
+    let Some(input) = input.filter(|input| input.weapon_cycle != 0) else {
+        return;
+    };
+    for (mut weapon, inventory) in player.iter_mut() {
+        let selected = cycle_weapon(inventory, weapon.selected, input.weapon_cycle);
+        if selected != weapon.selected {
+            weapon.selected = selected;
+        }
+    }
+}
+
+// Moves `step` owned weapons along the slot order from `current`, wrapping around
+pub fn cycle_weapon(inventory: &Inventory, current: WeaponType, step: i32) -> WeaponType {
+    let owned: Vec<WeaponType> = WeaponType::ALL
+        .into_iter()
+        .filter(|weapon| inventory.weapons.contains(weapon) || *weapon == current)
+        .collect();
+    let Some(index) = owned.iter().position(|&weapon| weapon == current) else {
+        return current;
+    };
+    owned[(index as i32 + step).rem_euclid(owned.len() as i32) as usize]
+}
+
+// What a hitscan shot struck and where along its path
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShotHit {
+    Target(Entity, (f64, f64)),
+    Wall((f64, f64)),
+}
+
+// Finds the nearest target of (entity, x, y, radius) that a ray fired from `from` at `angle`
+// passes through within `range`, skipping targets hidden behind walls. A shot that
+// misses them all stops at the first wall in range, if there is one.
+pub fn hitscan(
+    map: Option<&Map>,
+    from: (f64, f64),
+    angle: f64,
+    range: f64,
+    targets: &[(Entity, f64, f64, f64)],
+) -> Option<ShotHit> {
+    let (dir_x, dir_y) = (angle.cos(), angle.sin());
+    let point = |distance: f64| {
+        let distance = distance.max(0.0);
+        (from.0 + dir_x * distance, from.1 + dir_y * distance)
+    };
+
+    let nearest = targets
+        .iter()
+        .filter_map(|&(entity, x, y, radius)| {
+            let (dx, dy) = (x - from.0, y - from.1);
+            let along = dx * dir_x + dy * dir_y;
+            let across = dx * dir_y - dy * dir_x;
+            if along <= 0.0 || along - radius > range || across.abs() > radius {
+                return None;
+            }
+            Some((entity, along, radius, (x, y)))
+        })
+        .filter(|&(.., position)| can_see(map, from, position))
+        .min_by(|a, b| a.1.total_cmp(&b.1));
+    if let Some((entity, along, radius, _)) = nearest {
+        return Some(ShotHit::Target(entity, point(along - radius)));
+    }
+
+    let fraction = map?.sight_blocked_at(from, point(range))?;
+    Some(ShotHit::Wall(point(range * fraction - PUFF_WALL_OFFSET)))
+}
+
+// Applies damage to a monster, sending it into pain or leaving a corpse. Returns true if it died.
+fn damage_monster(
+    commands: &mut Commands,
+    monster: Entity,
+    entity_type: &mut EntityType,
+    mut ai: Option<Mut<MonsterAi>>,
+    damage: i32,
+) -> bool {
+    let EntityType::Monster { health, .. } = entity_type else {
+        return false;
+    };
+
+    *health -= damage;
+    if *health <= 0 {
+        // Leave the corpse behind as a non-solid decoration
+        *entity_type = EntityType::Decoration;
+        commands.entity(monster).remove::<Collider>();
+        if let Some(ai) = ai.as_mut() {
+            ai.enter(AiState::Death, 0.0);
+        }
+        true
+    } else {
+        if let Some(ai) = ai.as_mut() {
+            ai.enter(AiState::Pain, PAIN_TIME);
+        }
+        false
+    }
+}
+
+// Collects items the player walks over. Items with a respawn time are hidden and come
+// back later, the rest are removed.
+#[allow(clippy::type_complexity)]
+pub fn pickup_items(
+    mut commands: Commands,
+    mut player: Query<
+        (
+            &Transform,
+            &Collider,
+            &mut Health,
+            &mut Armor,
+            &mut Inventory,
+            &mut Powerups,
+            Option<&mut PaletteFlash>,
+        ),
+        With<Player>,
+    >,
+    items: Query<
+        (Entity, &Transform, &Collider, &EntityType, &Active),
+        (Without<Respawning>, Without<Player>),
+    >,
+) {
+    let Ok((
+        player_transform,
+        player_collider,
+        mut health,
+        mut armor,
+        mut inventory,
+        mut powerups,
+        mut flash,
+    )) = player.single_mut()
+    else {
+        return;
+    };
+
+    for (entity, transform, collider, entity_type, _) in
+        items.iter().filter(|(.., active)| active.0)
+    {
+        let EntityType::Item {
+            item_type,
+            respawn_time,
+        } = entity_type
+        else {
+            continue;
+        };
+
+        let distance = (transform.x - player_transform.x).hypot(transform.y - player_transform.y);
+        if distance >= collider.radius + player_collider.radius {
+            continue;
+        }
+        if !apply_pickup(
+            item_type,
+            &mut health,
+            &mut armor,
+            &mut inventory,
+            &mut powerups,
+        ) {
+            continue;
+        }
+        if let Some(flash) = flash.as_mut() {
+            flash.bonus += BONUS_FLASH;
+        }
+
+        match respawn_time {
+            Some(remaining) => {
+                commands.entity(entity).insert(Respawning {
+                    remaining: *remaining,
+                });
+            }
+            None => commands.entity(entity).despawn(),
+        }
+    }
+}
+
+// Gives an item to the player. Returns false when it would have no effect, in which
+// case the item stays where it is. Weapons, keys and powerups are always taken.
+pub fn apply_pickup(
+    item_type: &ItemType,
+    health: &mut Health,
+    armor: &mut Armor,
+    inventory: &mut Inventory,
+    powerups: &mut Powerups,
+) -> bool {
+    let raise = |current: &mut i32, amount: i32, limit: i32| {
+        if *current >= limit {
+            return false;
+        }
+        *current = (*current + amount).min(limit);
+        true
+    };
+
+    match item_type {
+        ItemType::Health { amount, limit } => raise(&mut health.0, *amount, *limit),
+        ItemType::Armor {
+            amount,
+            limit,
+            class,
+        } => {
+            if !raise(&mut armor.points, *amount, *limit) {
+                return false;
+            }
+            armor.class = class.or(armor.class).or(Some(ArmorClass::Green));
+            true
+        }
+        ItemType::Ammo { ammo_type, amount } => inventory.give_ammo(*ammo_type, *amount),
+        ItemType::Weapon(weapon) => {
+            inventory.weapons.insert(*weapon);
+            if let Some((ammo_type, amount)) = weapon.pickup_ammo() {
+                inventory.give_ammo(ammo_type, amount);
+            }
+            true
+        }
+        ItemType::Key(key) => {
+            inventory.keys.insert(*key);
+            true
+        }
+        ItemType::Powerup(powerup) => {
+            powerups.give(*powerup);
+            true
+        }
+    }
+}
+
+// Runs down the player's powerups until they wear off
+pub fn update_powerups(mut powerups: Query<&mut Powerups>, time: Res<Time>) {
+    for mut powerups in powerups.iter_mut() {
+        powerups.tick(time.delta_seconds_f64());
+    }
+}
+
+pub fn respawn_items(
+    mut commands: Commands,
+    mut items: Query<(Entity, &mut Respawning)>,
+    time: Res<Time>,
+) {
+    for (entity, mut respawning) in items.iter_mut() {
+        respawning.remaining = respawning.remaining.saturating_sub(time.delta);
+        if respawning.remaining.is_zero() {
+            commands.entity(entity).remove::<Respawning>();
+        }
+    }
+}
+
+// Applies damage to the player, letting armor soak up its share first as in Doom's
+// P_DamageMobj. Armor that can't cover its share is used up and loses its class.
+pub fn damage_player(health: &mut Health, armor: &mut Armor, damage: i32) {
+    let mut damage = damage;
+
+    if let Some(class) = armor.class {
+        let mut saved = class.absorbed(damage);
+        if armor.points <= saved {
+            saved = armor.points;
+            armor.class = None;
+        }
+        armor.points -= saved;
+        damage -= saved;
+    }
+
+    health.0 -= damage;
+}
+
+// Starts the damage flash from health the player has lost and counts both flashes down
+pub fn update_palette_flash(mut players: Query<(&Health, &mut PaletteFlash)>, time: Res<Time>) {
+    let elapsed_tics = time.delta_seconds_f64() * 35.0;
+
+    for (health, mut flash) in players.iter_mut() {
+        flash.damage = (flash.damage - elapsed_tics).max(0.0);
+        flash.bonus = (flash.bonus - elapsed_tics).max(0.0);
+        if let Some(last) = flash.last_health
+            && health.0 < last
+        {
+            flash.damage = (flash.damage + (last - health.0) as f64).min(MAX_DAMAGE_FLASH);
+        }
+        flash.last_health = Some(health.0);
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn update_player_death(
+    mut commands: Commands,
+    players: Query<(Entity, &Health), (With<Player>, Without<Dead>)>,
+) {
+    for (entity, health) in players.iter() {
+        if health.0 <= 0 {
+            commands.entity(entity).insert(Dead);
+        }
+    }
+}
+
+// Starts door and lift specials from lines the player uses or walks across
+#[allow(clippy::type_complexity)]
+pub fn activate_specials(
+    mut commands: Commands,
+    mut player: Query<
+        (&mut Transform, &Inventory, &mut LastPosition),
+        (With<Player>, Without<Dead>),
+    >,
+    mut movers: Query<(Entity, &mut SectorMover)>,
+    input: Option<Res<PlayerInput>>,
+    level: Option<ResMut<LevelMap>>,
+) {
+    let (Ok((mut transform, inventory, mut last)), Some(mut level)) = (player.single_mut(), level)
+    else {
+        return;
+    };
+    let map = &mut level.0;
+    let from = (last.x, last.y);
+    let to = (transform.x, transform.y);
+    *last = LastPosition { x: to.0, y: to.1 };
+
+    // Hexen-style specials are numbered differently, with lines and things both
+    // able to trigger them, so only Doom's are acted on here
+    if !map.format.doom_specials() {
+        return;
+    }
+
+    let mut triggered = Vec::new();
+    if from != to {
+        for (index, linedef) in map.linedefs.iter().enumerate() {
+            if linedef.special_type == 0 {
+                continue;
+            }
+            if let Some((start, end)) = linedef_points(map, index)
+                && segments_intersect(from, to, start, end)
+            {
+                triggered.push((index, LineTrigger::Walk));
+            }
+        }
+    }
+
+    if input.is_some_and(|input| input.use_pressed)
+        && let Some(index) = used_linedef(map, to, transform.angle)
+    {
+        let trigger = match LineSpecial::from_type(map.linedefs[index].special_type) {
+            Some(special) if special.trigger == LineTrigger::Manual => LineTrigger::Manual,
+            _ => LineTrigger::Switch,
+        };
+        triggered.push((index, trigger));
+    }
+
+    // Sectors given a mover this frame, which the query can't see until commands apply
+    let mut started = Vec::new();
+    for (index, trigger) in triggered {
+        if let Some((repeatable, monsters_only)) =
+            teleport_special(map.linedefs[index].special_type)
+        {
+            if trigger != LineTrigger::Walk
+                || monsters_only
+                || !crossed_from_front(map, index, from)
+            {
+                continue;
+            }
+            let Some(destination) = teleport(&mut commands, map, index, &mut transform) else {
+                continue;
+            };
+            if !repeatable {
+                map.linedefs[index].special_type = 0;
+            }
+            // Start the next move from the destination, so the lines between here and
+            // there aren't crossed
+            *last = LastPosition {
+                x: destination.x,
+                y: destination.y,
+            };
+            commands.insert_resource(destination);
+            return;
+        }
+        if let Some((exit_trigger, secret)) = exit_special(map.linedefs[index].special_type) {
+            if exit_trigger == trigger {
+                if trigger == LineTrigger::Switch {
+                    press_switch(&mut commands, map, index, false);
+                }
+                commands.insert_resource(LevelExit { secret });
+            }
+            continue;
+        }
+        let Some(special) = LineSpecial::from_type(map.linedefs[index].special_type) else {
+            continue;
+        };
+        if special.trigger != trigger {
+            continue;
+        }
+        if let Some(lock) = special.key
+            && !inventory.keys.iter().any(|key| key.opens(lock))
+        {
+            continue;
+        }
+
+        let linedef = &map.linedefs[index];
+        let sectors = match trigger {
+            LineTrigger::Manual => map.side_sector(linedef.back_sidedef).into_iter().collect(),
+            _ => map.tagged_sectors(linedef.sector_tag),
+        };
+
+        for sector in sectors {
+            if special.action == SectorAction::StopCrusher {
+                for (entity, mover) in movers.iter() {
+                    if mover.sector == sector && mover.crush.is_some() {
+                        commands.entity(entity).despawn();
+                    }
+                }
+                continue;
+            }
+            if let Some((_, mut mover)) =
+                movers.iter_mut().find(|(_, mover)| mover.sector == sector)
+            {
+                // Using a door that's already moving sends it the other way
+                if trigger == LineTrigger::Manual && mover.plane == Plane::Ceiling {
+                    match mover.phase {
+                        MoverPhase::Returning => mover.phase = MoverPhase::Moving,
+                        MoverPhase::Waiting => mover.timer = 0.0,
+                        MoverPhase::Moving => {}
+                    }
+                }
+                continue;
+            }
+            for mover in sector_movers(map, sector, special.action) {
+                let moving = started.contains(&mover.sector)
+                    || movers.iter().any(|(_, other)| other.sector == mover.sector);
+                if !moving {
+                    started.push(mover.sector);
+                    commands.spawn(mover);
+                }
+            }
+        }
+
+        if trigger == LineTrigger::Switch {
+            press_switch(&mut commands, map, index, special.repeatable);
+        }
+        if !special.repeatable {
+            map.linedefs[index].special_type = 0;
+        }
+    }
+}
+
+// Moves monsters that walked across a teleport line since the last tick
+#[allow(clippy::type_complexity)]
+pub fn teleport_monsters(
+    mut commands: Commands,
+    mut monsters: Query<(&mut Transform, &PreviousTransform, &EntityType), Without<Player>>,
+    level: Option<ResMut<LevelMap>>,
+) {
+    let Some(mut level) = level else {
+        return;
+    };
+    let map = &mut level.0;
+    if !map.format.doom_specials() {
+        return;
+    }
+
+    for (mut transform, previous, entity_type) in monsters.iter_mut() {
+        if !matches!(entity_type, EntityType::Monster { .. }) {
+            continue;
+        }
+        let from = (previous.0.x, previous.0.y);
+        let to = (transform.x, transform.y);
+        if from == to {
+            continue;
+        }
+
+        let crossed = (0..map.linedefs.len()).find(|&index| {
+            teleport_special(map.linedefs[index].special_type).is_some()
+                && linedef_points(map, index)
+                    .is_some_and(|(start, end)| segments_intersect(from, to, start, end))
+                && crossed_from_front(map, index, from)
+        });
+        let Some(index) = crossed else {
+            continue;
+        };
+        if teleport(&mut commands, map, index, &mut transform).is_some()
+            && teleport_special(map.linedefs[index].special_type)
+                .is_some_and(|(repeatable, _)| !repeatable)
+        {
+            map.linedefs[index].special_type = 0;
+        }
+    }
+}
+
+// Teleport lines, with whether they can be used again and whether only monsters use them
+pub fn teleport_special(special_type: u16) -> Option<(bool, bool)> {
+    match special_type {
+        39 => Some((false, false)),
+        97 => Some((true, false)),
+        125 => Some((false, true)),
+        126 => Some((true, true)),
+        _ => None,
+    }
+}
+
+// Things only teleport when crossing from the front of the line, so they can step off
+// a destination surrounded by teleport lines
+fn crossed_from_front(map: &Map, linedef: usize, from: (f64, f64)) -> bool {
+    linedef_points(map, linedef).is_some_and(|(start, end)| {
+        (end.0 - start.0) * (from.1 - start.1) - (end.1 - start.1) * (from.0 - start.0) <= 0.0
+    })
+}
+
+// The first teleport destination thing in a sector with the tag, and the angle it faces
+pub fn teleport_destination(map: &Map, tag: u16) -> Option<(f64, f64, f64)> {
+    let sectors = map.tagged_sectors(tag);
+    map.things_of(ThingKind::Teleport)
+        .map(|thing| (thing.x as f64, thing.y as f64, thing.angle_radians() as f64))
+        .find(|&(x, y, _)| {
+            map.sector_at(x, y)
+                .is_some_and(|sector| sectors.contains(&sector))
+        })
+}
+
+// Moves a thing to the destination of a teleport line, standing on its floor and
+// facing its way, and leaves fog where it left and arrived.
+fn teleport(
+    commands: &mut Commands,
+    map: &Map,
+    linedef: usize,
+    transform: &mut Transform,
+) -> Option<PlayerTeleport> {
+    let (x, y, angle) = teleport_destination(map, map.linedefs[linedef].sector_tag)?;
+    let floor = map
+        .sector_at(x, y)
+        .and_then(|sector| map.sectors.get(sector))
+        .map_or(transform.z, |sector| sector.floor_height as f64);
+
+    spawn_teleport_fog(commands, transform.x, transform.y, transform.z);
+    spawn_teleport_fog(
+        commands,
+        x + angle.cos() * TELEPORT_FOG_DISTANCE,
+        y + angle.sin() * TELEPORT_FOG_DISTANCE,
+        floor,
+    );
+    *transform = Transform {
+        x,
+        y,
+        z: floor,
+        angle,
+    };
+    Some(PlayerTeleport { x, y, angle })
+}
+
+fn spawn_teleport_fog(commands: &mut Commands, x: f64, y: f64, z: f64) {
+    commands.spawn((
+        EntityType::Decoration,
+        Transform {
+            x,
+            y,
+            z,
+            angle: 0.0,
+        },
+        Sprite {
+            name: "TFOG".to_string(),
+        },
+        Lifetime(TELEPORT_FOG_TIME),
+    ));
+}
+
+fn spawn_blood(commands: &mut Commands, x: f64, y: f64, z: f64) {
+    spawn_effect(
+        commands,
+        x,
+        y,
+        z,
+        "BLUD",
+        Animation::new(BLOOD_FRAMES, BLOOD_FRAME_TIME),
+    );
+}
+
+fn spawn_puff(commands: &mut Commands, x: f64, y: f64, z: f64) {
+    spawn_effect(
+        commands,
+        x,
+        y,
+        z,
+        "PUFF",
+        Animation::new(PUFF_FRAMES, PUFF_FRAME_TIME),
+    );
+}
+
+// A decoration that plays its animation once where something was hit
+fn spawn_effect(
+    commands: &mut Commands,
+    x: f64,
+    y: f64,
+    z: f64,
+    sprite: &str,
+    animation: Animation,
+) {
+    commands.spawn((
+        EntityType::Decoration,
+        Transform {
+            x,
+            y,
+            z,
+            angle: 0.0,
+        },
+        Sprite {
+            name: sprite.to_string(),
+        },
+        animation,
+    ));
+}
+
+// Steps effect animations on, removing each once its last frame has played
+pub fn animate_effects(
+    mut commands: Commands,
+    mut entities: Query<(Entity, &mut Animation)>,
+    time: Res<Time>,
+) {
+    for (entity, mut animation) in entities.iter_mut() {
+        animation.elapsed += time.delta_seconds_f64();
+        if animation.frame().is_none() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+pub fn expire_entities(
+    mut commands: Commands,
+    mut entities: Query<(Entity, &mut Lifetime)>,
+    time: Res<Time>,
+) {
+    for (entity, mut lifetime) in entities.iter_mut() {
+        lifetime.0 -= time.delta_seconds_f64();
+        if lifetime.0 <= 0.0 {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+// Shows a used switch line as pressed, popping repeatable ones back out later
+fn press_switch(commands: &mut Commands, map: &mut Map, linedef: usize, repeatable: bool) {
+    let sidedef = map.linedefs[linedef].front_sidedef as usize;
+    let Some(side) = map.sidedefs.get_mut(sidedef) else {
+        return;
+    };
+    if toggle_switch_texture(side) && repeatable {
+        commands.spawn(SwitchReset {
+            sidedef,
+            timer: BUTTON_TIME,
+        });
+    }
+}
+
+// Swaps the first switch texture on a sidedef, checking the upper, middle and lower
+// textures in that order as Doom does. Switch textures come in pairs named SW1 and
+// SW2 for off and on. Returns whether a texture was changed.
+pub fn toggle_switch_texture(sidedef: &mut Sidedef) -> bool {
+    for texture in [
+        &mut sidedef.upper_texture,
+        &mut sidedef.middle_texture,
+        &mut sidedef.lower_texture,
+    ] {
+        let other = if texture.starts_with("SW1") {
+            "SW2"
+        } else if texture.starts_with("SW2") {
+            "SW1"
+        } else {
+            continue;
+        };
+        texture.replace_range(..3, other);
+        return true;
+    }
+    false
+}
+
+pub fn reset_switches(
+    mut commands: Commands,
+    mut switches: Query<(Entity, &mut SwitchReset)>,
+    level: Option<ResMut<LevelMap>>,
+    time: Res<Time>,
+) {
+    let Some(mut level) = level else {
+        return;
+    };
+    for (entity, mut switch) in switches.iter_mut() {
+        switch.timer -= time.delta_seconds_f64();
+        if switch.timer > 0.0 {
+            continue;
+        }
+        if let Some(sidedef) = level.0.sidedefs.get_mut(switch.sidedef) {
+            toggle_switch_texture(sidedef);
+        }
+        commands.entity(entity).despawn();
+    }
+}
+
+// The first line along the player's view within reach that is special or can't be
+// passed through, if it's special. Use presses don't reach past solid walls.
+pub fn used_linedef(map: &Map, from: (f64, f64), angle: f64) -> Option<usize> {
+    let to = (
+        from.0 + angle.cos() * USE_RANGE,
+        from.1 + angle.sin() * USE_RANGE,
+    );
+
+    (0..map.linedefs.len())
+        .filter_map(|index| {
+            let linedef = &map.linedefs[index];
+            let solid = map
+                .opening(linedef)
+                .is_none_or(|(bottom, top)| top <= bottom);
+            if linedef.special_type == 0 && !solid {
+                return None;
+            }
+            let (start, end) = linedef_points(map, index)?;
+            if !segments_intersect(from, to, start, end) {
+                return None;
+            }
+            // Only lines that face the player can be used
+            let facing = (end.0 - start.0) * (from.1 - start.1)
+                - (end.1 - start.1) * (from.0 - start.0)
+                <= 0.0;
+            if !facing {
+                return None;
+            }
+            // How far along the use line it's crossed
+            let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+            let (ex, ey) = (end.0 - start.0, end.1 - start.1);
+            let denominator = dx * ey - dy * ex;
+            let along = if denominator == 0.0 {
+                0.0
+            } else {
+                ((start.0 - from.0) * ey - (start.1 - from.1) * ex) / denominator
+            };
+            Some((index, along))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(index, _)| index)
+        .filter(|&index| map.linedefs[index].special_type != 0)
+}
+
+fn linedef_points(map: &Map, index: usize) -> Option<((f64, f64), (f64, f64))> {
+    let linedef = map.linedefs.get(index)?;
+    let start = map.vertices.get(linedef.start_vertex as usize)?;
+    let end = map.vertices.get(linedef.end_vertex as usize)?;
+    Some((
+        (start.x as f64, start.y as f64),
+        (end.x as f64, end.y as f64),
+    ))
+}
+
+// Moves sector planes. Doors and lifts hold back rather than crushing the player or
+// monsters, crushers squeeze and hurt them, and things standing on a moving floor ride
+// it.
+#[allow(clippy::type_complexity)]
+pub fn move_sectors(
+    mut commands: Commands,
+    mut movers: Query<(Entity, &mut SectorMover)>,
+    mut solids: Query<(
+        Entity,
+        &mut Transform,
+        &Collider,
+        Option<&mut EntityType>,
+        Option<&mut Health>,
+        Option<&mut Armor>,
+        Option<&mut MonsterAi>,
+        Has<Player>,
+        Has<GodMode>,
+        Option<&Powerups>,
+    )>,
+    level: Option<ResMut<LevelMap>>,
+    sound_events: Option<ResMut<SoundEvents>>,
+    time: Res<Time>,
+) {
+    let Some(mut level) = level else {
+        return;
+    };
+    if movers.is_empty() {
+        return;
+    }
+    let map = &mut level.0;
+
+    let dt = time.delta_seconds_f64();
+
+    // Only things that can be hurt stop a door, like Doom's shootable things
+    let occupants: Vec<(Entity, usize, f64)> = solids
+        .iter()
+        .filter(|(.., entity_type, _, _, _, is_player, _, _)| {
+            *is_player || matches!(entity_type, Some(EntityType::Monster { .. }))
+        })
+        .filter_map(|(entity, transform, collider, ..)| {
+            map.sector_at(transform.x, transform.y)
+                .map(|sector| (entity, sector, collider.height))
+        })
+        .collect();
+
+    // Floors that moved with their old and new heights, things being crushed and the
+    // sounds of movers setting off and stopping, with their sectors
+    let mut moved_floors = Vec::new();
+    let mut crushed = Vec::new();
+    let mut sounds = Vec::new();
+    for (entity, mut mover) in movers.iter_mut() {
+        let sector_index = mover.sector;
+        let Some(sector) = map.sectors.get_mut(sector_index) else {
+            commands.entity(entity).despawn();
+            continue;
+        };
+        let squeezed = |floor: f64, ceiling: f64| {
+            occupants
+                .iter()
+                .filter(move |&&(_, occupied, height)| {
+                    occupied == sector_index && ceiling - floor < height
+                })
+                .map(|&(occupant, ..)| occupant)
+        };
+        if mover.is_added() {
+            sounds.extend(mover.start_sound().map(|sound| (sound, sector_index)));
+        }
+        let old_floor = sector.floor_height as f64;
+        let old_phase = mover.phase;
+        let finished = mover.step(sector, dt, |floor, ceiling| {
+            squeezed(floor, ceiling).next().is_some()
+        });
+        let sound = match mover.phase {
+            _ if finished => mover.stop_sound(),
+            phase if phase == old_phase => None,
+            MoverPhase::Waiting => mover.stop_sound(),
+            MoverPhase::Moving | MoverPhase::Returning => mover.start_sound(),
+        };
+        sounds.extend(sound.map(|sound| (sound, sector_index)));
+        if sector.floor_height as f64 != old_floor {
+            moved_floors.push((sector_index, old_floor, sector.floor_height as f64));
+        }
+        let gap = (sector.floor_height as f64, sector.ceiling_height as f64);
+        if let Some(cooldown) = mover.crush.as_mut() {
+            *cooldown -= dt;
+            if *cooldown <= 0.0 && squeezed(gap.0, gap.1).next().is_some() {
+                *cooldown = CRUSH_INTERVAL;
+                crushed.extend(squeezed(gap.0, gap.1));
+            }
+        }
+        if finished {
+            commands.entity(entity).despawn();
+        }
+    }
+
+    if let Some(mut sound_events) = sound_events {
+        for (sound, sector) in sounds {
+            if let Some((x, y)) = map.sound_origin(sector) {
+                sound_events.0.push(SoundEvent { sound, x, y });
+            }
+        }
+    }
+
+    for (entity, mut transform, _, entity_type, health, armor, ai, is_player, god, powerups) in
+        solids.iter_mut()
+    {
+        if let Some(sector) = map.sector_at(transform.x, transform.y)
+            && let Some(&(_, old_floor, new_floor)) =
+                moved_floors.iter().find(|(moved, ..)| *moved == sector)
+            && transform.z <= old_floor.max(new_floor)
+        {
+            transform.z = new_floor;
+        }
+
+        if !crushed.contains(&entity) {
+            continue;
+        }
+        if is_player {
+            if let (Some(mut health), Some(mut armor), false) =
+                (health, armor, shielded(god, powerups))
+            {
+                damage_player(&mut health, &mut armor, CRUSH_DAMAGE);
+            }
+        } else if let Some(mut entity_type) = entity_type {
+            damage_monster(&mut commands, entity, &mut entity_type, ai, CRUSH_DAMAGE);
+        }
+    }
+}
+
+// Every looping sound that should be playing, with the entity it belongs to and where
+// it comes from: ambient sound sources and grinding floors and crushers
+pub fn looping_sounds(world: &mut World) -> Vec<(Entity, &'static str, (f64, f64))> {
+    let mut sounds: Vec<_> = world
+        .query::<(Entity, &AmbientSound, &Transform)>()
+        .iter(world)
+        .map(|(entity, sound, transform)| (entity, sound.0, (transform.x, transform.y)))
+        .collect();
+
+    let movers: Vec<_> = world
+        .query::<(Entity, &SectorMover)>()
+        .iter(world)
+        .filter_map(|(entity, mover)| Some((entity, mover.looping_sound()?, mover.sector)))
+        .collect();
+    if let Some(level) = world.get_resource::<LevelMap>() {
+        sounds.extend(movers.into_iter().filter_map(|(entity, sound, sector)| {
+            Some((entity, sound, level.0.sound_origin(sector)?))
+        }));
+    }
+    sounds
+}
+
+// Projectiles pass over two-sided lines and only stop at one-sided walls
+fn hits_solid_wall(map: &Map, from: (f64, f64), to: (f64, f64), radius: f64) -> bool {
+    map.linedefs.iter().any(|linedef| {
+        if linedef.back_sidedef != 0xFFFF {
+            return false;
+        }
+
+        let (Some(start), Some(end)) = (
+            map.vertices.get(linedef.start_vertex as usize),
+            map.vertices.get(linedef.end_vertex as usize),
+        ) else {
+            return false;
+        };
+        let start = (start.x as f64, start.y as f64);
+        let end = (end.x as f64, end.y as f64);
+
+        segments_intersect(from, to, start, end) || point_segment_distance(to, start, end) < radius
+    })
+}
+
+fn point_segment_distance(point: (f64, f64), start: (f64, f64), end: (f64, f64)) -> f64 {
+    let (seg_x, seg_y) = (end.0 - start.0, end.1 - start.1);
+    let length_squared = seg_x * seg_x + seg_y * seg_y;
+
+    let t = if length_squared > 0.0 {
+        (((point.0 - start.0) * seg_x + (point.1 - start.1) * seg_y) / length_squared)
+            .clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    let (closest_x, closest_y) = (start.0 + seg_x * t, start.1 + seg_y * t);
+    (point.0 - closest_x).hypot(point.1 - closest_y)
+}
+
+fn segments_intersect(a1: (f64, f64), a2: (f64, f64), b1: (f64, f64), b2: (f64, f64)) -> bool {
+    let cross = |o: (f64, f64), p: (f64, f64), q: (f64, f64)| {
+        (p.0 - o.0) * (q.1 - o.1) - (p.1 - o.1) * (q.0 - o.0)
+    };
+
+    let d1 = cross(b1, b2, a1);
+    let d2 = cross(b1, b2, a2);
+    let d3 = cross(a1, a2, b1);
+    let d4 = cross(a1, a2, b2);
+
+    (d1 > 0.0) != (d2 > 0.0) && (d3 > 0.0) != (d4 > 0.0)
+}
+
+// Picks a map's things of one kind, classified by THING_INFO. Unknown thing types
+// are decorations, as they're spawned.
+pub trait MapThings {
+    fn things_of(&self, kind: ThingKind) -> impl Iterator<Item = &Thing>;
+}
+
+impl MapThings for Map {
+    fn things_of(&self, kind: ThingKind) -> impl Iterator<Item = &Thing> {
+        self.things
+            .iter()
+            .filter(move |thing| ThingInfo::lookup_or_unknown(thing.thing_type).kind() == kind)
+    }
+}
+
+// Spawn helper functions
+pub fn spawn_entity(
+    commands: &mut Commands,
+    x: f64,
+    y: f64,
+    angle: f64,
+    entity_type: EntityType,
+    sprite_name: String,
+) -> Entity {
+    let is_monster = matches!(entity_type, EntityType::Monster { .. });
+    let mut entity = commands.spawn((
+        entity_type,
+        Transform {
+            x,
+            y,
+            z: 0.0,
+            angle,
+        },
+        Collider {
+            radius: 20.0,
+            height: 56.0,
+        },
+        Sprite { name: sprite_name },
+        Active(true),
+    ));
+    if is_monster {
+        entity.insert(MonsterAi::default());
+    }
+    entity.id()
+}
+
+// Spawns a map thing with the size from its ThingInfo. Sound sources have no sprite
+// or size, just the sound they play.
+pub fn spawn_thing(
+    commands: &mut Commands,
+    x: f64,
+    y: f64,
+    angle: f64,
+    entity_type: EntityType,
+    info: &ThingInfo,
+) -> Entity {
+    if let ThingCategory::SoundSource(sound) = info.category {
+        let transform = Transform {
+            x,
+            y,
+            z: 0.0,
+            angle,
+        };
+        return commands
+            .spawn((entity_type, transform, AmbientSound(sound), Active(true)))
+            .id();
+    }
+    let entity = spawn_entity(commands, x, y, angle, entity_type, info.sprite.to_string());
+    commands.entity(entity).insert(Collider {
+        radius: info.radius,
+        height: info.height,
+    });
+    entity
+}
+
+pub fn spawn_player(commands: &mut Commands, x: f64, y: f64, angle: f64) -> Entity {
+    commands
+        .spawn((
+            Player,
+            Transform {
+                x,
+                y,
+                z: 0.0,
+                angle,
+            },
+            Collider {
+                radius: 16.0,
+                height: 56.0,
+            },
+            Health(100),
+            Armor::default(),
+            Inventory::default(),
+            Powerups::default(),
+            Weapon::default(),
+            LastPosition { x, y },
+            PaletteFlash::default(),
+        ))
+        .id()
+}
+
+// Pushes overlapping solid things apart. Projectiles and decorations pass through
+// everything. The player's position is owned by the engine, so a monster overlapping
+// the player takes the whole push, and a push into a wall is dropped.
+pub fn separate_entities(
+    mut things: Query<(&mut Transform, &Collider, &EntityType, Has<Player>), Without<Respawning>>,
+    level: Option<Res<LevelMap>>,
+) {
+    let mut solids: Vec<_> = things
+        .iter_mut()
+        .filter(|(_, _, entity_type, is_player)| {
+            *is_player || matches!(entity_type, EntityType::Monster { .. })
+        })
+        .collect();
+
+    // Each thing goes into every grid cell its bounding box touches, so only things
+    // sharing a cell need comparing
+    let mut grid: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+    for (index, (transform, collider, _, _)) in solids.iter().enumerate() {
+        let (min, max) = (
+            collision_cell(transform.x - collider.radius, transform.y - collider.radius),
+            collision_cell(transform.x + collider.radius, transform.y + collider.radius),
+        );
+        for cell_x in min.0..=max.0 {
+            for cell_y in min.1..=max.1 {
+                grid.entry((cell_x, cell_y)).or_default().push(index);
+            }
+        }
+    }
+
+    let mut checked = HashSet::new();
+    for cell in grid.values() {
+        for (position, &a) in cell.iter().enumerate() {
+            for &b in &cell[position + 1..] {
+                if !checked.insert((a.min(b), a.max(b))) {
+                    continue;
+                }
+
+                let (first, second) = (&solids[a], &solids[b]);
+                if first.3 && second.3 {
+                    continue;
+                }
+                let Some(push) = separation(
+                    (first.0.x, first.0.y),
+                    first.1.radius,
+                    (second.0.x, second.0.y),
+                    second.1.radius,
+                ) else {
+                    continue;
+                };
+
+                // Split the push between two monsters, or give it all to the one that
+                // isn't the player
+                let (first_share, second_share) = match (first.3, second.3) {
+                    (true, _) => (0.0, 1.0),
+                    (_, true) => (1.0, 0.0),
+                    _ => (0.5, 0.5),
+                };
+                for (index, share, sign) in [(a, first_share, -1.0), (b, second_share, 1.0)] {
+                    if share == 0.0 {
+                        continue;
+                    }
+                    let (transform, collider, _, _) = &mut solids[index];
+                    let from = (transform.x, transform.y);
+                    let to = (
+                        from.0 + push.0 * share * sign,
+                        from.1 + push.1 * share * sign,
+                    );
+                    if let Some(level) = &level
+                        && hits_solid_wall(&level.0, from, to, collider.radius)
+                    {
+                        continue;
+                    }
+                    transform.x = to.0;
+                    transform.y = to.1;
+                }
+            }
+        }
+    }
+}
+
+fn collision_cell(x: f64, y: f64) -> (i32, i32) {
+    let size = map::BLOCK_SIZE as f64;
+    ((x / size).floor() as i32, (y / size).floor() as i32)
+}
+
+// How far to move b away from a so two circles just touch, or None if they don't
+// overlap. Circles on the same spot are pushed apart along x.
+pub fn separation(
+    a: (f64, f64),
+    a_radius: f64,
+    b: (f64, f64),
+    b_radius: f64,
+) -> Option<(f64, f64)> {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let distance = (dx * dx + dy * dy).sqrt();
+    let overlap = a_radius + b_radius - distance;
+    if overlap <= 0.0 {
+        return None;
+    }
+
+    if distance > 0.0 {
+        Some((dx / distance * overlap, dy / distance * overlap))
+    } else {
+        Some((overlap, 0.0))
+    }
+}
+
+// Switches whether an entity's systems update it, as for a trigger that's been turned
+// off. Entities without Active are left alone.
+pub fn set_active(world: &mut World, entity: Entity, active: bool) {
+    if let Some(mut current) = world.get_mut::<Active>(entity) {
+        current.0 = active;
+    }
+}
+
+// Remembers every transform before a tick runs. Things spawned since the last call
+// get their current transform, so they don't appear to slide in from elsewhere.
+pub fn store_previous_transforms(world: &mut World) {
+    let previous: Vec<_> = world
+        .query::<(Entity, &Transform)>()
+        .iter(world)
+        .map(|(entity, transform)| (entity, PreviousTransform(transform.clone())))
+        .collect();
+    world.insert_batch(previous);
+}
+
+// Plugin to organize the systems
+pub struct EntityPlugin;
+
+impl EntityPlugin {
+    pub fn build(&self, schedule: &mut Schedule) {
+        schedule.add_systems((
+            update_dormancy.before(update_monsters),
+            update_monsters,
+            update_projectiles,
+            switch_weapons,
+            fire_weapons,
+            alert_monsters.after(fire_weapons),
+            activate_specials,
+            reset_switches.after(activate_specials),
+            teleport_monsters.after(update_monsters),
+            expire_entities,
+            animate_effects,
+            move_sectors,
+            pickup_items,
+            update_powerups,
+            respawn_items,
+            update_player_death,
+            separate_entities.after(update_monsters),
+            update_palette_flash
+                .after(update_monsters)
+                .after(update_projectiles)
+                .after(pickup_items),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_ecs::system::RunSystemOnce;
+
+    // A world for running systems in, with a tenth of a second passed
+    fn world() -> World {
+        let mut world = World::new();
+        let mut time = Time::default();
+        time.advance(Duration::from_millis(100));
+        world.insert_resource(time);
+        world.init_resource::<GameRng>();
+        world
+    }
+
+    fn imp(commands: &mut Commands, x: f64, y: f64) -> Entity {
+        let monster = EntityType::Monster {
+            health: 60,
+            monster_type: MonsterType::Imp,
+        };
+        spawn_entity(commands, x, y, 0.0, monster, "TROO".to_string())
+    }
+
+    #[test]
+    fn projectile_damages_monster_and_is_removed() {
+        let mut world = world();
+        let mut commands = world.commands();
+        let monster = imp(&mut commands, 100.0, 0.0);
+        let projectile = EntityType::Projectile {
+            damage: 20,
+            velocity: (1000.0, 0.0),
+        };
+        let projectile = spawn_entity(&mut commands, 0.0, 0.0, 0.0, projectile, "BAL1".to_string());
+        world.flush();
+
+        world.run_system_once(update_projectiles).unwrap();
+
+        assert!(world.get_entity(projectile).is_err());
+        assert!(matches!(
+            world.get::<EntityType>(monster),
+            Some(EntityType::Monster { health: 40, .. })
+        ));
+    }
+
+    #[test]
+    fn monster_closes_in_on_player() {
+        let mut world = world();
+        let mut commands = world.commands();
+        spawn_player(&mut commands, 0.0, 0.0, 0.0);
+        // Demons only bite, so they walk all the way up to the player
+        let demon = EntityType::Monster {
+            health: 150,
+            monster_type: MonsterType::Demon,
+        };
+        let monster = spawn_entity(&mut commands, 500.0, 0.0, 0.0, demon, "SARG".to_string());
+        world.flush();
+
+        // Long enough to notice the player, react and start chasing
+        for _ in 0..10 {
+            world.run_system_once(update_monsters).unwrap();
+        }
+
+        let transform = world.get::<Transform>(monster).unwrap();
+        assert!(transform.x.hypot(transform.y) < 500.0);
+    }
+
+    #[test]
+    fn monster_attacks_once_it_has_reacted() {
+        let mut world = world();
+        let mut commands = world.commands();
+        spawn_player(&mut commands, 0.0, 0.0, 0.0);
+        let monster = imp(&mut commands, 300.0, 0.0);
+        world.flush();
+        world
+            .get_mut::<MonsterAi>(monster)
+            .unwrap()
+            .enter(AiState::See, 0.0);
+
+        world.run_system_once(update_monsters).unwrap();
+        assert_eq!(
+            world.get::<MonsterAi>(monster).unwrap().state,
+            AiState::Chase
+        );
+
+        // In range and in plain sight, so the imp turns and throws a fireball
+        world.run_system_once(update_monsters).unwrap();
+        assert_eq!(
+            world.get::<MonsterAi>(monster).unwrap().state,
+            AiState::Attack
+        );
+        let angle = world.get::<Transform>(monster).unwrap().angle;
+        assert!((angle.abs() - std::f64::consts::PI).abs() < 1e-9);
+
+        let mut projectiles = world.query::<&EntityType>();
+        let fireballs = projectiles
+            .iter(&world)
+            .filter(|entity_type| {
+                matches!(entity_type, EntityType::Projectile { velocity, .. } if velocity.0 < 0.0)
+            })
+            .count();
+        assert_eq!(fireballs, 1);
+    }
+
+    fn health_item(
+        commands: &mut Commands,
+        amount: i32,
+        limit: i32,
+        respawn_time: Option<Duration>,
+    ) -> Entity {
+        let item = EntityType::Item {
+            item_type: ItemType::Health { amount, limit },
+            respawn_time,
+        };
+        spawn_entity(commands, 0.0, 0.0, 0.0, item, "STIM".to_string())
+    }
+
+    #[test]
+    fn walking_over_an_item_picks_it_up() {
+        let mut world = world();
+        let mut commands = world.commands();
+        let player = spawn_player(&mut commands, 0.0, 0.0, 0.0);
+        let stimpack = health_item(&mut commands, 10, 100, None);
+        world.flush();
+        world.get_mut::<Health>(player).unwrap().0 = 50;
+
+        world.run_system_once(pickup_items).unwrap();
+
+        assert_eq!(world.get::<Health>(player).unwrap().0, 60);
+        assert!(world.get_entity(stimpack).is_err());
+    }
+
+    #[test]
+    fn health_at_its_limit_is_left_unless_it_overfills() {
+        let mut world = world();
+        let mut commands = world.commands();
+        let player = spawn_player(&mut commands, 0.0, 0.0, 0.0);
+        let stimpack = health_item(&mut commands, 10, 100, None);
+        world.flush();
+
+        world.run_system_once(pickup_items).unwrap();
+        assert_eq!(world.get::<Health>(player).unwrap().0, 100);
+        assert!(world.get_entity(stimpack).is_ok());
+
+        // A soulsphere goes up to 200
+        let mut commands = world.commands();
+        let soulsphere = health_item(&mut commands, 100, 200, None);
+        world.flush();
+        world.run_system_once(pickup_items).unwrap();
+        assert_eq!(world.get::<Health>(player).unwrap().0, 200);
+        assert!(world.get_entity(soulsphere).is_err());
+    }
+
+    #[test]
+    fn respawning_item_comes_back_after_its_time() {
+        let mut world = world();
+        let mut commands = world.commands();
+        let player = spawn_player(&mut commands, 0.0, 0.0, 0.0);
+        let stimpack = health_item(&mut commands, 10, 100, Some(Duration::from_millis(300)));
+        world.flush();
+        world.get_mut::<Health>(player).unwrap().0 = 50;
+
+        world.run_system_once(pickup_items).unwrap();
+        assert!(world.get::<Respawning>(stimpack).is_some());
+
+        // Hidden while it waits, so standing on it doesn't collect it again
+        world.run_system_once(pickup_items).unwrap();
+        assert_eq!(world.get::<Health>(player).unwrap().0, 60);
+
+        for _ in 0..2 {
+            world.run_system_once(respawn_items).unwrap();
+        }
+        assert!(world.get::<Respawning>(stimpack).is_some());
+        world.run_system_once(respawn_items).unwrap();
+        assert!(world.get::<Respawning>(stimpack).is_none());
+
+        world.run_system_once(pickup_items).unwrap();
+        assert_eq!(world.get::<Health>(player).unwrap().0, 70);
+    }
+
+    #[test]
+    fn damage_without_armor_comes_off_health() {
+        let (mut health, mut armor) = (Health(100), Armor::default());
+        damage_player(&mut health, &mut armor, 30);
+        assert_eq!(health.0, 70);
+        assert_eq!(armor, Armor::default());
+    }
+
+    #[test]
+    fn armor_absorbs_its_share() {
+        let mut health = Health(100);
+        let mut armor = Armor {
+            points: 100,
+            class: Some(ArmorClass::Green),
+        };
+        damage_player(&mut health, &mut armor, 30);
+        assert_eq!((health.0, armor.points), (80, 90));
+
+        let mut health = Health(100);
+        let mut armor = Armor {
+            points: 100,
+            class: Some(ArmorClass::Blue),
+        };
+        damage_player(&mut health, &mut armor, 30);
+        assert_eq!((health.0, armor.points), (85, 85));
+    }
+
+    #[test]
+    fn armor_running_out_mid_hit_passes_the_rest_on() {
+        let mut health = Health(100);
+        let mut armor = Armor {
+            points: 5,
+            class: Some(ArmorClass::Blue),
+        };
+        damage_player(&mut health, &mut armor, 30);
+        assert_eq!(health.0, 75);
+        assert_eq!(
+            armor,
+            Armor {
+                points: 0,
+                class: None
+            }
+        );
+    }
+
+    #[test]
+    fn player_dies_when_health_runs_out() {
+        let mut world = world();
+        let mut commands = world.commands();
+        let player = spawn_player(&mut commands, 0.0, 0.0, 0.0);
+        world.flush();
+
+        world.run_system_once(update_player_death).unwrap();
+        assert!(world.get::<Dead>(player).is_none());
+
+        world.get_mut::<Health>(player).unwrap().0 = 0;
+        world.run_system_once(update_player_death).unwrap();
+        assert!(world.get::<Dead>(player).is_some());
+    }
+
+    // The player at the origin facing east with the fire input held, and a monster
+    // `distance` in front of them
+    fn firing_range(weapon: WeaponType, distance: f64) -> (World, Entity, Entity) {
+        let mut world = world();
+        world.insert_resource(PlayerInput {
+            fire: true,
+            ..PlayerInput::default()
+        });
+        let mut commands = world.commands();
+        let player = spawn_player(&mut commands, 0.0, 0.0, 0.0);
+        let baron = EntityType::Monster {
+            health: 1000,
+            monster_type: MonsterType::BaronOfHell,
+        };
+        let monster = spawn_entity(&mut commands, distance, 0.0, 0.0, baron, "BOSS".to_string());
+        world.flush();
+        let mut inventory = world.get_mut::<Inventory>(player).unwrap();
+        inventory.weapons.insert(weapon);
+        inventory.ammo.insert(AmmoType::Shells, 10);
+        world.get_mut::<Weapon>(player).unwrap().selected = weapon;
+        (world, player, monster)
+    }
+
+    fn monster_health(world: &World, monster: Entity) -> i32 {
+        match world.get::<EntityType>(monster) {
+            Some(EntityType::Monster { health, .. }) => *health,
+            _ => 0,
+        }
+    }
+
+    fn blood_splats(world: &mut World) -> usize {
+        let mut sprites = world.query::<&Sprite>();
+        sprites
+            .iter(world)
+            .filter(|sprite| sprite.name == "BLUD")
+            .count()
+    }
+
+    #[test]
+    fn pistol_shot_hits_the_monster_in_front() {
+        let (mut world, player, monster) = firing_range(WeaponType::Pistol, 200.0);
+
+        world.run_system_once(fire_weapons).unwrap();
+
+        // 1 to 3 times 5 damage
+        let damage = 1000 - monster_health(&world, monster);
+        assert!((5..=15).contains(&damage), "took {}", damage);
+        let inventory = world.get::<Inventory>(player).unwrap();
+        assert_eq!(inventory.ammo(AmmoType::Bullets), 49);
+        assert_eq!(blood_splats(&mut world), 1);
+    }
+
+    #[test]
+    fn shotgun_fires_seven_pellets() {
+        let (mut world, player, monster) = firing_range(WeaponType::Shotgun, 64.0);
+
+        world.run_system_once(fire_weapons).unwrap();
+
+        assert_eq!(blood_splats(&mut world), 7);
+        assert!(monster_health(&world, monster) <= 1000 - 7 * 5);
+        let inventory = world.get::<Inventory>(player).unwrap();
+        assert_eq!(inventory.ammo(AmmoType::Shells), 9);
+    }
+
+    #[test]
+    fn weapon_without_ammo_does_not_fire() {
+        let (mut world, player, monster) = firing_range(WeaponType::Pistol, 200.0);
+        world
+            .get_mut::<Inventory>(player)
+            .unwrap()
+            .ammo
+            .insert(AmmoType::Bullets, 0);
+
+        world.run_system_once(fire_weapons).unwrap();
+
+        assert_eq!(monster_health(&world, monster), 1000);
+        assert_eq!(world.get::<Weapon>(player).unwrap().cooldown, 0.0);
+        assert_eq!(blood_splats(&mut world), 0);
+    }
+
+    #[test]
+    fn weapon_waits_out_its_refire_delay() {
+        let (mut world, player, _) = firing_range(WeaponType::Pistol, 200.0);
+
+        world.run_system_once(fire_weapons).unwrap();
+        world.run_system_once(fire_weapons).unwrap();
+
+        // The pistol refires slower than a tenth of a second
+        assert_eq!(
+            world
+                .get::<Inventory>(player)
+                .unwrap()
+                .ammo(AmmoType::Bullets),
+            49
+        );
+    }
+
+    // A 256 unit square room, sector 0, with a closed door 64 units deep, sector 1, in
+    // its east wall. The door's line is a manual door special.
+    fn room_with_door() -> Map {
+        let mut text = String::from("namespace = \"doom\";\n");
+        for (x, y) in [(0, 0), (0, 256), (256, 256), (256, 0), (320, 256), (320, 0)] {
+            text += &format!("vertex {{ x = {}; y = {}; }}\n", x, y);
+        }
+        text += "sidedef { sector = 0; }\nsidedef { sector = 1; }\n";
+        for (v1, v2, side) in [
+            (0, 1, 0),
+            (1, 2, 0),
+            (3, 0, 0),
+            (2, 4, 1),
+            (4, 5, 1),
+            (5, 3, 1),
+        ] {
+            text += &format!(
+                "linedef {{ v1 = {}; v2 = {}; sidefront = {}; blocking = true; }}\n",
+                v1, v2, side
+            );
+        }
+        text += "linedef { v1 = 2; v2 = 3; sidefront = 0; sideback = 1; twosided = true; special = 1; }\n";
+        text += "sector { heightfloor = 0; heightceiling = 128; }\n";
+        text += "sector { heightfloor = 0; heightceiling = 0; }\n";
+        Map::parse_udmf(&text).unwrap()
+    }
+
+    fn door_height(world: &World) -> i16 {
+        world.resource::<LevelMap>().0.sectors[1].ceiling_height
+    }
+
+    #[test]
+    fn used_door_opens_waits_and_closes() {
+        let mut world = world();
+        world.insert_resource(LevelMap(room_with_door()));
+        world.insert_resource(PlayerInput {
+            use_pressed: true,
+            ..PlayerInput::default()
+        });
+        let mut commands = world.commands();
+        spawn_player(&mut commands, 220.0, 128.0, 0.0);
+        world.flush();
+
+        world.run_system_once(activate_specials).unwrap();
+        world.insert_resource(PlayerInput::default());
+
+        // Up to 4 under the room's ceiling, held open, then shut again
+        let mut highest = 0;
+        let mut ticks = 0;
+        loop {
+            world.run_system_once(move_sectors).unwrap();
+            ticks += 1;
+            highest = highest.max(door_height(&world));
+            let moving = world.query::<&SectorMover>().iter(&world).count() > 0;
+            if !moving || ticks > 200 {
+                break;
+            }
+        }
+        assert_eq!(highest, 124);
+        assert_eq!(door_height(&world), 0);
+        // Opening and closing at 70 units a second take about 18 ticks each, with the
+        // wait of 150 tics in between
+        assert!((70..90).contains(&ticks), "took {} ticks", ticks);
+    }
+
+    #[test]
+    fn closing_door_reopens_on_a_monster_underneath() {
+        let mut world = world();
+        let mut map = room_with_door();
+        map.sectors[1].ceiling_height = 124;
+        world.insert_resource(LevelMap(map));
+        let mut commands = world.commands();
+        let monster = imp(&mut commands, 288.0, 128.0);
+        world.flush();
+        let level = &world.resource::<LevelMap>().0;
+        let mut mover = SectorMover::new(
+            1,
+            level,
+            SectorAction::Door {
+                kind: DoorKind::Normal,
+                fast: false,
+            },
+        )
+        .unwrap();
+        mover.phase = MoverPhase::Returning;
+        mover.height = 124.0;
+        world.spawn(mover);
+
+        for _ in 0..30 {
+            world.run_system_once(move_sectors).unwrap();
+        }
+
+        // The imp is 56 tall, so the door never comes down onto it
+        assert!(door_height(&world) >= 56);
+        assert!(world.get_entity(monster).is_ok());
+    }
+
+    #[test]
+    fn known_doomednums_have_their_mobjinfo() {
+        let imp = ThingInfo::lookup(3001).unwrap();
+        assert_eq!(
+            (imp.sprite, imp.radius, imp.height, imp.health),
+            ("TROO", 20.0, 56.0, 60)
+        );
+        assert!(matches!(
+            imp.category,
+            ThingCategory::Monster(MonsterType::Imp)
+        ));
+
+        let bonus = ThingInfo::lookup(2014).unwrap();
+        assert_eq!(bonus.sprite, "BON1");
+        assert!(matches!(
+            bonus.category,
+            ThingCategory::Item(ItemType::Health {
+                amount: 1,
+                limit: 200
+            })
+        ));
+
+        let start = ThingInfo::lookup(1).unwrap();
+        assert_eq!((start.sprite, start.health), ("PLAY", 100));
+        assert!(matches!(start.category, ThingCategory::PlayerStart(1)));
+        assert!(start.entity_type().is_none());
+    }
+
+    #[test]
+    fn unknown_doomednums_are_decorations() {
+        assert!(ThingInfo::lookup(12345).is_none());
+        let unknown = ThingInfo::lookup_or_unknown(12345);
+        assert!(matches!(unknown.category, ThingCategory::Decoration));
+        assert!(matches!(
+            unknown.entity_type(),
+            Some(EntityType::Decoration)
+        ));
+    }
+
+    #[test]
+    fn overlapping_monsters_separate_after_one_step() {
+        let mut world = world();
+        let mut commands = world.commands();
+        let first = imp(&mut commands, 100.0, 100.0);
+        let second = imp(&mut commands, 105.0, 100.0);
+        let player = spawn_player(&mut commands, 120.0, 140.0, 0.0);
+        world.flush();
+
+        world.run_system_once(separate_entities).unwrap();
+
+        let position = |entity| {
+            let transform = world.get::<Transform>(entity).unwrap();
+            (transform.x, transform.y)
+        };
+        let (a, b) = (position(first), position(second));
+        let gap = (b.0 - a.0).hypot(b.1 - a.1);
+        assert!((gap - 40.0).abs() < 1e-9, "imps {} apart", gap);
+        // The push is shared, so they part around where they stood
+        assert!(((a.0 + b.0) / 2.0 - 102.5).abs() < 1e-9);
+        assert_eq!(position(player), (120.0, 140.0));
+    }
+
+    #[test]
+    fn using_a_door_only_works_within_reach() {
+        let map = room_with_door();
+        assert_eq!(used_linedef(&map, (220.0, 128.0), 0.0), Some(6));
+        assert_eq!(used_linedef(&map, (150.0, 128.0), 0.0), None);
+        // Facing away from it
+        assert_eq!(
+            used_linedef(&map, (220.0, 128.0), std::f64::consts::PI),
+            None
+        );
+
+        for (x, opens) in [(220.0, true), (150.0, false)] {
+            let mut world = world();
+            world.insert_resource(LevelMap(room_with_door()));
+            world.insert_resource(PlayerInput {
+                use_pressed: true,
+                ..PlayerInput::default()
+            });
+            let mut commands = world.commands();
+            spawn_player(&mut commands, x, 128.0, 0.0);
+            world.flush();
+
+            world.run_system_once(activate_specials).unwrap();
+            let movers = world.query::<&SectorMover>().iter(&world).count();
+            assert_eq!(movers, opens as usize, "using from x = {}", x);
+        }
+    }
+
+    #[test]
+    fn crusher_hurts_a_monster_trapped_under_it() {
+        let mut world = world();
+        let mut map = room_with_door();
+        map.sectors[1].ceiling_height = 128;
+        world.insert_resource(LevelMap(map));
+        let mut commands = world.commands();
+        let trapped = imp(&mut commands, 288.0, 128.0);
+        let outside = imp(&mut commands, 100.0, 128.0);
+        world.flush();
+        let level = &world.resource::<LevelMap>().0;
+        let crusher = SectorMover::new(1, level, SectorAction::Crusher { fast: false }).unwrap();
+        world.spawn(crusher);
+
+        // The imp is 56 tall, so the ceiling reaches it after a couple of seconds
+        for _ in 0..10 {
+            world.run_system_once(move_sectors).unwrap();
+        }
+        assert_eq!(monster_health(&world, trapped), 60);
+        for _ in 0..30 {
+            world.run_system_once(move_sectors).unwrap();
+        }
+        assert!(monster_health(&world, trapped) < 60);
+        assert_eq!(monster_health(&world, outside), 60);
+    }
+
+    #[test]
+    fn floors_raise_to_their_target_height() {
+        for (target, expected) in [(FloorTarget::NextHigher, 40), (FloorTarget::Raise24, 24)] {
+            let mut world = world();
+            let mut map = room_with_door();
+            map.sectors[0].floor_height = 40;
+            map.sectors[1].ceiling_height = 128;
+            world.insert_resource(LevelMap(map));
+            let level = &world.resource::<LevelMap>().0;
+            let mover = SectorMover::new(
+                1,
+                level,
+                SectorAction::Floor {
+                    target,
+                    fast: false,
+                },
+            )
+            .unwrap();
+            world.spawn(mover);
+
+            for _ in 0..40 {
+                world.run_system_once(move_sectors).unwrap();
+            }
+            assert_eq!(
+                world.resource::<LevelMap>().0.sectors[1].floor_height,
+                expected,
+                "{:?}",
+                target
+            );
+            assert_eq!(world.query::<&SectorMover>().iter(&world).count(), 0);
+        }
+    }
+
+    #[test]
+    fn monster_crossing_a_teleport_line_lands_on_the_destination() {
+        let mut world = world();
+        let mut map = room_with_door();
+        map.sectors[1].ceiling_height = 128;
+        map.sectors[1].tag = 3;
+        map.linedefs[6].special_type = 97;
+        map.linedefs[6].sector_tag = 3;
+        map.things = vec![Thing {
+            tid: 0,
+            x: 300,
+            y: 64,
+            z: 0,
+            angle: 90,
+            thing_type: 14,
+            flags: 0,
+            special: 0,
+            args: [0; 5],
+        }];
+        world.insert_resource(LevelMap(map));
+        let mut commands = world.commands();
+        let monster = imp(&mut commands, 250.0, 128.0);
+        world.flush();
+
+        world.run_system_once(store_previous_transforms).unwrap();
+        world.get_mut::<Transform>(monster).unwrap().x = 262.0;
+        world.run_system_once(teleport_monsters).unwrap();
+
+        let transform = world.get::<Transform>(monster).unwrap().clone();
+        assert_eq!((transform.x, transform.y), (300.0, 64.0));
+        assert!((transform.angle - std::f64::consts::FRAC_PI_2).abs() < 1e-6);
+        let fog = world
+            .query::<&Sprite>()
+            .iter(&world)
+            .filter(|sprite| sprite.name == "TFOG")
+            .count();
+        assert_eq!(fog, 2);
+
+        // Walking back over the line from its back side doesn't teleport again
+        world.run_system_once(store_previous_transforms).unwrap();
+        world.get_mut::<Transform>(monster).unwrap().x = 250.0;
+        world.run_system_once(teleport_monsters).unwrap();
+        assert_eq!(world.get::<Transform>(monster).unwrap().x, 250.0);
+    }
+
+    #[test]
+    fn distant_idle_monster_sleeps_while_a_near_one_wakes() {
+        let mut world = world();
+        let mut commands = world.commands();
+        spawn_player(&mut commands, 0.0, 0.0, 0.0);
+        let near = imp(&mut commands, 200.0, 0.0);
+        let far = imp(&mut commands, 3000.0, 0.0);
+        world.flush();
+        // Part way through its wait before it next looks for the player
+        world.get_mut::<MonsterAi>(far).unwrap().timer = DORMANT_LOOK_TIME;
+
+        world.run_system_once(update_dormancy).unwrap();
+        world.run_system_once(update_monsters).unwrap();
+        let state = |world: &World, monster| world.get::<MonsterAi>(monster).unwrap().state;
+        assert_eq!(state(&world, near), AiState::See);
+        assert_eq!(state(&world, far), AiState::Spawn);
+        assert!(world.get::<Dormant>(far).is_some());
+
+        // It still looks around now and then, and wakes when it spots the player
+        for _ in 0..3 {
+            world.run_system_once(update_dormancy).unwrap();
+        }
+        assert_eq!(state(&world, far), AiState::See);
+        assert!(world.get::<Dormant>(far).is_none());
+    }
+
+    #[test]
+    fn inactive_entities_are_not_updated() {
+        let mut world = world();
+        let mut commands = world.commands();
+        spawn_player(&mut commands, 0.0, 0.0, 0.0);
+        let monster = imp(&mut commands, 200.0, 0.0);
+        let projectile = EntityType::Projectile {
+            damage: 20,
+            velocity: (100.0, 0.0),
+        };
+        let projectile = spawn_entity(
+            &mut commands,
+            0.0,
+            300.0,
+            0.0,
+            projectile,
+            "BAL1".to_string(),
+        );
+        world.flush();
+        set_active(&mut world, monster, false);
+        set_active(&mut world, projectile, false);
+
+        // Dormancy leaves a monster that's been switched off alone
+        for _ in 0..3 {
+            world.run_system_once(update_dormancy).unwrap();
+            world.run_system_once(update_monsters).unwrap();
+            world.run_system_once(update_projectiles).unwrap();
+        }
+        assert_eq!(
+            world.get::<MonsterAi>(monster).unwrap().state,
+            AiState::Spawn
+        );
+        assert_eq!(world.get::<Active>(monster), Some(&Active(false)));
+        assert_eq!(world.get::<Transform>(projectile).unwrap().x, 0.0);
+
+        set_active(&mut world, monster, true);
+        world.run_system_once(update_monsters).unwrap();
+        assert_eq!(world.get::<MonsterAi>(monster).unwrap().state, AiState::See);
+    }
+
+    #[test]
+    fn shots_leave_blood_on_monsters_and_puffs_on_walls() {
+        let effect = |world: &mut World, name: &str| {
+            let mut effects = world.query::<(&Sprite, &Transform)>();
+            let found: Vec<Transform> = effects
+                .iter(world)
+                .filter(|(sprite, _)| sprite.name == name)
+                .map(|(_, transform)| transform.clone())
+                .collect();
+            assert_eq!(found.len(), 1, "{} effects", name);
+            found[0].clone()
+        };
+
+        // Blood comes out of the front of the monster, where the shot went in
+        let (mut world, _, monster) = firing_range(WeaponType::Pistol, 200.0);
+        let radius = world.get::<Collider>(monster).unwrap().radius;
+        world.run_system_once(fire_weapons).unwrap();
+        let blood = effect(&mut world, "BLUD");
+        assert!(
+            (blood.x - (200.0 - radius)).abs() < 1.0,
+            "blood at {:?}",
+            blood
+        );
+        assert!(blood.y.abs() < radius);
+
+        // Turned to the room's west wall, the shot puffs just short of the wall
+        let (mut world, player, _) = firing_range(WeaponType::Pistol, 200.0);
+        world.insert_resource(LevelMap(room_with_door()));
+        *world.get_mut::<Transform>(player).unwrap() = Transform {
+            x: 128.0,
+            y: 128.0,
+            z: 0.0,
+            angle: std::f64::consts::PI,
+        };
+        world.run_system_once(fire_weapons).unwrap();
+        let puff = effect(&mut world, "PUFF");
+        assert!(
+            (puff.x - PUFF_WALL_OFFSET).abs() < 0.5,
+            "puff at {:?}",
+            puff
+        );
+        assert!((puff.y - 128.0).abs() < 16.0);
+        assert_eq!(blood_splats(&mut world), 0);
+    }
+
+    #[test]
+    fn things_of_picks_out_one_kind_of_thing() {
+        let mut map = room_with_door();
+        map.things = [1, 11, 14, 3001, 3004, 2011, 2001, 9999]
+            .into_iter()
+            .map(|thing_type| Thing {
+                tid: 0,
+                x: 0,
+                y: 0,
+                z: 0,
+                angle: 0,
+                thing_type,
+                flags: 0,
+                special: 0,
+                args: [0; 5],
+            })
+            .collect();
+        let types =
+            |kind| -> Vec<u16> { map.things_of(kind).map(|thing| thing.thing_type).collect() };
 
-impl Plugin for EntityPlugin {
-    fn build(&self, app: &mut App) {
-        app.add_systems(Update, (update_monsters, update_projectiles));
+        assert_eq!(types(ThingKind::Monster), [3001, 3004]);
+        assert_eq!(types(ThingKind::PlayerStart), [1, 11]);
+        assert_eq!(types(ThingKind::Teleport), [14]);
+        assert_eq!(types(ThingKind::Item), [2011]);
+        assert_eq!(types(ThingKind::Weapon), [2001]);
+        // Unknown types are spawned as decorations, so they're listed as them
+        assert_eq!(types(ThingKind::Decoration), [9999]);
     }
 }
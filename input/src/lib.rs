@@ -1,7 +1,638 @@
-pub struct Input;
+use sdl2::EventPump;
+use sdl2::GameControllerSubsystem;
+use sdl2::controller::GameController;
+use sdl2::event::Event;
+use sdl2::mouse::MouseUtil;
+use std::collections::{HashMap, HashSet};
+
+pub use sdl2::controller::{Axis, Button};
+pub use sdl2::keyboard::Keycode;
+
+// Fraction of an analog stick's travel that is ignored, so worn sticks don't drift
+pub const CONTROLLER_DEADZONE: f64 = 0.25;
+
+// Radians turned per pixel of horizontal mouse motion at a sensitivity of 1
+const MOUSE_RADIANS_PER_PIXEL: f64 = 0.0025;
+// Frees or recaptures the mouse cursor
+const MOUSE_GRAB_KEY: Keycode = Keycode::Pause;
+
+// Logical actions the game responds to, bound to keys by KeyBindings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Forward,
+    Back,
+    StrafeLeft,
+    StrafeRight,
+    TurnLeft,
+    TurnRight,
+    Fire,
+    Use,
+    Run,
+    Pause,
+    // Debug movement: fly up and down while flying, and toggle noclip and flying
+    FlyUp,
+    FlyDown,
+    ToggleNoclip,
+    ToggleFly,
+}
+
+impl Action {
+    pub const ALL: [Action; 14] = [
+        Action::Forward,
+        Action::Back,
+        Action::StrafeLeft,
+        Action::StrafeRight,
+        Action::TurnLeft,
+        Action::TurnRight,
+        Action::Fire,
+        Action::Use,
+        Action::Run,
+        Action::Pause,
+        Action::FlyUp,
+        Action::FlyDown,
+        Action::ToggleNoclip,
+        Action::ToggleFly,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Forward => "Forward",
+            Action::Back => "Back",
+            Action::StrafeLeft => "StrafeLeft",
+            Action::StrafeRight => "StrafeRight",
+            Action::TurnLeft => "TurnLeft",
+            Action::TurnRight => "TurnRight",
+            Action::Fire => "Fire",
+            Action::Use => "Use",
+            Action::Run => "Run",
+            Action::Pause => "Pause",
+            Action::FlyUp => "FlyUp",
+            Action::FlyDown => "FlyDown",
+            Action::ToggleNoclip => "ToggleNoclip",
+            Action::ToggleFly => "ToggleFly",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::ALL
+            .into_iter()
+            .find(|action| action.name().eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyBindings {
+    bindings: HashMap<Action, Vec<Keycode>>,
+}
+
+// WASD to move and strafe with the arrow keys also moving and turning
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            bindings: HashMap::from([
+                (Action::Forward, vec![Keycode::W, Keycode::Up]),
+                (Action::Back, vec![Keycode::S, Keycode::Down]),
+                (Action::StrafeLeft, vec![Keycode::A]),
+                (Action::StrafeRight, vec![Keycode::D]),
+                (Action::TurnLeft, vec![Keycode::Left]),
+                (Action::TurnRight, vec![Keycode::Right]),
+                (Action::Fire, vec![Keycode::LCtrl, Keycode::RCtrl]),
+                (Action::Use, vec![Keycode::Space]),
+                (Action::Run, vec![Keycode::LShift, Keycode::RShift]),
+                (Action::Pause, vec![Keycode::P]),
+                (Action::FlyUp, vec![Keycode::PageUp]),
+                (Action::FlyDown, vec![Keycode::PageDown]),
+                (Action::ToggleNoclip, vec![Keycode::F3]),
+                (Action::ToggleFly, vec![Keycode::F4]),
+            ]),
+        }
+    }
+}
+
+impl KeyBindings {
+    // Reads "Action = Key, Key" lines over the defaults, naming keys as SDL does
+    // (for example "Left Ctrl"). Actions that aren't mentioned keep their keys.
+    pub fn parse(text: &str) -> Result<KeyBindings, Box<dyn std::error::Error>> {
+        let mut bindings = KeyBindings::default();
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (action, keys) = line
+                .split_once('=')
+                .ok_or_else(|| format!("Expected \"Action = Key\", got \"{}\"", line))?;
+            let action = Action::from_name(action.trim())
+                .ok_or_else(|| format!("Unknown action \"{}\"", action.trim()))?;
+            let keys = keys
+                .split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .map(|key| {
+                    Keycode::from_name(key).ok_or_else(|| format!("Unknown key \"{}\"", key))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            bindings.rebind(action, &keys);
+        }
+
+        Ok(bindings)
+    }
+
+    // Replaces the keys for one action, leaving every other binding alone
+    pub fn rebind(&mut self, action: Action, keys: &[Keycode]) {
+        self.bindings.insert(action, keys.to_vec());
+    }
+
+    pub fn keys(&self, action: Action) -> &[Keycode] {
+        self.bindings
+            .get(&action)
+            .map_or(&[], |keys| keys.as_slice())
+    }
+
+    pub fn is_bound(&self, action: Action, keycode: Keycode) -> bool {
+        self.keys(action).contains(&keycode)
+    }
+}
+
+pub struct Input {
+    bindings: KeyBindings,
+    pressed_keys: HashSet<Keycode>,
+    // Keys that went down since the previous call to handle_events
+    just_pressed: HashSet<Keycode>,
+    controller_subsystem: GameControllerSubsystem,
+    // Open controllers by joystick instance id, kept alive so SDL keeps sending events
+    controllers: HashMap<u32, GameController>,
+    axes: HashMap<Axis, i16>,
+    pressed_buttons: HashSet<Button>,
+    just_pressed_buttons: HashSet<Button>,
+    mouse: MouseUtil,
+    mouse_grabbed: bool,
+    // SDL reports the jump to the window centre as motion right after grabbing,
+    // so the first motion event after a grab is dropped
+    skip_mouse_motion: bool,
+    // Horizontal mouse motion since the previous call to handle_events
+    mouse_delta: i32,
+    // Text typed since the previous call to handle_events, for the console
+    text_input: String,
+    mouse_sensitivity: f64,
+    invert_mouse: bool,
+}
 
 impl Input {
-    pub fn new() -> Input {
-        Input
+    pub fn new(sdl_context: &sdl2::Sdl) -> Result<Input, Box<dyn std::error::Error>> {
+        let mut input = Input {
+            bindings: KeyBindings::default(),
+            pressed_keys: HashSet::new(),
+            just_pressed: HashSet::new(),
+            controller_subsystem: sdl_context.game_controller()?,
+            controllers: HashMap::new(),
+            axes: HashMap::new(),
+            pressed_buttons: HashSet::new(),
+            just_pressed_buttons: HashSet::new(),
+            mouse: sdl_context.mouse(),
+            mouse_grabbed: false,
+            skip_mouse_motion: false,
+            mouse_delta: 0,
+            text_input: String::new(),
+            mouse_sensitivity: 1.0,
+            invert_mouse: false,
+        };
+        input.set_mouse_grab(true);
+        Ok(input)
+    }
+
+    // Returns false once the user has asked to quit
+    pub fn handle_events(
+        &mut self,
+        event_pump: &mut EventPump,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        self.just_pressed.clear();
+        self.just_pressed_buttons.clear();
+        self.mouse_delta = 0;
+        self.text_input.clear();
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => return Ok(false),
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    repeat,
+                    ..
+                } => {
+                    self.pressed_keys.insert(keycode);
+                    if !repeat {
+                        self.just_pressed.insert(keycode);
+                        if keycode == MOUSE_GRAB_KEY {
+                            self.set_mouse_grab(!self.mouse_grabbed);
+                        }
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    self.pressed_keys.remove(&keycode);
+                }
+                // Sent for controllers already connected at startup as well as ones
+                // plugged in later. A controller that fails to open is just ignored.
+                Event::ControllerDeviceAdded { which, .. } => {
+                    match self.controller_subsystem.open(which) {
+                        Ok(controller) => {
+                            self.controllers
+                                .insert(controller.instance_id(), controller);
+                        }
+                        Err(e) => eprintln!("Failed to open controller {}: {}", which, e),
+                    }
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    self.controllers.remove(&which);
+                    // Don't leave the player running on a stick that's gone
+                    if self.controllers.is_empty() {
+                        self.axes.clear();
+                        self.pressed_buttons.clear();
+                    }
+                }
+                Event::MouseMotion { xrel, .. } if self.mouse_grabbed => {
+                    if self.skip_mouse_motion {
+                        self.skip_mouse_motion = false;
+                    } else {
+                        self.mouse_delta += xrel;
+                    }
+                }
+                Event::TextInput { text, .. } => self.text_input.push_str(&text),
+                Event::ControllerAxisMotion { axis, value, .. } => {
+                    self.axes.insert(axis, value);
+                }
+                Event::ControllerButtonDown { button, .. } => {
+                    self.pressed_buttons.insert(button);
+                    self.just_pressed_buttons.insert(button);
+                }
+                Event::ControllerButtonUp { button, .. } => {
+                    self.pressed_buttons.remove(&button);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(true)
+    }
+
+    pub fn is_key_down(&self, keycode: Keycode) -> bool {
+        self.pressed_keys.contains(&keycode)
+    }
+
+    pub fn was_key_pressed(&self, keycode: Keycode) -> bool {
+        self.just_pressed.contains(&keycode)
+    }
+
+    pub fn text_input(&self) -> &str {
+        &self.text_input
+    }
+
+    pub fn bindings(&self) -> &KeyBindings {
+        &self.bindings
+    }
+
+    pub fn set_bindings(&mut self, bindings: KeyBindings) {
+        self.bindings = bindings;
+    }
+
+    pub fn rebind(&mut self, action: Action, keys: &[Keycode]) {
+        self.bindings.rebind(action, keys);
+    }
+
+    pub fn is_action_down(&self, action: Action) -> bool {
+        self.bindings
+            .keys(action)
+            .iter()
+            .any(|&keycode| self.is_key_down(keycode))
+    }
+
+    pub fn was_action_pressed(&self, action: Action) -> bool {
+        self.bindings
+            .keys(action)
+            .iter()
+            .any(|&keycode| self.was_key_pressed(keycode))
+    }
+
+    // Position of a controller axis in -1..1 (0..1 for triggers) after the deadzone.
+    // Stick Y axes are positive when pushed down, as SDL reports them.
+    pub fn controller_axis(&self, axis: Axis) -> f64 {
+        let value = self.axes.get(&axis).copied().unwrap_or(0);
+        apply_deadzone(value, CONTROLLER_DEADZONE)
+    }
+
+    // Relative mouse mode hides the cursor and keeps it in the window during play
+    pub fn set_mouse_grab(&mut self, grabbed: bool) {
+        self.mouse.set_relative_mouse_mode(grabbed);
+        self.mouse_grabbed = grabbed;
+        self.skip_mouse_motion = grabbed;
+    }
+
+    pub fn is_mouse_grabbed(&self) -> bool {
+        self.mouse_grabbed
+    }
+
+    pub fn set_mouse_sensitivity(&mut self, sensitivity: f64) {
+        self.mouse_sensitivity = sensitivity.max(0.0);
+    }
+
+    pub fn set_invert_mouse(&mut self, invert: bool) {
+        self.invert_mouse = invert;
+    }
+
+    pub fn mouse_delta(&self) -> i32 {
+        self.mouse_delta
+    }
+
+    // How far this frame's mouse motion turns the player, counterclockwise positive
+    pub fn mouse_turn(&self) -> f64 {
+        mouse_turn_angle(self.mouse_delta, self.mouse_sensitivity, self.invert_mouse)
+    }
+
+    pub fn is_button_down(&self, button: Button) -> bool {
+        self.pressed_buttons.contains(&button)
+    }
+
+    pub fn was_button_pressed(&self, button: Button) -> bool {
+        self.just_pressed_buttons.contains(&button)
+    }
+}
+
+// Moving the mouse right turns right, which is clockwise
+pub fn mouse_turn_angle(delta_x: i32, sensitivity: f64, invert: bool) -> f64 {
+    let turn = -(delta_x as f64) * sensitivity * MOUSE_RADIANS_PER_PIXEL;
+    if invert { -turn } else { turn }
+}
+
+// Scales a raw axis value into -1..1, zeroing anything inside the deadzone and
+// stretching the rest so movement still starts from zero at its edge
+pub fn apply_deadzone(value: i16, deadzone: f64) -> f64 {
+    let normalized = (value as f64 / i16::MAX as f64).clamp(-1.0, 1.0);
+    if normalized.abs() <= deadzone {
+        return 0.0;
+    }
+    normalized.signum() * (normalized.abs() - deadzone) / (1.0 - deadzone)
+}
+
+// Doom's forwardmove and sidemove tables: movement per tic forward and sideways,
+// walking then running
+pub const FORWARD_MOVE: [i8; 2] = [25, 50];
+pub const SIDE_MOVE: [i8; 2] = [24, 40];
+
+// Bits of TicCmd::buttons
+pub const BT_ATTACK: u8 = 0x01;
+pub const BT_USE: u8 = 0x02;
+
+// Ends the commands of a demo lump where the next tic would start
+const DEMO_END_MARKER: u8 = 0x80;
+// Demos from Doom 1.4 on start with their version and a longer header. Recordings
+// are written as Doom 1.9 writes them.
+const DEMO_VERSION_HEADER: u8 = 104;
+const DEMO_VERSION: u8 = 109;
+const MAX_DEMO_PLAYERS: usize = 4;
+
+// What the player asks for during one tic, whether read from the devices or from a
+// recorded demo. Movement is in Doom's units (see FORWARD_MOVE), with positive side
+// movement to the right. Turning is in 65536ths of a full turn, counterclockwise.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TicCmd {
+    pub forward: i8,
+    pub side: i8,
+    pub turn: i16,
+    pub buttons: u8,
+}
+
+impl TicCmd {
+    pub fn turn_radians(&self) -> f64 {
+        self.turn as f64 / 65536.0 * std::f64::consts::TAU
+    }
+}
+
+// The nearest TicCmd turn to an angle, limited to half a turn either way
+pub fn turn_from_radians(radians: f64) -> i16 {
+    (radians / std::f64::consts::TAU * 65536.0)
+        .round()
+        .clamp(i16::MIN as f64, i16::MAX as f64) as i16
+}
+
+// A recorded game from a DEMOn lump: the session it starts and the tic commands of
+// the player it was recorded from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Demo {
+    // Doom version that recorded it, 0 for demos older than 1.4
+    pub version: u8,
+    pub skill: u8,
+    pub episode: u8,
+    pub map: u8,
+    pub tics: Vec<TicCmd>,
+}
+
+impl Demo {
+    // An empty single player demo to record into
+    pub fn new(skill: u8, episode: u8, map: u8) -> Demo {
+        Demo {
+            version: DEMO_VERSION,
+            skill,
+            episode,
+            map,
+            tics: Vec::new(),
+        }
+    }
+
+    // Adds a tic to the recording and returns it as it'll play back, with its turn
+    // rounded to the high byte. The recording game runs the returned command so that
+    // it stays in step with playback.
+    pub fn record(&mut self, cmd: TicCmd) -> TicCmd {
+        let cmd = TicCmd {
+            // -128 would read back as the end marker
+            forward: cmd.forward.max(-127),
+            turn: (((cmd.turn as i32 + 128) >> 8) << 8) as i16,
+            ..cmd
+        };
+        self.tics.push(cmd);
+        cmd
+    }
+
+    // The demo as a lump in the 1.4 and later format, with the recording player alone
+    // in the game, closed with the end marker
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = vec![
+            self.version.max(DEMO_VERSION_HEADER),
+            self.skill,
+            self.episode,
+            self.map,
+        ];
+        // No deathmatch, respawning, fast monsters or -nomonsters, recorded by player 1
+        data.extend_from_slice(&[0, 0, 0, 0, 0]);
+        data.extend_from_slice(&[1, 0, 0, 0]);
+        for cmd in &self.tics {
+            data.extend_from_slice(&[
+                cmd.forward as u8,
+                cmd.side as u8,
+                (cmd.turn >> 8) as u8,
+                cmd.buttons,
+            ]);
+        }
+        data.push(DEMO_END_MARKER);
+        data
+    }
+
+    // Demos from 1.4 on have a 13 byte header: version, skill, episode, map, four game
+    // options, the recording player and which of four players were in the game. Older
+    // ones have only skill, episode, map and the players. Each tic then holds four
+    // bytes per player in the game, with the turn as its high byte only, until the
+    // end marker.
+    pub fn parse(data: &[u8]) -> Result<Demo, Box<dyn std::error::Error>> {
+        let (version, header_len) = match data.first() {
+            Some(&version) if version >= DEMO_VERSION_HEADER => (version, 13),
+            Some(_) => (0, 7),
+            None => return Err("Demo is empty".into()),
+        };
+        let header = data
+            .get(..header_len)
+            .ok_or_else(|| format!("Demo is {} bytes, too short for its header", data.len()))?;
+        let (skill, episode, map, console_player) = if version == 0 {
+            (header[0], header[1], header[2], 0)
+        } else {
+            (header[1], header[2], header[3], header[8] as usize)
+        };
+        let in_game = &header[header_len - MAX_DEMO_PLAYERS..];
+        if console_player >= MAX_DEMO_PLAYERS || in_game[console_player] == 0 {
+            return Err(format!(
+                "Demo was recorded by player {}, who isn't in the game",
+                console_player + 1
+            )
+            .into());
+        }
+        // The recording player's command within each tic
+        let players = in_game.iter().filter(|&&present| present != 0).count();
+        let slot = in_game[..console_player]
+            .iter()
+            .filter(|&&present| present != 0)
+            .count();
+
+        let mut tics = Vec::new();
+        let mut rest = &data[header_len..];
+        loop {
+            match rest.first() {
+                Some(&DEMO_END_MARKER) => break,
+                None => return Err("Demo ends without its end marker".into()),
+                Some(_) => {}
+            }
+            let tic = rest
+                .get(..players * 4)
+                .ok_or("Demo ends part way through a tic")?;
+            let cmd = &tic[slot * 4..slot * 4 + 4];
+            tics.push(TicCmd {
+                forward: cmd[0] as i8,
+                side: cmd[1] as i8,
+                turn: ((cmd[2] as u16) << 8) as i16,
+                buttons: cmd[3],
+            });
+            rest = &rest[players * 4..];
+        }
+
+        Ok(Demo {
+            version,
+            skill,
+            episode,
+            map,
+            tics,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadzone_ignores_small_stick_movement() {
+        let small = (CONTROLLER_DEADZONE * i16::MAX as f64) as i16;
+        assert_eq!(apply_deadzone(0, CONTROLLER_DEADZONE), 0.0);
+        assert_eq!(apply_deadzone(small, CONTROLLER_DEADZONE), 0.0);
+        assert_eq!(apply_deadzone(-small, CONTROLLER_DEADZONE), 0.0);
+    }
+
+    #[test]
+    fn stick_past_the_deadzone_moves_from_zero_to_full() {
+        // Left stick pushed fully right and halfway between the deadzone and the top
+        let halfway = ((CONTROLLER_DEADZONE + 1.0) / 2.0 * i16::MAX as f64) as i16;
+        let movement = (
+            apply_deadzone(i16::MAX, CONTROLLER_DEADZONE),
+            apply_deadzone(-halfway, CONTROLLER_DEADZONE),
+        );
+        assert_eq!(movement.0, 1.0);
+        assert!((movement.1 + 0.5).abs() < 1e-3, "{:?}", movement);
+
+        // The axis minimum is one further than the maximum, and still full travel
+        assert_eq!(apply_deadzone(i16::MIN, CONTROLLER_DEADZONE), -1.0);
+    }
+
+    #[test]
+    fn rebinding_fire_moves_it_to_the_new_key() {
+        let mut bindings = KeyBindings::default();
+        let old_keys = bindings.keys(Action::Fire).to_vec();
+        let forward = bindings.keys(Action::Forward).to_vec();
+
+        bindings.rebind(Action::Fire, &[Keycode::F]);
+
+        assert!(bindings.is_bound(Action::Fire, Keycode::F));
+        for key in old_keys {
+            assert!(
+                !bindings.is_bound(Action::Fire, key),
+                "{:?} still fires",
+                key
+            );
+        }
+        assert_eq!(bindings.keys(Action::Forward), forward.as_slice());
+    }
+
+    #[test]
+    fn mouse_motion_turns_by_pixels_times_sensitivity() {
+        let turn = mouse_turn_angle(100, 2.0, false);
+        assert!((turn - -0.5).abs() < 1e-12, "turned {}", turn);
+
+        assert!((mouse_turn_angle(-40, 1.0, false) - 0.1).abs() < 1e-12);
+        assert_eq!(mouse_turn_angle(100, 2.0, true), -turn);
+        assert_eq!(mouse_turn_angle(0, 3.0, false), 0.0);
+    }
+
+    #[test]
+    fn recorded_demo_plays_back_the_same_commands() {
+        let mut demo = Demo::new(2, 1, 3);
+        let input = [
+            TicCmd {
+                forward: FORWARD_MOVE[0],
+                ..TicCmd::default()
+            },
+            TicCmd {
+                forward: -128,
+                side: -SIDE_MOVE[1],
+                turn: 1000,
+                buttons: BT_ATTACK,
+            },
+            TicCmd {
+                turn: -300,
+                buttons: BT_USE,
+                ..TicCmd::default()
+            },
+        ];
+        let recorded: Vec<TicCmd> = input.iter().map(|&cmd| demo.record(cmd)).collect();
+
+        let data = demo.to_bytes();
+        assert_eq!(data[..4], [DEMO_VERSION, 2, 1, 3]);
+        assert_eq!(data.last(), Some(&DEMO_END_MARKER));
+        let played = Demo::parse(&data).unwrap();
+        assert_eq!(played, demo);
+        assert_eq!(played.tics, recorded);
+        // Turns keep only their high byte
+        assert_eq!(recorded[1].turn, 1024);
+        assert_eq!(recorded[2].turn, -256);
     }
 }
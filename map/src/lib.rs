@@ -1,13 +1,59 @@
 use byteorder::{LittleEndian, ReadBytesExt};
+use std::collections::{HashMap, VecDeque};
 use std::io::{Cursor, Read};
-use wad::WadFile;
+use thiserror::Error;
+use wad::{WadFile, WadLump, is_map_lump};
 
+// Sound gets through one line with this flag but is stopped by a second
+const ML_SOUNDBLOCK: u16 = 0x0040;
+
+#[derive(Error, Debug)]
+pub enum MapError {
+    #[error("Map {0} not found")]
+    MapNotFound(String),
+    #[error("Map {map} is missing its {lump} lump")]
+    MissingLump { map: String, lump: &'static str },
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("TEXTMAP isn't valid UTF-8: {0}")]
+    InvalidText(#[from] std::str::Utf8Error),
+    #[error("{0}")]
+    Parse(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapFormat {
+    Doom,
+    // Hexen-style maps give every line a special with arguments and every thing a
+    // tid, height and special. They're marked by a BEHAVIOR lump.
+    Hexen,
+    // Text maps from a TEXTMAP lump. The doom, heretic and strife namespaces keep
+    // Doom's special numbering, the others use Hexen's.
+    Udmf { doom_specials: bool },
+}
+
+impl MapFormat {
+    pub fn doom_specials(self) -> bool {
+        match self {
+            MapFormat::Doom => true,
+            MapFormat::Hexen => false,
+            MapFormat::Udmf { doom_specials } => doom_specials,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Map {
+    pub format: MapFormat,
     pub vertices: Vec<Vertex>,
     pub linedefs: Vec<Linedef>,
     pub sidedefs: Vec<Sidedef>,
     pub sectors: Vec<Sector>,
     pub things: Vec<Thing>,
+    pub blockmap: Option<BlockMap>,
+    pub reject: Reject,
+    // Extent of the vertices when the map was loaded, as (min_x, min_y, max_x, max_y)
+    bounds: (i16, i16, i16, i16),
 }
 
 #[derive(Debug, Clone)]
@@ -21,8 +67,11 @@ pub struct Linedef {
     pub start_vertex: u16,
     pub end_vertex: u16,
     pub flags: u16,
+    // Doom or Hexen special numbers depending on the map's format
     pub special_type: u16,
+    // Always 0 in Hexen-format maps, where specials name their tag among the args
     pub sector_tag: u16,
+    pub args: [u8; 5],
     pub front_sidedef: u16,
     pub back_sidedef: u16,
 }
@@ -48,35 +97,553 @@ pub struct Sector {
     pub tag: u16,
 }
 
-impl Map {
-    pub fn load_from_wad(
-        wad: &WadFile,
-        map_name: &str,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
-        // Find the map marker lump
-        let map_index = wad
-            .lumps
+// Height above the floor that monsters look from and at, 3/4 of a 56 unit thing
+pub const SIGHT_HEIGHT: f64 = 42.0;
+
+// Players 1 to 4 start on things of their own number, and deathmatch players on any
+// of the deathmatch starts
+const MAX_PLAYERS: u8 = 4;
+const DEATHMATCH_START: u16 = 11;
+
+// The data lumps belonging to a single map, matched by name
+pub struct MapLumps<'a> {
+    map_name: String,
+    lumps: Vec<&'a WadLump>,
+}
+
+impl<'a> MapLumps<'a> {
+    pub fn find(wad: &'a WadFile, map_name: &str) -> Result<Self, MapError> {
+        // Find the map marker lump, skipping any other lump that happens to share its name
+        let map_index = (0..wad.lumps.len())
+            .find(|&index| {
+                wad.lumps[index].name == map_name
+                    && wad
+                        .lumps
+                        .get(index + 1)
+                        .is_some_and(|next| is_map_lump(&next.name))
+            })
+            .ok_or_else(|| MapError::MapNotFound(map_name.to_string()))?;
+
+        // Scan forward until the next map marker or any lump that isn't map data.
+        // GL node lumps (GL_VERT, GL_SEGS, ...) may be interleaved and are skipped over.
+        let lumps = wad.lumps[map_index + 1..]
             .iter()
-            .position(|lump| lump.name == map_name)
-            .ok_or("Map not found")?;
+            .take_while(|lump| is_map_lump(&lump.name) || lump.name.starts_with("GL_"))
+            .filter(|lump| is_map_lump(&lump.name))
+            .collect();
 
-        // Map data follows the marker in a specific order
-        let vertices = Self::parse_vertices(&wad.lumps[map_index + 4].data)?;
-        let linedefs = Self::parse_linedefs(&wad.lumps[map_index + 2].data)?;
-        let sidedefs = Self::parse_sidedefs(&wad.lumps[map_index + 3].data)?;
-        let sectors = Self::parse_sectors(&wad.lumps[map_index + 8].data)?;
-        let things = Self::parse_things(&wad.lumps[map_index + 1].data)?;
+        Ok(MapLumps {
+            map_name: map_name.to_string(),
+            lumps,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&'a WadLump> {
+        self.lumps.iter().copied().find(|lump| lump.name == name)
+    }
+
+    pub fn require(&self, name: &'static str) -> Result<&'a WadLump, MapError> {
+        self.get(name).ok_or_else(|| MapError::MissingLump {
+            map: self.map_name.clone(),
+            lump: name,
+        })
+    }
+}
+
+impl Map {
+    pub fn load_from_wad(wad: &WadFile, map_name: &str) -> Result<Self, MapError> {
+        let lumps = MapLumps::find(wad, map_name)?;
+
+        let mut map = match lumps.get("TEXTMAP") {
+            Some(textmap) => Self::parse_udmf(std::str::from_utf8(&textmap.data)?)?,
+            None => Self::parse_binary(&lumps)?,
+        };
+        map.blockmap = match lumps.get("BLOCKMAP") {
+            Some(lump) => Some(BlockMap::parse(&lump.data)?),
+            None => None,
+        };
+        if let Some(lump) = lumps.get("REJECT") {
+            map.reject = Reject::parse(&lump.data, map.sectors.len());
+        }
+
+        Ok(map)
+    }
+
+    // Doom and Hexen maps, with one binary lump per kind of map data
+    fn parse_binary(lumps: &MapLumps) -> Result<Self, MapError> {
+        let hexen = lumps.get("BEHAVIOR").is_some();
+
+        let vertices = Self::parse_vertices(&lumps.require("VERTEXES")?.data)?;
+        let linedefs_data = &lumps.require("LINEDEFS")?.data;
+        let linedefs = if hexen {
+            Self::parse_hexen_linedefs(linedefs_data)?
+        } else {
+            Self::parse_linedefs(linedefs_data)?
+        };
+        let sidedefs = Self::parse_sidedefs(&lumps.require("SIDEDEFS")?.data)?;
+        let sectors = Self::parse_sectors(&lumps.require("SECTORS")?.data)?;
+        let things_data = &lumps.require("THINGS")?.data;
+        let things = if hexen {
+            Self::parse_hexen_things(things_data)?
+        } else {
+            Self::parse_things(things_data)?
+        };
+        let reject = Reject::parse(&[], sectors.len());
+        let bounds = vertex_bounds(&vertices);
 
         Ok(Map {
+            format: if hexen {
+                MapFormat::Hexen
+            } else {
+                MapFormat::Doom
+            },
             vertices,
             linedefs,
             sidedefs,
             sectors,
             things,
+            blockmap: None,
+            reject,
+            bounds,
         })
     }
 
-    fn parse_vertices(data: &[u8]) -> Result<Vec<Vertex>, Box<dyn std::error::Error>> {
+    // Reads a UDMF TEXTMAP. Coordinates are rounded to whole units, and blocks and
+    // fields that aren't understood are skipped.
+    pub fn parse_udmf(text: &str) -> Result<Self, MapError> {
+        let (namespace, blocks) = parse_udmf_blocks(text)?;
+        let doom_specials = matches!(
+            namespace.to_ascii_lowercase().as_str(),
+            "doom" | "heretic" | "strife"
+        );
+
+        let mut map = Map {
+            format: MapFormat::Udmf { doom_specials },
+            vertices: Vec::new(),
+            linedefs: Vec::new(),
+            sidedefs: Vec::new(),
+            sectors: Vec::new(),
+            things: Vec::new(),
+            blockmap: None,
+            reject: Reject::parse(&[], 0),
+            bounds: (0, 0, 0, 0),
+        };
+
+        for block in &blocks {
+            match block.kind.as_str() {
+                "vertex" => map.vertices.push(Vertex {
+                    x: block.number("x", 0.0).round() as i16,
+                    y: block.number("y", 0.0).round() as i16,
+                }),
+                "linedef" => {
+                    let flags = [
+                        ("blocking", 0x0001),
+                        ("blockmonsters", 0x0002),
+                        ("twosided", 0x0004),
+                        ("dontpegtop", 0x0008),
+                        ("dontpegbottom", 0x0010),
+                        ("secret", 0x0020),
+                        ("blocksound", 0x0040),
+                        ("dontdraw", 0x0080),
+                        ("mapped", 0x0100),
+                    ]
+                    .into_iter()
+                    .filter(|(name, _)| block.flag(name))
+                    .fold(0, |flags, (_, bit)| flags | bit);
+                    // Doom namespaces tag lines with their id, Hexen's name the tag in an arg
+                    let sector_tag = if doom_specials {
+                        block.number("id", 0.0).max(0.0) as u16
+                    } else {
+                        0
+                    };
+
+                    map.linedefs.push(Linedef {
+                        start_vertex: block.index("v1")?,
+                        end_vertex: block.index("v2")?,
+                        flags,
+                        special_type: block.number("special", 0.0) as u16,
+                        sector_tag,
+                        args: block.args(),
+                        front_sidedef: block.index("sidefront")?,
+                        back_sidedef: block.number("sideback", -1.0) as i32 as u16,
+                    });
+                }
+                "sidedef" => map.sidedefs.push(Sidedef {
+                    x_offset: block.number("offsetx", 0.0).round() as i16,
+                    y_offset: block.number("offsety", 0.0).round() as i16,
+                    upper_texture: block.texture("texturetop"),
+                    lower_texture: block.texture("texturebottom"),
+                    middle_texture: block.texture("texturemiddle"),
+                    sector: block.index("sector")?,
+                }),
+                "sector" => map.sectors.push(Sector {
+                    floor_height: block.number("heightfloor", 0.0).round() as i16,
+                    ceiling_height: block.number("heightceiling", 0.0).round() as i16,
+                    floor_texture: block.texture("texturefloor"),
+                    ceiling_texture: block.texture("textureceiling"),
+                    light_level: block.number("lightlevel", 160.0) as i16,
+                    special_type: block.number("special", 0.0) as u16,
+                    tag: block.number("id", 0.0).max(0.0) as u16,
+                }),
+                "thing" => {
+                    let mut flags = 0;
+                    if block.flag("skill1") || block.flag("skill2") {
+                        flags |= MTF_EASY;
+                    }
+                    if block.flag("skill3") {
+                        flags |= MTF_NORMAL;
+                    }
+                    if block.flag("skill4") || block.flag("skill5") {
+                        flags |= MTF_HARD;
+                    }
+                    if block.flag("ambush") {
+                        flags |= MTF_AMBUSH;
+                    }
+                    if !block.flag("single") {
+                        flags |= MTF_MULTIPLAYER;
+                    }
+
+                    map.things.push(Thing {
+                        tid: block.number("id", 0.0).max(0.0) as u16,
+                        x: block.number("x", 0.0).round() as i16,
+                        y: block.number("y", 0.0).round() as i16,
+                        z: block.number("height", 0.0).round() as i16,
+                        angle: (block.number("angle", 0.0) as i32).rem_euclid(360) as u16,
+                        thing_type: block.number("type", 0.0) as u16,
+                        flags,
+                        special: block.number("special", 0.0) as u8,
+                        args: block.args(),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        map.reject = Reject::parse(&[], map.sectors.len());
+        map.bounds = vertex_bounds(&map.vertices);
+        Ok(map)
+    }
+
+    // The smallest and largest vertex coordinates as (min_x, min_y, max_x, max_y), all
+    // 0 for a map without vertices. Worked out once on loading, as vertices don't move.
+    pub fn bounds(&self) -> (i16, i16, i16, i16) {
+        self.bounds
+    }
+
+    // Middle of the map's bounds
+    pub fn center(&self) -> (f64, f64) {
+        let (min_x, min_y, max_x, max_y) = self.bounds;
+        (
+            (min_x as f64 + max_x as f64) / 2.0,
+            (min_y as f64 + max_y as f64) / 2.0,
+        )
+    }
+
+    // Finds the sector containing a point from the nearest linedef crossed by a ray
+    // cast towards +x, using the side of that line the point lies on. Points in the void
+    // land behind a one-sided line (or hit nothing) and give None. BspTree::sector_at
+    // answers the same question faster when the map's nodes are loaded.
+    pub fn sector_at(&self, x: f64, y: f64) -> Option<usize> {
+        let mut nearest: Option<(f64, &Linedef, bool)> = None;
+
+        for linedef in &self.linedefs {
+            let (Some(start), Some(end)) = (
+                self.vertices.get(linedef.start_vertex as usize),
+                self.vertices.get(linedef.end_vertex as usize),
+            ) else {
+                continue;
+            };
+            let (x1, y1, x2, y2) = (start.x as f64, start.y as f64, end.x as f64, end.y as f64);
+            if (y1 > y) == (y2 > y) {
+                continue;
+            }
+
+            let crossing = x1 + (y - y1) * (x2 - x1) / (y2 - y1);
+            if crossing < x || nearest.is_some_and(|(best, _, _)| crossing >= best) {
+                continue;
+            }
+
+            // The front side of a linedef is on its right
+            let front = (x2 - x1) * (y - y1) - (y2 - y1) * (x - x1) <= 0.0;
+            nearest = Some((crossing, linedef, front));
+        }
+
+        let (_, linedef, front) = nearest?;
+        let sidedef = if front {
+            linedef.front_sidedef
+        } else {
+            linedef.back_sidedef
+        };
+        self.side_sector(sidedef)
+    }
+
+    // Whether a monster at `from` can see `to`. Sight runs between points SIGHT_HEIGHT
+    // above each sector's floor and is cut by one-sided lines, closed two-sided lines
+    // and openings the sight line passes above or below.
+    pub fn line_of_sight(&self, from: (f64, f64), to: (f64, f64)) -> bool {
+        let from_sector = self.sector_at(from.0, from.1);
+        let to_sector = self.sector_at(to.0, to.1);
+
+        if let (Some(a), Some(b)) = (from_sector, to_sector) {
+            if a == b {
+                return true;
+            }
+            if !self.reject.can_see(a as u16, b as u16) {
+                return false;
+            }
+        }
+
+        self.sight_blocked_at(from, to).is_none()
+    }
+
+    // How far along the sight line from `from` to `to`, as a fraction, it's first cut,
+    // None if nothing cuts it. Unlike line_of_sight this doesn't consult the reject
+    // table, so it can say where a shot meets a wall.
+    pub fn sight_blocked_at(&self, from: (f64, f64), to: (f64, f64)) -> Option<f64> {
+        let from_sector = self.sector_at(from.0, from.1);
+        let to_sector = self.sector_at(to.0, to.1);
+
+        let eye_z = |sector: Option<usize>| {
+            sector
+                .and_then(|index| self.sectors.get(index))
+                .map(|sector| sector.floor_height as f64)
+                .unwrap_or(0.0)
+                + SIGHT_HEIGHT
+        };
+        let (from_z, to_z) = (eye_z(from_sector), eye_z(to_sector));
+
+        let candidates: Vec<u16> = match &self.blockmap {
+            Some(blockmap) => blockmap.linedefs_in_box(
+                from.0.min(to.0),
+                from.1.min(to.1),
+                from.0.max(to.0),
+                from.1.max(to.1),
+            ),
+            None => (0..self.linedefs.len() as u16).collect(),
+        };
+
+        candidates
+            .into_iter()
+            .filter_map(|index| {
+                let linedef = self.linedefs.get(index as usize)?;
+                let fraction = self.sight_crossing(linedef, from, to)?;
+                let Some((bottom, top)) = self.opening(linedef) else {
+                    return Some(fraction);
+                };
+
+                let z = from_z + (to_z - from_z) * fraction;
+                (bottom >= top || z <= bottom || z >= top).then_some(fraction)
+            })
+            .min_by(f64::total_cmp)
+    }
+
+    // Fraction along from..to where the segment properly crosses a linedef
+    fn sight_crossing(&self, linedef: &Linedef, from: (f64, f64), to: (f64, f64)) -> Option<f64> {
+        let start = self.vertices.get(linedef.start_vertex as usize)?;
+        let end = self.vertices.get(linedef.end_vertex as usize)?;
+        let (line_x, line_y) = (start.x as f64, start.y as f64);
+        let (line_dx, line_dy) = (end.x as f64 - line_x, end.y as f64 - line_y);
+        let (sight_dx, sight_dy) = (to.0 - from.0, to.1 - from.1);
+
+        let denominator = sight_dx * line_dy - sight_dy * line_dx;
+        if denominator == 0.0 {
+            return None;
+        }
+
+        let (offset_x, offset_y) = (line_x - from.0, line_y - from.1);
+        let along_sight = (offset_x * line_dy - offset_y * line_dx) / denominator;
+        let along_line = (offset_x * sight_dy - offset_y * sight_dx) / denominator;
+
+        ((0.0..=1.0).contains(&along_sight) && (0.0..=1.0).contains(&along_line))
+            .then_some(along_sight)
+    }
+
+    // Floor and ceiling of the gap through a two-sided line, None for one-sided lines
+    pub fn opening(&self, linedef: &Linedef) -> Option<(f64, f64)> {
+        if linedef.back_sidedef == 0xFFFF {
+            return None;
+        }
+
+        let sector = |side: u16| {
+            self.side_sector(side)
+                .and_then(|sector| self.sectors.get(sector))
+        };
+        let front = sector(linedef.front_sidedef)?;
+        let back = sector(linedef.back_sidedef)?;
+
+        let bottom = front.floor_height.max(back.floor_height) as f64;
+        let top = front.ceiling_height.min(back.ceiling_height) as f64;
+        Some((bottom, top))
+    }
+
+    // Sector a sidedef faces, None for a missing side (0xFFFF)
+    pub fn side_sector(&self, sidedef: u16) -> Option<usize> {
+        self.sidedefs
+            .get(sidedef as usize)
+            .map(|side| side.sector as usize)
+    }
+
+    // The start for player 1-4, None if the map has none for them
+    pub fn player_start(&self, player: u8) -> Option<&Thing> {
+        if !(1..=MAX_PLAYERS).contains(&player) {
+            return None;
+        }
+        self.things
+            .iter()
+            .find(|thing| thing.thing_type == player as u16)
+    }
+
+    pub fn deathmatch_starts(&self) -> Vec<&Thing> {
+        self.things
+            .iter()
+            .filter(|thing| thing.thing_type == DEATHMATCH_START)
+            .collect()
+    }
+
+    pub fn tagged_sectors(&self, tag: u16) -> Vec<usize> {
+        self.sectors
+            .iter()
+            .enumerate()
+            .filter(|(_, sector)| sector.tag == tag)
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    // Sectors sharing a two-sided linedef with `sector`
+    pub fn neighbor_sectors(&self, sector: usize) -> Vec<usize> {
+        let mut neighbors = Vec::new();
+        for linedef in &self.linedefs {
+            let front = self.side_sector(linedef.front_sidedef);
+            let back = self.side_sector(linedef.back_sidedef);
+            let other = match (front, back) {
+                (Some(front), Some(back)) if front == sector => back,
+                (Some(front), Some(back)) if back == sector => front,
+                _ => continue,
+            };
+            if other != sector && !neighbors.contains(&other) {
+                neighbors.push(other);
+            }
+        }
+        neighbors
+    }
+
+    pub fn lowest_neighbor_ceiling(&self, sector: usize) -> Option<i16> {
+        self.neighbor_sectors(sector)
+            .into_iter()
+            .filter_map(|neighbor| self.sectors.get(neighbor))
+            .map(|neighbor| neighbor.ceiling_height)
+            .min()
+    }
+
+    pub fn lowest_neighbor_floor(&self, sector: usize) -> Option<i16> {
+        self.neighbor_sectors(sector)
+            .into_iter()
+            .filter_map(|neighbor| self.sectors.get(neighbor))
+            .map(|neighbor| neighbor.floor_height)
+            .min()
+    }
+
+    // The sectors a noise made in `origin_sector` reaches, as Doom's noise alert spreads
+    // it: through two-sided lines with an opening, so not past closed doors, and across
+    // at most one sound-blocking line. `max_depth` limits how many lines it crosses.
+    pub fn flood_sound(&self, origin_sector: u16, max_depth: u32) -> Vec<u16> {
+        if origin_sector as usize >= self.sectors.len() {
+            return Vec::new();
+        }
+
+        // The fewest sound-blocking lines crossed to reach each sector so far. A sector
+        // reached again past fewer of them spreads the noise further than before.
+        let mut blocks_crossed = HashMap::from([(origin_sector as usize, 0)]);
+        let mut reached = vec![origin_sector];
+        let mut queue = VecDeque::from([(origin_sector as usize, 0, 0)]);
+        while let Some((sector, depth, blocks)) = queue.pop_front() {
+            if depth >= max_depth {
+                continue;
+            }
+            for linedef in &self.linedefs {
+                let front = self.side_sector(linedef.front_sidedef);
+                let back = self.side_sector(linedef.back_sidedef);
+                let other = match (front, back) {
+                    (Some(front), Some(back)) if front == sector => back,
+                    (Some(front), Some(back)) if back == sector => front,
+                    _ => continue,
+                };
+                if self
+                    .opening(linedef)
+                    .is_none_or(|(bottom, top)| top <= bottom)
+                {
+                    continue;
+                }
+                let blocks = blocks + (linedef.flags & ML_SOUNDBLOCK != 0) as u32;
+                if blocks > 1
+                    || blocks_crossed
+                        .get(&other)
+                        .is_some_and(|&seen| seen <= blocks)
+                {
+                    continue;
+                }
+                if blocks_crossed.insert(other, blocks).is_none() {
+                    reached.push(other as u16);
+                }
+                queue.push_back((other, depth + 1, blocks));
+            }
+        }
+        reached
+    }
+
+    // Where sounds made by a sector's floor or ceiling come from: the middle of the box
+    // around its lines, as in Doom
+    pub fn sound_origin(&self, sector: usize) -> Option<(f64, f64)> {
+        let vertices: Vec<Vertex> = self
+            .linedefs
+            .iter()
+            .filter(|linedef| {
+                self.side_sector(linedef.front_sidedef) == Some(sector)
+                    || self.side_sector(linedef.back_sidedef) == Some(sector)
+            })
+            .flat_map(|linedef| [linedef.start_vertex, linedef.end_vertex])
+            .filter_map(|vertex| self.vertices.get(vertex as usize).cloned())
+            .collect();
+        if vertices.is_empty() {
+            return None;
+        }
+        let (min_x, min_y, max_x, max_y) = vertex_bounds(&vertices);
+        Some((
+            (min_x as f64 + max_x as f64) / 2.0,
+            (min_y as f64 + max_y as f64) / 2.0,
+        ))
+    }
+
+    // The darkest light level among a sector and its neighbours
+    pub fn min_neighbor_light(&self, sector: usize) -> Option<i16> {
+        let own = self.sectors.get(sector)?.light_level;
+        let neighbors = self.neighbor_sectors(sector).into_iter();
+        neighbors
+            .filter_map(|neighbor| self.sectors.get(neighbor))
+            .map(|neighbor| neighbor.light_level)
+            .chain([own])
+            .min()
+    }
+
+    pub fn highest_neighbor_floor(&self, sector: usize) -> Option<i16> {
+        self.neighbor_sectors(sector)
+            .into_iter()
+            .filter_map(|neighbor| self.sectors.get(neighbor))
+            .map(|neighbor| neighbor.floor_height)
+            .max()
+    }
+
+    // The lowest neighbouring floor above `height`
+    pub fn next_higher_neighbor_floor(&self, sector: usize, height: i16) -> Option<i16> {
+        self.neighbor_sectors(sector)
+            .into_iter()
+            .filter_map(|neighbor| self.sectors.get(neighbor))
+            .map(|neighbor| neighbor.floor_height)
+            .filter(|&floor| floor > height)
+            .min()
+    }
+
+    fn parse_vertices(data: &[u8]) -> Result<Vec<Vertex>, MapError> {
         let mut cursor = Cursor::new(data);
         let mut vertices = Vec::new();
 
@@ -89,7 +656,7 @@ impl Map {
         Ok(vertices)
     }
 
-    fn parse_linedefs(data: &[u8]) -> Result<Vec<Linedef>, Box<dyn std::error::Error>> {
+    fn parse_linedefs(data: &[u8]) -> Result<Vec<Linedef>, MapError> {
         let mut cursor = Cursor::new(data);
         let mut linedefs = Vec::new();
 
@@ -108,6 +675,37 @@ impl Map {
                 flags,
                 special_type,
                 sector_tag,
+                args: [0; 5],
+                front_sidedef,
+                back_sidedef,
+            });
+        }
+
+        Ok(linedefs)
+    }
+
+    // 16 byte Hexen linedefs swap the tag for a one byte special and five arguments
+    pub fn parse_hexen_linedefs(data: &[u8]) -> Result<Vec<Linedef>, MapError> {
+        let mut cursor = Cursor::new(data);
+        let mut linedefs = Vec::new();
+
+        while cursor.position() < data.len() as u64 {
+            let start_vertex = cursor.read_u16::<LittleEndian>()?;
+            let end_vertex = cursor.read_u16::<LittleEndian>()?;
+            let flags = cursor.read_u16::<LittleEndian>()?;
+            let special_type = cursor.read_u8()? as u16;
+            let mut args = [0u8; 5];
+            cursor.read_exact(&mut args)?;
+            let front_sidedef = cursor.read_u16::<LittleEndian>()?;
+            let back_sidedef = cursor.read_u16::<LittleEndian>()?;
+
+            linedefs.push(Linedef {
+                start_vertex,
+                end_vertex,
+                flags,
+                special_type,
+                sector_tag: 0,
+                args,
                 front_sidedef,
                 back_sidedef,
             });
@@ -118,7 +716,7 @@ impl Map {
 
     // Similar parsing functions for sidedefs, sectors, and things...
 
-    fn parse_sidedefs(data: &[u8]) -> Result<Vec<Sidedef>, Box<dyn std::error::Error>> {
+    fn parse_sidedefs(data: &[u8]) -> Result<Vec<Sidedef>, MapError> {
         let mut cursor = Cursor::new(data);
         let mut sidedefs = Vec::new();
 
@@ -156,7 +754,7 @@ impl Map {
         Ok(sidedefs)
     }
 
-    fn parse_sectors(data: &[u8]) -> Result<Vec<Sector>, Box<dyn std::error::Error>> {
+    fn parse_sectors(data: &[u8]) -> Result<Vec<Sector>, MapError> {
         let mut cursor = Cursor::new(data);
         let mut sectors = Vec::new();
 
@@ -191,7 +789,7 @@ impl Map {
         Ok(sectors)
     }
 
-    fn parse_things(data: &[u8]) -> Result<Vec<Thing>, Box<dyn std::error::Error>> {
+    fn parse_things(data: &[u8]) -> Result<Vec<Thing>, MapError> {
         let mut cursor = Cursor::new(data);
         let mut things = Vec::new();
 
@@ -203,23 +801,897 @@ impl Map {
             let flags = cursor.read_u16::<LittleEndian>()?;
 
             things.push(Thing {
+                tid: 0,
                 x,
                 y,
+                z: 0,
                 angle,
                 thing_type,
                 flags,
+                special: 0,
+                args: [0; 5],
             });
         }
 
         Ok(things)
     }
+
+    // 20 byte Hexen things, with flags translated to their Doom equivalents
+    pub fn parse_hexen_things(data: &[u8]) -> Result<Vec<Thing>, MapError> {
+        let mut cursor = Cursor::new(data);
+        let mut things = Vec::new();
+
+        while cursor.position() < data.len() as u64 {
+            let tid = cursor.read_u16::<LittleEndian>()?;
+            let x = cursor.read_i16::<LittleEndian>()?;
+            let y = cursor.read_i16::<LittleEndian>()?;
+            let z = cursor.read_i16::<LittleEndian>()?;
+            let angle = cursor.read_u16::<LittleEndian>()?;
+            let thing_type = cursor.read_u16::<LittleEndian>()?;
+            let hexen_flags = cursor.read_u16::<LittleEndian>()?;
+            let special = cursor.read_u8()?;
+            let mut args = [0u8; 5];
+            cursor.read_exact(&mut args)?;
+
+            // Skill and ambush bits are shared. Hexen marks the modes a thing appears
+            // in, so one that's left out of single player is multiplayer-only.
+            let mut flags = hexen_flags & (MTF_EASY | MTF_NORMAL | MTF_HARD | MTF_AMBUSH);
+            if hexen_flags & HEXEN_MTF_SINGLE == 0 {
+                flags |= MTF_MULTIPLAYER;
+            }
+
+            things.push(Thing {
+                tid,
+                x,
+                y,
+                z,
+                angle,
+                thing_type,
+                flags,
+                special,
+                args,
+            });
+        }
+
+        Ok(things)
+    }
+}
+
+// UDMF values, with integers and floats both read as numbers
+#[derive(Debug, Clone, PartialEq)]
+enum UdmfValue {
+    Number(f64),
+    String(String),
+    Bool(bool),
 }
 
+// One `kind { field = value; ... }` block, with names lowercased as UDMF ignores case
+struct UdmfBlock {
+    kind: String,
+    fields: HashMap<String, UdmfValue>,
+}
+
+impl UdmfBlock {
+    fn number(&self, name: &str, default: f64) -> f64 {
+        match self.fields.get(name) {
+            Some(UdmfValue::Number(value)) => *value,
+            _ => default,
+        }
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        matches!(self.fields.get(name), Some(UdmfValue::Bool(true)))
+    }
+
+    // Textures left unset are "-", meaning no texture
+    fn texture(&self, name: &str) -> String {
+        match self.fields.get(name) {
+            Some(UdmfValue::String(texture)) => texture.to_ascii_uppercase(),
+            _ => "-".to_string(),
+        }
+    }
+
+    // A required reference to another block by its position in the map
+    fn index(&self, name: &str) -> Result<u16, MapError> {
+        match self.fields.get(name) {
+            Some(UdmfValue::Number(value)) if *value >= 0.0 => Ok(*value as u16),
+            _ => Err(MapError::Parse(format!(
+                "UDMF {} is missing its {} field",
+                self.kind, name
+            ))),
+        }
+    }
+
+    fn args(&self) -> [u8; 5] {
+        std::array::from_fn(|i| self.number(&format!("arg{}", i), 0.0) as u8)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum UdmfToken {
+    Identifier(String),
+    Value(UdmfValue),
+    Symbol(char),
+}
+
+// The extent of a set of vertices as (min_x, min_y, max_x, max_y), all 0 when empty
+pub fn vertex_bounds(vertices: &[Vertex]) -> (i16, i16, i16, i16) {
+    let Some(first) = vertices.first() else {
+        return (0, 0, 0, 0);
+    };
+    vertices.iter().fold(
+        (first.x, first.y, first.x, first.y),
+        |(min_x, min_y, max_x, max_y), vertex| {
+            (
+                min_x.min(vertex.x),
+                min_y.min(vertex.y),
+                max_x.max(vertex.x),
+                max_y.max(vertex.y),
+            )
+        },
+    )
+}
+
+fn tokenize_udmf(text: &str) -> Result<Vec<(UdmfToken, usize)>, MapError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            '\n' => {
+                line += 1;
+                chars.next();
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '/' => {
+                chars.next();
+                match chars.next() {
+                    Some('/') => while chars.next_if(|&c| c != '\n').is_some() {},
+                    Some('*') => {
+                        let mut previous = ' ';
+                        loop {
+                            let c = chars.next().ok_or_else(|| {
+                                MapError::Parse("UDMF comment is never closed".to_string())
+                            })?;
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            if previous == '*' && c == '/' {
+                                break;
+                            }
+                            previous = c;
+                        }
+                    }
+                    _ => {
+                        return Err(MapError::Parse(format!(
+                            "UDMF line {}: unexpected '/'",
+                            line
+                        )));
+                    }
+                }
+            }
+            '"' => {
+                chars.next();
+                let mut string = String::new();
+                loop {
+                    match chars
+                        .next()
+                        .ok_or_else(|| MapError::Parse("UDMF string is never closed".to_string()))?
+                    {
+                        '"' => break,
+                        '\\' => string.extend(chars.next()),
+                        c => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            string.push(c);
+                        }
+                    }
+                }
+                tokens.push((UdmfToken::Value(UdmfValue::String(string)), line));
+            }
+            '{' | '}' | '=' | ';' => {
+                chars.next();
+                tokens.push((UdmfToken::Symbol(c), line));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut word = String::new();
+                while let Some(c) = chars.next_if(|&c| c.is_ascii_alphanumeric() || c == '_') {
+                    word.push(c);
+                }
+                let word = word.to_ascii_lowercase();
+                let token = match word.as_str() {
+                    "true" => UdmfToken::Value(UdmfValue::Bool(true)),
+                    "false" => UdmfToken::Value(UdmfValue::Bool(false)),
+                    _ => UdmfToken::Identifier(word),
+                };
+                tokens.push((token, line));
+            }
+            c if c.is_ascii_digit() || matches!(c, '+' | '-' | '.') => {
+                let mut number = String::new();
+                while let Some(c) =
+                    chars.next_if(|&c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+                {
+                    number.push(c);
+                }
+                let value = parse_udmf_number(&number).ok_or_else(|| {
+                    MapError::Parse(format!("UDMF line {}: invalid number \"{}\"", line, number))
+                })?;
+                tokens.push((UdmfToken::Value(UdmfValue::Number(value)), line));
+            }
+            c => {
+                return Err(MapError::Parse(format!(
+                    "UDMF line {}: unexpected '{}'",
+                    line, c
+                )));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+// Decimal, hexadecimal (0x...) or floating point
+fn parse_udmf_number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let value = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()? as f64,
+        None => digits.parse::<f64>().ok()?,
+    };
+    Some(if negative { -value } else { value })
+}
+
+// Splits a TEXTMAP into its namespace and blocks. Top-level assignments other than
+// the namespace are ignored.
+fn parse_udmf_blocks(text: &str) -> Result<(String, Vec<UdmfBlock>), MapError> {
+    let tokens = tokenize_udmf(text)?;
+    let mut tokens = tokens.into_iter().peekable();
+    let mut namespace = String::new();
+    let mut blocks = Vec::new();
+
+    let unexpected = |token: Option<(UdmfToken, usize)>, expected: &str| -> MapError {
+        match token {
+            Some((token, line)) => MapError::Parse(format!(
+                "UDMF line {}: expected {}, found {:?}",
+                line, expected, token
+            )),
+            None => MapError::Parse(format!("UDMF ended early, expected {}", expected)),
+        }
+    };
+
+    while let Some((token, line)) = tokens.next() {
+        let UdmfToken::Identifier(name) = token else {
+            return Err(unexpected(Some((token, line)), "a block or assignment"));
+        };
+
+        match tokens.next() {
+            Some((UdmfToken::Symbol('='), _)) => {
+                let value = match tokens.next() {
+                    Some((UdmfToken::Value(value), _)) => value,
+                    token => return Err(unexpected(token, "a value")),
+                };
+                match tokens.next() {
+                    Some((UdmfToken::Symbol(';'), _)) => {}
+                    token => return Err(unexpected(token, "';'")),
+                }
+                if let (true, UdmfValue::String(value)) = (name == "namespace", value) {
+                    namespace = value;
+                }
+            }
+            Some((UdmfToken::Symbol('{'), _)) => {
+                let mut fields = HashMap::new();
+                loop {
+                    let field = match tokens.next() {
+                        Some((UdmfToken::Symbol('}'), _)) => break,
+                        Some((UdmfToken::Identifier(field), _)) => field,
+                        token => return Err(unexpected(token, "a field or '}'")),
+                    };
+                    match tokens.next() {
+                        Some((UdmfToken::Symbol('='), _)) => {}
+                        token => return Err(unexpected(token, "'='")),
+                    }
+                    let value = match tokens.next() {
+                        Some((UdmfToken::Value(value), _)) => value,
+                        token => return Err(unexpected(token, "a value")),
+                    };
+                    match tokens.next() {
+                        Some((UdmfToken::Symbol(';'), _)) => {}
+                        token => return Err(unexpected(token, "';'")),
+                    }
+                    fields.insert(field, value);
+                }
+                blocks.push(UdmfBlock { kind: name, fields });
+            }
+            token => return Err(unexpected(token, "'=' or '{'")),
+        }
+    }
+
+    Ok((namespace, blocks))
+}
+
+// tid, z, special and args are only set in Hexen and UDMF maps. z is the height
+// above the floor the thing starts at.
 #[derive(Debug, Clone)]
 pub struct Thing {
+    pub tid: u16,
     pub x: i16,
     pub y: i16,
+    pub z: i16,
     pub angle: u16,
     pub thing_type: u16,
     pub flags: u16,
+    pub special: u8,
+    pub args: [u8; 5],
+}
+
+// Thing flag bits
+pub const MTF_EASY: u16 = 0x0001;
+pub const MTF_NORMAL: u16 = 0x0002;
+pub const MTF_HARD: u16 = 0x0004;
+pub const MTF_AMBUSH: u16 = 0x0008;
+pub const MTF_MULTIPLAYER: u16 = 0x0010;
+// Hexen's flag for things present in single player
+const HEXEN_MTF_SINGLE: u16 = 0x0100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkillLevel {
+    ImTooYoungToDie,
+    HeyNotTooRough,
+    HurtMePlenty,
+    UltraViolence,
+    Nightmare,
+}
+
+impl SkillLevel {
+    // The thing flag bit that marks a thing as present on this skill
+    pub fn thing_flag(self) -> u16 {
+        match self {
+            SkillLevel::ImTooYoungToDie | SkillLevel::HeyNotTooRough => MTF_EASY,
+            SkillLevel::HurtMePlenty => MTF_NORMAL,
+            SkillLevel::UltraViolence | SkillLevel::Nightmare => MTF_HARD,
+        }
+    }
+}
+
+impl Thing {
+    // Thing angles are stored in whole degrees, unlike the binary angles used by segs
+    pub fn angle_radians(&self) -> f32 {
+        (self.angle as f32).to_radians()
+    }
+
+    pub fn appears_on(&self, skill: SkillLevel, multiplayer: bool) -> bool {
+        if self.flags & MTF_MULTIPLAYER != 0 && !multiplayer {
+            return false;
+        }
+        self.flags & skill.thing_flag() != 0
+    }
+
+    // Ambush ("deaf") monsters wait for sight instead of waking on noise
+    pub fn is_ambush(&self) -> bool {
+        self.flags & MTF_AMBUSH != 0
+    }
+}
+
+pub const BLOCK_SIZE: i32 = 128;
+
+// Grid of 128x128 unit blocks, each listing the linedefs that touch it
+#[derive(Debug, Clone)]
+pub struct BlockMap {
+    pub origin_x: i16,
+    pub origin_y: i16,
+    pub columns: u16,
+    pub rows: u16,
+    pub blocks: Vec<Vec<u16>>,
+}
+
+impl BlockMap {
+    pub fn parse(data: &[u8]) -> Result<Self, MapError> {
+        let mut cursor = Cursor::new(data);
+
+        let origin_x = cursor.read_i16::<LittleEndian>()?;
+        let origin_y = cursor.read_i16::<LittleEndian>()?;
+        let columns = cursor.read_u16::<LittleEndian>()?;
+        let rows = cursor.read_u16::<LittleEndian>()?;
+
+        let block_count = columns as usize * rows as usize;
+        let mut offsets = Vec::with_capacity(block_count);
+        for _ in 0..block_count {
+            offsets.push(cursor.read_u16::<LittleEndian>()?);
+        }
+
+        let mut blocks = Vec::with_capacity(block_count);
+        for offset in offsets {
+            // Offsets are in 16-bit words from the start of the lump
+            cursor.set_position(offset as u64 * 2);
+
+            // Each blocklist starts with a dummy 0 and ends with 0xFFFF
+            let mut linedefs = Vec::new();
+            let first = cursor.read_u16::<LittleEndian>()?;
+            if first != 0 && first != 0xFFFF {
+                linedefs.push(first);
+            }
+            if first != 0xFFFF {
+                loop {
+                    let linedef = cursor.read_u16::<LittleEndian>()?;
+                    if linedef == 0xFFFF {
+                        break;
+                    }
+                    linedefs.push(linedef);
+                }
+            }
+
+            blocks.push(linedefs);
+        }
+
+        Ok(BlockMap {
+            origin_x,
+            origin_y,
+            columns,
+            rows,
+            blocks,
+        })
+    }
+
+    pub fn block_at(&self, x: f64, y: f64) -> Option<(usize, usize)> {
+        let column = ((x - self.origin_x as f64) / BLOCK_SIZE as f64).floor();
+        let row = ((y - self.origin_y as f64) / BLOCK_SIZE as f64).floor();
+
+        if column < 0.0 || row < 0.0 || column >= self.columns as f64 || row >= self.rows as f64 {
+            return None;
+        }
+
+        Some((column as usize, row as usize))
+    }
+
+    pub fn linedefs_near(&self, x: f64, y: f64) -> &[u16] {
+        match self.block_at(x, y) {
+            Some((column, row)) => &self.blocks[row * self.columns as usize + column],
+            None => &[],
+        }
+    }
+
+    // Every linedef in the blocks overlapping a box, without duplicates
+    pub fn linedefs_in_box(&self, min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Vec<u16> {
+        let to_column = |x: f64| ((x - self.origin_x as f64) / BLOCK_SIZE as f64).floor() as i64;
+        let to_row = |y: f64| ((y - self.origin_y as f64) / BLOCK_SIZE as f64).floor() as i64;
+
+        let first_column = to_column(min_x).max(0);
+        let last_column = to_column(max_x).min(self.columns as i64 - 1);
+        let first_row = to_row(min_y).max(0);
+        let last_row = to_row(max_y).min(self.rows as i64 - 1);
+
+        let mut linedefs = Vec::new();
+        for row in first_row..=last_row {
+            for column in first_column..=last_column {
+                let block = &self.blocks[row as usize * self.columns as usize + column as usize];
+                for &linedef in block {
+                    if !linedefs.contains(&linedef) {
+                        linedefs.push(linedef);
+                    }
+                }
+            }
+        }
+
+        linedefs
+    }
+}
+
+// Sector-to-sector visibility matrix, one bit per (from, to) pair
+#[derive(Debug, Clone)]
+pub struct Reject {
+    pub sector_count: usize,
+    pub data: Vec<u8>,
+}
+
+impl Reject {
+    pub fn parse(data: &[u8], sector_count: usize) -> Self {
+        let expected_len = (sector_count * sector_count).div_ceil(8);
+
+        // A short or empty table can't be trusted, so treat everything as visible
+        let data = if data.len() < expected_len || data.iter().all(|&byte| byte == 0) {
+            Vec::new()
+        } else {
+            data[..expected_len].to_vec()
+        };
+
+        Reject { sector_count, data }
+    }
+
+    pub fn can_see(&self, sector_a: u16, sector_b: u16) -> bool {
+        let (a, b) = (sector_a as usize, sector_b as usize);
+        if self.data.is_empty() || a >= self.sector_count || b >= self.sector_count {
+            return true;
+        }
+
+        // Row-major: bit (a * sector_count + b), least significant bit first
+        let bit = a * self.sector_count + b;
+        self.data[bit / 8] & (1 << (bit % 8)) == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doom1() -> WadFile {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../game/Doom1.WAD");
+        WadFile::from_bytes(&std::fs::read(path).unwrap()).unwrap()
+    }
+
+    fn lump(name: &str, data: Vec<u8>) -> WadLump {
+        WadLump {
+            name: name.to_string(),
+            data,
+        }
+    }
+
+    #[test]
+    fn missing_lump_is_an_error() {
+        let wad = WadFile {
+            lumps: vec![
+                lump("E1M1", Vec::new()),
+                lump("THINGS", Vec::new()),
+                lump("LINEDEFS", Vec::new()),
+                lump("VERTEXES", Vec::new()),
+                lump("SECTORS", Vec::new()),
+            ],
+        };
+
+        match Map::load_from_wad(&wad, "E1M1") {
+            Err(MapError::MissingLump { map, lump }) => {
+                assert_eq!(map, "E1M1");
+                assert_eq!(lump, "SIDEDEFS");
+            }
+            other => panic!("expected a missing SIDEDEFS lump, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn blockmap_lists_linedefs_near_the_e1m1_start() {
+        let map = Map::load_from_wad(&doom1(), "E1M1").unwrap();
+        let blockmap = map.blockmap.unwrap();
+
+        // Block (14, 9) holds the player 1 start at (1056, -3616). Its blocklist is the
+        // dummy 0 followed by linedef 0 and the rest.
+        assert_eq!(blockmap.block_at(1056.0, -3616.0), Some((14, 9)));
+        assert_eq!(
+            blockmap.linedefs_near(1056.0, -3616.0),
+            &[0, 1, 2, 3, 4, 49]
+        );
+        assert!(blockmap.linedefs_near(-10000.0, 0.0).is_empty());
+    }
+
+    #[test]
+    fn reject_blocks_only_flagged_pairs() {
+        // Three sectors, so bits 0-8 in rows of three. Sector 0 can't see sector 2
+        // (bit 2) and sector 2 can't see sector 1 (bit 7).
+        let reject = Reject::parse(&[0b1000_0100, 0], 3);
+
+        assert!(!reject.can_see(0, 2));
+        assert!(!reject.can_see(2, 1));
+        assert!(reject.can_see(2, 0));
+        assert!(reject.can_see(1, 1));
+        assert!(reject.can_see(0, 5));
+    }
+
+    #[test]
+    fn short_or_empty_reject_sees_everything() {
+        assert!(Reject::parse(&[0xFF], 3).can_see(0, 2));
+        assert!(Reject::parse(&[0, 0], 3).can_see(0, 2));
+    }
+
+    fn thing_with_flags(flags: u16) -> Thing {
+        Thing {
+            tid: 0,
+            x: 0,
+            y: 0,
+            z: 0,
+            angle: 0,
+            thing_type: 3001,
+            flags,
+            special: 0,
+            args: [0; 5],
+        }
+    }
+
+    #[test]
+    fn things_appear_only_on_their_skills() {
+        use SkillLevel::*;
+        let skills = [
+            ImTooYoungToDie,
+            HeyNotTooRough,
+            HurtMePlenty,
+            UltraViolence,
+            Nightmare,
+        ];
+        let cases = [
+            (MTF_EASY, [true, true, false, false, false]),
+            (MTF_NORMAL, [false, false, true, false, false]),
+            (MTF_HARD, [false, false, false, true, true]),
+            (0, [false; 5]),
+        ];
+        for (flags, expected) in cases {
+            let thing = thing_with_flags(flags);
+            for (skill, appears) in skills.iter().zip(expected) {
+                assert_eq!(thing.appears_on(*skill, false), appears, "{skill:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn multiplayer_things_are_left_out_of_single_player() {
+        let thing = thing_with_flags(MTF_NORMAL | MTF_MULTIPLAYER);
+        assert!(!thing.appears_on(SkillLevel::HurtMePlenty, false));
+        assert!(thing.appears_on(SkillLevel::HurtMePlenty, true));
+        assert!(!thing.appears_on(SkillLevel::Nightmare, true));
+    }
+
+    #[test]
+    fn ambush_flag_marks_deaf_monsters() {
+        assert!(thing_with_flags(MTF_NORMAL | MTF_AMBUSH).is_ambush());
+        assert!(!thing_with_flags(MTF_NORMAL).is_ambush());
+    }
+
+    // Two 256 unit square rooms side by side, the west one sector 0 and the east one
+    // sector 1, split at x = 256 by `divider` linedef blocks
+    fn two_rooms(divider: &str, east_ceiling: i16) -> Map {
+        let mut text = String::from("namespace = \"doom\";\n");
+        for (x, y) in [(0, 0), (0, 256), (256, 256), (256, 0), (512, 256), (512, 0)] {
+            text += &format!("vertex {{ x = {}; y = {}; }}\n", x, y);
+        }
+        text += "sidedef { sector = 0; }\nsidedef { sector = 1; }\n";
+        // Clockwise round each room, so the front sides face in
+        for (v1, v2, side) in [
+            (0, 1, 0),
+            (1, 2, 0),
+            (3, 0, 0),
+            (2, 4, 1),
+            (4, 5, 1),
+            (5, 3, 1),
+        ] {
+            text += &format!(
+                "linedef {{ v1 = {}; v2 = {}; sidefront = {}; blocking = true; }}\n",
+                v1, v2, side
+            );
+        }
+        text += divider;
+        text += "sector { heightfloor = 0; heightceiling = 128; }\n";
+        text += &format!(
+            "sector {{ heightfloor = 0; heightceiling = {}; }}\n",
+            east_ceiling
+        );
+        Map::parse_udmf(&text).unwrap()
+    }
+
+    const DOORWAY: &str =
+        "linedef { v1 = 2; v2 = 3; sidefront = 0; sideback = 1; twosided = true; }\n";
+
+    #[test]
+    fn solid_wall_blocks_sight() {
+        let wall = "linedef { v1 = 2; v2 = 3; sidefront = 0; blocking = true; }\n\
+            linedef { v1 = 3; v2 = 2; sidefront = 1; blocking = true; }\n";
+        let map = two_rooms(wall, 128);
+        assert!(!map.line_of_sight((128.0, 128.0), (384.0, 128.0)));
+        assert!(!map.line_of_sight((384.0, 128.0), (128.0, 128.0)));
+    }
+
+    #[test]
+    fn open_doorway_allows_sight() {
+        let map = two_rooms(DOORWAY, 128);
+        assert_eq!(map.sector_at(128.0, 128.0), Some(0));
+        assert_eq!(map.sector_at(384.0, 128.0), Some(1));
+        assert!(map.line_of_sight((128.0, 128.0), (384.0, 128.0)));
+    }
+
+    #[test]
+    fn closed_door_blocks_sight() {
+        let map = two_rooms(DOORWAY, 0);
+        assert!(!map.line_of_sight((128.0, 128.0), (384.0, 128.0)));
+    }
+
+    #[test]
+    fn points_in_one_sector_always_see_each_other() {
+        let map = two_rooms(DOORWAY, 0);
+        assert!(map.line_of_sight((10.0, 10.0), (250.0, 250.0)));
+    }
+
+    #[test]
+    fn sector_at_finds_each_room_and_the_void() {
+        let map = two_rooms(DOORWAY, 128);
+        assert_eq!(map.sector_at(64.0, 200.0), Some(0));
+        assert_eq!(map.sector_at(450.0, 30.0), Some(1));
+        assert_eq!(map.sector_at(-64.0, 128.0), None);
+        assert_eq!(map.sector_at(600.0, 128.0), None);
+        assert_eq!(map.sector_at(128.0, 300.0), None);
+    }
+
+    #[test]
+    fn each_map_reads_its_own_things_lump() {
+        let wad = doom1();
+        let mut counts = Vec::new();
+        for name in ["E1M1", "E1M2"] {
+            let marker = wad.lumps.iter().position(|lump| lump.name == name).unwrap();
+            let things = wad.find_lump_after("THINGS", marker).unwrap();
+            let map = Map::load_from_wad(&wad, name).unwrap();
+            assert_eq!(map.things.len(), things.data.len() / 10, "{}", name);
+            counts.push(map.things.len());
+        }
+        assert_ne!(counts[0], counts[1]);
+    }
+
+    #[test]
+    fn hexen_linedef_keeps_its_special_and_args() {
+        // Vertex 0 to 1, two-sided, special 80 and its args, then the sidedefs
+        let mut data = Vec::new();
+        for field in [0u16, 1, 0x0004] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        data.extend_from_slice(&[80, 1, 2, 3, 4, 5]);
+        for field in [0u16, 0xFFFF] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+
+        let linedefs = Map::parse_hexen_linedefs(&data).unwrap();
+        assert_eq!(linedefs.len(), 1);
+        let line = &linedefs[0];
+        assert_eq!(
+            (line.start_vertex, line.end_vertex, line.flags),
+            (0, 1, 0x0004)
+        );
+        assert_eq!((line.special_type, line.sector_tag), (80, 0));
+        assert_eq!(line.args, [1, 2, 3, 4, 5]);
+        assert_eq!((line.front_sidedef, line.back_sidedef), (0, 0xFFFF));
+    }
+
+    #[test]
+    fn hexen_thing_keeps_its_tid_height_and_special() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&7u16.to_le_bytes());
+        for field in [-64i16, 128, 24] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        for field in [90u16, 3001, MTF_HARD | HEXEN_MTF_SINGLE] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        data.extend_from_slice(&[72, 9, 8, 7, 6, 5]);
+
+        let things = Map::parse_hexen_things(&data).unwrap();
+        assert_eq!(things.len(), 1);
+        let thing = &things[0];
+        assert_eq!((thing.tid, thing.x, thing.y, thing.z), (7, -64, 128, 24));
+        assert_eq!((thing.angle, thing.thing_type), (90, 3001));
+        assert_eq!((thing.special, thing.args), (72, [9, 8, 7, 6, 5]));
+        assert_eq!(thing.flags, MTF_HARD);
+
+        // Left out of single player, so only in multiplayer
+        data[12..14].copy_from_slice(&MTF_HARD.to_le_bytes());
+        let things = Map::parse_hexen_things(&data).unwrap();
+        assert_eq!(things[0].flags, MTF_HARD | MTF_MULTIPLAYER);
+    }
+
+    #[test]
+    fn minimal_udmf_map_parses() {
+        let text = "// A comment\n\
+            namespace = \"zdoom\";\n\
+            vertex { x = 0.0; y = 0.0; }\n\
+            vertex { x = 64.6; y = -32; }\n\
+            sidedef { sector = 0; texturemiddle = \"STARTAN3\"; user_colour = 3; }\n\
+            linedef { v1 = 0; v2 = 1; sidefront = 0; blocking = true; special = 12; arg0 = 5; }\n\
+            sector { heightfloor = 8; heightceiling = 136; texturefloor = \"FLOOR4_8\"; lightlevel = 192; }\n\
+            thing { x = 16; y = 24; angle = 90; type = 1; id = 3; height = 32; skill3 = true; single = true; }\n\
+            unknownblock { anything = 1; }\n";
+        let map = Map::parse_udmf(text).unwrap();
+
+        assert_eq!(
+            map.format,
+            MapFormat::Udmf {
+                doom_specials: false
+            }
+        );
+        assert_eq!(map.vertices.len(), 2);
+        assert_eq!((map.vertices[1].x, map.vertices[1].y), (65, -32));
+        assert_eq!(map.sectors.len(), 1);
+        let sector = &map.sectors[0];
+        assert_eq!((sector.floor_height, sector.ceiling_height), (8, 136));
+        assert_eq!(
+            (sector.floor_texture.as_str(), sector.light_level),
+            ("FLOOR4_8", 192)
+        );
+        assert_eq!(map.sidedefs[0].middle_texture, "STARTAN3");
+
+        let line = &map.linedefs[0];
+        assert_eq!((line.start_vertex, line.end_vertex), (0, 1));
+        assert_eq!((line.special_type, line.args[0]), (12, 5));
+        assert_eq!(line.back_sidedef, 0xFFFF);
+
+        let thing = &map.things[0];
+        assert_eq!((thing.tid, thing.x, thing.y, thing.z), (3, 16, 24, 32));
+        assert_eq!((thing.thing_type, thing.flags), (1, MTF_NORMAL));
+    }
+
+    #[test]
+    fn bounds_and_center_come_from_the_outermost_vertices() {
+        let mut text = String::from("namespace = \"doom\";\n");
+        for (x, y) in [(-128, 64), (300, -40), (96, 512), (0, 0)] {
+            text += &format!("vertex {{ x = {}; y = {}; }}\n", x, y);
+        }
+        let map = Map::parse_udmf(&text).unwrap();
+
+        assert_eq!(map.bounds(), (-128, -40, 300, 512));
+        assert_eq!(map.center(), (86.0, 236.0));
+        assert_eq!(two_rooms("", 128).bounds(), (0, 0, 512, 256));
+    }
+
+    #[test]
+    fn missing_map_is_reported_by_name() {
+        let wad = doom1();
+        match Map::load_from_wad(&wad, "E9M9") {
+            Err(MapError::MapNotFound(name)) => assert_eq!(name, "E9M9"),
+            other => panic!("expected MapNotFound, got {:?}", other.map(|_| ())),
+        }
+
+        // A map with only some of its lumps is missing the rest rather than not found
+        let partial = WadFile {
+            lumps: ["E1M1", "THINGS"]
+                .iter()
+                .map(|name| WadLump {
+                    name: name.to_string(),
+                    data: Vec::new(),
+                })
+                .collect(),
+        };
+        assert!(matches!(
+            Map::load_from_wad(&partial, "E1M1"),
+            Err(MapError::MissingLump { .. })
+        ));
+    }
+
+    #[test]
+    fn sound_floods_through_an_open_doorway_but_not_a_closed_door() {
+        assert_eq!(two_rooms(DOORWAY, 128).flood_sound(0, 8), [0, 1]);
+        // A door shut to the floor leaves no opening to cross
+        assert_eq!(two_rooms(DOORWAY, 0).flood_sound(0, 8), [0]);
+        // Noise spreads across one sound-blocking line, but no further than max_depth
+        let muffled = DOORWAY.replace("twosided = true;", "twosided = true; blocksound = true;");
+        assert_eq!(two_rooms(&muffled, 128).flood_sound(1, 8), [1, 0]);
+        assert_eq!(two_rooms(DOORWAY, 128).flood_sound(0, 0), [0]);
+    }
+
+    #[test]
+    fn starts_are_found_by_player_and_for_deathmatch() {
+        let start = |thing_type, x| Thing {
+            thing_type,
+            x,
+            ..thing_with_flags(0)
+        };
+        let mut map = two_rooms(DOORWAY, 128);
+        map.things = vec![
+            start(11, 300),
+            start(2, 200),
+            start(3001, 50),
+            start(1, 100),
+            start(11, 400),
+        ];
+
+        assert_eq!(map.player_start(1).map(|thing| thing.x), Some(100));
+        assert_eq!(map.player_start(2).map(|thing| thing.x), Some(200));
+        assert!(map.player_start(3).is_none());
+        assert!(map.player_start(0).is_none());
+        assert!(map.player_start(5).is_none());
+        let deathmatch: Vec<i16> = map
+            .deathmatch_starts()
+            .iter()
+            .map(|thing| thing.x)
+            .collect();
+        assert_eq!(deathmatch, [300, 400]);
+    }
 }
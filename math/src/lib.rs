@@ -1,5 +1,46 @@
+use std::f32::consts::TAU;
 use std::fmt;
-use std::ops::{Add, Mul, Sub};
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+// Binary angles map a full turn onto 0..65536, with 0 facing east and
+// values increasing counterclockwise
+pub fn bam_to_radians(bam: u16) -> f32 {
+    bam as f32 / 65536.0 * TAU
+}
+
+pub fn radians_to_bam(rad: f32) -> u16 {
+    let turns = normalize_angle(rad) / TAU;
+    ((turns * 65536.0).round() as u32 & 0xFFFF) as u16
+}
+
+// Wraps an angle into 0..2pi. rem_euclid can round up to a full turn for tiny
+// negative inputs, which is folded back to 0.
+pub fn normalize_angle(rad: f32) -> f32 {
+    let wrapped = rad.rem_euclid(TAU);
+    if wrapped >= TAU { 0.0 } else { wrapped }
+}
+
+pub fn normalize_angle_f64(rad: f64) -> f64 {
+    let wrapped = rad.rem_euclid(std::f64::consts::TAU);
+    if wrapped >= std::f64::consts::TAU {
+        0.0
+    } else {
+        wrapped
+    }
+}
+
+// Linear interpolation giving a at t = 0 and b at t = 1
+pub fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+// Interpolates between two angles the short way round, so halfway from 350
+// to 10 degrees is 0 rather than 180
+pub fn lerp_angle(a: f64, b: f64, t: f64) -> f64 {
+    use std::f64::consts::{PI, TAU};
+    let difference = (b - a + PI).rem_euclid(TAU) - PI;
+    normalize_angle_f64(a + difference * t)
+}
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Point2D {
@@ -46,6 +87,10 @@ impl Point2D {
             y: self.x * sin + self.y * cos,
         }
     }
+
+    pub fn to_vec3(&self, z: f32) -> Vec3 {
+        Vec3::new(self.x, self.y, z)
+    }
 }
 
 impl Add for Point2D {
@@ -83,3 +128,474 @@ impl fmt::Display for Point2D {
         write!(f, "({:.2}, {:.2})", self.x, self.y)
     }
 }
+
+// A point or direction in the world, with z as height above the map's floor plane
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vec3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Vec3 {
+    pub fn new(x: f32, y: f32, z: f32) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn origin() -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    }
+
+    pub fn length(&self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn distance_to(&self, other: &Self) -> f32 {
+        (*self - *other).length()
+    }
+
+    pub fn dot(&self, other: &Self) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    // Perpendicular to both, following the right-hand rule
+    pub fn cross(&self, other: &Self) -> Self {
+        Self {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+
+    pub fn normalize(&self) -> Self {
+        let len = self.length();
+        if len > 0.0 {
+            *self * (1.0 / len)
+        } else {
+            Self::origin()
+        }
+    }
+
+    // Drops the height
+    pub fn to_point2d(&self) -> Point2D {
+        Point2D::new(self.x, self.y)
+    }
+}
+
+impl Add for Vec3 {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self::Output {
+        Self {
+            x: self.x + rhs.x,
+            y: self.y + rhs.y,
+            z: self.z + rhs.z,
+        }
+    }
+}
+
+impl Sub for Vec3 {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self {
+            x: self.x - rhs.x,
+            y: self.y - rhs.y,
+            z: self.z - rhs.z,
+        }
+    }
+}
+
+impl Mul<f32> for Vec3 {
+    type Output = Self;
+    fn mul(self, rhs: f32) -> Self::Output {
+        Self {
+            x: self.x * rhs,
+            y: self.y * rhs,
+            z: self.z * rhs,
+        }
+    }
+}
+
+impl Neg for Vec3 {
+    type Output = Self;
+    fn neg(self) -> Self::Output {
+        Self {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+}
+
+impl fmt::Display for Vec3 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({:.2}, {:.2}, {:.2})", self.x, self.y, self.z)
+    }
+}
+
+// Axis-aligned box on the map plane, edges inclusive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AABB {
+    pub min: Point2D,
+    pub max: Point2D,
+}
+
+impl AABB {
+    // Corners may be given in either order
+    pub fn new(a: Point2D, b: Point2D) -> Self {
+        Self {
+            min: Point2D::new(a.x.min(b.x), a.y.min(b.y)),
+            max: Point2D::new(a.x.max(b.x), a.y.max(b.y)),
+        }
+    }
+
+    pub fn contains(&self, point: Point2D) -> bool {
+        point.x >= self.min.x
+            && point.x <= self.max.x
+            && point.y >= self.min.y
+            && point.y <= self.max.y
+    }
+
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.min.x <= other.max.x
+            && self.max.x >= other.min.x
+            && self.min.y <= other.max.y
+            && self.max.y >= other.min.y
+    }
+
+    // Counterclockwise from the minimum corner
+    pub fn corners(&self) -> [Point2D; 4] {
+        [
+            self.min,
+            Point2D::new(self.max.x, self.min.y),
+            self.max,
+            Point2D::new(self.min.x, self.max.y),
+        ]
+    }
+
+    // Whether any of the box may fall inside the view wedge of an eye facing `angle`
+    // with `fov` radians between its edges. Boxes are culled when every corner is
+    // outside the same edge, so a box straddling the wedge's tip is sometimes kept.
+    pub fn in_view(&self, eye: Point2D, angle: f32, fov: f32) -> bool {
+        if self.contains(eye) {
+            return true;
+        }
+        // Past a half turn the wedge is no longer convex and the edge test doesn't hold
+        let half_fov = fov / 2.0;
+        if half_fov >= std::f32::consts::FRAC_PI_2 {
+            return true;
+        }
+
+        let left = Point2D::new((angle + half_fov).cos(), (angle + half_fov).sin());
+        let right = Point2D::new((angle - half_fov).cos(), (angle - half_fov).sin());
+        let offsets = self.corners().map(|corner| corner - eye);
+
+        // Outside means counterclockwise of the left edge or clockwise of the right
+        let beyond_left = offsets.iter().all(|p| left.x * p.y - left.y * p.x > 0.0);
+        let beyond_right = offsets.iter().all(|p| right.x * p.y - right.y * p.x < 0.0);
+        !(beyond_left || beyond_right)
+    }
+}
+
+pub const FRACBITS: i32 = 16;
+pub const FRACUNIT: i32 = 1 << FRACBITS;
+
+// 16.16 fixed-point number, as used for vanilla Doom coordinates and velocities.
+// Arithmetic saturates instead of wrapping on overflow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Fixed(pub i32);
+
+impl Fixed {
+    pub const ZERO: Self = Self(0);
+    pub const ONE: Self = Self(FRACUNIT);
+    pub const MAX: Self = Self(i32::MAX);
+    pub const MIN: Self = Self(i32::MIN);
+
+    pub fn from_int(value: i32) -> Self {
+        Self(value.saturating_mul(FRACUNIT))
+    }
+
+    pub fn from_f64(value: f64) -> Self {
+        // Float-to-int casts saturate, and NaN becomes zero
+        Self((value * FRACUNIT as f64) as i32)
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / FRACUNIT as f64
+    }
+
+    pub fn to_int(self) -> i32 {
+        self.0 >> FRACBITS
+    }
+
+    pub fn raw(self) -> i32 {
+        self.0
+    }
+}
+
+impl Add for Fixed {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self::Output {
+        Self(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl Mul for Fixed {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self::Output {
+        let product = (self.0 as i64 * rhs.0 as i64) >> FRACBITS;
+        Self(product.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+    }
+}
+
+impl Div for Fixed {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self::Output {
+        // Same overflow guard as FixedDiv: if the quotient can't fit, saturate
+        // toward the sign of the result. This also covers division by zero.
+        if (self.0.unsigned_abs() >> 14) >= rhs.0.unsigned_abs() {
+            return if (self.0 ^ rhs.0) < 0 {
+                Self::MIN
+            } else {
+                Self::MAX
+            };
+        }
+
+        let quotient = ((self.0 as i64) << FRACBITS) / rhs.0 as i64;
+        Self(quotient.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Self;
+    fn neg(self) -> Self::Output {
+        Self(self.0.saturating_neg())
+    }
+}
+
+impl fmt::Display for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.4}", self.to_f64())
+    }
+}
+
+// Doom's random numbers: a fixed table stepped through by an index, so the same
+// starting index gives the same numbers every time. This is what keeps demos in step.
+const RANDOM_TABLE: [u8; 256] = [
+    0, 8, 109, 220, 222, 241, 149, 107, 75, 248, 254, 140, 16, 66, 74, 21, 211, 47, 80, 242, 154,
+    27, 205, 128, 161, 89, 77, 36, 95, 110, 85, 48, 212, 140, 211, 249, 22, 79, 200, 50, 28, 188,
+    52, 140, 202, 120, 68, 145, 62, 70, 184, 190, 91, 197, 152, 224, 149, 104, 25, 178, 252, 182,
+    202, 182, 141, 197, 4, 81, 181, 242, 145, 42, 39, 227, 156, 198, 225, 193, 219, 93, 122, 175,
+    249, 0, 175, 143, 70, 239, 46, 246, 163, 53, 163, 109, 168, 135, 2, 235, 25, 92, 20, 145, 138,
+    77, 69, 166, 78, 176, 173, 212, 166, 113, 94, 161, 41, 50, 239, 49, 111, 164, 70, 60, 2, 37,
+    171, 75, 136, 156, 11, 56, 42, 146, 138, 229, 73, 146, 77, 61, 98, 196, 135, 106, 63, 197, 195,
+    86, 96, 203, 113, 101, 170, 247, 181, 113, 80, 250, 108, 7, 255, 237, 129, 226, 79, 107, 112,
+    166, 103, 241, 24, 223, 239, 120, 198, 58, 60, 82, 128, 3, 184, 66, 143, 224, 145, 224, 81,
+    206, 163, 45, 63, 90, 168, 114, 59, 33, 159, 95, 28, 139, 123, 98, 125, 196, 15, 70, 194, 253,
+    54, 14, 109, 226, 71, 17, 161, 93, 186, 87, 244, 138, 20, 52, 123, 251, 26, 36, 17, 46, 52,
+    231, 232, 76, 31, 221, 84, 37, 216, 165, 212, 106, 197, 242, 98, 43, 39, 175, 254, 145, 190,
+    84, 118, 222, 187, 136, 120, 163, 236, 249,
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DoomRng {
+    index: u8,
+}
+
+impl DoomRng {
+    // Starts from a table index. Doom starts new games and demos from 0.
+    pub fn new(seed: u8) -> Self {
+        DoomRng { index: seed }
+    }
+
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    // Steps to the next entry and returns it, as P_Random does
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> u8 {
+        self.index = self.index.wrapping_add(1);
+        RANDOM_TABLE[self.index as usize]
+    }
+
+    // The difference of two numbers, from -255 to 255 and most often near 0
+    pub fn sub_random(&mut self) -> i32 {
+        let first = self.next() as i32;
+        first - self.next() as i32
+    }
+
+    // Doom's bullets do 1 to 3 times 5 damage. This rolls the same 1 to 3, scaled so the
+    // middle roll does `damage`.
+    pub fn random_damage(&mut self, damage: i32) -> i32 {
+        damage * (self.next() % 3 + 1) as i32 / 2
+    }
+
+    // An angle of up to `max` either way, most often close to straight ahead
+    pub fn random_spread(&mut self, max: f64) -> f64 {
+        self.sub_random() as f64 / 255.0 * max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::{FRAC_PI_2, PI};
+
+    #[test]
+    fn bam_cardinal_directions_round_trip() {
+        let cardinals = [
+            (0, 0.0),
+            (16384, FRAC_PI_2),
+            (32768, PI),
+            (49152, 3.0 * FRAC_PI_2),
+        ];
+        for (bam, radians) in cardinals {
+            assert!((bam_to_radians(bam) - radians).abs() < 1e-6, "bam {bam}");
+            assert_eq!(radians_to_bam(radians), bam);
+        }
+    }
+
+    #[test]
+    fn full_turn_wraps_to_east() {
+        assert_eq!(radians_to_bam(TAU), 0);
+        assert_eq!(radians_to_bam(-FRAC_PI_2), 49152);
+    }
+
+    #[test]
+    fn multiplies_fractional_values() {
+        let half = Fixed::from_f64(0.5);
+        let quarter = Fixed::from_f64(0.25);
+        assert_eq!(half * half, quarter);
+        assert_eq!(
+            Fixed::from_f64(1.5) * Fixed::from_f64(-2.5),
+            Fixed::from_f64(-3.75)
+        );
+        assert_eq!(Fixed::from_int(3) * Fixed::ONE, Fixed::from_int(3));
+    }
+
+    #[test]
+    fn multiplication_saturates() {
+        assert_eq!(Fixed::from_int(30000) * Fixed::from_int(30000), Fixed::MAX);
+        assert_eq!(Fixed::from_int(-30000) * Fixed::from_int(30000), Fixed::MIN);
+    }
+
+    #[test]
+    fn divides_into_fractions() {
+        assert_eq!(Fixed::ONE / Fixed::from_int(4), Fixed::from_f64(0.25));
+        assert_eq!(
+            Fixed::from_int(-3) / Fixed::from_int(2),
+            Fixed::from_f64(-1.5)
+        );
+        assert_eq!(Fixed::from_int(7) / Fixed::ONE, Fixed::from_int(7));
+    }
+
+    #[test]
+    fn division_by_zero_saturates_by_sign() {
+        assert_eq!(Fixed::ONE / Fixed::ZERO, Fixed::MAX);
+        assert_eq!(-Fixed::ONE / Fixed::ZERO, Fixed::MIN);
+        assert_eq!(Fixed::ZERO / Fixed::ZERO, Fixed::MAX);
+    }
+
+    #[test]
+    fn division_overflow_saturates_toward_result_sign() {
+        let big = Fixed::from_int(20000);
+        let tiny = Fixed(1);
+        assert_eq!(big / tiny, Fixed::MAX);
+        assert_eq!(big / -tiny, Fixed::MIN);
+        assert_eq!(-big / tiny, Fixed::MIN);
+        assert_eq!(-big / -tiny, Fixed::MAX);
+    }
+
+    #[test]
+    fn negation_saturates() {
+        assert_eq!(-Fixed::MIN, Fixed::MAX);
+    }
+
+    #[test]
+    fn angles_above_a_turn_wrap_down() {
+        assert!((normalize_angle(TAU + 1.0) - 1.0).abs() < 1e-5);
+        assert!((normalize_angle_f64(3.0 * std::f64::consts::TAU + 0.5) - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn negative_angles_wrap_up() {
+        assert!((normalize_angle(-1.0) - (TAU - 1.0)).abs() < 1e-5);
+        let wrapped = normalize_angle_f64(-std::f64::consts::FRAC_PI_2);
+        assert!((wrapped - 3.0 * std::f64::consts::FRAC_PI_2).abs() < 1e-12);
+
+        // Too small to survive the subtraction from a full turn
+        assert_eq!(normalize_angle(-1e-9), 0.0);
+        assert_eq!(normalize_angle_f64(-1e-20), 0.0);
+    }
+
+    #[test]
+    fn whole_turns_wrap_to_zero() {
+        for turns in [-2, -1, 0, 1, 4] {
+            assert_eq!(normalize_angle(turns as f32 * TAU), 0.0, "{} turns", turns);
+            assert_eq!(
+                normalize_angle_f64(turns as f64 * std::f64::consts::TAU),
+                0.0,
+                "{} turns",
+                turns
+            );
+        }
+    }
+
+    #[test]
+    fn cross_product_is_orthogonal_to_both() {
+        let a = Vec3::new(1.0, 2.0, 3.0);
+        let b = Vec3::new(-4.0, 0.5, 2.0);
+        let cross = a.cross(&b);
+        assert!(cross.dot(&a).abs() < 1e-5);
+        assert!(cross.dot(&b).abs() < 1e-5);
+
+        // Right-handed: x cross y is z
+        let z = Vec3::new(1.0, 0.0, 0.0).cross(&Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(z, Vec3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn normalizing_keeps_the_direction_at_unit_length() {
+        let unit = Vec3::new(3.0, 0.0, 4.0).normalize();
+        assert!((unit.x - 0.6).abs() < 1e-6);
+        assert_eq!(unit.y, 0.0);
+        assert!((unit.z - 0.8).abs() < 1e-6);
+        assert!((unit.length() - 1.0).abs() < 1e-6);
+
+        assert_eq!(Vec3::origin().normalize(), Vec3::origin());
+        assert_eq!(
+            Point2D::new(3.0, 4.0).to_vec3(5.0),
+            Vec3::new(3.0, 4.0, 5.0)
+        );
+    }
+
+    #[test]
+    fn random_numbers_follow_vanilla_from_index_zero() {
+        let mut rng = DoomRng::new(0);
+        let first: Vec<u8> = (0..10).map(|_| rng.next()).collect();
+        assert_eq!(first, [8, 109, 220, 222, 241, 149, 107, 75, 248, 254]);
+
+        // The table wraps after 256 numbers, back to its first entry
+        for _ in 10..255 {
+            rng.next();
+        }
+        assert_eq!(rng.next(), 0);
+        assert_eq!(rng.index(), 0);
+        assert_eq!(rng.next(), 8);
+
+        // The same starting index always gives the same numbers
+        let (mut a, mut b) = (DoomRng::new(42), DoomRng::new(42));
+        assert!((0..300).all(|_| a.next() == b.next()));
+    }
+}
@@ -1,6 +1,411 @@
+use input::{Action, Axis, BT_ATTACK, BT_USE, FORWARD_MOVE, Input, SIDE_MOVE, TicCmd, turn_from_radians};
+use map::{Map, MapError, MapLumps};
+use math::{AABB, Point2D, bam_to_radians, lerp, lerp_angle, normalize_angle_f64};
+use std::time::Duration;
+use thiserror::Error;
 use wad::WadFile;
 
-pub struct Player {}
+// Default walking speeds in world units per second and radians per second. Running is
+// twice as fast.
+const MOVE_SPEED: f64 = 250.0;
+const RUN_SPEED: f64 = 500.0;
+const STRAFE_SPEED: f64 = 200.0;
+const TURN_SPEED: f64 = 3.0;
+// How fast key turning speeds up and slows down, in radians per second squared. From
+// a standstill it reaches TURN_SPEED in about 6 tics, like Doom's SLOWTURNTICS, and it
+// stops again in about 2.
+const TURN_ACCELERATION: f64 = 18.0;
+const TURN_DECELERATION: f64 = 54.0;
+const FLY_SPEED: f64 = 150.0;
+// Doom's gravity of one unit per tic per tic, in units per second squared
+const GRAVITY: f64 = 1225.0;
+// The tallest rise the player can walk up
+const MAX_STEP_HEIGHT: f64 = 24.0;
+
+// Doom's default eye height above the floor and collision radius
+const VIEW_HEIGHT: f64 = 41.0;
+const PLAYER_RADIUS: f64 = 16.0;
+const PLAYER_HEIGHT: f64 = 56.0;
+
+// Linedef flag marking a line that blocks players and monsters
+const ML_BLOCKING: u16 = 0x0001;
+
+// How fast the player walks, runs, strafes and turns with the keys, in world units and
+// radians per second. Strafing and turning speed up by as much as running speeds up
+// walking. Key and controller turning speeds up and slows down at the turn
+// acceleration and deceleration, in radians per second squared. With `always_run` the
+// player runs unless the run key is held.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MovementConfig {
+    pub walk_speed: f64,
+    pub run_speed: f64,
+    pub turn_speed: f64,
+    pub turn_acceleration: f64,
+    pub turn_deceleration: f64,
+    pub strafe_speed: f64,
+    pub always_run: bool,
+}
+
+impl Default for MovementConfig {
+    fn default() -> Self {
+        MovementConfig {
+            walk_speed: MOVE_SPEED,
+            run_speed: RUN_SPEED,
+            turn_speed: TURN_SPEED,
+            turn_acceleration: TURN_ACCELERATION,
+            turn_deceleration: TURN_DECELERATION,
+            strafe_speed: STRAFE_SPEED,
+            always_run: false,
+        }
+    }
+}
+
+impl MovementConfig {
+    // How much faster running is than walking
+    pub fn run_factor(&self) -> f64 {
+        if self.walk_speed > 0.0 { self.run_speed / self.walk_speed } else { 1.0 }
+    }
+
+    // Forward and sideways speeds for movement given as fractions of walking, as in a
+    // tic command. Up to 1 walks, and 2 runs.
+    pub fn speeds(&self, forward: f64, strafe: f64) -> (f64, f64) {
+        let run_factor = self.run_factor();
+        let speed = |fraction: f64, walk: f64| {
+            let magnitude = fraction.abs().min(2.0);
+            let speed = if magnitude <= 1.0 { magnitude * walk } else { walk + (magnitude - 1.0) * (run_factor - 1.0) * walk };
+            speed.copysign(fraction)
+        };
+        (speed(forward, self.walk_speed), speed(strafe, self.strafe_speed))
+    }
+
+    // Moves a turning rate towards `target` over `dt` seconds, slowing down at the
+    // deceleration and speeding up at the acceleration. Returns the new rate and the
+    // angle turned, from the average of the two rates so it doesn't matter how time is
+    // split into steps.
+    pub fn ramp_turn(&self, rate: f64, target: f64, dt: f64) -> (f64, f64) {
+        let slowing = target.abs() < rate.abs() || target * rate < 0.0;
+        let step = if slowing { self.turn_deceleration } else { self.turn_acceleration } * dt;
+        let next = rate + (target - rate).clamp(-step, step);
+        (next, (rate + next) / 2.0 * dt)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Player {
+    pub x: f64,
+    pub y: f64,
+    pub angle: f64,
+    // Height of the player's feet, and of their eye above their feet
+    pub z: f64,
+    pub height: f64,
+    pub velocity: (f64, f64),
+    // Vertical speed in units per second, negative when falling
+    pub z_velocity: f64,
+    // How fast the keys or controller are turning the player, in radians per second
+    // anticlockwise. See MovementConfig::ramp_turn.
+    pub turn_rate: f64,
+    pub radius: f64,
+    // Debug movement. A noclip player walks through walls and off the map, and a
+    // flying one moves up and down freely without falling.
+    pub noclip: bool,
+    pub fly: bool,
+}
+
+impl Player {
+    pub fn new(x: f64, y: f64, angle: f64) -> Self {
+        Player {
+            x,
+            y,
+            angle,
+            z: 0.0,
+            height: VIEW_HEIGHT,
+            velocity: (0.0, 0.0),
+            z_velocity: 0.0,
+            turn_rate: 0.0,
+            radius: PLAYER_RADIUS,
+            noclip: false,
+            fly: false,
+        }
+    }
+
+    // Stopping flying leaves the player to fall from wherever they are
+    pub fn set_fly(&mut self, fly: bool) {
+        self.fly = fly;
+        self.z_velocity = 0.0;
+    }
+
+    // Moves the player up (positive) or down, only while flying
+    pub fn fly_vertical(&mut self, dz: f64) {
+        if self.fly {
+            self.z += dz;
+        }
+    }
+
+    // Floor height of the sector the player is in, None off the map
+    pub fn floor_height(&self, map: &Map) -> Option<f64> {
+        map.sector_at(self.x, self.y)
+            .and_then(|sector| map.sectors.get(sector))
+            .map(|sector| sector.floor_height as f64)
+    }
+
+    // Puts the player's feet on the floor, as when they arrive in a level
+    pub fn snap_to_floor(&mut self, map: &Map) {
+        if let Some(floor) = self.floor_height(map) {
+            self.z = floor;
+            self.z_velocity = 0.0;
+        }
+    }
+
+    // Keeps the player on the floor of the sector they're in, stepping straight up onto
+    // higher floors and falling under gravity to lower ones. Flying players and ones
+    // off the map stay at their height.
+    pub fn apply_gravity(&mut self, map: &Map, dt: Duration) {
+        if self.fly {
+            return;
+        }
+        let Some(floor) = self.floor_height(map) else {
+            return;
+        };
+
+        if self.z > floor {
+            let dt = dt.as_secs_f64();
+            self.z_velocity -= GRAVITY * dt;
+            self.z += self.z_velocity * dt;
+        }
+        if self.z <= floor {
+            self.z = floor;
+            self.z_velocity = 0.0;
+        }
+    }
+
+    // Where the player appears a fraction alpha of the way from this state to the next
+    pub fn lerp(&self, next: &Player, alpha: f64) -> Player {
+        Player {
+            x: lerp(self.x, next.x, alpha),
+            y: lerp(self.y, next.y, alpha),
+            angle: lerp_angle(self.angle, next.angle, alpha),
+            z: lerp(self.z, next.z, alpha),
+            height: lerp(self.height, next.height, alpha),
+            ..next.clone()
+        }
+    }
+
+    // The debug movement keys, which aren't part of a tic command
+    pub fn update_debug(&mut self, dt: Duration, input: &Input) {
+        if input.was_action_pressed(Action::ToggleNoclip) {
+            self.noclip = !self.noclip;
+        }
+        if input.was_action_pressed(Action::ToggleFly) {
+            self.set_fly(!self.fly);
+        }
+        let vertical = input.is_action_down(Action::FlyUp) as i32 - input.is_action_down(Action::FlyDown) as i32;
+        self.fly_vertical(vertical as f64 * FLY_SPEED * dt.as_secs_f64());
+    }
+
+    // Turns and moves the player for one tic's command. Walking speed is a command
+    // movement of the walking entry of FORWARD_MOVE or SIDE_MOVE, and running speed
+    // the running one.
+    pub fn run_tic_cmd(&mut self, dt: Duration, cmd: &TicCmd, map: Option<&Map>, movement: &MovementConfig) {
+        let elapsed = dt;
+        let dt = dt.as_secs_f64();
+
+        self.angle = normalize_angle_f64(self.angle + cmd.turn_radians());
+
+        let forward = move_fraction(cmd.forward, FORWARD_MOVE);
+        let strafe = move_fraction(cmd.side, SIDE_MOVE);
+        self.velocity = self.movement_velocity(forward, strafe, movement);
+        let (dx, dy) = (self.velocity.0 * dt, self.velocity.1 * dt);
+
+        match map {
+            Some(map) => {
+                self.try_move(map, dx, dy);
+                self.apply_gravity(map, elapsed);
+            }
+            None => {
+                self.x += dx;
+                self.y += dy;
+            }
+        }
+    }
+
+    pub fn try_move(&mut self, map: &Map, dx: f64, dy: f64) {
+        if dx == 0.0 && dy == 0.0 {
+            return;
+        }
+        if self.noclip {
+            self.x += dx;
+            self.y += dy;
+            return;
+        }
+
+        let Some(wall) = self.blocking_linedef(map, self.x + dx, self.y + dy) else {
+            self.x += dx;
+            self.y += dy;
+            return;
+        };
+
+        // Project the motion onto the wall so the player slides along it
+        let (start, end) = (
+            &map.vertices[map.linedefs[wall].start_vertex as usize],
+            &map.vertices[map.linedefs[wall].end_vertex as usize],
+        );
+        let (wall_dx, wall_dy) = ((end.x - start.x) as f64, (end.y - start.y) as f64);
+        let wall_length = wall_dx.hypot(wall_dy);
+        if wall_length > 0.0 {
+            let (tangent_x, tangent_y) = (wall_dx / wall_length, wall_dy / wall_length);
+            let along = dx * tangent_x + dy * tangent_y;
+            let (slide_x, slide_y) = (tangent_x * along, tangent_y * along);
+
+            if self.blocking_linedef(map, self.x + slide_x, self.y + slide_y).is_none() {
+                self.x += slide_x;
+                self.y += slide_y;
+                return;
+            }
+        }
+
+        // Sliding pushed us into a second wall (a corner), so try each axis on its own
+        if self.blocking_linedef(map, self.x + dx, self.y).is_none() {
+            self.x += dx;
+        }
+        if self.blocking_linedef(map, self.x, self.y + dy).is_none() {
+            self.y += dy;
+        }
+    }
+
+    // Finds a solid linedef that a player circle at (x, y) would overlap
+    fn blocking_linedef(&self, map: &Map, x: f64, y: f64) -> Option<usize> {
+        let candidates: Vec<usize> = match &map.blockmap {
+            Some(blockmap) => blockmap
+                .linedefs_in_box(x - self.radius, y - self.radius, x + self.radius, y + self.radius)
+                .into_iter()
+                .map(|linedef| linedef as usize)
+                .collect(),
+            None => (0..map.linedefs.len()).collect(),
+        };
+
+        candidates.into_iter().find(|&index| {
+            let Some(linedef) = map.linedefs.get(index) else {
+                return false;
+            };
+            // Two-sided lines only block when flagged or when the player can't get through
+            // the opening: it's too low, like a closed door, its ceiling is below the
+            // player's head or its floor is too high a step up
+            let closed = map.opening(linedef).is_some_and(|(bottom, top)| {
+                top - bottom < PLAYER_HEIGHT || top - self.z < PLAYER_HEIGHT || bottom - self.z > MAX_STEP_HEIGHT
+            });
+            if linedef.back_sidedef != 0xFFFF && linedef.flags & ML_BLOCKING == 0 && !closed {
+                return false;
+            }
+
+            let (Some(start), Some(end)) = (
+                map.vertices.get(linedef.start_vertex as usize),
+                map.vertices.get(linedef.end_vertex as usize),
+            ) else {
+                return false;
+            };
+
+            point_segment_distance(
+                (x, y),
+                (start.x as f64, start.y as f64),
+                (end.x as f64, end.y as f64),
+            ) < self.radius
+        })
+    }
+
+    // Converts forward/strafe input in [-1, 1] into a world-space velocity.
+    // Angles increase counterclockwise, so strafing right is a quarter turn clockwise.
+    pub fn movement_velocity(&self, forward: f64, strafe: f64, movement: &MovementConfig) -> (f64, f64) {
+        let (sin, cos) = self.angle.sin_cos();
+        let (forward, strafe) = movement.speeds(forward, strafe);
+
+        (cos * forward + sin * strafe, sin * forward - cos * strafe)
+    }
+}
+
+// A tic command's movement as a fraction of walking for MovementConfig::speeds: 1 at
+// the walking entry of Doom's move table and 2 at the running one
+fn move_fraction(amount: i8, table: [i8; 2]) -> f64 {
+    let [walk, run] = table.map(f64::from);
+    let magnitude = (amount as f64).abs();
+    let fraction = if magnitude <= walk { magnitude / walk } else { 1.0 + (magnitude - walk) / (run - walk) };
+    fraction.copysign(amount as f64)
+}
+
+// The command for one tic of live play from the keys, controller and mouse. Running
+// moves by the running entries of Doom's move tables and turns faster, while mouse
+// turning is unchanged.
+// Key and controller turning ramps `turn_rate` up and down, and the mouse turns
+// directly.
+pub fn tic_cmd(input: &Input, dt: Duration, movement: &MovementConfig, turn_rate: &mut f64) -> TicCmd {
+    let dt = dt.as_secs_f64();
+    let running = input.is_action_down(Action::Run) != movement.always_run;
+    let speed = running as usize;
+    let turn_speed = if running { movement.turn_speed * movement.run_factor() } else { movement.turn_speed };
+
+    let mut turn = 0.0;
+    if input.is_action_down(Action::TurnLeft) {
+        turn += 1.0;
+    }
+    if input.is_action_down(Action::TurnRight) {
+        turn -= 1.0;
+    }
+    turn -= input.controller_axis(Axis::RightX);
+    let (rate, turn) = movement.ramp_turn(*turn_rate, turn.clamp(-1.0, 1.0) * turn_speed, dt);
+    *turn_rate = rate;
+    let turn = turn + input.mouse_turn();
+
+    let mut forward = 0.0;
+    if input.is_action_down(Action::Forward) {
+        forward += 1.0;
+    }
+    if input.is_action_down(Action::Back) {
+        forward -= 1.0;
+    }
+    forward -= input.controller_axis(Axis::LeftY);
+
+    let mut strafe = 0.0;
+    if input.is_action_down(Action::StrafeRight) {
+        strafe += 1.0;
+    }
+    if input.is_action_down(Action::StrafeLeft) {
+        strafe -= 1.0;
+    }
+    strafe += input.controller_axis(Axis::LeftX);
+
+    let mut buttons = 0;
+    if input.is_action_down(Action::Fire) || input.controller_axis(Axis::TriggerRight) > 0.5 {
+        buttons |= BT_ATTACK;
+    }
+    if input.is_action_down(Action::Use) || input.is_button_down(input::Button::A) {
+        buttons |= BT_USE;
+    }
+
+    TicCmd {
+        forward: (forward.clamp(-1.0, 1.0) * FORWARD_MOVE[speed] as f64).round() as i8,
+        side: (strafe.clamp(-1.0, 1.0) * SIDE_MOVE[speed] as f64).round() as i8,
+        turn: turn_from_radians(turn),
+        buttons,
+    }
+}
+
+fn point_segment_distance(point: (f64, f64), start: (f64, f64), end: (f64, f64)) -> f64 {
+    let (seg_x, seg_y) = (end.0 - start.0, end.1 - start.1);
+    let length_squared = seg_x * seg_x + seg_y * seg_y;
+
+    let t = if length_squared > 0.0 {
+        (((point.0 - start.0) * seg_x + (point.1 - start.1) * seg_y) / length_squared).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    let (closest_x, closest_y) = (start.0 + seg_x * t, start.1 + seg_y * t);
+    (point.0 - closest_x).hypot(point.1 - closest_y)
+}
+
+// Child indices with this bit set refer to subsectors. Classic nodes use 0x8000 and are
+// widened on load so both formats share one representation.
+pub const NF_SUBSECTOR: u32 = 0x8000_0000;
+const NF_SUBSECTOR_CLASSIC: u16 = 0x8000;
 
 #[derive(Debug, Clone)]
 pub struct BspNode {
@@ -10,8 +415,39 @@ pub struct BspNode {
     pub dy: i16,
     pub bbox_right: [i16; 4],
     pub bbox_left: [i16; 4],
-    pub right_child: u16,
-    pub left_child: u16,
+    pub right_child: u32,
+    pub left_child: u32,
+}
+
+impl BspNode {
+    pub fn right_bbox(&self) -> AABB {
+        bbox_to_aabb(&self.bbox_right)
+    }
+
+    pub fn left_bbox(&self) -> AABB {
+        bbox_to_aabb(&self.bbox_left)
+    }
+}
+
+// Node bounding boxes are stored as top, bottom, left, right
+fn bbox_to_aabb(bbox: &[i16; 4]) -> AABB {
+    let [top, bottom, left, right] = bbox.map(|edge| edge as f32);
+    AABB::new(Point2D::new(left, bottom), Point2D::new(right, top))
+}
+
+// Whether any part of a node's box could be inside the player's view
+pub fn bbox_visible(player: &Player, fov: f64, bbox: &AABB) -> bool {
+    bbox.in_view(Point2D::new(player.x as f32, player.y as f32), player.angle as f32, fov as f32)
+}
+
+#[derive(Error, Debug)]
+pub enum BspError {
+    #[error(transparent)]
+    Map(#[from] MapError),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Parse(String),
 }
 
 #[derive(Debug, Clone)]
@@ -19,121 +455,374 @@ pub struct BspTree {
     pub nodes: Vec<BspNode>,
     pub subsectors: Vec<Subsector>,
     pub segs: Vec<Seg>,
+    // Vertices added by an extended node builder. Seg vertex indices at or past
+    // `original_vertex_count` index into this list instead of the map's VERTEXES.
+    pub original_vertex_count: u32,
+    pub extra_vertices: Vec<(f64, f64)>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Subsector {
-    pub seg_count: u16,
-    pub first_seg: u16,
+    pub seg_count: u32,
+    pub first_seg: u32,
 }
 
 #[derive(Debug, Clone)]
 pub struct Seg {
-    pub start_vertex: u16,
-    pub end_vertex: u16,
+    pub start_vertex: u32,
+    pub end_vertex: u32,
     pub angle: u16,
     pub linedef: u16,
     pub direction: u16,
     pub offset: u16,
 }
 
+impl Seg {
+    pub fn angle_radians(&self) -> f32 {
+        bam_to_radians(self.angle)
+    }
+}
+
+impl Subsector {
+    // The sector a subsector is part of, the one its first seg faces as in Doom. Every
+    // seg of a subsector faces the same sector.
+    pub fn sector(&self, bsp: &BspTree, map: &Map) -> Option<u16> {
+        let seg = bsp.segs.get(self.first_seg as usize)?;
+        bsp.seg_sector(seg, map)
+    }
+}
+
 impl BspTree {
-    pub fn load_from_wad(wad: &WadFile, map_name: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let map_index = wad.lumps.iter().position(|lump| lump.name == map_name)
-            .ok_or("Map not found")?;
+    pub fn load_from_wad(wad: &WadFile, map_name: &str) -> Result<Self, BspError> {
+        let lumps = MapLumps::find(wad, map_name)?;
+        let nodes_data = &lumps.require("NODES")?.data;
+
+        // ZDoom builders store the whole tree, including segs and subsectors, in NODES
+        if nodes_data.starts_with(b"XNOD") || nodes_data.starts_with(b"ZNOD") {
+            let mut tree = Self::parse_extended(nodes_data)?;
+            let vertices = Self::parse_vertex_positions(&lumps.require("VERTEXES")?.data)?;
+            // Hexen linedefs are 16 bytes, with the special's args where Doom has its tag
+            let linedef_size = if lumps.get("BEHAVIOR").is_some() { 16 } else { 14 };
+            let linedef_starts = Self::parse_linedef_vertices(&lumps.require("LINEDEFS")?.data, linedef_size)?;
+            tree.fill_seg_geometry(&vertices, &linedef_starts);
+            return Ok(tree);
+        }
 
-        let nodes = Self::parse_nodes(&wad.lumps[map_index + 7].data)?;
-        let subsectors = Self::parse_subsectors(&wad.lumps[map_index + 6].data)?;
-        let segs = Self::parse_segs(&wad.lumps[map_index + 5].data)?;
+        let nodes = Self::parse_nodes(nodes_data)?;
+        let subsectors = Self::parse_subsectors(&lumps.require("SSECTORS")?.data)?;
+        let segs = Self::parse_segs(&lumps.require("SEGS")?.data)?;
 
-        Ok(BspTree { nodes, subsectors, segs })
+        Ok(BspTree {
+            nodes,
+            subsectors,
+            segs,
+            original_vertex_count: u32::MAX,
+            extra_vertices: Vec::new(),
+        })
     }
 
-    fn parse_nodes(data: &[u8]) -> Result<Vec<BspNode>, Box<dyn std::error::Error>> {
+    fn parse_nodes(data: &[u8]) -> Result<Vec<BspNode>, BspError> {
         let mut cursor = std::io::Cursor::new(data);
         let mut nodes = Vec::new();
 
+        let widen = |child: u16| {
+            if child & NF_SUBSECTOR_CLASSIC != 0 {
+                (child & !NF_SUBSECTOR_CLASSIC) as u32 | NF_SUBSECTOR
+            } else {
+                child as u32
+            }
+        };
+
         while cursor.position() < data.len() as u64 {
             use byteorder::{LittleEndian, ReadBytesExt};
 
-            let x = cursor.read_i16::<LittleEndian>()?;
-            let y = cursor.read_i16::<LittleEndian>()?;
-            let dx = cursor.read_i16::<LittleEndian>()?;
-            let dy = cursor.read_i16::<LittleEndian>()?;
+            let mut node = Self::read_node_geometry(&mut cursor)?;
+            node.right_child = widen(cursor.read_u16::<LittleEndian>()?);
+            node.left_child = widen(cursor.read_u16::<LittleEndian>()?);
+            nodes.push(node);
+        }
 
-            let mut bbox_right = [0i16; 4];
-            let mut bbox_left = [0i16; 4];
+        Ok(nodes)
+    }
 
-            for i in 0..4 {
-                bbox_right[i] = cursor.read_i16::<LittleEndian>()?;
-            }
-            for i in 0..4 {
-                bbox_left[i] = cursor.read_i16::<LittleEndian>()?;
-            }
+    // Partition line and both bounding boxes, shared by classic and extended nodes.
+    // The children differ in width between the formats and are left for the caller.
+    fn read_node_geometry(cursor: &mut std::io::Cursor<&[u8]>) -> Result<BspNode, BspError> {
+        use byteorder::{LittleEndian, ReadBytesExt};
+
+        let x = cursor.read_i16::<LittleEndian>()?;
+        let y = cursor.read_i16::<LittleEndian>()?;
+        let dx = cursor.read_i16::<LittleEndian>()?;
+        let dy = cursor.read_i16::<LittleEndian>()?;
+
+        let mut bbox_right = [0i16; 4];
+        let mut bbox_left = [0i16; 4];
+        cursor.read_i16_into::<LittleEndian>(&mut bbox_right)?;
+        cursor.read_i16_into::<LittleEndian>(&mut bbox_left)?;
+
+        Ok(BspNode {
+            x, y, dx, dy,
+            bbox_right,
+            bbox_left,
+            right_child: 0,
+            left_child: 0,
+        })
+    }
+
+    // XNOD is uncompressed, ZNOD is the same data deflated with zlib after the signature
+    fn parse_extended(data: &[u8]) -> Result<Self, BspError> {
+        use byteorder::{LittleEndian, ReadBytesExt};
+        use std::io::Read;
+
+        let body = if data.starts_with(b"ZNOD") {
+            let mut inflated = Vec::new();
+            flate2::read::ZlibDecoder::new(&data[4..]).read_to_end(&mut inflated)?;
+            inflated
+        } else {
+            data[4..].to_vec()
+        };
+        let mut cursor = std::io::Cursor::new(body.as_slice());
+
+        let original_vertex_count = cursor.read_u32::<LittleEndian>()?;
+        let new_vertex_count = cursor.read_u32::<LittleEndian>()?;
+        let mut extra_vertices = Vec::with_capacity(new_vertex_count as usize);
+        for _ in 0..new_vertex_count {
+            // 16.16 fixed point
+            let x = cursor.read_i32::<LittleEndian>()? as f64 / 65536.0;
+            let y = cursor.read_i32::<LittleEndian>()? as f64 / 65536.0;
+            extra_vertices.push((x, y));
+        }
+
+        // Subsectors only store their seg counts, segs are laid out consecutively
+        let subsector_count = cursor.read_u32::<LittleEndian>()?;
+        let mut subsectors = Vec::with_capacity(subsector_count as usize);
+        let mut first_seg = 0u32;
+        for _ in 0..subsector_count {
+            let seg_count = cursor.read_u32::<LittleEndian>()?;
+            subsectors.push(Subsector { seg_count, first_seg });
+            first_seg = first_seg.checked_add(seg_count).ok_or_else(|| BspError::Parse("Extended node seg count overflow".to_string()))?;
+        }
 
-            let right_child = cursor.read_u16::<LittleEndian>()?;
-            let left_child = cursor.read_u16::<LittleEndian>()?;
+        let seg_count = cursor.read_u32::<LittleEndian>()?;
+        if first_seg != seg_count {
+            return Err(BspError::Parse(format!(
+                "Extended nodes list {} segs but subsectors use {}",
+                seg_count, first_seg
+            )));
+        }
+        let mut segs = Vec::with_capacity(seg_count as usize);
+        for _ in 0..seg_count {
+            let start_vertex = cursor.read_u32::<LittleEndian>()?;
+            let end_vertex = cursor.read_u32::<LittleEndian>()?;
+            let linedef = cursor.read_u16::<LittleEndian>()?;
+            let direction = cursor.read_u8()? as u16;
 
-            nodes.push(BspNode {
-                x, y, dx, dy,
-                bbox_right,
-                bbox_left,
-                right_child,
-                left_child,
+            // Angle and offset aren't stored, see fill_seg_geometry
+            segs.push(Seg {
+                start_vertex,
+                end_vertex,
+                angle: 0,
+                linedef,
+                direction,
+                offset: 0,
             });
         }
 
-        Ok(nodes)
+        let node_count = cursor.read_u32::<LittleEndian>()?;
+        let mut nodes = Vec::with_capacity(node_count as usize);
+        for _ in 0..node_count {
+            let mut node = Self::read_node_geometry(&mut cursor)?;
+            node.right_child = cursor.read_u32::<LittleEndian>()?;
+            node.left_child = cursor.read_u32::<LittleEndian>()?;
+            nodes.push(node);
+        }
+
+        Ok(BspTree {
+            nodes,
+            subsectors,
+            segs,
+            original_vertex_count,
+            extra_vertices,
+        })
+    }
+
+    fn parse_vertex_positions(data: &[u8]) -> Result<Vec<(f64, f64)>, BspError> {
+        use byteorder::{LittleEndian, ReadBytesExt};
+
+        let mut cursor = std::io::Cursor::new(data);
+        let mut vertices = Vec::with_capacity(data.len() / 4);
+        while cursor.position() + 4 <= data.len() as u64 {
+            let x = cursor.read_i16::<LittleEndian>()? as f64;
+            let y = cursor.read_i16::<LittleEndian>()? as f64;
+            vertices.push((x, y));
+        }
+
+        Ok(vertices)
+    }
+
+    // Start and end vertex of every linedef, in LINEDEFS order
+    fn parse_linedef_vertices(data: &[u8], record_size: usize) -> Result<Vec<(u16, u16)>, BspError> {
+        use byteorder::{LittleEndian, ReadBytesExt};
+
+        let mut linedefs = Vec::with_capacity(data.len() / record_size);
+        for record in data.chunks_exact(record_size) {
+            let mut cursor = std::io::Cursor::new(record);
+            let start = cursor.read_u16::<LittleEndian>()?;
+            let end = cursor.read_u16::<LittleEndian>()?;
+            linedefs.push((start, end));
+        }
+
+        Ok(linedefs)
+    }
+
+    // Map position of a seg's vertex
+    pub fn seg_vertex(&self, map: &Map, index: u32) -> Option<(f64, f64)> {
+        if index < self.original_vertex_count {
+            map.vertices.get(index as usize).map(|v| (v.x as f64, v.y as f64))
+        } else {
+            self.extra_vertices.get((index - self.original_vertex_count) as usize).copied()
+        }
+    }
+
+    // The sector on a seg's side of its linedef: the front sidedef's for a seg running
+    // along the line, the back sidedef's for one running against it
+    pub fn seg_sector(&self, seg: &Seg, map: &Map) -> Option<u16> {
+        let linedef = map.linedefs.get(seg.linedef as usize)?;
+        let sidedef = if seg.direction == 0 { linedef.front_sidedef } else { linedef.back_sidedef };
+        map.sidedefs.get(sidedef as usize).map(|side| side.sector)
+    }
+
+    // Position of a seg vertex, resolving extended node vertices past the map's own
+    fn vertex_position(&self, map_vertices: &[(f64, f64)], index: u32) -> Option<(f64, f64)> {
+        if index < self.original_vertex_count {
+            map_vertices.get(index as usize).copied()
+        } else {
+            self.extra_vertices.get((index - self.original_vertex_count) as usize).copied()
+        }
+    }
+
+    // Extended segs omit the BAM angle and the offset along their linedef, so derive them
+    fn fill_seg_geometry(&mut self, vertices: &[(f64, f64)], linedefs: &[(u16, u16)]) {
+        for index in 0..self.segs.len() {
+            let seg = &self.segs[index];
+            let (Some(start), Some(end)) = (
+                self.vertex_position(vertices, seg.start_vertex),
+                self.vertex_position(vertices, seg.end_vertex),
+            ) else {
+                continue;
+            };
+
+            // Offset is measured from the vertex the seg's side of the line starts at
+            let line_start = linedefs.get(seg.linedef as usize).and_then(|&(v1, v2)| {
+                let vertex = if seg.direction == 0 { v1 } else { v2 };
+                vertices.get(vertex as usize).copied()
+            });
+            let offset = line_start
+                .map(|(x, y)| (start.0 - x).hypot(start.1 - y))
+                .unwrap_or(0.0);
+            let angle = math::radians_to_bam((end.1 - start.1).atan2(end.0 - start.0) as f32);
+
+            let seg = &mut self.segs[index];
+            seg.angle = angle;
+            seg.offset = offset as u16;
+        }
+    }
+
+    // Subsector containing a point, found by walking down from the root node
+    pub fn subsector_at(&self, x: f64, y: f64) -> Option<u32> {
+        // A map with a single subsector has no nodes at all
+        if self.nodes.is_empty() {
+            return (!self.subsectors.is_empty()).then_some(0);
+        }
+
+        let mut node_index = self.nodes.len() as u32 - 1;
+        while node_index & NF_SUBSECTOR == 0 {
+            let node = self.nodes.get(node_index as usize)?;
+            node_index = if self.point_on_side(x, y, node) == 0 {
+                node.right_child
+            } else {
+                node.left_child
+            };
+        }
+        Some(node_index & !NF_SUBSECTOR)
+    }
+
+    // Sector containing a point, through its subsector's segs to their sidedef's sector.
+    // Every point lands in some subsector, so one that is behind any of the subsector's
+    // segs is outside the map and gives None.
+    pub fn sector_at(&self, map: &Map, x: f64, y: f64) -> Option<usize> {
+        let subsector = self.subsectors.get(self.subsector_at(x, y)? as usize)?;
+        let first = subsector.first_seg as usize;
+        let segs = self.segs.get(first..first + subsector.seg_count as usize)?;
+
+        let mut sector = None;
+        for seg in segs {
+            let (Some(start), Some(end)) = (
+                self.seg_vertex(map, seg.start_vertex),
+                self.seg_vertex(map, seg.end_vertex),
+            ) else {
+                continue;
+            };
+            // Segs face into their subsector, so the inside is on their right
+            if (end.0 - start.0) * (y - start.1) - (end.1 - start.1) * (x - start.0) > 0.0 {
+                return None;
+            }
+
+            // Minisegs from extended nodes have no linedef
+            if sector.is_none() {
+                sector = self.seg_sector(seg, map).map(usize::from);
+            }
+        }
+
+        sector
     }
 
-    pub fn traverse_bsp(&self, player_x: f64, player_y: f64, node_index: u16) -> Vec<u16> {
-        if node_index & 0x8000 != 0 {
-            return vec![node_index & 0x7FFF];
+    // Subsectors front to back from the player's point of view, skipping subtrees
+    // whose bounding box lies outside the player's `fov` wide view
+    pub fn traverse_bsp(&self, player: &Player, fov: f64, node_index: u32) -> Vec<u32> {
+        if node_index & NF_SUBSECTOR != 0 {
+            return vec![node_index & !NF_SUBSECTOR];
         }
 
         let node = &self.nodes[node_index as usize];
-        let side = self.point_on_side(player_x, player_y, node);
+        let side = self.point_on_side(player.x, player.y, node);
 
         let mut visible_subsectors = Vec::new();
 
-        if side <= 0 {
-            visible_subsectors.extend(self.traverse_bsp(player_x, player_y, node.left_child));
-            if self.bbox_visible(player_x, player_y, &node.bbox_right) {
-                visible_subsectors.extend(self.traverse_bsp(player_x, player_y, node.right_child));
-            }
+        // The child on the player's side is nearer and goes first
+        let (near, far, far_bbox) = if side == 0 {
+            (node.right_child, node.left_child, node.left_bbox())
         } else {
-            visible_subsectors.extend(self.traverse_bsp(player_x, player_y, node.right_child));
-            if self.bbox_visible(player_x, player_y, &node.bbox_left) {
-                visible_subsectors.extend(self.traverse_bsp(player_x, player_y, node.left_child));
-            }
+            (node.left_child, node.right_child, node.right_bbox())
+        };
+        visible_subsectors.extend(self.traverse_bsp(player, fov, near));
+        if bbox_visible(player, fov, &far_bbox) {
+            visible_subsectors.extend(self.traverse_bsp(player, fov, far));
         }
 
         visible_subsectors
     }
 
-    fn point_on_side(&self, x: f64, y: f64, node: &BspNode) -> i32 {
+    // 0 for the front (right) side of the partition line, 1 for the back. As in
+    // Doom's R_PointOnSide, a point exactly on the line counts as the back.
+    pub fn point_on_side(&self, x: f64, y: f64, node: &BspNode) -> u8 {
         let dx = x - node.x as f64;
         let dy = y - node.y as f64;
 
         let cross_product = dx * node.dy as f64 - dy * node.dx as f64;
 
-        if cross_product > 0.0 { 1 } else { -1 }
-    }
-
-    fn bbox_visible(&self, player_x: f64, player_y: f64, bbox: &[i16; 4]) -> bool {
-        let distance = ((bbox[2] as f64 - player_x).powi(2) + (bbox[3] as f64 - player_y).powi(2)).sqrt();
-        distance < 1000.0
+        if cross_product > 0.0 { 0 } else { 1 }
     }
 
-    fn parse_subsectors(data: &[u8]) -> Result<Vec<Subsector>, Box<dyn std::error::Error>> {
+    fn parse_subsectors(data: &[u8]) -> Result<Vec<Subsector>, BspError> {
         let mut cursor = std::io::Cursor::new(data);
         let mut subsectors = Vec::new();
 
         while cursor.position() < data.len() as u64 {
             use byteorder::{LittleEndian, ReadBytesExt};
 
-            let seg_count = cursor.read_u16::<LittleEndian>()?;
-            let first_seg = cursor.read_u16::<LittleEndian>()?;
+            let seg_count = cursor.read_u16::<LittleEndian>()? as u32;
+            let first_seg = cursor.read_u16::<LittleEndian>()? as u32;
 
             subsectors.push(Subsector { seg_count, first_seg });
         }
@@ -141,15 +830,15 @@ impl BspTree {
         Ok(subsectors)
     }
 
-    fn parse_segs(data: &[u8]) -> Result<Vec<Seg>, Box<dyn std::error::Error>> {
+    fn parse_segs(data: &[u8]) -> Result<Vec<Seg>, BspError> {
         let mut cursor = std::io::Cursor::new(data);
         let mut segs = Vec::new();
 
         while cursor.position() < data.len() as u64 {
             use byteorder::{LittleEndian, ReadBytesExt};
 
-            let start_vertex = cursor.read_u16::<LittleEndian>()?;
-            let end_vertex = cursor.read_u16::<LittleEndian>()?;
+            let start_vertex = cursor.read_u16::<LittleEndian>()? as u32;
+            let end_vertex = cursor.read_u16::<LittleEndian>()? as u32;
             let angle = cursor.read_u16::<LittleEndian>()?;
             let linedef = cursor.read_u16::<LittleEndian>()?;
             let direction = cursor.read_u16::<LittleEndian>()?;
@@ -168,3 +857,429 @@ impl BspTree {
         Ok(segs)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 512 unit square room of one sector with its floor at 0
+    fn room() -> Map {
+        let corners = [(0, 0), (0, 512), (512, 512), (512, 0)];
+        let mut text = String::from("namespace = \"doom\";\n");
+        for (x, y) in corners {
+            text += &format!("vertex {{ x = {}; y = {}; }}\n", x, y);
+        }
+        for line in 0..corners.len() {
+            text += &format!("linedef {{ v1 = {}; v2 = {}; sidefront = {}; blocking = true; }}\n", line, (line + 1) % corners.len(), line);
+            text += "sidedef { sector = 0; }\n";
+        }
+        text += "sector { heightfloor = 0; heightceiling = 128; }\n";
+        Map::parse_udmf(&text).unwrap()
+    }
+
+    #[test]
+    fn forward_moves_along_facing() {
+        let mut player = Player::new(0.0, 0.0, std::f64::consts::FRAC_PI_4);
+        let cmd = TicCmd { forward: FORWARD_MOVE[0], ..TicCmd::default() };
+        player.run_tic_cmd(Duration::from_millis(100), &cmd, None, &MovementConfig::default());
+
+        // A tenth of a second at walking speed, half along each axis
+        let distance = MOVE_SPEED * 0.1;
+        assert!((player.x.hypot(player.y) - distance).abs() < 1e-9);
+        assert!((player.y.atan2(player.x) - std::f64::consts::FRAC_PI_4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn diagonal_move_into_wall_slides_along_it() {
+        let map = room();
+        let mut player = Player::new(490.0, 256.0, 0.0);
+
+        // Up and right into the east wall at x = 512, which the player's radius reaches
+        player.try_move(&map, 10.0, 10.0);
+        assert_eq!((player.x, player.y), (490.0, 266.0));
+    }
+
+    // One node over two subsectors of one and two segs, with a vertex past the map's four
+    fn xnod() -> Vec<u8> {
+        let mut data = b"XNOD".to_vec();
+        let u32s = |data: &mut Vec<u8>, values: &[u32]| values.iter().for_each(|value| data.extend(value.to_le_bytes()));
+        u32s(&mut data, &[4, 1]);
+        data.extend((3i32 << 15).to_le_bytes());
+        data.extend((-2i32 << 16).to_le_bytes());
+        u32s(&mut data, &[2, 1, 2, 3]);
+        for (start, end, linedef) in [(0u32, 1u32, 0u16), (1, 4, 1), (4, 2, 1)] {
+            u32s(&mut data, &[start, end]);
+            data.extend(linedef.to_le_bytes());
+            data.push(0);
+        }
+        u32s(&mut data, &[1]);
+        for value in [256i16, 0, 0, 512, 512, 0, 512, 256, 512, 0, 256, 0] {
+            data.extend(value.to_le_bytes());
+        }
+        u32s(&mut data, &[NF_SUBSECTOR, NF_SUBSECTOR | 1]);
+        data
+    }
+
+    #[test]
+    fn parses_extended_nodes() {
+        let tree = BspTree::parse_extended(&xnod()).unwrap();
+
+        assert_eq!(tree.original_vertex_count, 4);
+        assert_eq!(tree.extra_vertices, [(1.5, -2.0)]);
+        assert_eq!(tree.subsectors.len(), 2);
+        assert_eq!((tree.subsectors[1].first_seg, tree.subsectors[1].seg_count), (1, 2));
+        assert_eq!(tree.segs.len(), 3);
+        assert_eq!((tree.segs[1].start_vertex, tree.segs[1].end_vertex), (1, 4));
+        assert_eq!(tree.nodes.len(), 1);
+        assert_eq!((tree.nodes[0].x, tree.nodes[0].dy), (256, 512));
+        assert_eq!((tree.nodes[0].right_child, tree.nodes[0].left_child), (NF_SUBSECTOR, NF_SUBSECTOR | 1));
+    }
+
+    #[test]
+    fn extended_nodes_measure_offsets_along_hexen_linedefs() {
+        let mut vertexes = Vec::new();
+        for value in [0i16, 0, 0, 512, 512, 512, 512, 0] {
+            vertexes.extend(value.to_le_bytes());
+        }
+        // Two Hexen linedefs, the second from the room's first corner to its third
+        let mut linedefs = Vec::new();
+        for (v1, v2) in [(0u16, 1u16), (0, 2)] {
+            linedefs.extend(v1.to_le_bytes());
+            linedefs.extend(v2.to_le_bytes());
+            linedefs.extend([0; 8]);
+            linedefs.extend(0u16.to_le_bytes());
+            linedefs.extend(u16::MAX.to_le_bytes());
+        }
+        let lump = |name: &str, data: Vec<u8>| wad::WadLump { name: name.to_string(), data };
+        let wad = WadFile {
+            lumps: vec![
+                lump("MAP01", Vec::new()),
+                lump("VERTEXES", vertexes),
+                lump("LINEDEFS", linedefs),
+                lump("NODES", xnod()),
+                lump("BEHAVIOR", Vec::new()),
+            ],
+        };
+
+        let tree = BspTree::load_from_wad(&wad, "MAP01").unwrap();
+        // The second seg starts at the room's second corner, 512 units along its line
+        assert_eq!(tree.segs[1].offset, 512);
+        assert_eq!(tree.segs[0].offset, 0);
+    }
+
+    #[test]
+    fn extended_nodes_must_use_every_seg() {
+        let mut data = xnod();
+        // Claim a fourth seg the subsectors don't account for. The count follows the
+        // signature, vertex counts, the vertex and the subsectors.
+        let seg_count = 4 + 8 + 8 + 12;
+        data[seg_count..seg_count + 4].copy_from_slice(&4u32.to_le_bytes());
+        assert!(BspTree::parse_extended(&data).is_err());
+    }
+
+    #[test]
+    fn classic_subsector_flag_is_widened() {
+        let mut data = Vec::new();
+        for value in [0i16; 12] {
+            data.extend(value.to_le_bytes());
+        }
+        data.extend(0x8003u16.to_le_bytes());
+        data.extend(2u16.to_le_bytes());
+
+        let nodes = BspTree::parse_nodes(&data).unwrap();
+        assert_eq!((nodes[0].right_child, nodes[0].left_child), (NF_SUBSECTOR | 3, 2));
+    }
+
+    // A vertical partition at x, with x above it on the front (right) side
+    fn partition(x: i16, left: i16, right: i16, right_child: u32, left_child: u32) -> BspNode {
+        BspNode {
+            x, y: 0, dx: 0, dy: 100,
+            bbox_right: [100, 0, x, right],
+            bbox_left: [100, 0, left, x],
+            right_child,
+            left_child,
+        }
+    }
+
+    // A 400x100 corridor cut by partitions at x = 100, 200 and 300 into four
+    // subsectors, numbered from the west end
+    fn corridor() -> BspTree {
+        BspTree {
+            nodes: vec![
+                partition(100, 0, 200, NF_SUBSECTOR | 1, NF_SUBSECTOR),
+                partition(300, 200, 400, NF_SUBSECTOR | 3, NF_SUBSECTOR | 2),
+                partition(200, 0, 400, 1, 0),
+            ],
+            subsectors: (0..4).map(|_| Subsector { seg_count: 0, first_seg: 0 }).collect(),
+            segs: Vec::new(),
+            original_vertex_count: u32::MAX,
+            extra_vertices: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn traversal_goes_from_near_to_far() {
+        let tree = corridor();
+        let root = tree.nodes.len() as u32 - 1;
+        let centre = |subsector: u32| subsector as f64 * 100.0 + 50.0;
+
+        for (x, angle) in [(50.0, 0.0), (120.0, 0.0), (350.0, std::f64::consts::PI)] {
+            let player = Player::new(x, 50.0, angle);
+            let order = tree.traverse_bsp(&player, std::f64::consts::TAU, root);
+            assert_eq!(order.len(), 4);
+
+            let distances: Vec<f64> = order.iter().map(|&subsector| (centre(subsector) - x).abs()).collect();
+            assert!(distances.windows(2).all(|pair| pair[0] <= pair[1]), "from {}: {:?}", x, order);
+        }
+    }
+
+    #[test]
+    fn point_on_side_is_zero_in_front_and_one_behind() {
+        let tree = corridor();
+        let node = &tree.nodes[2];
+
+        assert_eq!(tree.point_on_side(350.0, 50.0, node), 0);
+        assert_eq!(tree.point_on_side(50.0, 50.0, node), 1);
+        // On the line itself, including past its end, as Doom counts it
+        assert_eq!(tree.point_on_side(200.0, 50.0, node), 1);
+        assert_eq!(tree.point_on_side(200.0, 500.0, node), 1);
+    }
+
+    #[test]
+    fn point_on_the_partition_takes_the_back_child_first() {
+        let tree = corridor();
+        let player = Player::new(200.0, 50.0, 0.0);
+        let order = tree.traverse_bsp(&player, std::f64::consts::TAU, 2);
+        assert_eq!(order[..2], [1, 0]);
+        assert_eq!(tree.subsector_at(200.0, 50.0), Some(1));
+        assert_eq!(tree.subsector_at(200.5, 50.0), Some(2));
+    }
+
+    #[test]
+    fn boxes_behind_or_beside_the_view_are_culled() {
+        // Stored top, bottom, left, right: 64 units square, 200 ahead of the player
+        let node = BspNode { bbox_right: [32, -32, 200, 264], bbox_left: [32, -32, -264, -200], ..partition(0, 0, 0, 0, 0) };
+        let ahead = node.right_bbox();
+        assert_eq!((ahead.min, ahead.max), (Point2D::new(200.0, -32.0), Point2D::new(264.0, 32.0)));
+
+        let player = Player::new(0.0, 0.0, 0.0);
+        let fov = std::f64::consts::FRAC_PI_2;
+        assert!(bbox_visible(&player, fov, &ahead));
+        assert!(!bbox_visible(&player, fov, &node.left_bbox()));
+
+        // Wholly outside the left edge of the view, though not behind
+        let beside = AABB::new(Point2D::new(50.0, 200.0), Point2D::new(100.0, 300.0));
+        assert!(!bbox_visible(&player, fov, &beside));
+
+        // Turning round brings the box behind into view
+        let turned = Player::new(0.0, 0.0, std::f64::consts::PI);
+        assert!(bbox_visible(&turned, fov, &node.left_bbox()));
+        assert!(!bbox_visible(&turned, fov, &ahead));
+    }
+
+    #[test]
+    fn lerping_across_east_takes_the_short_way_round() {
+        let from = Player::new(0.0, 0.0, 350f64.to_radians());
+        let to = Player { x: 64.0, ..Player::new(0.0, 0.0, 10f64.to_radians()) };
+
+        let halfway = from.lerp(&to, 0.5);
+        let off_east = halfway.angle.min(std::f64::consts::TAU - halfway.angle);
+        assert!(off_east < 1e-9, "halfway angle is {} degrees", halfway.angle.to_degrees());
+        assert_eq!(halfway.x, 32.0);
+
+        let quarter = from.lerp(&to, 0.25);
+        assert!((quarter.angle.to_degrees() - 355.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn noclip_player_walks_through_the_wall() {
+        let map = room();
+        let mut blocked = Player::new(490.0, 256.0, 0.0);
+        blocked.try_move(&map, 30.0, 0.0);
+        assert_eq!(blocked.x, 490.0);
+
+        let mut ghost = Player { noclip: true, ..Player::new(490.0, 256.0, 0.0) };
+        ghost.try_move(&map, 30.0, 0.0);
+        assert_eq!((ghost.x, ghost.y), (520.0, 256.0));
+        assert!(map.sector_at(ghost.x, ghost.y).is_none());
+    }
+
+    #[test]
+    fn flying_player_changes_height_and_stays_there() {
+        let map = room();
+        let mut player = Player::new(256.0, 256.0, 0.0);
+        player.snap_to_floor(&map);
+
+        player.fly_vertical(32.0);
+        assert_eq!(player.z, 0.0, "rose without flying");
+
+        player.set_fly(true);
+        player.fly_vertical(32.0);
+        player.apply_gravity(&map, Duration::from_millis(500));
+        assert_eq!(player.z, 32.0);
+
+        // Landing again once flying stops
+        player.set_fly(false);
+        for _ in 0..20 {
+            player.apply_gravity(&map, Duration::from_millis(100));
+        }
+        assert_eq!(player.z, 0.0);
+    }
+
+    // Two 256 unit rooms side by side, open to each other at x = 256. The west floor is
+    // at 0 and the east one at `east_floor`.
+    fn ledge(east_floor: i16) -> Map {
+        let mut text = String::from("namespace = \"doom\";\n");
+        for (x, y) in [(0, 0), (0, 256), (256, 256), (256, 0), (512, 256), (512, 0)] {
+            text += &format!("vertex {{ x = {}; y = {}; }}\n", x, y);
+        }
+        text += "sidedef { sector = 0; }\nsidedef { sector = 1; }\n";
+        for (v1, v2, side) in [(0, 1, 0), (1, 2, 0), (3, 0, 0), (2, 4, 1), (4, 5, 1), (5, 3, 1)] {
+            text += &format!("linedef {{ v1 = {}; v2 = {}; sidefront = {}; blocking = true; }}\n", v1, v2, side);
+        }
+        text += "linedef { v1 = 2; v2 = 3; sidefront = 0; sideback = 1; twosided = true; }\n";
+        text += "sector { heightfloor = 0; heightceiling = 128; }\n";
+        text += &format!("sector {{ heightfloor = {}; heightceiling = 128; }}\n", east_floor);
+        Map::parse_udmf(&text).unwrap()
+    }
+
+    #[test]
+    fn player_steps_up_a_low_ledge() {
+        let map = ledge(16);
+        let mut player = Player::new(240.0, 128.0, 0.0);
+        player.snap_to_floor(&map);
+
+        player.try_move(&map, 30.0, 0.0);
+        assert_eq!(player.x, 270.0);
+        player.apply_gravity(&map, Duration::from_millis(10));
+        assert_eq!(player.z, 16.0);
+    }
+
+    #[test]
+    fn player_is_blocked_by_a_high_ledge() {
+        let map = ledge(32);
+        let mut player = Player::new(230.0, 128.0, 0.0);
+        player.snap_to_floor(&map);
+
+        player.try_move(&map, 20.0, 0.0);
+        assert_eq!((player.x, player.y, player.z), (230.0, 128.0, 0.0));
+    }
+
+    #[test]
+    fn player_falls_off_a_ledge() {
+        let map = ledge(64);
+        let mut player = Player::new(280.0, 128.0, 0.0);
+        player.snap_to_floor(&map);
+        assert_eq!(player.z, 64.0);
+
+        player.try_move(&map, -60.0, 0.0);
+        assert_eq!(player.x, 220.0);
+        player.apply_gravity(&map, Duration::from_millis(100));
+        assert!(player.z > 0.0 && player.z < 64.0, "at {}", player.z);
+        for _ in 0..20 {
+            player.apply_gravity(&map, Duration::from_millis(100));
+        }
+        assert_eq!(player.z, 0.0);
+    }
+
+    #[test]
+    fn segs_resolve_to_the_sector_on_their_side() {
+        let map = ledge(0);
+        let seg = |start_vertex, end_vertex, linedef, direction| Seg { start_vertex, end_vertex, angle: 0, linedef, direction, offset: 0 };
+        let tree = BspTree {
+            nodes: Vec::new(),
+            subsectors: vec![Subsector { seg_count: 1, first_seg: 0 }, Subsector { seg_count: 1, first_seg: 1 }],
+            segs: vec![seg(2, 3, 6, 0), seg(3, 2, 6, 1), seg(1, 0, 0, 1)],
+            original_vertex_count: 6,
+            extra_vertices: Vec::new(),
+        };
+
+        // Along the open line is the west room's side, against it the east room's
+        assert_eq!(tree.seg_sector(&tree.segs[0], &map), Some(0));
+        assert_eq!(tree.seg_sector(&tree.segs[1], &map), Some(1));
+        // A one-sided line has nothing behind it
+        assert_eq!(tree.seg_sector(&tree.segs[2], &map), None);
+        assert_eq!(tree.subsectors[1].sector(&tree, &map), Some(1));
+    }
+
+    #[test]
+    fn demo_walks_the_player_along_its_recorded_path() {
+        // A 1.9 demo on E1M1 at skill 3: two tics walking east, then a quarter turn
+        // left while walking and one more step north
+        let mut data = vec![109, 2, 1, 1, 0, 0, 0, 0, 0, 1, 0, 0, 0];
+        for (forward, turn) in [(25, 0), (25, 0), (25, 0x40), (25, 0)] {
+            data.extend_from_slice(&[forward, 0, turn, 0]);
+        }
+        data.push(0x80);
+        let demo = input::Demo::parse(&data).unwrap();
+        assert_eq!((demo.skill, demo.episode, demo.map), (2, 1, 1));
+
+        let mut player = Player::new(0.0, 0.0, 0.0);
+        let tic = Duration::from_secs(1) / 35;
+        let step = MOVE_SPEED * tic.as_secs_f64();
+        let mut path = Vec::new();
+        for cmd in &demo.tics {
+            player.run_tic_cmd(tic, cmd, None, &MovementConfig::default());
+            path.push((player.x, player.y));
+        }
+
+        let expected = [(step, 0.0), (2.0 * step, 0.0), (2.0 * step, step), (2.0 * step, 2.0 * step)];
+        for (tic, (&(x, y), (expected_x, expected_y))) in path.iter().zip(expected).enumerate() {
+            assert!((x - expected_x).abs() < 1e-9 && (y - expected_y).abs() < 1e-9, "tic {} at {:?}", tic, (x, y));
+        }
+    }
+
+    #[test]
+    fn running_moves_further_than_walking_in_the_same_time() {
+        let movement = MovementConfig::default();
+        let distance = |forward, side| {
+            let mut player = Player::new(0.0, 0.0, 0.0);
+            player.run_tic_cmd(Duration::from_millis(100), &TicCmd { forward, side, ..TicCmd::default() }, None, &movement);
+            player.x.hypot(player.y)
+        };
+
+        // Doom's running move table entries give the running speeds
+        assert!((distance(FORWARD_MOVE[0], 0) - MOVE_SPEED * 0.1).abs() < 1e-9);
+        assert!((distance(FORWARD_MOVE[1], 0) - RUN_SPEED * 0.1).abs() < 1e-9);
+        assert!((distance(0, SIDE_MOVE[0]) - STRAFE_SPEED * 0.1).abs() < 1e-9);
+        assert!((distance(0, SIDE_MOVE[1]) - STRAFE_SPEED * movement.run_factor() * 0.1).abs() < 1e-9);
+        assert!(distance(-FORWARD_MOVE[1], 0) > distance(-FORWARD_MOVE[0], 0));
+    }
+
+    #[test]
+    fn turning_ramps_up_while_held_and_down_when_released() {
+        let movement = MovementConfig::default();
+        let tic = 1.0 / 35.0;
+
+        // A single tic's tap turns less than a tic at full speed would
+        let (rate, angle) = movement.ramp_turn(0.0, TURN_SPEED, tic);
+        assert!(rate < TURN_SPEED);
+        assert!(angle < TURN_SPEED * tic);
+
+        // Held for a while, it turns at full speed, and it takes about Doom's 6 tics
+        let mut rate = 0.0;
+        let mut tics = 0;
+        while rate < TURN_SPEED {
+            rate = movement.ramp_turn(rate, TURN_SPEED, tic).0;
+            tics += 1;
+        }
+        assert_eq!(rate, TURN_SPEED);
+        assert_eq!(tics, 6);
+        let (steady, angle) = movement.ramp_turn(rate, TURN_SPEED, tic);
+        assert_eq!(steady, TURN_SPEED);
+        assert!((angle - TURN_SPEED * tic).abs() < 1e-12);
+
+        // Letting go stops it in a couple of tics
+        let rate = movement.ramp_turn(rate, 0.0, tic).0;
+        assert_eq!(movement.ramp_turn(rate, 0.0, tic).0, 0.0);
+
+        // The angle turned doesn't depend on the frame rate
+        let (_, whole) = movement.ramp_turn(0.0, TURN_SPEED, 0.1);
+        let (mut rate, mut split) = (0.0, 0.0);
+        for _ in 0..10 {
+            let (next, angle) = movement.ramp_turn(rate, TURN_SPEED, 0.01);
+            (rate, split) = (next, split + angle);
+        }
+        assert!((whole - split).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,107 @@
+// Times whole frames of E1M1 from the player start, drawn headless so only
+// the renderer is measured, and the floor and ceiling row maths on its own. The frames
+// need game/Doom1.WAD; run with `cargo bench -p renderer`.
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use map::Map;
+use player::{BspTree, Player};
+use renderer::{FrameView, RenderConfig, Renderer, plane_row_step, plane_world_position};
+use wad::WadFile;
+
+const WAD_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../game/Doom1.WAD");
+const MAP_NAME: &str = "E1M1";
+
+fn render_frame(c: &mut Criterion) {
+    let Ok(file) = std::fs::File::open(WAD_PATH) else {
+        eprintln!("Skipping render benchmarks, {} not found", WAD_PATH);
+        return;
+    };
+    let wad = WadFile::load(file).expect("WAD should load");
+    let map = Map::load_from_wad(&wad, MAP_NAME).expect("map should load");
+
+    let mut player = match map.things.iter().find(|thing| thing.thing_type == 1) {
+        Some(start) => Player::new(start.x as f64, start.y as f64, start.angle_radians() as f64),
+        None => Player::new(0.0, 0.0, 0.0),
+    };
+    player.snap_to_floor(&map);
+
+    let mut group = c.benchmark_group("render_frame");
+    // The raycaster shares its columns out between threads, which pays off most at
+    // high resolutions
+    for (use_bsp, width, height) in [(true, 800, 600), (false, 800, 600), (false, 1920, 1080)] {
+        let config = RenderConfig {
+            width,
+            height,
+            use_bsp,
+            ..RenderConfig::default()
+        };
+        let mut renderer = Renderer::new_headless(config).expect("headless renderer should start");
+        renderer.load_textures(&wad).expect("textures should load");
+        renderer.load_map(&map);
+        renderer.load_bsp(BspTree::load_from_wad(&wad, MAP_NAME).expect("BSP should load"));
+
+        let view = FrameView {
+            map: Some(&map),
+            player: &player,
+            previous_player: None,
+            alpha: 1.0,
+            automap: false,
+            paused: false,
+            palette: 0,
+            fixed_colormap: None,
+            hud: None,
+            weapon: None,
+            console: None,
+            sprites: &[],
+        };
+        let name = format!(
+            "{}_{}x{}",
+            if use_bsp { "bsp" } else { "raycast" },
+            width,
+            height
+        );
+        group.bench_function(name, |b| {
+            b.iter(|| renderer.render_frame(&view).expect("frame should render"))
+        });
+    }
+    group.finish();
+}
+
+// One 800 pixel plane row, with trig per column against stepping between its edges
+fn plane_row(c: &mut Criterion) {
+    let player = Player::new(1056.0, -3616.0, 1.0);
+    let config = RenderConfig::default();
+    let projection_distance = config.projection_distance();
+    let half_width = config.width as f64 / 2.0;
+    let distance = 200.0;
+
+    let mut group = c.benchmark_group("plane_row");
+    group.bench_function("trig", |b| {
+        b.iter(|| {
+            (0..config.width).fold(0.0, |sum, x| {
+                let angle = ((x as f64 + 0.5 - half_width) / projection_distance).atan();
+                let (world_x, world_y) = plane_world_position(&player, angle, black_box(distance));
+                sum + world_x + world_y
+            })
+        })
+    });
+    group.bench_function("stepped", |b| {
+        b.iter(|| {
+            let ((mut world_x, mut world_y), (step_x, step_y)) = plane_row_step(
+                &player,
+                black_box(distance),
+                config.width,
+                projection_distance,
+            );
+            (0..config.width).fold(0.0, |sum, _| {
+                let position = world_x + world_y;
+                world_x += step_x;
+                world_y += step_y;
+                sum + position
+            })
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, render_frame, plane_row);
+criterion_main!(benches);
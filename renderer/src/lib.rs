@@ -1,249 +1,3622 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use rayon::prelude::*;
 use sdl2::render::Canvas;
+use sdl2::rect::Point;
 use sdl2::video::Window;
-use sdl2::pixels::Color;
+use sdl2::pixels::{Color, PixelFormatEnum};
 use std::f64::consts::PI;
+use std::io::{BufWriter, Cursor, Read};
+use std::path::{Path, PathBuf};
+
+use map::Map;
+use math::{AABB, Point2D, normalize_angle_f64};
+use player::{BspTree, NF_SUBSECTOR, Player};
+use wad::WadFile;
+
+const WINDOW_TITLE: &str = "Doom Port";
+const DEFAULT_MAX_VIEW_DISTANCE: f64 = 1000.0;
+// Fraction of the view distance where fog starts to thicken, reaching full fog at the far plane
+const FOG_START: f64 = 0.5;
+const WALL_CELL_SIZE: f64 = 8.0;
+const FLAT_CELL_SIZE: f64 = 16.0;
+const FLAT_SIZE: usize = 64;
+// Where the weapon sprite is drawn from in 320x200 space before its offsets and the
+// bob, which moves it up to MAX_WEAPON_BOB units
+const WEAPON_X: i32 = 1;
+const WEAPON_TOP: i32 = 32;
+const MAX_WEAPON_BOB: f64 = 16.0;
+
+// The resolution Doom's menus and status bar pictures are laid out for
+const DOOM_SCREEN_WIDTH: i32 = 320;
+const DOOM_SCREEN_HEIGHT: i32 = 200;
+
+// Doom's status bar layout in that space: the bar along the bottom, the right edges of
+// its big ammo, health and armor numbers, and the key slots from top to bottom
+const STATUS_BAR_Y: i32 = 168;
+const ST_NUMBER_Y: i32 = 171;
+const ST_AMMO_X: i32 = 44;
+const ST_HEALTH_X: i32 = 90;
+const ST_ARMOR_X: i32 = 221;
+const ST_KEY_X: i32 = 239;
+const ST_KEY_Y: [i32; 3] = [171, 181, 191];
+// The small current and maximum counts of each type of ammo down the right hand side,
+// in HudStatus::ammo_counts order
+const ST_AMMO_COUNT_X: i32 = 288;
+const ST_MAX_AMMO_X: i32 = 314;
+const ST_AMMO_COUNT_Y: [i32; 4] = [173, 179, 185, 191];
+
+// Doom's HUD font has pictures STCFN033 to STCFN095 for '!' to '_', with no lower
+// case. Spaces and characters it lacks advance by HU_SPACE_WIDTH.
+const HU_FONT_START: u8 = b'!';
+const HU_FONT_SIZE: usize = 63;
+const HU_SPACE_WIDTH: i32 = 4;
+// Console lines in 320x200 space, and the band drawn behind them
+const CONSOLE_LINE_HEIGHT: i32 = 9;
+const CONSOLE_MARGIN: i32 = 2;
+
+// Doom's light diminishing tables: 16 sector light bands mapped onto 32 colormaps,
+// shifted darker with distance up to 48 steps of wall scale
+const LIGHT_LEVELS: usize = 16;
+const NUM_COLORMAPS: usize = 32;
+const MAX_LIGHT_SCALE: usize = 48;
+// The colormap after the light levels, which turns everything an inverted grey while
+// the player is invulnerable
+pub const INVERSE_COLORMAP: usize = 32;
+// Invulnerability starts flickering off when this many tics are left
+const INVULNERABILITY_FLICKER_TICS: f64 = 4.0 * 32.0;
+
+// Fuzzy things, like a partially invisible player's weapon, copy what's drawn one row
+// above or below each of their pixels, darkened by a colormap, stepping through
+// Doom's fuzz offsets from pixel to pixel
+const FUZZ_OFFSETS: [i32; 50] = [
+    1, -1, 1, -1, 1, 1, -1, 1, 1, -1, 1, 1, 1, -1, 1, 1, 1, -1, -1, -1, -1, 1, -1, -1, 1, 1, 1, 1, -1, 1, -1, 1, 1, -1, -1, 1, 1, -1, -1,
+    -1, -1, 1, 1, 1, 1, -1, 1, 1, -1, 1,
+];
+const FUZZ_COLORMAP: usize = 6;
+
+// PLAYPAL's 14 palettes: the normal one, 8 increasingly red ones for pain, 4 gold ones
+// for pickups and green for the radiation suit
+pub const NUM_PALETTES: usize = 14;
+const START_RED_PALETTES: usize = 1;
+const NUM_RED_PALETTES: usize = 8;
+const START_BONUS_PALETTES: usize = 9;
+const NUM_BONUS_PALETTES: usize = 4;
+pub const RADIATION_PALETTE: usize = 13;
+
+const AUTOMAP_MIN_SCALE: f64 = 0.02;
+const AUTOMAP_MAX_SCALE: f64 = 2.0;
+const AUTOMAP_ARROW_LENGTH: f64 = 16.0;
+
+// Linedef flags moving where a wall texture's rows start: unpegged upper textures
+// hang from the ceiling, unpegged lower and middle ones sit on the floor
+const ML_DONTPEGTOP: u16 = 0x0008;
+const ML_DONTPEGBOTTOM: u16 = 0x0010;
+
+// Sector light effects in tics, from Doom's lights.c. Strobes stay bright for
+// STROBE_BRIGHT and dark for FAST_DARK or SLOW_DARK, glows move GLOW_SPEED light
+// levels a tic, and random blinks and fire flickers change in steps of the given tics.
+const STROBE_BRIGHT: u64 = 5;
+const FAST_DARK: u64 = 15;
+const SLOW_DARK: u64 = 35;
+const GLOW_SPEED: u64 = 8;
+const BLINK_STEP: u64 = 72;
+const FLICKER_STEP: u64 = 4;
+
+// Segs closer than this to the eye are clipped before projection
+const NEAR_PLANE: f64 = 1.0;
+
+const TICS_PER_SECOND: f64 = 35.0;
+const ANIMATION_SPEED: u32 = 8;
+
+// Vanilla Doom's animated flats and wall textures as (is_flat, last frame, first frame),
+// used when the WAD has no Boom ANIMATED lump
+const VANILLA_ANIMATIONS: &[(bool, &str, &str)] = &[
+    (true, "NUKAGE3", "NUKAGE1"),
+    (true, "FWATER4", "FWATER1"),
+    (true, "SWATER4", "SWATER1"),
+    (true, "LAVA4", "LAVA1"),
+    (true, "BLOOD3", "BLOOD1"),
+    (true, "RROCK08", "RROCK05"),
+    (true, "SLIME04", "SLIME01"),
+    (true, "SLIME08", "SLIME05"),
+    (true, "SLIME12", "SLIME09"),
+    (false, "BLODGR4", "BLODGR1"),
+    (false, "SLADRIP3", "SLADRIP1"),
+    (false, "BLODRIP4", "BLODRIP1"),
+    (false, "FIREWALL", "FIREWALA"),
+    (false, "GSTFONT3", "GSTFONT1"),
+    (false, "FIRELAVA", "FIRELAV3"),
+    (false, "FIREMAG3", "FIREMAG1"),
+    (false, "FIREBLU2", "FIREBLU1"),
+    (false, "ROCKRED3", "ROCKRED1"),
+    (false, "BFALL4", "BFALL1"),
+    (false, "SFALL4", "SFALL1"),
+    (false, "WFALL4", "WFALL1"),
+    (false, "DBRAIN4", "DBRAIN1"),
+];
+
+#[derive(Debug, Clone, Copy)]
+pub struct RenderConfig {
+    pub width: u32,
+    pub height: u32,
+    pub fov: f64, // Horizontal field of view in radians
+    // Walk the BSP tree front to back when one is loaded, otherwise raycast the wall grid
+    pub use_bsp: bool,
+    // Walls and planes further than this many map units aren't drawn
+    pub max_view_distance: f64,
+    // Colour the view fades to towards max_view_distance, on top of light diminishing
+    pub fog_color: Option<[u8; 3]>,
+    // Present frames in step with the display's refresh. Headless renderers ignore it.
+    pub vsync: bool,
+}
+
+impl RenderConfig {
+    // Distance from the eye to the projection plane in pixels. Walls and planes use
+    // it vertically too, so pixels stay square at any aspect ratio.
+    pub fn projection_distance(&self) -> f64 {
+        (self.width as f64 / 2.0) / (self.fov / 2.0).tan()
+    }
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        RenderConfig {
+            width: 800,
+            height: 600,
+            fov: PI / 3.0,
+            use_bsp: true,
+            max_view_distance: DEFAULT_MAX_VIEW_DISTANCE,
+            fog_color: None,
+            vsync: false,
+        }
+    }
+}
 
 pub struct Renderer {
-    canvas: Canvas<Window>,
+    canvas: RenderTarget,
     screen_width: u32,
     screen_height: u32,
+    fov: f64,
+    use_bsp: bool,
+    max_view_distance: f64,
+    fog_color: Option<[u8; 3]>,
+    vsync: bool,
+    bsp: Option<BspTree>,
+    // Columns already covered by a solid wall during BSP rendering
+    solid_columns: ClipRange,
+    wall_grid: Option<WallGrid>,
+    sector_grid: Option<SectorGrid>,
+    textures: Option<TextureManager>,
+    // Doom's M_PAUSE picture, drawn over the view while the game is paused
+    pause_picture: Option<Picture>,
+    // None when the WAD is missing any of them, for a status bar of plain rectangles
+    status_bar_pictures: Option<StatusBarPictures>,
+    // Doom's HUD font by character from HU_FONT_START, None for the ones the WAD lacks
+    hud_font: Vec<Option<Picture>>,
+    // Screen pixels per map unit on the automap
+    automap_scale: f64,
+    // Distance to the nearest wall drawn in each screen column this frame
+    depth_buffer: Vec<f64>,
+    // Each sector's darkest neighbouring light, for light effects, and the light levels
+    // the effects give this frame. Sectors without an entry use their own level.
+    min_lights: Vec<i16>,
+    light_levels: Vec<i16>,
+    stats: RenderStats,
+    // Where fuzzed pixels are in FUZZ_OFFSETS, carried on from one fuzzed thing to the next
+    fuzz_position: usize,
+    // Rows of each column still open for walls: from the ceiling clip down to, but not
+    // including, the floor clip. Two-sided lines narrow them as they're drawn.
+    ceiling_clip: Vec<i32>,
+    floor_clip: Vec<i32>,
+    // Written from the back buffer at the end of the next frame, before it's presented
+    pending_screenshot: Option<PathBuf>,
+}
+
+// Frames are drawn into an RGBA buffer in memory and handed to the window once
+// they're finished, as one texture, rather than making an SDL call per pixel.
+// Without a window the buffer itself is the output.
+struct RenderTarget {
+    window: Option<Canvas<Window>>,
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+    draw_color: Color,
+}
+
+impl RenderTarget {
+    fn new(window: Option<Canvas<Window>>, width: u32, height: u32) -> Self {
+        RenderTarget {
+            window,
+            width,
+            height,
+            pixels: vec![0; width as usize * height as usize * 4],
+            draw_color: Color::RGB(0, 0, 0),
+        }
+    }
+
+    fn set_draw_color(&mut self, color: Color) {
+        self.draw_color = color;
+    }
+
+    fn clear(&mut self) {
+        let Color { r, g, b, a } = self.draw_color;
+        for pixel in self.pixels.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[r, g, b, a]);
+        }
+    }
+
+    // Points outside the frame are ignored
+    fn draw_point<P: Into<Point>>(&mut self, point: P) -> Result<(), String> {
+        let point = point.into();
+        if point.x() < 0 || point.y() < 0 || point.x() as u32 >= self.width || point.y() as u32 >= self.height {
+            return Ok(());
+        }
+        let index = (point.y() as usize * self.width as usize + point.x() as usize) * 4;
+        let Color { r, g, b, a } = self.draw_color;
+        self.pixels[index..index + 4].copy_from_slice(&[r, g, b, a]);
+        Ok(())
+    }
+
+    // Replaces a pixel with a darkened copy of the one `offset` rows from it, kept
+    // inside the frame
+    fn draw_fuzz_point(&mut self, x: i32, y: i32, offset: i32) -> Result<(), String> {
+        if x < 0 || y < 0 || x as u32 >= self.width || y as u32 >= self.height {
+            return Ok(());
+        }
+        let source_y = (y + offset).clamp(0, self.height as i32 - 1);
+        let index = (source_y as usize * self.width as usize + x as usize) * 4;
+        let source = Color::RGB(self.pixels[index], self.pixels[index + 1], self.pixels[index + 2]);
+        self.set_draw_color(darken(source, FUZZ_COLORMAP));
+        self.draw_point((x, y))
+    }
+
+    // Bresenham's line over the part of the line inside the frame
+    fn draw_line<P1: Into<Point>, P2: Into<Point>>(&mut self, start: P1, end: P2) -> Result<(), String> {
+        let (start, end) = (start.into(), end.into());
+        let Some(((mut x, mut y), (end_x, end_y))) = clip_line(
+            (start.x() as f64, start.y() as f64),
+            (end.x() as f64, end.y() as f64),
+            self.width as f64,
+            self.height as f64,
+        ) else {
+            return Ok(());
+        };
+
+        let (dx, dy) = ((end_x - x).abs(), -(end_y - y).abs());
+        let (step_x, step_y) = ((end_x - x).signum(), (end_y - y).signum());
+        let mut error = dx + dy;
+        loop {
+            self.draw_point((x, y))?;
+            if x == end_x && y == end_y {
+                return Ok(());
+            }
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    // Copies the finished frame to the window through a streaming texture, stretched
+    // to fill it
+    fn present(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(canvas) = self.window.as_mut() else {
+            return Ok(());
+        };
+        let texture_creator = canvas.texture_creator();
+        let mut texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGBA32, self.width, self.height)?;
+        texture.update(None, &self.pixels, self.width as usize * 4)?;
+        canvas.copy(&texture, None, None)?;
+        canvas.present();
+        Ok(())
+    }
+
+    fn window_mut(&mut self) -> Option<&mut Window> {
+        self.window.as_mut().map(|canvas| canvas.window_mut())
+    }
+}
+
+// Draws a picture's opaque pixels with its origin at `position`, in units `scale`
+// output pixels across and down. With `fuzz` the pixels are fuzzed instead, carrying
+// on through the fuzz offsets from that position.
+fn draw_picture_into(
+    canvas: &mut RenderTarget,
+    palette: &[[u8; 3]; 256],
+    picture: &Picture,
+    position: (i32, i32),
+    scale: (f64, f64),
+    mut fuzz: Option<&mut usize>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let texture = &picture.texture;
+    let (scale_x, scale_y) = scale;
+    let left = (position.0 - picture.left_offset as i32) as f64;
+    let top = (position.1 - picture.top_offset as i32) as f64;
+
+    let columns = (left * scale_x).max(0.0) as u32..((left + texture.width as f64) * scale_x).clamp(0.0, canvas.width as f64) as u32;
+    let rows = (top * scale_y).max(0.0) as u32..((top + texture.height as f64) * scale_y).clamp(0.0, canvas.height as f64) as u32;
+    for screen_y in rows {
+        let v = (screen_y as f64 / scale_y - top) as usize;
+        for screen_x in columns.clone() {
+            let u = (screen_x as f64 / scale_x - left) as usize;
+            if u >= texture.width as usize || v >= texture.height as usize || !texture.is_opaque(u, v) {
+                continue;
+            }
+            if let Some(position) = fuzz.as_deref_mut() {
+                canvas.draw_fuzz_point(screen_x as i32, screen_y as i32, FUZZ_OFFSETS[*position])?;
+                *position = (*position + 1) % FUZZ_OFFSETS.len();
+                continue;
+            }
+            let [r, g, b] = palette[texture.pixels[v * texture.width as usize + u] as usize];
+            canvas.set_draw_color(Color::RGB(r, g, b));
+            canvas.draw_point((screen_x as i32, screen_y as i32))?;
+        }
+    }
+
+    Ok(())
+}
+
+// Liang-Barsky clipping of a line to a width by height area, giving the pixel
+// coordinates of the visible part
+fn clip_line(start: (f64, f64), end: (f64, f64), width: f64, height: f64) -> Option<((i32, i32), (i32, i32))> {
+    let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+    let (mut enter, mut exit) = (0.0f64, 1.0f64);
+
+    for (direction, distance) in [(-dx, start.0), (dx, width - 1.0 - start.0), (-dy, start.1), (dy, height - 1.0 - start.1)] {
+        if direction == 0.0 {
+            if distance < 0.0 {
+                return None;
+            }
+            continue;
+        }
+        let t = distance / direction;
+        if direction < 0.0 {
+            enter = enter.max(t);
+        } else {
+            exit = exit.min(t);
+        }
+    }
+    if enter > exit {
+        return None;
+    }
+
+    let point = |t: f64| ((start.0 + dx * t).round() as i32, (start.1 + dy * t).round() as i32);
+    Some((point(enter), point(exit)))
+}
+
+// Everything from the game needed to draw one frame
+pub struct FrameView<'a> {
+    pub map: Option<&'a Map>,
+    pub player: &'a Player,
+    // The player as of the previous tick. The view is drawn alpha of the way from it
+    // to player, the fraction of a tick that has passed since the latest one.
+    pub previous_player: Option<&'a Player>,
+    pub alpha: f64,
+    pub automap: bool,
+    pub paused: bool,
+    // PLAYPAL palette to draw with, 0 for no tint. See flash_palette.
+    pub palette: usize,
+    // Colormap the view is drawn with in place of its light levels, like
+    // INVERSE_COLORMAP while invulnerable. See fixed_colormap.
+    pub fixed_colormap: Option<usize>,
+    // What the status bar shows, None to leave it off
+    pub hud: Option<HudStatus>,
+    // The weapon drawn over the view, None for none
+    pub weapon: Option<WeaponOverlay>,
+    // Lines of the console from top to bottom, drawn over the top of the screen. None
+    // while it's closed.
+    pub console: Option<&'a [String]>,
+    // Things to draw in the view, in any order
+    pub sprites: &'a [Sprite],
+}
+
+// The player's weapon as drawn over the view: a frame of its sprite, swaying with the
+// player's movement while it's ready to fire
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WeaponOverlay {
+    pub sprite: &'static str,
+    pub frame: char,
+    pub ready: bool,
+    // Seconds of game time, for where the bob is in its swing
+    pub time: f64,
+    // Drawn fuzzed, while the player is partially invisible
+    pub fuzz: bool,
+}
+
+// The player's state as the status bar shows it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HudStatus {
+    pub health: i32,
+    pub armor: i32,
+    // Ammo for the selected weapon and the most of it that can be carried, None for
+    // weapons that don't use ammo
+    pub ammo: Option<u32>,
+    pub ammo_max: u32,
+    // Current and maximum bullets, shells, rockets and cells
+    pub ammo_counts: [(u32, u32); 4],
+    // The blue, yellow and red keys held
+    pub keys: [HudKey; 3],
+}
+
+// Which key of a colour the player has. With both, Doom shows the skull key.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HudKey {
+    #[default]
+    None,
+    Card,
+    Skull,
+}
+
+// Work done drawing the last frame, for finding out where frame time goes. Only the
+// raycaster casts rays, and only BSP rendering visits subsectors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    pub rays_cast: u32,
+    pub wall_slices: u32,
+    pub subsectors_visited: u32,
+    pub sprites_drawn: u32,
+    pub sprites_culled: u32,
 }
 
+// A thing in the view: a frame of one of the WAD's sprites with its feet at (x, y, z)
+#[derive(Debug, Clone, PartialEq)]
 pub struct Sprite {
-    pub texture: Texture,
+    pub name: String,
+    pub frame: char,
     pub x: f64,
     pub y: f64,
-    pub scale: f64,
+    pub z: f64,
+    // The direction the thing faces in radians, which picks the rotation seen
+    pub angle: f64,
+    // Drawn fuzzed, for things that are partially invisible such as spectres
+    pub fuzz: bool,
+}
+
+// Where a sprite lands on screen, shared by each of its columns: the row its top is
+// drawn from, screen pixels per texel, depth and the light it stands in
+struct SpritePlacement {
+    top: f64,
+    scale: f64,
+    depth: f64,
+    light: i16,
+    fuzz: bool,
 }
 
 impl Renderer {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn new(sdl_context: &sdl2::Sdl, config: RenderConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::check_config(&config)?;
+
         let video_subsystem = sdl_context.video()?;
-        let window = video_subsystem.window("Doom Port", 800, 600)
+        let window = video_subsystem.window(WINDOW_TITLE, config.width, config.height)
             .position_centered()
             .build()?;
 
-        let canvas = window.into_canvas().build()?;
+        let canvas = if config.vsync {
+            window.into_canvas().present_vsync().build()?
+        } else {
+            window.into_canvas().build()?
+        };
+
+        Ok(Self::with_target(RenderTarget::new(Some(canvas), config.width, config.height), config))
+    }
+
+    // Draws into memory only, for tests, benchmarks and screenshots without a display.
+    // Frames are read back with frame_buffer.
+    pub fn new_headless(config: RenderConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::check_config(&config)?;
+
+        Ok(Self::with_target(RenderTarget::new(None, config.width, config.height), config))
+    }
+
+    fn check_config(config: &RenderConfig) -> Result<(), Box<dyn std::error::Error>> {
+        if config.width == 0 || config.height == 0 {
+            return Err("Screen resolution must be non-zero".into());
+        }
+        if !(config.fov > 0.0 && config.fov < PI) {
+            return Err(format!("Field of view {} must be between 0 and pi radians", config.fov).into());
+        }
+        if config.max_view_distance.is_nan() || config.max_view_distance <= 0.0 {
+            return Err(format!("View distance {} must be positive", config.max_view_distance).into());
+        }
+        Ok(())
+    }
 
-        Ok(Renderer {
+    fn with_target(canvas: RenderTarget, config: RenderConfig) -> Self {
+        Renderer {
             canvas,
-            screen_width: 800,
-            screen_height: 600,
-        })
+            screen_width: config.width,
+            screen_height: config.height,
+            fov: config.fov,
+            use_bsp: config.use_bsp,
+            max_view_distance: config.max_view_distance,
+            fog_color: config.fog_color,
+            vsync: config.vsync,
+            bsp: None,
+            solid_columns: ClipRange::new(config.width),
+            wall_grid: None,
+            sector_grid: None,
+            textures: None,
+            pause_picture: None,
+            status_bar_pictures: None,
+            hud_font: Vec::new(),
+            automap_scale: 0.2,
+            depth_buffer: vec![f64::INFINITY; config.width as usize],
+            min_lights: Vec::new(),
+            light_levels: Vec::new(),
+            stats: RenderStats::default(),
+            fuzz_position: 0,
+            ceiling_clip: vec![0; config.width as usize],
+            floor_clip: vec![config.height as i32; config.width as usize],
+            pending_screenshot: None,
+        }
     }
 
-    pub fn render_frame(&mut self, game_state: &GameState) -> Result<(), Box<dyn std::error::Error>> {
-        // Clear screen
-        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
-        self.canvas.clear();
+    // The last frame drawn, as tightly packed rows of RGBA bytes
+    pub fn frame_buffer(&self) -> &[u8] {
+        &self.canvas.pixels
+    }
 
-        if let Some(map) = &game_state.current_map {
-            self.render_3d_view(map, &game_state.player)?;
+    pub fn stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    pub fn config(&self) -> RenderConfig {
+        RenderConfig {
+            width: self.screen_width,
+            height: self.screen_height,
+            fov: self.fov,
+            use_bsp: self.use_bsp,
+            max_view_distance: self.max_view_distance,
+            fog_color: self.fog_color,
+            vsync: self.vsync,
         }
+    }
 
-        self.canvas.present();
+    pub fn load_textures(&mut self, wad: &WadFile) -> Result<(), Box<dyn std::error::Error>> {
+        self.textures = Some(TextureManager::load_from_wad(wad)?);
+        self.pause_picture = match wad.find_lump("M_PAUSE") {
+            Some(lump) => Some(Picture::from_lump(&lump.data)?),
+            None => None,
+        };
+        self.status_bar_pictures = StatusBarPictures::load(wad)?;
+        self.hud_font = (0..HU_FONT_SIZE)
+            .map(|index| match wad.find_lump(&format!("STCFN{:03}", HU_FONT_START as usize + index)) {
+                Some(lump) => Picture::from_lump(&lump.data).map(Some),
+                None => Ok(None),
+            })
+            .collect::<Result<_, _>>()?;
         Ok(())
     }
 
-    fn render_floor_ceiling(&mut self, map: &Map, player: &Player) -> Result<(), Box<dyn std::error::Error>> {
-        let half_height = self.screen_height as f64 / 2.0;
+    pub fn load_map(&mut self, map: &Map) {
+        self.wall_grid = Some(WallGrid::from_map(map, WALL_CELL_SIZE));
+        self.sector_grid = Some(SectorGrid::from_map(map, FLAT_CELL_SIZE));
+        self.bsp = None;
+        self.min_lights = (0..map.sectors.len())
+            .map(|sector| map.min_neighbor_light(sector).unwrap_or(map.sectors[sector].light_level))
+            .collect();
+        self.light_levels.clear();
+    }
 
-        for y in 0..self.screen_height {
-            if y < half_height as u32 {
-                self.render_horizontal_plane(y, player, true)?;
+    // Nodes for the map passed to load_map, used instead of raycasting when enabled
+    pub fn load_bsp(&mut self, bsp: BspTree) {
+        self.bsp = Some(bsp);
+    }
+
+    pub fn render_frame(&mut self, view: &FrameView) -> Result<(), Box<dyn std::error::Error>> {
+        // Clear screen. With fog the horizon and anything past the far plane is fog.
+        let [r, g, b] = self.fog_color.unwrap_or([0, 0, 0]);
+        self.canvas.set_draw_color(Color::RGB(r, g, b));
+        self.canvas.clear();
+        self.depth_buffer.fill(f64::INFINITY);
+        self.stats = RenderStats::default();
+
+        if let Some(textures) = self.textures.as_mut() {
+            textures.set_active_palette(view.palette);
+            textures.set_fixed_colormap(view.fixed_colormap);
+        }
+
+        let player = match view.previous_player {
+            Some(previous) => previous.lerp(view.player, view.alpha.clamp(0.0, 1.0)),
+            None => view.player.clone(),
+        };
+
+        if let Some(map) = view.map {
+            if view.automap {
+                self.render_automap(map, &player)?;
             } else {
-                // Render floor
-                self.render_horizontal_plane(y, player, false)?;
+                if self.wall_grid.is_none() {
+                    self.load_map(map);
+                }
+                self.render_floor_ceiling(map, &player)?;
+                self.render_3d_view(map, &player)?;
+                self.render_sprites(view.sprites, map, &player)?;
             }
         }
 
-        Ok(())
-    }
+        if let (Some(weapon), false) = (&view.weapon, view.automap) {
+            self.draw_weapon(weapon, player.velocity)?;
+        }
+        if let Some(status) = &view.hud {
+            self.draw_status_bar(status)?;
+        }
 
-    fn render_horizontal_plane(&mut self, screen_y: u32, player: &Player, is_ceiling: bool) -> Result<(), Box<dyn std::error::Error>> {
-        let half_height = self.screen_height as f64 / 2.0;
-        let distance = if is_ceiling {
-            (player.height * half_height) / (half_height - screen_y as f64)
-        } else {
-            (player.height * half_height) / (screen_y as f64 - half_height)
-        };
+        if let Some(lines) = view.console {
+            self.draw_console(lines)?;
+        }
 
-        for x in 0..self.screen_width {
-            let angle = player.angle + (x as f64 - self.screen_width as f64 / 2.0) * 0.001;
-            let world_x = player.x + angle.cos() * distance;
-            let world_y = player.y + angle.sin() * distance;
+        // Centred near the top of the screen, as in Doom
+        if view.paused
+            && let Some(picture) = self.pause_picture.take()
+        {
+            let result = self.draw_screen_picture(&picture, (DOOM_SCREEN_WIDTH - picture.texture.width as i32) / 2, 4);
+            self.pause_picture = Some(picture);
+            result?;
+        }
 
-            let color = self.sample_floor_texture(world_x, world_y, is_ceiling);
-            self.canvas.set_draw_color(color);
-            self.canvas.draw_point((x as i32, screen_y as i32))?;
+        if let Some(path) = self.pending_screenshot.take() {
+            self.save_screenshot(&path)
+                .map_err(|e| format!("Failed to save screenshot {}: {}", path.display(), e))?;
         }
 
-        Ok(())
+        self.canvas.present()
     }
 
-    fn render_sprites(&mut self, sprites: &[Sprite], player: &Player) -> Result<(), Box<dyn std::error::Error>> {
-        // Sort sprites by distance for proper depth ordering
-        let mut sorted_sprites: Vec<_> = sprites.iter().enumerate().collect();
-        sorted_sprites.sort_by(|a, b| {
-            let dist_a = ((a.1.x - player.x).powi(2) + (a.1.y - player.y).powi(2)).sqrt();
-            let dist_b = ((b.1.x - player.x).powi(2) + (b.1.y - player.y).powi(2)).sqrt();
-            dist_b.partial_cmp(&dist_a).unwrap()
-        });
+    // Draws a picture with its pixels one to one with the output's, at screen pixel
+    // (x, y) less its offsets. Its transparent pixels leave the frame as it was.
+    pub fn draw_picture(&mut self, picture: &Picture, x: i32, y: i32) -> Result<(), Box<dyn std::error::Error>> {
+        self.blit_picture(picture, x, y, 1.0, 1.0)
+    }
+
+    // Draws a picture placed in Doom's 320x200 screen space, stretched to cover the
+    // same part of the output. Its offsets move it left and up from (x, y).
+    pub fn draw_screen_picture(&mut self, picture: &Picture, x: i32, y: i32) -> Result<(), Box<dyn std::error::Error>> {
+        let scale_x = self.screen_width as f64 / DOOM_SCREEN_WIDTH as f64;
+        let scale_y = self.screen_height as f64 / DOOM_SCREEN_HEIGHT as f64;
+        self.blit_picture(picture, x, y, scale_x, scale_y)
+    }
 
-        for (_, sprite) in sorted_sprites {
-            self.render_sprite(sprite, player)?;
+    // Draws a line of text in Doom's HUD font from (x, y) in 320x200 space, in upper
+    // case
+    pub fn draw_text(&mut self, text: &str, x: i32, y: i32) -> Result<(), Box<dyn std::error::Error>> {
+        let font = std::mem::take(&mut self.hud_font);
+        let mut result = Ok(());
+        let mut x = x;
+        for character in text.bytes().map(|byte| byte.to_ascii_uppercase()) {
+            let picture = character
+                .checked_sub(HU_FONT_START)
+                .and_then(|index| font.get(index as usize))
+                .and_then(Option::as_ref);
+            let Some(picture) = picture else {
+                x += HU_SPACE_WIDTH;
+                continue;
+            };
+            result = self.draw_screen_picture(picture, x, y);
+            if result.is_err() {
+                break;
+            }
+            x += picture.texture.width as i32;
         }
+        self.hud_font = font;
+        result
+    }
 
+    // The console's lines over a dark band across the top of the screen
+    fn draw_console(&mut self, lines: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+        let height = lines.len() as i32 * CONSOLE_LINE_HEIGHT + CONSOLE_MARGIN * 2;
+        self.fill_screen_rect(0, 0, DOOM_SCREEN_WIDTH, height, Color::RGB(24, 24, 24))?;
+        for (row, line) in lines.iter().enumerate() {
+            self.draw_text(line, CONSOLE_MARGIN, CONSOLE_MARGIN + row as i32 * CONSOLE_LINE_HEIGHT)?;
+        }
         Ok(())
     }
 
-    fn render_sprite(&mut self, sprite: &Sprite, player: &Player) -> Result<(), Box<dyn std::error::Error>> {
-        // Calculate sprite position relative to player
-        let dx = sprite.x - player.x;
-        let dy = sprite.y - player.y;
+    // Draws the weapon sprite at the bottom of the view, offset by its bob
+    fn draw_weapon(&mut self, weapon: &WeaponOverlay, velocity: (f64, f64)) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(textures) = self.textures.as_ref() else {
+            return Ok(());
+        };
+        let Some((picture, _)) = textures.sprite_frame(weapon.sprite, weapon.frame, 0) else {
+            return Ok(());
+        };
+
+        let (bob_x, bob_y) = if weapon.ready { weapon_bob(velocity, weapon.time) } else { (0.0, 0.0) };
+        let position = (WEAPON_X + bob_x.round() as i32, WEAPON_TOP + bob_y.round() as i32);
+        let scale = (self.screen_width as f64 / DOOM_SCREEN_WIDTH as f64, self.screen_height as f64 / DOOM_SCREEN_HEIGHT as f64);
+        let fuzz = weapon.fuzz.then_some(&mut self.fuzz_position);
+        draw_picture_into(&mut self.canvas, &textures.view_palette(), picture, position, scale, fuzz)
+    }
 
-        // Transform to screen coordinates
-        let distance = (dx * dx + dy * dy).sqrt();
-        let angle_to_sprite = dy.atan2(dx) - player.angle;
+    // Draws the status bar across the bottom of the screen as Doom lays it out, or
+    // plain bars for health, armor and ammo without the WAD's pictures
+    fn draw_status_bar(&mut self, status: &HudStatus) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(pictures) = self.status_bar_pictures.take().filter(|_| self.textures.is_some()) else {
+            return self.draw_plain_status_bar(status);
+        };
+        let result = self.draw_status_bar_pictures(&pictures, status);
+        self.status_bar_pictures = Some(pictures);
+        result
+    }
 
-        let screen_x = (self.screen_width as f64 / 2.0) +
-            (angle_to_sprite.tan() * self.screen_width as f64 / 2.0);
+    fn draw_status_bar_pictures(&mut self, pictures: &StatusBarPictures, status: &HudStatus) -> Result<(), Box<dyn std::error::Error>> {
+        self.draw_screen_picture(&pictures.background, 0, STATUS_BAR_Y)?;
 
-        let sprite_height = (sprite.texture.height as f64 * sprite.scale) / distance;
+        if let Some(ammo) = status.ammo {
+            self.draw_number(&pictures.digits, ammo as i32, ST_AMMO_X, ST_NUMBER_Y)?;
+        }
+        self.draw_number(&pictures.digits, status.health, ST_HEALTH_X, ST_NUMBER_Y)?;
+        self.draw_screen_picture(&pictures.percent, ST_HEALTH_X, ST_NUMBER_Y)?;
+        self.draw_number(&pictures.digits, status.armor, ST_ARMOR_X, ST_NUMBER_Y)?;
+        self.draw_screen_picture(&pictures.percent, ST_ARMOR_X, ST_NUMBER_Y)?;
 
-        // Render the sprite if it's visible
-        if screen_x >= 0.0 && screen_x < self.screen_width as f64 {
-            self.draw_sprite_column(sprite, screen_x as u32, sprite_height as u32)?;
+        // STKEYS0-2 are the cards and STKEYS3-5 the skull keys
+        for (slot, key) in status.keys.iter().enumerate() {
+            let picture = match key {
+                HudKey::None => continue,
+                HudKey::Card => &pictures.keys[slot],
+                HudKey::Skull => &pictures.keys[slot + 3],
+            };
+            self.draw_screen_picture(picture, ST_KEY_X, ST_KEY_Y[slot])?;
         }
 
+        for (&(count, max), y) in status.ammo_counts.iter().zip(ST_AMMO_COUNT_Y) {
+            self.draw_number(&pictures.small_digits, count as i32, ST_AMMO_COUNT_X, y)?;
+            self.draw_number(&pictures.small_digits, max as i32, ST_MAX_AMMO_X, y)?;
+        }
         Ok(())
     }
 
-    fn render_3d_view(&mut self, map: &Map, player: &Player) -> Result<(), Box<dyn std::error::Error>> {
-        let fov = PI / 3.0;
-        let half_fov = fov / 2.0;
+    // Draws up to three digits of a number, clamped to 0..=999, leftwards from `right`
+    // in steps of the width of the digit 0
+    fn draw_number(&mut self, digits: &[Picture], value: i32, right: i32, y: i32) -> Result<(), Box<dyn std::error::Error>> {
+        let step = digits[0].texture.width as i32;
+        let mut value = value.clamp(0, 999) as usize;
+        let mut x = right;
+        loop {
+            x -= step;
+            self.draw_screen_picture(&digits[value % 10], x, y)?;
+            value /= 10;
+            if value == 0 {
+                return Ok(());
+            }
+        }
+    }
 
-        for x in 0..self.screen_width {
-            let ray_angle = player.angle - half_fov + (x as f64 / self.screen_width as f64) * fov;
+    fn draw_plain_status_bar(&mut self, status: &HudStatus) -> Result<(), Box<dyn std::error::Error>> {
+        let height = DOOM_SCREEN_HEIGHT - STATUS_BAR_Y;
+        self.fill_screen_rect(0, STATUS_BAR_Y, DOOM_SCREEN_WIDTH, height, Color::RGB(64, 64, 64))?;
+
+        // Bars a pixel wide for each point of health and armor, and as long for a full
+        // load of the selected ammo as for 100 health
+        let ammo = status.ammo.map_or(0, |ammo| ammo as i32);
+        let max_ammo = status.ammo_max.max(1) as i32;
+        let bars = [
+            (status.health.clamp(0, 200), Color::RGB(200, 0, 0)),
+            (status.armor.clamp(0, 200), Color::RGB(0, 160, 0)),
+            ((ammo * 100 / max_ammo).clamp(0, 100), Color::RGB(220, 200, 0)),
+        ];
+        for (row, (length, color)) in bars.into_iter().enumerate() {
+            self.fill_screen_rect(8, ST_NUMBER_Y + row as i32 * 9, length, 7, color)?;
+        }
+
+        let key_colors = [Color::RGB(0, 0, 255), Color::RGB(255, 255, 0), Color::RGB(255, 0, 0)];
+        for ((key, color), y) in status.keys.iter().zip(key_colors).zip(ST_KEY_Y) {
+            // Skull keys fill their slot, cards only part of it
+            let size = match key {
+                HudKey::None => continue,
+                HudKey::Card => 5,
+                HudKey::Skull => 7,
+            };
+            self.fill_screen_rect(ST_KEY_X, y, size, size, color)?;
+        }
+        Ok(())
+    }
+
+    // Fills a rectangle given in Doom's 320x200 screen space
+    fn fill_screen_rect(&mut self, x: i32, y: i32, width: i32, height: i32, color: Color) -> Result<(), Box<dyn std::error::Error>> {
+        let scale_x = self.screen_width as f64 / DOOM_SCREEN_WIDTH as f64;
+        let scale_y = self.screen_height as f64 / DOOM_SCREEN_HEIGHT as f64;
+        let columns = (x as f64 * scale_x) as i32..((x + width) as f64 * scale_x) as i32;
+        let rows = (y as f64 * scale_y) as i32..((y + height) as f64 * scale_y) as i32;
 
-            if let Some(hit) = self.cast_ray(map, player, ray_angle) {
-                self.draw_wall_slice(x, &hit)?;
+        self.canvas.set_draw_color(color);
+        for screen_y in rows {
+            for screen_x in columns.clone() {
+                self.canvas.draw_point((screen_x, screen_y))?;
             }
         }
+        Ok(())
+    }
+
+    // Pictures are drawn in the current palette, so nothing is drawn before textures
+    // are loaded
+    fn blit_picture(&mut self, picture: &Picture, x: i32, y: i32, scale_x: f64, scale_y: f64) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(textures) = self.textures.as_ref() else {
+            return Ok(());
+        };
+        draw_picture_into(&mut self.canvas, textures.current_palette(), picture, (x, y), (scale_x, scale_y), None)
+    }
 
+    // Writes the frame being drawn as an RGB PNG at the render resolution
+    pub fn save_screenshot(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+        let (width, height, pixels) = self.frame_rgb()?;
+
+        let file = BufWriter::new(std::fs::File::create(path)?);
+        let mut encoder = png::Encoder::new(file, width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&pixels)?;
         Ok(())
     }
 
-    fn cast_ray(&self, map: &Map, player: &Player, angle: f64) -> Option<RayHit> {
-        let ray_dx = angle.cos();
-        let ray_dy = angle.sin();
+    pub fn queue_screenshot(&mut self, path: impl Into<PathBuf>) {
+        self.pending_screenshot = Some(path.into());
+    }
 
-        let mut distance = 0.0;
-        let step_size = 1.0;
+    // The current frame as tightly packed rows of RGB bytes, with its width and height
+    fn frame_rgb(&self) -> Result<(u32, u32, Vec<u8>), Box<dyn std::error::Error>> {
+        let pixels = self
+            .canvas
+            .pixels
+            .chunks_exact(4)
+            .flat_map(|rgba| [rgba[0], rgba[1], rgba[2]])
+            .collect();
+        Ok((self.canvas.width, self.canvas.height, pixels))
+    }
 
-        while distance < 1000.0 { // Max view distance
-            let test_x = player.x + ray_dx * distance;
-            let test_y = player.y + ray_dy * distance;
+    // Moves animated walls and flats on to the frame for the current game time
+    pub fn animate_textures(&mut self, game_time: std::time::Duration) {
+        if let Some(textures) = self.textures.as_mut() {
+            textures.tick(game_time);
+        }
+    }
 
-            // Check if we hit a wall
-            if let Some(wall_hit) = self.check_wall_collision(map, test_x, test_y) {
-                return Some(RayHit {
-                    distance,
-                    wall_type: wall_hit,
-                    hit_x: test_x,
-                    hit_y: test_y,
-                });
-            }
+    // Works out every sector's light level with its special's effect at `game_time`,
+    // leaving the map's own levels alone
+    pub fn animate_lights(&mut self, map: &Map, game_time: std::time::Duration) {
+        let tic = (game_time.as_secs_f64() * TICS_PER_SECOND) as u64;
+        self.light_levels = map
+            .sectors
+            .iter()
+            .enumerate()
+            .map(|(index, sector)| {
+                let min_light = self.min_lights.get(index).copied().unwrap_or(sector.light_level);
+                sector_light(sector.special_type, sector.light_level, min_light, index, tic)
+            })
+            .collect();
+    }
 
-            distance += step_size;
+    // Shows extra status, such as the frame rate, after the game's name in the title bar
+    pub fn set_title_status(&mut self, status: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+        let title = match status {
+            Some(status) => format!("{} - {}", WINDOW_TITLE, status),
+            None => WINDOW_TITLE.to_string(),
+        };
+        if let Some(window) = self.canvas.window_mut() {
+            window.set_title(&title)?;
         }
+        Ok(())
+    }
 
-        None
+    pub fn set_automap_scale(&mut self, scale: f64) {
+        self.automap_scale = scale.clamp(AUTOMAP_MIN_SCALE, AUTOMAP_MAX_SCALE);
     }
 
-    fn draw_wall_slice(&mut self, screen_x: u32, hit: &RayHit) -> Result<(), Box<dyn std::error::Error>> {
-        // Calculate wall height on screen based on distance
-        let wall_height = (self.screen_height as f64 / hit.distance * 100.0) as i32;
-        let wall_top = (self.screen_height as i32 - wall_height) / 2;
-        let wall_bottom = wall_top + wall_height;
+    pub fn automap_scale(&self) -> f64 {
+        self.automap_scale
+    }
 
-        // Choose color based on wall type (simplified)
-        let color = match hit.wall_type {
-            WallType::Stone => Color::RGB(128, 128, 128),
-            WallType::Wood => Color::RGB(139, 69, 19),
-            WallType::Metal => Color::RGB(192, 192, 192),
+    // Overhead view of the linedefs, north up and centred on the player
+    pub fn render_automap(&mut self, map: &Map, player: &Player) -> Result<(), Box<dyn std::error::Error>> {
+        let project = |x: f64, y: f64| {
+            automap_project(x, y, player.x, player.y, self.automap_scale, self.screen_width, self.screen_height)
         };
 
-        self.canvas.set_draw_color(color);
+        for linedef in &map.linedefs {
+            let (Some(start), Some(end)) = (
+                map.vertices.get(linedef.start_vertex as usize),
+                map.vertices.get(linedef.end_vertex as usize),
+            ) else {
+                continue;
+            };
 
-        // Draw vertical line from wall_top to wall_bottom
-        for y in wall_top.max(0)..wall_bottom.min(self.screen_height as i32) {
-            self.canvas.draw_point((screen_x as i32, y))?;
+            let color = if linedef.back_sidedef == 0xFFFF {
+                Color::RGB(252, 0, 0)
+            } else {
+                Color::RGB(190, 123, 71)
+            };
+            self.canvas.set_draw_color(color);
+            self.canvas.draw_line(
+                project(start.x as f64, start.y as f64),
+                project(end.x as f64, end.y as f64),
+            )?;
         }
 
+        // Player arrow, sized in map units so it zooms with the map
+        let (cos, sin) = (player.angle.cos(), player.angle.sin());
+        let length = AUTOMAP_ARROW_LENGTH;
+        let tip = project(player.x + cos * length, player.y + sin * length);
+        let tail = project(player.x - cos * length, player.y - sin * length);
+        let left = project(
+            player.x - cos * length * 0.5 - sin * length * 0.5,
+            player.y - sin * length * 0.5 + cos * length * 0.5,
+        );
+        let right = project(
+            player.x - cos * length * 0.5 + sin * length * 0.5,
+            player.y - sin * length * 0.5 - cos * length * 0.5,
+        );
+
+        self.canvas.set_draw_color(Color::RGB(255, 255, 255));
+        self.canvas.draw_line(tail, tip)?;
+        self.canvas.draw_line(left, tip)?;
+        self.canvas.draw_line(right, tip)?;
+
         Ok(())
     }
-}
 
-struct RayHit {
-    distance: f64,
-    wall_type: WallType,
-    hit_x: f64,
-    hit_y: f64,
-}
+    fn render_floor_ceiling(&mut self, map: &Map, player: &Player) -> Result<(), Box<dyn std::error::Error>> {
+        let half_height = self.screen_height as f64 / 2.0;
 
-enum WallType {
-    Stone,
-    Wood,
-    Metal,
-}
+        // Plane heights come from the sector the player is standing in
+        let sector = self
+            .sector_grid
+            .as_ref()
+            .and_then(|grid| grid.sector_at(player.x, player.y))
+            .and_then(|index| map.sectors.get(index));
+        let (floor_height, ceiling_height) = sector
+            .map(|sector| (sector.floor_height as f64, sector.ceiling_height as f64))
+            .unwrap_or((0.0, player.height * 2.0));
+        let eye_height = player.z + player.height;
 
-pub struct Texture {
-    pub width: u16,
-    pub height: u16,
-    pub pixels: Vec<u8>, // Palette indices
-}
+        for y in 0..self.screen_height {
+            if y < half_height as u32 {
+                self.render_horizontal_plane(y, map, player, ceiling_height - eye_height, true)?;
+            } else {
+                // Render floor
+                self.render_horizontal_plane(y, map, player, eye_height - floor_height, false)?;
+            }
+        }
 
-pub struct TextureManager {
-    textures: std::collections::HashMap<String, Texture>,
-    palette: Vec<[u8; 3]>, // RGB values
-}
+        Ok(())
+    }
 
-impl TextureManager {
-    pub fn load_from_wad(wad: &WadFile) -> Result<Self, Box<dyn std::error::Error>> {
-        let mut textures = std::collections::HashMap::new();
-        let palette = Self::load_palette(wad)?;
+    fn render_horizontal_plane(
+        &mut self,
+        screen_y: u32,
+        map: &Map,
+        player: &Player,
+        plane_height: f64,
+        is_ceiling: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let projection_distance = self.config().projection_distance();
+        let Some(distance) = plane_row_distance(screen_y, self.screen_height, projection_distance, plane_height) else {
+            return Ok(());
+        };
 
-        // Load PNAMES (patch names)
-        if let Some(pnames_lump) = wad.find_lump("PNAMES") {
-            let patch_names = Self::parse_patch_names(&pnames_lump.data)?;
+        let ((mut world_x, mut world_y), (step_x, step_y)) = plane_row_step(player, distance, self.screen_width, projection_distance);
 
-            // Load TEXTURE1 and TEXTURE2
-            if let Some(texture1_lump) = wad.find_lump("TEXTURE1") {
-                let texture1_textures = Self::parse_textures(&texture1_lump.data, &patch_names, wad)?;
-                textures.extend(texture1_textures);
-            }
+        for x in 0..self.screen_width {
+            let color = self.fogged(self.sample_floor_texture(map, world_x, world_y, distance, is_ceiling), distance);
+            world_x += step_x;
+            world_y += step_y;
+
+            self.canvas.set_draw_color(color);
+            self.canvas.draw_point((x as i32, screen_y as i32))?;
         }
 
-        Ok(TextureManager { textures, palette })
+        Ok(())
     }
 
-    fn load_palette(wad: &WadFile) -> Result<Vec<[u8; 3]>, Box<dyn std::error::Error>> {
-        let playpal = wad.find_lump("PLAYPAL")
-            .ok_or("PLAYPAL lump not found")?;
+    fn sample_floor_texture(&self, map: &Map, world_x: f64, world_y: f64, distance: f64, is_ceiling: bool) -> Color {
+        let fallback = if is_ceiling {
+            Color::RGB(64, 64, 64)
+        } else {
+            Color::RGB(96, 96, 96)
+        };
 
-        let mut palette = Vec::new();
-        for chunk in playpal.data.chunks(3) {
-            if chunk.len() == 3 {
-                palette.push([chunk[0], chunk[1], chunk[2]]);
-            }
-        }
+        let (Some(grid), Some(textures)) = (self.sector_grid.as_ref(), self.textures.as_ref()) else {
+            return fallback;
+        };
+        let Some((sector_index, sector)) = grid
+            .sector_at(world_x, world_y)
+            .and_then(|index| map.sectors.get(index).map(|sector| (index, sector)))
+        else {
+            return fallback;
+        };
+        let name = if is_ceiling { &sector.ceiling_texture } else { &sector.floor_texture };
+        let Some(flat) = textures.get_flat(name) else {
+            return fallback;
+        };
 
-        Ok(palette)
+        // Flats tile the world on a fixed 64 unit grid
+        let u = (world_x.floor() as i64).rem_euclid(FLAT_SIZE as i64) as usize;
+        let v = (world_y.floor() as i64).rem_euclid(FLAT_SIZE as i64) as usize;
+        let index = flat[v * FLAT_SIZE + u];
+
+        Self::shade_texel(textures, index, effective_light(&self.light_levels, map, sector_index), distance)
     }
 
-    pub fn get_texture(&self, name: &str) -> Option<&Texture> {
-        self.textures.get(name)
+    // Blends a colour towards the fog colour, if there is one, for its distance from the eye
+    fn fogged(&self, color: Color, distance: f64) -> Color {
+        let Some(fog) = self.fog_color else {
+            return color;
+        };
+        let [r, g, b] = fog_blend([color.r, color.g, color.b], fog, distance, self.max_view_distance);
+        Color::RGB(r, g, b)
     }
-}
 
+    // Final colour of a palette index lit by a sector light level (0-255) at a distance
+    fn shade_texel(textures: &TextureManager, palette_index: u8, light_level: i16, distance: f64) -> Color {
+        let [r, g, b] = textures.shade(palette_index, light_index(light_level, distance));
+        Color::RGB(r, g, b)
+    }
+
+    // Draws sprites from the farthest to the nearest, so nearer ones cover farther ones
+    fn render_sprites(&mut self, sprites: &[Sprite], map: &Map, player: &Player) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(textures) = self.textures.take() else {
+            return Ok(());
+        };
+
+        let distance = |sprite: &Sprite| (sprite.x - player.x).powi(2) + (sprite.y - player.y).powi(2);
+        let mut sorted_sprites: Vec<_> = sprites.iter().collect();
+        sorted_sprites.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+
+        let mut result = Ok(());
+        for sprite in sorted_sprites {
+            result = self.render_sprite(&textures, sprite, map, player);
+            if result.is_err() {
+                break;
+            }
+        }
+        self.textures = Some(textures);
+        result
+    }
+
+    fn render_sprite(&mut self, textures: &TextureManager, sprite: &Sprite, map: &Map, player: &Player) -> Result<(), Box<dyn std::error::Error>> {
+        let projection_distance = self.config().projection_distance();
+        let Some((screen_x, depth)) = project_sprite(player, (sprite.x, sprite.y), self.screen_width, projection_distance)
+        else {
+            self.stats.sprites_culled += 1;
+            return Ok(());
+        };
+        let to_viewer = (player.y - sprite.y).atan2(player.x - sprite.x);
+        let Some((picture, flipped)) = textures.sprite_view(&sprite.name, sprite.frame, sprite.angle, to_viewer) else {
+            return Ok(());
+        };
+        let texture = &picture.texture;
+
+        // The picture's offsets put its origin at the thing's position, its left offset
+        // measured from the other side when it's mirrored
+        let scale = projection_distance / depth;
+        let origin_x = if flipped { texture.width as f64 - picture.left_offset as f64 } else { picture.left_offset as f64 };
+        let left = screen_x - origin_x * scale;
+        let right = left + texture.width as f64 * scale;
+        if right < 0.0 || left > self.screen_width as f64 {
+            self.stats.sprites_culled += 1;
+            return Ok(());
+        }
+        self.stats.sprites_drawn += 1;
+
+        let light = self
+            .sector_grid
+            .as_ref()
+            .and_then(|grid| grid.sector_at(sprite.x, sprite.y))
+            .map_or(255, |sector| effective_light(&self.light_levels, map, sector));
+        let eye_z = player.z + player.height;
+        let placement = SpritePlacement {
+            top: self.screen_height as f64 / 2.0 - (sprite.z + picture.top_offset as f64 - eye_z) * scale,
+            scale,
+            depth,
+            light,
+            fuzz: sprite.fuzz,
+        };
+
+        // Draw column by column so a sprite straddling a wall edge is only
+        // hidden where the wall is actually in front of it
+        let first_column = left.max(0.0) as u32;
+        let last_column = right.min(self.screen_width as f64).max(0.0) as u32;
+        for column in first_column..last_column {
+            let texture_x = ((column as f64 + 0.5 - left) / scale) as usize;
+            let texture_x = if flipped { (texture.width as usize).saturating_sub(texture_x + 1) } else { texture_x };
+            self.draw_sprite_column(textures, texture, &placement, column, texture_x)?;
+        }
+
+        Ok(())
+    }
+
+    fn sprite_column_visible(&self, screen_x: u32, depth: f64) -> bool {
+        depth > 0.0
+            && self
+                .depth_buffer
+                .get(screen_x as usize)
+                .is_some_and(|&wall_depth| depth < wall_depth)
+    }
+
+    fn draw_sprite_column(
+        &mut self,
+        textures: &TextureManager,
+        texture: &Texture,
+        placement: &SpritePlacement,
+        screen_x: u32,
+        texture_x: usize,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.sprite_column_visible(screen_x, placement.depth) || texture_x >= texture.width as usize {
+            return Ok(());
+        }
+
+        let bottom = placement.top + texture.height as f64 * placement.scale;
+        let first_row = placement.top.max(0.0) as i32;
+        let last_row = bottom.min(self.screen_height as f64).max(0.0) as i32;
+        for y in first_row..last_row {
+            let texture_y = ((y as f64 + 0.5 - placement.top) / placement.scale) as usize;
+            if !texture.is_opaque(texture_x, texture_y) {
+                continue;
+            }
+            if placement.fuzz {
+                self.canvas.draw_fuzz_point(screen_x as i32, y, FUZZ_OFFSETS[self.fuzz_position])?;
+                self.fuzz_position = (self.fuzz_position + 1) % FUZZ_OFFSETS.len();
+                continue;
+            }
+            let index = texture.pixels[texture_y * texture.width as usize + texture_x];
+            let color = self.fogged(Self::shade_texel(textures, index, placement.light, placement.depth), placement.depth);
+
+            self.canvas.set_draw_color(color);
+            self.canvas.draw_point((screen_x as i32, y))?;
+        }
+
+        Ok(())
+    }
+
+    fn render_3d_view(&mut self, map: &Map, player: &Player) -> Result<(), Box<dyn std::error::Error>> {
+        self.ceiling_clip.fill(0);
+        self.floor_clip.fill(self.screen_height as i32);
+
+        if self.use_bsp
+            && let Some(bsp) = self.bsp.take()
+        {
+            let result = self.render_bsp_view(&bsp, map, player);
+            self.bsp = Some(bsp);
+            return result;
+        }
+
+        // Every column's ray is cast and shaded on its own, so they're shared out between
+        // threads, then drawn in order on this one
+        let eye_z = player.z + player.height;
+        let walls = self.wall_columns();
+        let column_angles: Vec<f64> = (0..self.screen_width).map(|x| self.column_angle(x)).collect();
+        let clips: Vec<(i32, i32)> = self.ceiling_clip.iter().copied().zip(self.floor_clip.iter().copied()).collect();
+        let columns: Vec<_> = column_angles
+            .par_iter()
+            .zip(clips.par_iter())
+            .map(|(&column_angle, &clip)| {
+                let hit = walls.cast_ray(player, normalize_angle_f64(player.angle + column_angle))?;
+                let mut pixels = Vec::new();
+                walls.shade(&hit, map, eye_z, clip, &mut pixels);
+                Some((hit.distance, pixels))
+            })
+            .collect();
+
+        self.stats.rays_cast += self.screen_width;
+        for (x, column) in columns.into_iter().enumerate() {
+            if let Some((distance, pixels)) = column {
+                self.stats.wall_slices += 1;
+                self.depth_buffer[x] = distance;
+                self.draw_column_pixels(x as u32, &pixels)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn render_bsp_view(&mut self, bsp: &BspTree, map: &Map, player: &Player) -> Result<(), Box<dyn std::error::Error>> {
+        self.solid_columns.clear();
+
+        // The root is the last node. A map with a single subsector has no nodes at all.
+        match bsp.nodes.len() {
+            0 => self.render_subsector(bsp, map, player, 0),
+            count => self.render_bsp_node(bsp, map, player, count as u32 - 1),
+        }
+    }
+
+    // Front to back: the child on the player's side first, then the far child if any
+    // of its bounding box may still be visible through unfilled columns
+    fn render_bsp_node(
+        &mut self,
+        bsp: &BspTree,
+        map: &Map,
+        player: &Player,
+        node_index: u32,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.solid_columns.is_full() {
+            return Ok(());
+        }
+        if node_index & NF_SUBSECTOR != 0 {
+            return self.render_subsector(bsp, map, player, node_index & !NF_SUBSECTOR);
+        }
+        let Some(node) = bsp.nodes.get(node_index as usize) else {
+            return Ok(());
+        };
+
+        let (near, far, far_bbox) = if bsp.point_on_side(player.x, player.y, node) == 0 {
+            (node.right_child, node.left_child, node.left_bbox())
+        } else {
+            (node.left_child, node.right_child, node.right_bbox())
+        };
+
+        self.render_bsp_node(bsp, map, player, near)?;
+        if self.bbox_in_view(player, &far_bbox) {
+            self.render_bsp_node(bsp, map, player, far)?;
+        }
+
+        Ok(())
+    }
+
+    fn render_subsector(
+        &mut self,
+        bsp: &BspTree,
+        map: &Map,
+        player: &Player,
+        subsector_index: u32,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(subsector) = bsp.subsectors.get(subsector_index as usize) else {
+            return Ok(());
+        };
+        self.stats.subsectors_visited += 1;
+
+        for seg_index in subsector.first_seg..subsector.first_seg + subsector.seg_count {
+            let Some(seg) = bsp.segs.get(seg_index as usize) else {
+                continue;
+            };
+            let (Some(start), Some(end)) = (bsp.seg_vertex(map, seg.start_vertex), bsp.seg_vertex(map, seg.end_vertex)) else {
+                continue;
+            };
+
+            self.render_seg(map, player, seg.linedef, seg.direction != 0, start, end)?;
+        }
+
+        Ok(())
+    }
+
+    // Draws a seg into the unfilled columns it covers, marking them filled where it's
+    // solid. `reversed` segs run along the back side of their line.
+    fn render_seg(
+        &mut self,
+        map: &Map,
+        player: &Player,
+        linedef: u16,
+        reversed: bool,
+        start: (f64, f64),
+        end: (f64, f64),
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Segs are seen from their right side only
+        let (seg_dx, seg_dy) = (end.0 - start.0, end.1 - start.1);
+        if seg_dx * (player.y - start.1) - seg_dy * (player.x - start.0) >= 0.0 {
+            return Ok(());
+        }
+
+        let Some((first_column, last_column)) = self.seg_columns(player, start, end) else {
+            return Ok(());
+        };
+        if self.solid_columns.is_occluded(first_column, last_column) {
+            return Ok(());
+        }
+        let wall_type = WallType::for_linedef(map, linedef);
+        let columns = self.solid_columns.visible_spans(first_column, last_column);
+
+        for x in columns.into_iter().flat_map(|(first, last)| first..last) {
+            // Intersect this column's ray with the seg for the exact hit point
+            let column_angle = self.column_angle(x);
+            let (ray_dx, ray_dy) = ((player.angle + column_angle).cos(), (player.angle + column_angle).sin());
+            let denominator = ray_dx * seg_dy - ray_dy * seg_dx;
+            if denominator.abs() < f64::EPSILON {
+                continue;
+            }
+            let (to_start_x, to_start_y) = (start.0 - player.x, start.1 - player.y);
+            let ray_length = (to_start_x * seg_dy - to_start_y * seg_dx) / denominator;
+            let along = ((to_start_x * ray_dy - to_start_y * ray_dx) / denominator).clamp(0.0, 1.0);
+            if ray_length <= 0.0 {
+                continue;
+            }
+
+            let hit = RayHit {
+                distance: (ray_length * column_angle.cos()).max(NEAR_PLANE),
+                wall_type,
+                linedef,
+                reversed,
+                hit_x: start.0 + seg_dx * along,
+                hit_y: start.1 + seg_dy * along,
+            };
+            if hit.distance > self.max_view_distance {
+                continue;
+            }
+
+            if self.draw_wall_slice(x, &hit, map, player.z + player.height)? {
+                self.depth_buffer[x as usize] = hit.distance;
+                self.solid_columns.add(x, x + 1);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Screen column range covered by a world-space segment after near-plane clipping
+    fn seg_columns(&self, player: &Player, start: (f64, f64), end: (f64, f64)) -> Option<(u32, u32)> {
+        let (cos, sin) = (player.angle.cos(), player.angle.sin());
+        // Depth along the view direction and lateral offset towards increasing angle
+        let to_view = |(x, y): (f64, f64)| {
+            let (dx, dy) = (x - player.x, y - player.y);
+            (dx * cos + dy * sin, dy * cos - dx * sin)
+        };
+
+        let (mut a, mut b) = (to_view(start), to_view(end));
+        if a.0 < NEAR_PLANE && b.0 < NEAR_PLANE {
+            return None;
+        }
+        let clip = |front: (f64, f64), back: (f64, f64)| {
+            let t = (NEAR_PLANE - front.0) / (back.0 - front.0);
+            (NEAR_PLANE, front.1 + (back.1 - front.1) * t)
+        };
+        if a.0 < NEAR_PLANE {
+            a = clip(a, b);
+        } else if b.0 < NEAR_PLANE {
+            b = clip(b, a);
+        }
+
+        let half_width = self.screen_width as f64 / 2.0;
+        let projection_distance = self.config().projection_distance();
+        let project = |(depth, lateral): (f64, f64)| half_width + projection_distance * lateral / depth;
+
+        // A column is covered when its centre falls inside the projected span
+        let (left, right) = {
+            let (sa, sb) = (project(a), project(b));
+            (sa.min(sb), sa.max(sb))
+        };
+        let first = (left - 0.5).ceil().max(0.0);
+        let last = (right - 0.5).ceil().min(self.screen_width as f64);
+        if first >= last {
+            return None;
+        }
+
+        Some((first as u32, last as u32))
+    }
+
+    // Conservative test that some unfilled column can see into a node's bounding box
+    fn bbox_in_view(&self, player: &Player, bbox: &AABB) -> bool {
+        if bbox.contains(Point2D::new(player.x as f32, player.y as f32)) {
+            return true;
+        }
+
+        // Nothing in a box wholly past the far plane gets drawn
+        let nearest_x = player.x.clamp(bbox.min.x as f64, bbox.max.x as f64);
+        let nearest_y = player.y.clamp(bbox.min.y as f64, bbox.max.y as f64);
+        if (nearest_x - player.x).hypot(nearest_y - player.y) > self.max_view_distance {
+            return false;
+        }
+
+        let corners = bbox.corners().map(|corner| (corner.x as f64, corner.y as f64));
+        let (cos, sin) = (player.angle.cos(), player.angle.sin());
+        let half_width = self.screen_width as f64 / 2.0;
+        let projection_distance = self.config().projection_distance();
+
+        let mut min_x = f64::INFINITY;
+        let mut max_x = f64::NEG_INFINITY;
+        for (x, y) in corners {
+            let (dx, dy) = (x - player.x, y - player.y);
+            let depth = dx * cos + dy * sin;
+            if depth < NEAR_PLANE {
+                // Part of the box is beside or behind the eye, so assume the whole view
+                min_x = 0.0;
+                max_x = self.screen_width as f64;
+                break;
+            }
+            let screen_x = half_width + projection_distance * (dy * cos - dx * sin) / depth;
+            min_x = min_x.min(screen_x);
+            max_x = max_x.max(screen_x);
+        }
+
+        let first = min_x.floor().max(0.0) as u32;
+        let last = (max_x.ceil().max(0.0) as u32).min(self.screen_width);
+        !self.solid_columns.is_occluded(first, last)
+    }
+
+    // Columns are evenly spaced on the projection plane, not in angle. Spacing them
+    // linearly in angle bends straight walls even with the cosine-corrected distance.
+    fn column_angle(&self, screen_x: u32) -> f64 {
+        let half_width = self.screen_width as f64 / 2.0;
+        let projection_distance = self.config().projection_distance();
+
+        ((screen_x as f64 + 0.5 - half_width) / projection_distance).atan()
+    }
+
+    // Draws the spans of wall a hit shows in one column, inside the rows nearer walls
+    // have left open, then narrows those rows to the gap seen through a two-sided
+    // line. Returns whether the column is now covered from top to bottom.
+    fn draw_wall_slice(&mut self, screen_x: u32, hit: &RayHit, map: &Map, eye_z: f64) -> Result<bool, Box<dyn std::error::Error>> {
+        let column = screen_x as usize;
+        self.stats.wall_slices += 1;
+        let mut pixels = Vec::new();
+        let (clip, closed) = self.wall_columns().shade(hit, map, eye_z, (self.ceiling_clip[column], self.floor_clip[column]), &mut pixels);
+        self.draw_column_pixels(screen_x, &pixels)?;
+        (self.ceiling_clip[column], self.floor_clip[column]) = clip;
+        Ok(closed)
+    }
+
+    fn draw_column_pixels(&mut self, screen_x: u32, pixels: &[(i32, Color)]) -> Result<(), Box<dyn std::error::Error>> {
+        for &(y, color) in pixels {
+            self.canvas.set_draw_color(color);
+            self.canvas.draw_point((screen_x as i32, y))?;
+        }
+        Ok(())
+    }
+
+    // The parts of the renderer walls are cast and shaded from, which can be shared
+    // between threads where the window can't
+    fn wall_columns(&self) -> WallColumns<'_> {
+        WallColumns {
+            wall_grid: self.wall_grid.as_ref(),
+            textures: self.textures.as_ref(),
+            light_levels: &self.light_levels,
+            screen_height: self.screen_height,
+            projection_distance: self.config().projection_distance(),
+            max_view_distance: self.max_view_distance,
+            fog_color: self.fog_color,
+        }
+    }
+
+    // Light level of the sector a sidedef faces
+    fn wall_light_level(light_levels: &[i16], map: &Map, sidedef: u16) -> i16 {
+        map.side_sector(sidedef)
+            .filter(|&sector| sector < map.sectors.len())
+            .map_or(255, |sector| effective_light(light_levels, map, sector))
+    }
+
+    // Resolves the texture for one span of a hit, its manager, the texel column and the
+    // texel row at the top of the span
+    fn wall_texture_column<'a>(
+        textures: Option<&'a TextureManager>,
+        map: &Map,
+        hit: &RayHit,
+        span: WallSpan,
+        front_ceiling: f64,
+    ) -> Option<(&'a Texture, &'a TextureManager, usize, f64)> {
+        let textures = textures?;
+        let linedef = map.linedefs.get(hit.linedef as usize)?;
+        let sidedef = map.sidedefs.get(hit.sides(map).0 as usize)?;
+        let name = match span.section {
+            WallSection::Upper => &sidedef.upper_texture,
+            WallSection::Middle => &sidedef.middle_texture,
+            WallSection::Lower => &sidedef.lower_texture,
+        };
+        let texture = textures.get_texture(name)?;
+        if texture.width == 0 || texture.height == 0 {
+            return None;
+        }
+
+        // U is the distance along the wall from the start of the side that was hit,
+        // which for a line's back side is its end vertex
+        let start = if hit.reversed { linedef.end_vertex } else { linedef.start_vertex };
+        let start = map.vertices.get(start as usize)?;
+        let along = (hit.hit_x - start.x as f64).hypot(hit.hit_y - start.y as f64);
+        let u = texture_column(along, sidedef.x_offset, texture.width);
+
+        let v_offset = texture_row_offset(
+            span.section,
+            linedef.flags,
+            texture.height as f64,
+            span.top - span.bottom,
+            front_ceiling - span.top,
+        ) + sidedef.y_offset as f64;
+
+        Some((texture, textures, u, v_offset))
+    }
+}
+
+// Map coordinates to automap screen pixels. Screen y grows downwards, map y upwards.
+pub fn automap_project(
+    x: f64,
+    y: f64,
+    center_x: f64,
+    center_y: f64,
+    scale: f64,
+    screen_width: u32,
+    screen_height: u32,
+) -> (i32, i32) {
+    let screen_x = screen_width as f64 / 2.0 + (x - center_x) * scale;
+    let screen_y = screen_height as f64 / 2.0 - (y - center_y) * scale;
+
+    (screen_x.round() as i32, screen_y.round() as i32)
+}
+
+// Colormap index for a sector light level at a distance, 0 being full brightness.
+// Matches Doom's scalelight table with its 320 pixel wide projection.
+pub fn light_index(light_level: i16, distance: f64) -> usize {
+    let band = (light_level.clamp(0, 255) as usize) >> 4;
+    let start_map = (LIGHT_LEVELS - 1 - band) * 2 * NUM_COLORMAPS / LIGHT_LEVELS;
+
+    // Closer surfaces project larger and get brighter, up to the table limit
+    let scale = if distance > 0.0 {
+        ((160.0 * 16.0 / distance) as usize).min(MAX_LIGHT_SCALE - 1)
+    } else {
+        MAX_LIGHT_SCALE - 1
+    };
+
+    start_map.saturating_sub(scale / 2).min(NUM_COLORMAPS - 1)
+}
+
+// The parts of a sidedef: the upper and lower textures fill the steps between two
+// sectors' ceilings and floors, the middle one the space between
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WallSection {
+    Upper,
+    Middle,
+    Lower,
+}
+
+// Classic Doom's solidsegs: the spans of screen columns already filled by solid walls,
+// kept sorted and merged, so segs behind them can be skipped or cut down to the
+// columns still open. Spans run from their first column up to, not including, their
+// last.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipRange {
+    width: u32,
+    spans: Vec<(u32, u32)>,
+}
+
+impl ClipRange {
+    pub fn new(width: u32) -> Self {
+        ClipRange { width, spans: Vec::new() }
+    }
+
+    pub fn clear(&mut self) {
+        self.spans.clear();
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.spans.first() == Some(&(0, self.width))
+    }
+
+    // Marks columns first..last filled, merging with spans it touches
+    pub fn add(&mut self, first: u32, last: u32) {
+        let (mut first, mut last) = (first, last.min(self.width));
+        if first >= last {
+            return;
+        }
+        // The spans overlapping or touching the new one are replaced by their union
+        let start = self.spans.partition_point(|&(_, end)| end < first);
+        let end = self.spans.partition_point(|&(begin, _)| begin <= last);
+        if start < end {
+            first = first.min(self.spans[start].0);
+            last = last.max(self.spans[end - 1].1);
+        }
+        self.spans.splice(start..end, [(first, last)]);
+    }
+
+    // Whether every column in first..last is filled. An empty range counts as hidden.
+    pub fn is_occluded(&self, first: u32, last: u32) -> bool {
+        let last = last.min(self.width);
+        if first >= last {
+            return true;
+        }
+        let index = self.spans.partition_point(|&(_, end)| end <= first);
+        self.spans
+            .get(index)
+            .is_some_and(|&(begin, end)| begin <= first && end >= last)
+    }
+
+    // The open stretches of first..last, left to right
+    pub fn visible_spans(&self, first: u32, last: u32) -> Vec<(u32, u32)> {
+        let last = last.min(self.width);
+        let mut visible = Vec::new();
+        let mut column = first;
+        for &(begin, end) in &self.spans {
+            if column >= last {
+                break;
+            }
+            if end <= column {
+                continue;
+            }
+            if begin > column {
+                visible.push((column, begin.min(last)));
+            }
+            column = column.max(end);
+        }
+        if column < last {
+            visible.push((column, last));
+        }
+        visible
+    }
+}
+
+// A vertical piece of wall between two heights, drawn with one of a sidedef's textures
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WallSpan {
+    pub section: WallSection,
+    pub top: f64,
+    pub bottom: f64,
+}
+
+// The spans of wall a line shows from its front sector, given as (floor, ceiling)
+// heights. A one-sided line is a middle span from floor to ceiling. A two-sided one
+// has an upper span down to a lower back ceiling and a lower span up to a higher back
+// floor, and is open in between.
+pub fn wall_spans(front: (f64, f64), back: Option<(f64, f64)>) -> Vec<WallSpan> {
+    let (front_floor, front_ceiling) = front;
+    let Some((back_floor, back_ceiling)) = back else {
+        return vec![WallSpan {
+            section: WallSection::Middle,
+            top: front_ceiling,
+            bottom: front_floor,
+        }];
+    };
+
+    let mut spans = Vec::new();
+    if back_ceiling < front_ceiling {
+        spans.push(WallSpan {
+            section: WallSection::Upper,
+            top: front_ceiling,
+            bottom: back_ceiling.max(front_floor),
+        });
+    }
+    if back_floor > front_floor {
+        spans.push(WallSpan {
+            section: WallSection::Lower,
+            top: back_floor.min(front_ceiling),
+            bottom: front_floor,
+        });
+    }
+    spans
+}
+
+// Texel column at a distance along a wall, shifted by the sidedef's x offset
+pub fn texture_column(along: f64, x_offset: i16, texture_width: u16) -> usize {
+    ((along + x_offset as f64).floor() as i64).rem_euclid(texture_width.max(1) as i64) as usize
+}
+
+// Texel row (before the sidedef's y offset) at the top of a wall section as Doom pegs
+// it. By default upper textures hang down to the lower ceiling, lower textures start
+// at the higher floor and middle textures hang from the ceiling. Unpegging hangs upper
+// textures from the higher ceiling, lowers lower textures as if they began at the
+// front ceiling `ceiling_drop` above the section's top, and rests middle textures on
+// the floor.
+pub fn texture_row_offset(section: WallSection, flags: u16, texture_height: f64, section_height: f64, ceiling_drop: f64) -> f64 {
+    match section {
+        WallSection::Upper if flags & ML_DONTPEGTOP == 0 => texture_height - section_height,
+        WallSection::Lower if flags & ML_DONTPEGBOTTOM != 0 => ceiling_drop,
+        WallSection::Middle if flags & ML_DONTPEGBOTTOM != 0 => texture_height - section_height,
+        _ => 0.0,
+    }
+}
+
+// Mixes a colour with fog linearly from FOG_START of the view distance, untouched
+// nearer than that and all fog at the far plane
+pub fn fog_blend(color: [u8; 3], fog: [u8; 3], distance: f64, max_distance: f64) -> [u8; 3] {
+    let start = max_distance * FOG_START;
+    let amount = ((distance - start) / (max_distance - start)).clamp(0.0, 1.0);
+    std::array::from_fn(|i| (color[i] as f64 + (fog[i] as f64 - color[i] as f64) * amount).round() as u8)
+}
+
+// The colormap the view is drawn with for the tics of invulnerability left: the
+// inverse one, flickering off as it runs out as in Doom, and None without it
+pub fn fixed_colormap(invulnerability_tics: f64) -> Option<usize> {
+    let tics = invulnerability_tics.ceil().max(0.0) as u32;
+    (invulnerability_tics > INVULNERABILITY_FLICKER_TICS || tics & 8 != 0).then_some(INVERSE_COLORMAP)
+}
+
+// The palette Doom shows for the player's damage and pickup flash counts in tics.
+// Pain wins over pickups, and as in Doom the first red palette is never chosen.
+pub fn flash_palette(damage_count: f64, bonus_count: f64) -> usize {
+    let steps = |count: f64, palettes: usize| (((count.ceil() as usize) + 7) >> 3).min(palettes - 1);
+
+    if damage_count > 0.0 {
+        START_RED_PALETTES + steps(damage_count, NUM_RED_PALETTES)
+    } else if bonus_count > 0.0 {
+        START_BONUS_PALETTES + steps(bonus_count, NUM_BONUS_PALETTES)
+    } else {
+        0
+    }
+}
+
+// Linear darkening for colours that don't come from the palette
+fn darken(color: Color, light_index: usize) -> Color {
+    let factor = 1.0 - light_index.min(NUM_COLORMAPS) as f64 / NUM_COLORMAPS as f64;
+    let scale = |channel: u8| (channel as f64 * factor) as u8;
+
+    Color::RGB(scale(color.r), scale(color.g), scale(color.b))
+}
+
+// Distance along the view direction to a horizontal plane seen at a screen row,
+// projected with the same vertical scale as the walls
+pub fn plane_row_distance(
+    screen_y: u32,
+    screen_height: u32,
+    projection_distance: f64,
+    plane_height: f64,
+) -> Option<f64> {
+    let row_offset = (screen_y as f64 + 0.5 - screen_height as f64 / 2.0).abs();
+    if plane_height <= 0.0 {
+        return None;
+    }
+
+    Some(plane_height * projection_distance / row_offset)
+}
+
+// Screen column of a sprite's centre and its depth along the view direction, or None
+// when it's behind the near plane. The sprite is rotated into view space and divided by
+// its depth, so this stays well behaved at any angle.
+pub fn project_sprite(player: &Player, position: (f64, f64), screen_width: u32, projection_distance: f64) -> Option<(f64, f64)> {
+    let (dx, dy) = (position.0 - player.x, position.1 - player.y);
+    let (cos, sin) = (player.angle.cos(), player.angle.sin());
+
+    // Depth comparable with the wall depth buffer, and the offset towards increasing
+    // angle, which screen columns follow
+    let depth = dx * cos + dy * sin;
+    let lateral = dy * cos - dx * sin;
+    if depth < NEAR_PLANE {
+        return None;
+    }
+
+    Some((screen_width as f64 / 2.0 + lateral / depth * projection_distance, depth))
+}
+
+// World position on a plane for a column at `column_angle` from the view direction,
+// given the distance along the view direction
+pub fn plane_world_position(player: &Player, column_angle: f64, distance: f64) -> (f64, f64) {
+    let ray_length = distance / column_angle.cos();
+    let angle = player.angle + column_angle;
+
+    (player.x + angle.cos() * ray_length, player.y + angle.sin() * ray_length)
+}
+
+// Where a row of a plane starts in the world and how far it moves per column. A row
+// at one distance is a straight line on the plane, so positions between its edges are
+// interpolated rather than each needing plane_world_position's trig.
+pub fn plane_row_step(player: &Player, distance: f64, screen_width: u32, projection_distance: f64) -> ((f64, f64), (f64, f64)) {
+    let half_width = screen_width as f64 / 2.0;
+    let edge = |screen_x: f64| plane_world_position(player, ((screen_x + 0.5 - half_width) / projection_distance).atan(), distance);
+
+    let left = edge(0.0);
+    let columns = screen_width.saturating_sub(1).max(1) as f64;
+    let right = edge(columns);
+    (left, ((right.0 - left.0) / columns, (right.1 - left.1) / columns))
+}
+
+struct WallColumns<'a> {
+    wall_grid: Option<&'a WallGrid>,
+    textures: Option<&'a TextureManager>,
+    light_levels: &'a [i16],
+    screen_height: u32,
+    projection_distance: f64,
+    max_view_distance: f64,
+    fog_color: Option<[u8; 3]>,
+}
+
+impl WallColumns<'_> {
+    fn cast_ray(&self, player: &Player, angle: f64) -> Option<RayHit> {
+        let grid = self.wall_grid?;
+
+        let ray_dx = angle.cos();
+        let ray_dy = angle.sin();
+
+        // Work in grid units so each step crosses exactly one cell boundary
+        let pos_x = (player.x - grid.origin_x) / grid.cell_size;
+        let pos_y = (player.y - grid.origin_y) / grid.cell_size;
+        let mut cell_x = pos_x.floor() as i64;
+        let mut cell_y = pos_y.floor() as i64;
+
+        // Ray length needed to cross one full cell along each axis
+        let delta_x = if ray_dx == 0.0 { f64::INFINITY } else { (1.0 / ray_dx).abs() };
+        let delta_y = if ray_dy == 0.0 { f64::INFINITY } else { (1.0 / ray_dy).abs() };
+
+        let (step_x, mut side_x) = if ray_dx < 0.0 {
+            (-1, (pos_x - cell_x as f64) * delta_x)
+        } else {
+            (1, (cell_x as f64 + 1.0 - pos_x) * delta_x)
+        };
+        let (step_y, mut side_y) = if ray_dy < 0.0 {
+            (-1, (pos_y - cell_y as f64) * delta_y)
+        } else {
+            (1, (cell_y as f64 + 1.0 - pos_y) * delta_y)
+        };
+
+        let max_distance = self.max_view_distance / grid.cell_size;
+
+        loop {
+            // Ray length at which we enter the next cell
+            let traveled = if side_x < side_y {
+                cell_x += step_x;
+                side_x += delta_x;
+                side_x - delta_x
+            } else {
+                cell_y += step_y;
+                side_y += delta_y;
+                side_y - delta_y
+            };
+
+            if traveled > max_distance {
+                return None;
+            }
+
+            if let Some(cell) = grid.cell(cell_x, cell_y) {
+                let ray_distance = traveled * grid.cell_size;
+
+                return Some(RayHit {
+                    // Distance to the view plane rather than along the ray
+                    distance: ray_distance * (angle - player.angle).cos(),
+                    wall_type: cell.wall_type,
+                    linedef: cell.linedef,
+                    reversed: false,
+                    hit_x: player.x + ray_dx * ray_distance,
+                    hit_y: player.y + ray_dy * ray_distance,
+                });
+            }
+        }
+    }
+
+    // Shades the spans of wall a hit shows in one column into rows and colours, inside
+    // the clip of rows nearer walls have left open. Returns the clip narrowed to the
+    // gap seen through a two-sided line and whether the column is now covered from top
+    // to bottom.
+    fn shade(&self, hit: &RayHit, map: &Map, eye_z: f64, clip: (i32, i32), pixels: &mut Vec<(i32, Color)>) -> ((i32, i32), bool) {
+        let (front_sidedef, back_sidedef) = hit.sides(map);
+        let side_heights = |sidedef: u16| {
+            map.side_sector(sidedef)
+                .and_then(|sector| map.sectors.get(sector))
+                .map(|sector| (sector.floor_height as f64, sector.ceiling_height as f64))
+        };
+        // Without sector heights, draw a wall 100 units tall centred on the eye
+        let front = side_heights(front_sidedef).unwrap_or((eye_z - 50.0, eye_z + 50.0));
+        let back = side_heights(back_sidedef);
+
+        let half_height = self.screen_height as f64 / 2.0;
+        let scale = self.projection_distance / hit.distance;
+        // First row whose centre is below height z
+        let row = |z: f64| (half_height - (z - eye_z) * scale - 0.5).ceil().clamp(-1.0e6, 1.0e6) as i32;
+        let light_level = Renderer::wall_light_level(self.light_levels, map, front_sidedef);
+
+        for span in wall_spans(front, back) {
+            let rows = row(span.top).max(clip.0)..row(span.bottom).min(clip.1);
+            if rows.is_empty() {
+                continue;
+            }
+
+            let Some((texture, textures, u, v_offset)) = Renderer::wall_texture_column(self.textures, map, hit, span, front.1) else {
+                // Choose color based on wall type when there's no texture to sample
+                let color = match hit.wall_type {
+                    WallType::Stone => Color::RGB(128, 128, 128),
+                    WallType::Wood => Color::RGB(139, 69, 19),
+                    WallType::Metal => Color::RGB(192, 192, 192),
+                };
+                let color = self.fogged(darken(color, light_index(light_level, hit.distance)), hit.distance);
+                pixels.extend(rows.map(|y| (y, color)));
+                continue;
+            };
+
+            // One texel per world unit down from the top of the span
+            for y in rows {
+                let z = eye_z + (half_height - y as f64 - 0.5) / scale;
+                let v = ((v_offset + span.top - z).floor() as i64).rem_euclid(texture.height as i64) as usize;
+                let index = texture.pixels[v * texture.width as usize + u];
+
+                let color = self.fogged(Renderer::shade_texel(textures, index, light_level, hit.distance), hit.distance);
+                pixels.push((y, color));
+            }
+        }
+
+        let Some((back_floor, back_ceiling)) = back else {
+            return (clip, true);
+        };
+        let clip = (clip.0.max(row(front.1.min(back_ceiling))), clip.1.min(row(front.0.max(back_floor))));
+        (clip, clip.0 >= clip.1)
+    }
+
+    fn fogged(&self, color: Color, distance: f64) -> Color {
+        let Some(fog) = self.fog_color else {
+            return color;
+        };
+        let [r, g, b] = fog_blend([color.r, color.g, color.b], fog, distance, self.max_view_distance);
+        Color::RGB(r, g, b)
+    }
+}
+
+struct RayHit {
+    distance: f64,
+    wall_type: WallType,
+    linedef: u16,
+    // Whether the line was hit from its back side
+    reversed: bool,
+    hit_x: f64,
+    hit_y: f64,
+}
+
+impl RayHit {
+    // The sidedefs facing towards and away from the viewer, 0xFFFF for a missing one
+    fn sides(&self, map: &Map) -> (u16, u16) {
+        let Some(linedef) = map.linedefs.get(self.linedef as usize) else {
+            return (0xFFFF, 0xFFFF);
+        };
+        if self.reversed {
+            (linedef.back_sidedef, linedef.front_sidedef)
+        } else {
+            (linedef.front_sidedef, linedef.back_sidedef)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum WallType {
+    Stone,
+    Wood,
+    Metal,
+}
+
+impl WallType {
+    fn for_linedef(map: &Map, linedef: u16) -> Self {
+        map.linedefs
+            .get(linedef as usize)
+            .and_then(|linedef| map.sidedefs.get(linedef.front_sidedef as usize))
+            .map(|side| WallType::from_texture_name(&side.middle_texture))
+            .unwrap_or(WallType::Stone)
+    }
+
+    fn from_texture_name(name: &str) -> Self {
+        if name.starts_with("WOOD") {
+            WallType::Wood
+        } else if name.starts_with("METAL") || name.starts_with("SUPPORT") {
+            WallType::Metal
+        } else {
+            WallType::Stone
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct WallCell {
+    linedef: u16,
+    wall_type: WallType,
+}
+
+// One-sided linedefs rasterized onto a uniform grid for DDA raycasting
+struct WallGrid {
+    origin_x: f64,
+    origin_y: f64,
+    cell_size: f64,
+    columns: usize,
+    rows: usize,
+    cells: Vec<Option<WallCell>>,
+}
+
+impl WallGrid {
+    fn from_map(map: &Map, cell_size: f64) -> Self {
+        let (min_x, min_y, max_x, max_y) = map.bounds();
+        let (min_x, min_y, max_x, max_y) = (min_x as f64, min_y as f64, max_x as f64, max_y as f64);
+
+        let columns = ((max_x - min_x) / cell_size).floor() as usize + 1;
+        let rows = ((max_y - min_y) / cell_size).floor() as usize + 1;
+
+        let mut grid = WallGrid {
+            origin_x: min_x,
+            origin_y: min_y,
+            cell_size,
+            columns,
+            rows,
+            cells: vec![None; columns * rows],
+        };
+
+        for (index, linedef) in map.linedefs.iter().enumerate() {
+            // Only one-sided lines are solid
+            if linedef.back_sidedef != 0xFFFF {
+                continue;
+            }
+
+            let (Some(start), Some(end)) = (
+                map.vertices.get(linedef.start_vertex as usize),
+                map.vertices.get(linedef.end_vertex as usize),
+            ) else {
+                continue;
+            };
+
+            let cell = WallCell {
+                linedef: index as u16,
+                wall_type: WallType::for_linedef(map, index as u16),
+            };
+
+            // Sample along the line at half-cell spacing so no cell is skipped
+            let (dx, dy) = ((end.x - start.x) as f64, (end.y - start.y) as f64);
+            let steps = ((dx * dx + dy * dy).sqrt() / (cell_size * 0.5)).ceil() as usize;
+            for step in 0..=steps {
+                let t = step as f64 / steps.max(1) as f64;
+                grid.mark(start.x as f64 + dx * t, start.y as f64 + dy * t, cell);
+            }
+        }
+
+        grid
+    }
+
+    fn mark(&mut self, x: f64, y: f64, cell: WallCell) {
+        let column = ((x - self.origin_x) / self.cell_size).floor() as usize;
+        let row = ((y - self.origin_y) / self.cell_size).floor() as usize;
+        if column < self.columns && row < self.rows {
+            self.cells[row * self.columns + column] = Some(cell);
+        }
+    }
+
+    fn cell(&self, column: i64, row: i64) -> Option<WallCell> {
+        if column < 0 || row < 0 || column as usize >= self.columns || row as usize >= self.rows {
+            return None;
+        }
+        self.cells[row as usize * self.columns + column as usize]
+    }
+}
+
+// Sector indices sampled on a uniform grid so floors can find their sector per pixel
+struct SectorGrid {
+    origin_x: f64,
+    origin_y: f64,
+    cell_size: f64,
+    columns: usize,
+    rows: usize,
+    cells: Vec<Option<usize>>,
+}
+
+impl SectorGrid {
+    fn from_map(map: &Map, cell_size: f64) -> Self {
+        let (min_x, min_y, max_x, max_y) = map.bounds();
+        let (min_x, min_y, max_x, max_y) = (min_x as f64, min_y as f64, max_x as f64, max_y as f64);
+
+        let columns = ((max_x - min_x) / cell_size).floor() as usize + 1;
+        let rows = ((max_y - min_y) / cell_size).floor() as usize + 1;
+
+        let mut cells = Vec::with_capacity(columns * rows);
+        for row in 0..rows {
+            for column in 0..columns {
+                let x = min_x + (column as f64 + 0.5) * cell_size;
+                let y = min_y + (row as f64 + 0.5) * cell_size;
+                cells.push(map.sector_at(x, y));
+            }
+        }
+
+        SectorGrid {
+            origin_x: min_x,
+            origin_y: min_y,
+            cell_size,
+            columns,
+            rows,
+            cells,
+        }
+    }
+
+    fn sector_at(&self, x: f64, y: f64) -> Option<usize> {
+        let column = ((x - self.origin_x) / self.cell_size).floor();
+        let row = ((y - self.origin_y) / self.cell_size).floor();
+        if column < 0.0 || row < 0.0 || column as usize >= self.columns || row as usize >= self.rows {
+            return None;
+        }
+        self.cells[row as usize * self.columns + column as usize]
+    }
+}
+
+pub struct Texture {
+    pub width: u16,
+    pub height: u16,
+    pub pixels: Vec<u8>, // Palette indices
+    pub opaque: Vec<bool>, // False where nothing was drawn and the texel is see-through
+}
+
+impl Texture {
+    pub fn is_opaque(&self, x: usize, y: usize) -> bool {
+        x < self.width as usize && self.opaque.get(y * self.width as usize + x).copied().unwrap_or(false)
+    }
+}
+
+// A graphic in Doom's picture format, used by patches, sprites and the HUD. The offsets
+// place it relative to its origin: a sprite's feet or a patch's position in a texture.
+pub struct Picture {
+    pub texture: Texture,
+    pub left_offset: i16,
+    pub top_offset: i16,
+}
+
+impl Picture {
+    // Pictures are stored as columns of posts: runs of pixels with a starting row,
+    // ended by 0xFF. Rows not covered by a post are transparent.
+    pub fn from_lump(data: &[u8]) -> Result<Picture, Box<dyn std::error::Error>> {
+        let mut cursor = Cursor::new(data);
+        let width = cursor.read_u16::<LittleEndian>()?;
+        let height = cursor.read_u16::<LittleEndian>()?;
+        let left_offset = cursor.read_i16::<LittleEndian>()?;
+        let top_offset = cursor.read_i16::<LittleEndian>()?;
+
+        let mut column_offsets = Vec::with_capacity(width as usize);
+        for _ in 0..width {
+            column_offsets.push(cursor.read_u32::<LittleEndian>()?);
+        }
+
+        let size = width as usize * height as usize;
+        let mut texture = Texture {
+            width,
+            height,
+            pixels: vec![0u8; size],
+            opaque: vec![false; size],
+        };
+
+        for (x, offset) in column_offsets.into_iter().enumerate() {
+            cursor.set_position(offset as u64);
+
+            // Tall patches go past row 254 by giving a top delta no greater than the
+            // previous one, which is then relative to that post instead of the column top
+            let mut top: Option<usize> = None;
+            loop {
+                let top_delta = cursor.read_u8()?;
+                if top_delta == 0xFF {
+                    break;
+                }
+                let row = match top {
+                    Some(previous) if top_delta as usize <= previous => previous + top_delta as usize,
+                    _ => top_delta as usize,
+                };
+                top = Some(row);
+
+                let length = cursor.read_u8()?;
+                let _pad = cursor.read_u8()?;
+                for y in row..row + length as usize {
+                    let value = cursor.read_u8()?;
+                    if y < height as usize {
+                        let index = y * width as usize + x;
+                        texture.pixels[index] = value;
+                        texture.opaque[index] = true;
+                    }
+                }
+                let _pad = cursor.read_u8()?;
+            }
+        }
+
+        Ok(Picture {
+            texture,
+            left_offset,
+            top_offset,
+        })
+    }
+}
+
+// How far the weapon sways with movement, in 320x200 screen units, at `time` seconds
+// into the level. As in Doom the sway grows with the square of the speed up to
+// MAX_WEAPON_BOB and swings side to side once every 64 tics, dipping at both ends.
+pub fn weapon_bob(velocity: (f64, f64), time: f64) -> (f64, f64) {
+    let (x_per_tic, y_per_tic) = (velocity.0 / TICS_PER_SECOND, velocity.1 / TICS_PER_SECOND);
+    let bob = ((x_per_tic * x_per_tic + y_per_tic * y_per_tic) / 4.0).min(MAX_WEAPON_BOB);
+    let angle = time * TICS_PER_SECOND / 64.0 * std::f64::consts::TAU;
+    (bob * angle.cos(), bob * angle.sin().abs())
+}
+
+// The status bar's background, its big digits and percent sign, the small yellow
+// digits for ammo counts and the six key icons
+struct StatusBarPictures {
+    background: Picture,
+    digits: Vec<Picture>,
+    percent: Picture,
+    small_digits: Vec<Picture>,
+    keys: Vec<Picture>,
+}
+
+impl StatusBarPictures {
+    // None if any of the pictures is missing from the WAD
+    fn load(wad: &WadFile) -> Result<Option<StatusBarPictures>, Box<dyn std::error::Error>> {
+        let picture = |name: String| -> Result<Option<Picture>, Box<dyn std::error::Error>> {
+            match wad.find_lump(&name) {
+                Some(lump) => Ok(Some(Picture::from_lump(&lump.data)?)),
+                None => Ok(None),
+            }
+        };
+        let pictures = |prefix: &str, count: usize| -> Result<Option<Vec<Picture>>, Box<dyn std::error::Error>> {
+            (0..count).map(|index| picture(format!("{}{}", prefix, index))).collect::<Result<Option<Vec<_>>, _>>()
+        };
+
+        let (Some(background), Some(digits), Some(percent), Some(small_digits), Some(keys)) = (
+            picture("STBAR".to_string())?,
+            pictures("STTNUM", 10)?,
+            picture("STTPRCNT".to_string())?,
+            pictures("STYSNUM", 10)?,
+            pictures("STKEYS", 6)?,
+        ) else {
+            return Ok(None);
+        };
+        Ok(Some(StatusBarPictures {
+            background,
+            digits,
+            percent,
+            small_digits,
+            keys,
+        }))
+    }
+}
+
+// An animation cycles through every texture or flat between its first and last frame,
+// in the order they appear in the WAD, advancing every `speed` tics
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnimationDef {
+    pub is_flat: bool,
+    pub first: String,
+    pub last: String,
+    pub speed: u32,
+}
+
+impl AnimationDef {
+    pub fn vanilla() -> Vec<AnimationDef> {
+        VANILLA_ANIMATIONS
+            .iter()
+            .map(|&(is_flat, last, first)| AnimationDef {
+                is_flat,
+                first: first.to_string(),
+                last: last.to_string(),
+                speed: ANIMATION_SPEED,
+            })
+            .collect()
+    }
+
+    // Boom's ANIMATED lump: 23 byte records of type, last name, first name and speed,
+    // ended by a record with type 255
+    pub fn parse_animated(data: &[u8]) -> Result<Vec<AnimationDef>, Box<dyn std::error::Error>> {
+        let mut cursor = Cursor::new(data);
+        let mut animations = Vec::new();
+
+        loop {
+            let kind = cursor.read_u8()?;
+            if kind == 0xFF {
+                break;
+            }
+
+            let mut last = [0u8; 9];
+            cursor.read_exact(&mut last)?;
+            let mut first = [0u8; 9];
+            cursor.read_exact(&mut first)?;
+            let speed = cursor.read_u32::<LittleEndian>()?;
+
+            let name = |bytes: &[u8]| {
+                let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+                String::from_utf8_lossy(&bytes[..end]).to_uppercase()
+            };
+            animations.push(AnimationDef {
+                // Bit 0 set means a wall texture, the other bits are Boom flags
+                is_flat: kind & 1 == 0,
+                first: name(&first),
+                last: name(&last),
+                speed: speed.max(1),
+            });
+        }
+
+        Ok(animations)
+    }
+}
+
+struct Animation {
+    is_flat: bool,
+    frames: Vec<String>,
+    speed: u32,
+}
+
+// A sector's light level this frame, from animate_lights if it's run
+fn effective_light(light_levels: &[i16], map: &Map, sector: usize) -> i16 {
+    light_levels
+        .get(sector)
+        .copied()
+        .unwrap_or_else(|| map.sectors[sector].light_level)
+}
+
+// Light level of sector number `sector` after `tic` tics with Doom's light effect for
+// its special: 1 blinks off at random, 2, 3 and 4 strobe out of step with other
+// sectors and 12 and 13 in step, 8 glows and 17 flickers like fire. `light` is the
+// sector's own level and `min_light` the darkest of it and its neighbours. Random
+// effects come from a hash of the sector and time, so they're the same every run.
+pub fn sector_light(special_type: u16, light: i16, min_light: i16, sector: usize, tic: u64) -> i16 {
+    let random = |step: u64| {
+        let mut hash = (sector as u64) << 32 ^ step;
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+        hash ^ (hash >> 31)
+    };
+    let strobe = |dark: u64, in_sync: bool| {
+        // Strobing between equal levels would show nothing, so it goes to black
+        let dark_light = if min_light == light { 0 } else { min_light };
+        let offset = if in_sync { 0 } else { random(0) % 8 };
+        if (tic + offset) % (STROBE_BRIGHT + dark) < STROBE_BRIGHT { light } else { dark_light }
+    };
+
+    match special_type {
+        // Dark for up to 8 tics at the start of every BLINK_STEP
+        1 => {
+            let dark = (random(tic / BLINK_STEP) & 7) + 1;
+            if tic % BLINK_STEP < dark { min_light } else { light }
+        }
+        2 | 4 => strobe(FAST_DARK, false),
+        3 => strobe(SLOW_DARK, false),
+        12 => strobe(SLOW_DARK, true),
+        13 => strobe(FAST_DARK, true),
+        // Down to the darkest neighbour and back up again
+        8 => {
+            let range = (light - min_light).max(0) as u64;
+            if range == 0 {
+                return light;
+            }
+            let position = (tic * GLOW_SPEED) % (2 * range);
+            if position < range { light - position as i16 } else { min_light + (position - range) as i16 }
+        }
+        // Dips by up to 48 every few tics, never below 16 over the darkest neighbour
+        17 => {
+            let dip = (random(tic / FLICKER_STEP) & 3) as i16 * 16;
+            (light - dip).max(min_light + 16).min(light)
+        }
+        _ => light,
+    }
+}
+
+// Index of the frame shown in place of frame `index` of an animation after `tic` tics
+pub fn animation_frame(index: usize, frame_count: usize, speed: u32, tic: u64) -> usize {
+    if frame_count == 0 {
+        return index;
+    }
+    ((tic / speed.max(1) as u64) as usize + index) % frame_count
+}
+
+// Which of a sprite's 8 rotations faces the viewer, 0 for its front and counting
+// counterclockwise around it as Doom does. `facing` is the direction the thing faces
+// and `to_viewer` the direction from it to the viewer, in radians. Each rotation
+// covers the 45 degrees centred on its direction.
+pub fn sprite_rotation(facing: f64, to_viewer: f64) -> usize {
+    let relative = (to_viewer - facing + std::f64::consts::FRAC_PI_8).rem_euclid(std::f64::consts::TAU);
+    (relative / std::f64::consts::FRAC_PI_4) as usize % 8
+}
+
+// The sprite lump to draw one frame of a sprite with from each rotation, and whether
+// it's mirrored. A frame without rotations has the same lump in all 8.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpriteFrame {
+    pub rotations: [Option<(String, bool)>; 8],
+}
+
+impl SpriteFrame {
+    // Adds a lump as one rotation (1-8) of the frame, or every rotation for 0
+    pub fn install(&mut self, lump_name: &str, rotation: u8, flipped: bool) {
+        let entry = Some((lump_name.to_string(), flipped));
+        match rotation {
+            0 => self.rotations = std::array::from_fn(|_| entry.clone()),
+            1..=8 => self.rotations[rotation as usize - 1] = entry,
+            _ => {}
+        }
+    }
+}
+
+// A frame and rotation a sprite lump is drawn for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpriteLumpUse {
+    pub frame: char,
+    pub rotation: u8,
+    pub flipped: bool,
+}
+
+// Splits a sprite lump name such as TROOA2A8 into its sprite name and the frames and
+// rotations it's used for: here frame A rotation 2, and rotation 8 mirrored
+pub fn parse_sprite_lump_name(name: &str) -> Option<(&str, Vec<SpriteLumpUse>)> {
+    let sprite = name.get(..4)?;
+    let bytes = name.as_bytes();
+    let mut uses = Vec::new();
+    for (offset, flipped) in [(4, false), (6, true)] {
+        let (Some(&frame), Some(&rotation)) = (bytes.get(offset), bytes.get(offset + 1)) else {
+            break;
+        };
+        if !rotation.is_ascii_digit() {
+            return None;
+        }
+        uses.push(SpriteLumpUse {
+            frame: frame as char,
+            rotation: rotation - b'0',
+            flipped,
+        });
+    }
+    if uses.is_empty() {
+        return None;
+    }
+    Some((sprite, uses))
+}
+
+pub struct TextureManager {
+    textures: std::collections::HashMap<String, Texture>,
+    // Sprite pictures by lump name, and each sprite's frames by sprite name and letter
+    sprites: std::collections::HashMap<String, Picture>,
+    sprite_frames: std::collections::HashMap<(String, char), SpriteFrame>,
+    flats: std::collections::HashMap<String, Vec<u8>>, // 64x64 palette indices
+    palettes: Vec<[[u8; 3]; 256]>, // RGB values, never empty
+    active_palette: usize,
+    colormaps: Vec<[u8; 256]>, // Palette index remaps, brightest first
+    // Colormap used for everything in place of light levels, see set_fixed_colormap
+    fixed_colormap: Option<usize>,
+    animations: Vec<Animation>,
+    // Names of animated textures and flats mapped to the frame currently shown for them
+    texture_frames: std::collections::HashMap<String, String>,
+    flat_frames: std::collections::HashMap<String, String>,
+}
+
+impl TextureManager {
+    pub fn load_from_wad(wad: &WadFile) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut textures = std::collections::HashMap::new();
+        let mut texture_order = Vec::new();
+        let palettes = Self::load_palettes(wad)?;
+
+        // Load PNAMES (patch names). Without it the textures still get their sizes, just
+        // no patches drawn into them.
+        let patch_names = match wad.find_lump("PNAMES") {
+            Some(pnames_lump) => Self::parse_patch_names(&pnames_lump.data)?,
+            None => Vec::new(),
+        };
+
+        // Load TEXTURE1 and TEXTURE2 (registered Doom only). As in Doom, the first
+        // definition of a name wins.
+        for lump_name in ["TEXTURE1", "TEXTURE2"] {
+            let Some(lump) = wad.find_lump(lump_name) else {
+                continue;
+            };
+            if patch_names.is_empty() {
+                eprintln!("{} has no PNAMES lump, so its textures will be blank", lump_name);
+            }
+
+            for (name, texture) in Self::parse_textures(&lump.data, &patch_names, wad)? {
+                if let std::collections::hash_map::Entry::Vacant(entry) = textures.entry(name) {
+                    texture_order.push(entry.key().to_uppercase());
+                    entry.insert(texture);
+                }
+            }
+        }
+
+        let (flats, flat_order) = Self::load_flats(wad);
+        let (sprites, sprite_frames) = Self::load_sprites(wad)?;
+        let colormaps = Self::load_colormap(wad)?;
+
+        let definitions = match wad.find_lump("ANIMATED") {
+            Some(lump) => AnimationDef::parse_animated(&lump.data)?,
+            None => AnimationDef::vanilla(),
+        };
+        let animations = Self::build_animations(&definitions, &texture_order, &flat_order);
+
+        let mut manager = TextureManager {
+            textures,
+            sprites,
+            sprite_frames,
+            flats,
+            palettes,
+            active_palette: 0,
+            colormaps,
+            fixed_colormap: None,
+            animations,
+            texture_frames: std::collections::HashMap::new(),
+            flat_frames: std::collections::HashMap::new(),
+        };
+        manager.tick(std::time::Duration::ZERO);
+        Ok(manager)
+    }
+
+    // Turns each definition into its list of frames, dropping any whose first or last
+    // frame isn't in the WAD or comes in the wrong order
+    fn build_animations(definitions: &[AnimationDef], texture_order: &[String], flat_order: &[String]) -> Vec<Animation> {
+        definitions
+            .iter()
+            .filter_map(|definition| {
+                let order = if definition.is_flat { flat_order } else { texture_order };
+                let first = order.iter().position(|name| *name == definition.first)?;
+                let last = order.iter().position(|name| *name == definition.last)?;
+                if last <= first {
+                    return None;
+                }
+                Some(Animation {
+                    is_flat: definition.is_flat,
+                    frames: order[first..=last].to_vec(),
+                    speed: definition.speed,
+                })
+            })
+            .collect()
+    }
+
+    // Advances every animated texture and flat to the frame shown at `game_time`
+    pub fn tick(&mut self, game_time: std::time::Duration) {
+        let tic = (game_time.as_secs_f64() * TICS_PER_SECOND) as u64;
+
+        for animation in &self.animations {
+            let frames = if animation.is_flat { &mut self.flat_frames } else { &mut self.texture_frames };
+            for (index, name) in animation.frames.iter().enumerate() {
+                let shown = animation_frame(index, animation.frames.len(), animation.speed, tic);
+                frames.insert(name.clone(), animation.frames[shown].clone());
+            }
+        }
+    }
+
+    // The texture or flat currently drawn in place of `name`
+    pub fn resolve_texture_name<'a>(&'a self, name: &'a str) -> &'a str {
+        self.texture_frames.get(&name.to_uppercase()).map_or(name, |frame| frame.as_str())
+    }
+
+    pub fn resolve_flat_name<'a>(&'a self, name: &'a str) -> &'a str {
+        self.flat_frames.get(&name.to_uppercase()).map_or(name, |frame| frame.as_str())
+    }
+
+    // Flats live between the F_START/F_END markers (FF_ in PWADs). The names are also
+    // returned in lump order, which is what animation ranges run over.
+    fn load_flats(wad: &WadFile) -> (std::collections::HashMap<String, Vec<u8>>, Vec<String>) {
+        let mut flats = std::collections::HashMap::new();
+        let mut order = Vec::new();
+
+        let lumps = wad.namespace_lumps("F_START", "F_END").chain(wad.namespace_lumps("FF_START", "FF_END"));
+        for lump in lumps.filter(|lump| lump.data.len() >= FLAT_SIZE * FLAT_SIZE) {
+            flats.insert(lump.name.to_uppercase(), lump.data[..FLAT_SIZE * FLAT_SIZE].to_vec());
+            order.push(lump.name.to_uppercase());
+        }
+
+        (flats, order)
+    }
+
+    // Sprites are between S_START and S_END, plus SS_START and SS_END in PWADs. A later
+    // lump for the same frame and rotation replaces an earlier one.
+    #[allow(clippy::type_complexity)]
+    fn load_sprites(
+        wad: &WadFile,
+    ) -> Result<(std::collections::HashMap<String, Picture>, std::collections::HashMap<(String, char), SpriteFrame>), Box<dyn std::error::Error>> {
+        let mut sprites = std::collections::HashMap::new();
+        let mut frames: std::collections::HashMap<(String, char), SpriteFrame> = std::collections::HashMap::new();
+
+        for lump in wad.namespace_lumps("S_START", "S_END").chain(wad.namespace_lumps("SS_START", "SS_END")) {
+            let name = lump.name.to_uppercase();
+            let Some((sprite, uses)) = parse_sprite_lump_name(&name) else {
+                continue;
+            };
+            for lump_use in uses {
+                frames
+                    .entry((sprite.to_string(), lump_use.frame))
+                    .or_default()
+                    .install(&name, lump_use.rotation, lump_use.flipped);
+            }
+            sprites.insert(name.clone(), Picture::from_lump(&lump.data)?);
+        }
+
+        Ok((sprites, frames))
+    }
+
+    // The picture showing a frame of a sprite from one rotation (see sprite_rotation),
+    // and whether to draw it mirrored
+    pub fn sprite_frame(&self, sprite: &str, frame: char, rotation: usize) -> Option<(&Picture, bool)> {
+        let sprite_frame = self.sprite_frames.get(&(sprite.to_uppercase(), frame.to_ascii_uppercase()))?;
+        let (lump, flipped) = sprite_frame.rotations.get(rotation)?.as_ref()?;
+        Some((self.sprites.get(lump)?, *flipped))
+    }
+
+    // The picture for a frame of a sprite facing `facing`, seen from the direction
+    // `to_viewer` points in from the thing
+    pub fn sprite_view(&self, sprite: &str, frame: char, facing: f64, to_viewer: f64) -> Option<(&Picture, bool)> {
+        self.sprite_frame(sprite, frame, sprite_rotation(facing, to_viewer))
+    }
+
+    fn load_palettes(wad: &WadFile) -> Result<Vec<[[u8; 3]; 256]>, Box<dyn std::error::Error>> {
+        let playpal = wad.find_lump("PLAYPAL")
+            .ok_or("PLAYPAL lump not found")?;
+        Self::parse_playpal(&playpal.data)
+    }
+
+    // PLAYPAL is a run of 768 byte palettes. Doom's has NUM_PALETTES of them, but any
+    // number is read, and a partial one at the end is ignored.
+    pub fn parse_playpal(data: &[u8]) -> Result<Vec<[[u8; 3]; 256]>, Box<dyn std::error::Error>> {
+        let palettes: Vec<[[u8; 3]; 256]> = data
+            .chunks_exact(256 * 3)
+            .map(|chunk| std::array::from_fn(|index| [chunk[index * 3], chunk[index * 3 + 1], chunk[index * 3 + 2]]))
+            .collect();
+        if palettes.is_empty() {
+            return Err(format!("PLAYPAL is {} bytes, too short for a palette", data.len()).into());
+        }
+        Ok(palettes)
+    }
+
+    pub fn palette_count(&self) -> usize {
+        self.palettes.len()
+    }
+
+    pub fn palette(&self, index: usize) -> Option<&[[u8; 3]; 256]> {
+        self.palettes.get(index)
+    }
+
+    // Palettes past the last one in the WAD fall back to the normal palette
+    pub fn set_active_palette(&mut self, index: usize) {
+        self.active_palette = if index < self.palettes.len() { index } else { 0 };
+    }
+
+    fn current_palette(&self) -> &[[u8; 3]; 256] {
+        &self.palettes[self.active_palette]
+    }
+
+    // Draws the whole view through one colormap whatever its lighting, None to go back
+    // to light levels
+    pub fn set_fixed_colormap(&mut self, colormap: Option<usize>) {
+        self.fixed_colormap = colormap;
+    }
+
+    pub fn fixed_colormap(&self) -> Option<usize> {
+        self.fixed_colormap
+    }
+
+    // The active palette as things drawn at full brightness in the view show it,
+    // through the fixed colormap when there is one
+    fn view_palette(&self) -> [[u8; 3]; 256] {
+        match self.fixed_colormap {
+            Some(light) => std::array::from_fn(|index| self.shade(index as u8, light)),
+            None => *self.current_palette(),
+        }
+    }
+
+    // COLORMAP holds 34 remaps of 256 indices: 32 light levels, invulnerability and
+    // all black. Without one, darken the palette linearly and match the nearest colours.
+    pub fn load_colormap(wad: &WadFile) -> Result<Vec<[u8; 256]>, Box<dyn std::error::Error>> {
+        if let Some(lump) = wad.find_lump("COLORMAP") {
+            let colormaps: Vec<[u8; 256]> = lump
+                .data
+                .chunks_exact(256)
+                .map(|chunk| {
+                    let mut map = [0u8; 256];
+                    map.copy_from_slice(chunk);
+                    map
+                })
+                .collect();
+
+            if colormaps.len() >= NUM_COLORMAPS {
+                return Ok(colormaps);
+            }
+        }
+
+        let palettes = Self::load_palettes(wad)?;
+        Ok(Self::synthesize_colormaps(&palettes[0]))
+    }
+
+    fn synthesize_colormaps(palette: &[[u8; 3]]) -> Vec<[u8; 256]> {
+        let base = &palette[..palette.len().min(256)];
+
+        (0..NUM_COLORMAPS + 2)
+            .map(|level| {
+                // Matches COLORMAP's inverse map: each colour's brightness inverted, in grey
+                if level == INVERSE_COLORMAP {
+                    let mut map = [0u8; 256];
+                    for (index, &[r, g, b]) in base.iter().enumerate() {
+                        let grey = 255.0 - (r as f64 * 0.299 + g as f64 * 0.587 + b as f64 * 0.114);
+                        map[index] = Self::nearest_palette_index(base, [grey; 3]);
+                    }
+                    return map;
+                }
+
+                let factor = 1.0 - level.min(NUM_COLORMAPS) as f64 / NUM_COLORMAPS as f64;
+                let mut map = [0u8; 256];
+
+                for (index, &[r, g, b]) in base.iter().enumerate() {
+                    let target = [r as f64 * factor, g as f64 * factor, b as f64 * factor];
+                    map[index] = Self::nearest_palette_index(base, target);
+                }
+
+                map
+            })
+            .collect()
+    }
+
+    fn nearest_palette_index(palette: &[[u8; 3]], target: [f64; 3]) -> u8 {
+        palette
+            .iter()
+            .enumerate()
+            .map(|(index, color)| {
+                let distance: f64 = color
+                    .iter()
+                    .zip(target)
+                    .map(|(&channel, wanted)| (channel as f64 - wanted).powi(2))
+                    .sum();
+                (index, distance)
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(index, _)| index as u8)
+            .unwrap_or(0)
+    }
+
+    // RGB of a palette index after remapping through colormap `light`, 0 being brightest,
+    // or through the fixed colormap when one is set
+    pub fn shade(&self, index: u8, light: usize) -> [u8; 3] {
+        let light = self.fixed_colormap.unwrap_or(light);
+        let remapped = match self.colormaps.get(light.min(self.colormaps.len().saturating_sub(1))) {
+            Some(colormap) => colormap[index as usize],
+            None => index,
+        };
+
+        self.current_palette()[remapped as usize]
+    }
+
+    fn parse_patch_names(data: &[u8]) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut cursor = Cursor::new(data);
+        let count = cursor.read_u32::<LittleEndian>()?;
+
+        let mut names = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let mut name = [0u8; 8];
+            cursor.read_exact(&mut name)?;
+            // Patch lump names are matched case-insensitively
+            names.push(
+                String::from_utf8_lossy(&name)
+                    .trim_end_matches('\0')
+                    .to_uppercase(),
+            );
+        }
+
+        Ok(names)
+    }
+
+    fn parse_textures(
+        data: &[u8],
+        patch_names: &[String],
+        wad: &WadFile,
+    ) -> Result<Vec<(String, Texture)>, Box<dyn std::error::Error>> {
+        let mut cursor = Cursor::new(data);
+        let count = cursor.read_u32::<LittleEndian>()?;
+
+        let mut offsets = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            offsets.push(cursor.read_u32::<LittleEndian>()?);
+        }
+
+        let mut textures = Vec::with_capacity(count as usize);
+        for offset in offsets {
+            cursor.set_position(offset as u64);
+
+            let mut name = [0u8; 8];
+            cursor.read_exact(&mut name)?;
+            let name = String::from_utf8_lossy(&name).trim_end_matches('\0').to_string();
+
+            let _masked = cursor.read_u32::<LittleEndian>()?;
+            let width = cursor.read_u16::<LittleEndian>()?;
+            let height = cursor.read_u16::<LittleEndian>()?;
+            let _column_directory = cursor.read_u32::<LittleEndian>()?;
+            let patch_count = cursor.read_u16::<LittleEndian>()?;
+
+            let mut texture = Texture {
+                width,
+                height,
+                pixels: vec![0u8; width as usize * height as usize],
+                opaque: vec![false; width as usize * height as usize],
+            };
+
+            for _ in 0..patch_count {
+                let origin_x = cursor.read_i16::<LittleEndian>()?;
+                let origin_y = cursor.read_i16::<LittleEndian>()?;
+                let patch_index = cursor.read_u16::<LittleEndian>()?;
+                let _step_dir = cursor.read_u16::<LittleEndian>()?;
+                let _colormap = cursor.read_u16::<LittleEndian>()?;
+
+                let patch = patch_names
+                    .get(patch_index as usize)
+                    .and_then(|patch_name| {
+                        // Prefer the patch namespace so a flat or sprite sharing the name isn't used
+                        wad.find_lump_in_namespace(patch_name, "P_START", "P_END")
+                            .or_else(|| wad.find_lump_in_namespace(patch_name, "PP_START", "PP_END"))
+                            .or_else(|| wad.find_lump(patch_name))
+                    });
+                if let Some(patch) = patch {
+                    Self::draw_patch(&mut texture, &patch.data, origin_x as i32, origin_y as i32)?;
+                }
+            }
+
+            textures.push((name, texture));
+        }
+
+        Ok(textures)
+    }
+
+    // Copies a patch's column posts into a texture at the given origin
+    fn draw_patch(
+        texture: &mut Texture,
+        patch: &[u8],
+        origin_x: i32,
+        origin_y: i32,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let patch = Picture::from_lump(patch)?.texture;
+
+        for patch_y in 0..patch.height as i32 {
+            for patch_x in 0..patch.width as i32 {
+                let (x, y) = (origin_x + patch_x, origin_y + patch_y);
+                if !patch.is_opaque(patch_x as usize, patch_y as usize)
+                    || x < 0
+                    || y < 0
+                    || x >= texture.width as i32
+                    || y >= texture.height as i32
+                {
+                    continue;
+                }
+                let source = patch_y as usize * patch.width as usize + patch_x as usize;
+                let target = y as usize * texture.width as usize + x as usize;
+                texture.pixels[target] = patch.pixels[source];
+                texture.opaque[target] = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get_flat(&self, name: &str) -> Option<&[u8]> {
+        let name = self.resolve_flat_name(name);
+        self.flats.get(&name.to_uppercase()).map(|flat| flat.as_slice())
+    }
+
+    pub fn get_texture(&self, name: &str) -> Option<&Texture> {
+        let name = self.resolve_texture_name(name);
+        self.textures.get(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use player::{BspNode, Seg, Subsector};
+    use std::collections::HashSet;
+    use std::f64::consts::{FRAC_PI_2, FRAC_PI_4};
+
+    // A 512 unit square room of one sector, floor at 0 and ceiling at 128, lit to the
+    // full and walled with `texture`
+    fn room(texture: &str) -> Map {
+        let corners = [(0, 0), (0, 512), (512, 512), (512, 0)];
+        let mut text = String::from("namespace = \"doom\";\n");
+        for (x, y) in corners {
+            text += &format!("vertex {{ x = {}; y = {}; }}\n", x, y);
+        }
+        // Clockwise, so the inside is on every line's front side
+        for line in 0..corners.len() {
+            text += &format!("linedef {{ v1 = {}; v2 = {}; sidefront = {}; blocking = true; }}\n", line, (line + 1) % corners.len(), line);
+            text += &format!("sidedef {{ sector = 0; texturemiddle = \"{}\"; }}\n", texture);
+        }
+        text += "sector { heightfloor = 0; heightceiling = 128; texturefloor = \"FLOOR4_8\"; textureceiling = \"CEIL3_5\"; lightlevel = 255; }\n";
+        Map::parse_udmf(&text).unwrap()
+    }
+
+    fn doom1() -> WadFile {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../game/Doom1.WAD");
+        WadFile::from_bytes(&std::fs::read(path).unwrap()).unwrap()
+    }
+
+    // Just the 3D view of the map, with nothing over it
+    fn view<'a>(map: &'a Map, player: &'a Player) -> FrameView<'a> {
+        FrameView {
+            map: Some(map),
+            player,
+            previous_player: None,
+            alpha: 1.0,
+            automap: false,
+            paused: false,
+            palette: 0,
+            fixed_colormap: None,
+            hud: None,
+            weapon: None,
+            console: None,
+            sprites: &[],
+        }
+    }
+
+    // RGB of the pixel at (x, y) of the last frame
+    fn pixel(renderer: &Renderer, x: u32, y: u32) -> [u8; 3] {
+        let index = (y * renderer.screen_width + x) as usize * 4;
+        let pixels = renderer.frame_buffer();
+        [pixels[index], pixels[index + 1], pixels[index + 2]]
+    }
+
+    fn raycaster(map: &Map) -> Renderer {
+        let mut renderer = Renderer::new_headless(RenderConfig { use_bsp: false, ..RenderConfig::default() }).unwrap();
+        renderer.load_map(map);
+        renderer
+    }
+
+    #[test]
+    fn rays_hit_walls_at_hand_computed_distances() {
+        let renderer = raycaster(&room("STARTAN3"));
+        let walls = renderer.wall_columns();
+
+        // From the middle of the room, looking along each ray so the distance is its
+        // length: straight at the east and north walls, and across to (512, 384) and
+        // (384, 512)
+        let diagonal = (256.0f64 * 256.0 + 128.0 * 128.0).sqrt();
+        for (angle, expected) in [(0.0, 256.0), (0.5f64.atan(), diagonal), (FRAC_PI_2, 256.0), (2.0f64.atan(), diagonal)] {
+            let player = Player::new(256.0, 256.0, angle);
+            let hit = walls.cast_ray(&player, angle).unwrap();
+            assert!((hit.distance - expected).abs() < 1e-6, "ray at {} hit at {}, not {}", angle, hit.distance, expected);
+        }
+    }
+
+    #[test]
+    fn flat_wall_is_the_same_distance_across_the_view() {
+        let renderer = raycaster(&room("STARTAN3"));
+        let walls = renderer.wall_columns();
+
+        // Facing the east wall from 256 units away, every column sees it at that depth
+        let player = Player::new(256.0, 256.0, 0.0);
+        for x in 0..renderer.screen_width {
+            let hit = walls.cast_ray(&player, player.angle + renderer.column_angle(x)).unwrap();
+            assert!((hit.distance - 256.0).abs() < 1e-6, "column {} hit at {}", x, hit.distance);
+        }
+    }
+
+    #[test]
+    fn textured_wall_column_varies_in_colour() {
+        let map = room("STARTAN3");
+        let mut renderer = raycaster(&map);
+        renderer.load_textures(&doom1()).unwrap();
+
+        let mut player = Player::new(256.0, 256.0, 0.0);
+        player.snap_to_floor(&map);
+        renderer.render_frame(&view(&map, &player)).unwrap();
+
+        // The wall fills the rows around the horizon of the middle column
+        let x = renderer.screen_width / 2;
+        let middle = renderer.screen_height / 2;
+        let colours: HashSet<[u8; 3]> = (middle - 50..middle + 50).map(|y| pixel(&renderer, x, y)).collect();
+        assert!(colours.len() > 1, "column is a flat {:?}", colours);
+    }
+
+    fn imp(x: f64, y: f64) -> Sprite {
+        Sprite { name: "TROO".to_string(), frame: 'A', x, y, z: 0.0, angle: 0.0, fuzz: false }
+    }
+
+    #[test]
+    fn sprite_behind_a_wall_is_hidden() {
+        let map = room("STARTAN3");
+        let mut renderer = raycaster(&map);
+        renderer.load_textures(&doom1()).unwrap();
+        let mut player = Player::new(256.0, 256.0, 0.0);
+        player.snap_to_floor(&map);
+
+        renderer.render_frame(&view(&map, &player)).unwrap();
+        let empty = renderer.frame_buffer().to_vec();
+
+        // Past the east wall every column of the imp is behind it
+        let behind = [imp(700.0, 256.0)];
+        renderer.render_frame(&FrameView { sprites: &behind, ..view(&map, &player) }).unwrap();
+        assert_eq!(renderer.stats().sprites_drawn, 1);
+        assert!(renderer.frame_buffer() == empty.as_slice(), "imp drawn through the wall");
+
+        let in_front = [imp(400.0, 256.0)];
+        renderer.render_frame(&FrameView { sprites: &in_front, ..view(&map, &player) }).unwrap();
+        assert!(renderer.frame_buffer() != empty.as_slice(), "imp in the room not drawn");
+    }
+
+    #[test]
+    fn floor_rows_project_to_the_expected_world_positions() {
+        let config = RenderConfig { fov: FRAC_PI_2, ..RenderConfig::default() };
+        let projection_distance = config.projection_distance();
+        assert!((projection_distance - 400.0).abs() < 1e-9);
+
+        // Looking north from (100, 200) with the eye 50 units over the floor, row 449 is
+        // 149.5 rows under the horizon, so it sees the floor 50 * 400 / 149.5 units ahead
+        let player = Player::new(100.0, 200.0, FRAC_PI_2);
+        let distance = plane_row_distance(449, config.height, projection_distance, 50.0).unwrap();
+        assert!((distance - 50.0 * 400.0 / 149.5).abs() < 1e-9);
+
+        let (x, y) = plane_world_position(&player, 0.0, distance);
+        assert!((x - 100.0).abs() < 1e-9 && (y - (200.0 + distance)).abs() < 1e-9);
+
+        // 45 degrees round from straight ahead the ray is sqrt(2) times as long
+        let (x, y) = plane_world_position(&player, FRAC_PI_4, distance);
+        assert!((x - (100.0 - distance)).abs() < 1e-9 && (y - (200.0 + distance)).abs() < 1e-9);
+
+        // Stepping along the row lands on the same positions as projecting each column
+        let (start, step) = plane_row_step(&player, distance, config.width, projection_distance);
+        for column in [0, 250, 799] {
+            let angle = ((column as f64 + 0.5 - 400.0) / projection_distance).atan();
+            let (x, y) = plane_world_position(&player, angle, distance);
+            let stepped = (start.0 + step.0 * column as f64, start.1 + step.1 * column as f64);
+            assert!((stepped.0 - x).abs() < 1e-6 && (stepped.1 - y).abs() < 1e-6, "column {}", column);
+        }
+    }
+
+    #[test]
+    fn planes_level_with_the_eye_are_not_drawn() {
+        assert_eq!(plane_row_distance(400, 600, 400.0, 0.0), None);
+    }
+
+    fn brightness([r, g, b]: [u8; 3]) -> u32 {
+        r as u32 + g as u32 + b as u32
+    }
+
+    #[test]
+    fn texels_darken_with_distance() {
+        let textures = TextureManager::load_from_wad(&doom1()).unwrap();
+
+        // Palette index 4 is white
+        let shades: Vec<u32> = [128.0, 256.0, 512.0, 1024.0]
+            .into_iter()
+            .map(|distance| {
+                let color = Renderer::shade_texel(&textures, 4, 160, distance);
+                brightness([color.r, color.g, color.b])
+            })
+            .collect();
+        assert!(shades.windows(2).all(|pair| pair[0] > pair[1]), "{:?}", shades);
+    }
+
+    #[test]
+    fn colormap_zero_is_brightest_and_31_darkest() {
+        let textures = TextureManager::load_from_wad(&doom1()).unwrap();
+
+        let shades: Vec<u32> = (0..NUM_COLORMAPS).map(|light| brightness(textures.shade(4, light))).collect();
+        assert_eq!(textures.shade(4, 0), [255, 255, 255]);
+        assert_eq!(shades.iter().max(), Some(&shades[0]));
+        assert_eq!(shades.iter().min(), Some(&shades[31]));
+        assert!(shades[31] < shades[0]);
+    }
+
+    #[test]
+    fn missing_colormap_is_synthesized() {
+        let wad = doom1();
+        let playpal = wad.find_lump("PLAYPAL").unwrap();
+        let wad = WadFile { lumps: vec![wad::WadLump { name: "PLAYPAL".to_string(), data: playpal.data.clone() }] };
+
+        let colormaps = TextureManager::load_colormap(&wad).unwrap();
+        assert_eq!(colormaps.len(), NUM_COLORMAPS + 2);
+        assert_eq!(colormaps[0][4], 4);
+        let palette = &TextureManager::parse_playpal(&playpal.data).unwrap()[0];
+        assert!(brightness(palette[colormaps[31][4] as usize]) < brightness(palette[4]));
+    }
+
+    #[test]
+    fn automap_projects_vertices_around_the_player() {
+        // A vertex 100 units east and 50 north of the player at half scale lands 50
+        // pixels right of and 25 above the middle of an 800x600 screen
+        assert_eq!(automap_project(1100.0, -3550.0, 1000.0, -3600.0, 0.5, 800, 600), (450, 275));
+        assert_eq!(automap_project(1000.0, -3600.0, 1000.0, -3600.0, 0.5, 800, 600), (400, 300));
+        assert_eq!(automap_project(0.0, 0.0, 100.0, 0.0, 2.0, 800, 600), (200, 300));
+    }
+
+    #[test]
+    fn projection_distance_follows_the_configured_resolution_and_fov() {
+        let config = RenderConfig { width: 1280, height: 720, fov: FRAC_PI_2, ..RenderConfig::default() };
+        let renderer = Renderer::new_headless(config).unwrap();
+
+        // Half the width over tan(45 degrees)
+        assert!((renderer.config().projection_distance() - 640.0).abs() < 1e-9);
+        assert_eq!((renderer.screen_width, renderer.screen_height), (1280, 720));
+        assert_eq!(renderer.frame_buffer().len(), 1280 * 720 * 4);
+
+        // The edge columns look out half the field of view either way
+        assert!((renderer.column_angle(0) + FRAC_PI_4).abs() < 1e-2);
+        assert!((renderer.column_angle(1279) - FRAC_PI_4).abs() < 1e-2);
+    }
+
+    #[test]
+    fn unusable_configs_are_refused() {
+        for config in [
+            RenderConfig { width: 0, ..RenderConfig::default() },
+            RenderConfig { fov: PI, ..RenderConfig::default() },
+            RenderConfig { max_view_distance: 0.0, ..RenderConfig::default() },
+        ] {
+            assert!(Renderer::new_headless(config).is_err());
+        }
+    }
+
+    #[test]
+    fn animated_flats_and_textures_cycle_and_wrap() {
+        let mut textures = TextureManager::load_from_wad(&doom1()).unwrap();
+        let tics = |tic: u64| std::time::Duration::from_secs_f64(tic as f64 / TICS_PER_SECOND + 1e-6);
+
+        // Each frame shows for 8 tics
+        for (tic, flat, texture) in [(0, "NUKAGE1", "SLADRIP1"), (8, "NUKAGE2", "SLADRIP2"), (16, "NUKAGE3", "SLADRIP3"), (24, "NUKAGE1", "SLADRIP1")] {
+            textures.tick(tics(tic));
+            assert_eq!(textures.resolve_flat_name("NUKAGE1"), flat, "tic {}", tic);
+            assert_eq!(textures.resolve_texture_name("SLADRIP1"), texture, "tic {}", tic);
+        }
+        // Each frame of the sequence is offset by its place in it, and unanimated flats stay put
+        assert_eq!(textures.resolve_flat_name("NUKAGE2"), "NUKAGE2");
+        assert_eq!(textures.resolve_flat_name("FLOOR4_8"), "FLOOR4_8");
+    }
+
+    #[test]
+    fn animation_frames_wrap() {
+        assert_eq!((0..5).map(|tic| animation_frame(1, 3, 2, tic)).collect::<Vec<_>>(), [1, 1, 2, 2, 0]);
+        assert_eq!(animation_frame(4, 0, 8, 100), 4);
+    }
+
+    // A picture lump `height` rows tall with a column per entry of `columns`, each a list
+    // of posts as (top delta, pixels)
+    fn picture(height: u16, left_offset: i16, top_offset: i16, columns: &[&[(u8, &[u8])]]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for field in [columns.len() as u16, height] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        data.extend_from_slice(&left_offset.to_le_bytes());
+        data.extend_from_slice(&top_offset.to_le_bytes());
+
+        // Column offsets, then each column's posts ended by 0xFF
+        let header_length = data.len() + columns.len() * 4;
+        let mut posts = Vec::new();
+        for column in columns {
+            data.extend_from_slice(&((header_length + posts.len()) as u32).to_le_bytes());
+            for (top_delta, pixels) in column.iter() {
+                posts.extend_from_slice(&[*top_delta, pixels.len() as u8, 0]);
+                posts.extend_from_slice(pixels);
+                posts.push(0);
+            }
+            posts.push(0xFF);
+        }
+        data.extend_from_slice(&posts);
+        data
+    }
+
+    #[test]
+    fn two_patch_texture_is_assembled_side_by_side() {
+        let lump = |name: &str, data: Vec<u8>| wad::WadLump { name: name.to_string(), data };
+        let wad = WadFile {
+            lumps: vec![
+                lump("P_START", Vec::new()),
+                lump("LEFTHALF", picture(4, 0, 0, &[&[(0, &[10, 11, 12, 13])], &[(0, &[20, 21, 22, 23])]])),
+                lump("RIGHTHAL", picture(2, 0, 0, &[&[(0, &[30, 31])], &[(0, &[40, 41])]])),
+                lump("P_END", Vec::new()),
+            ],
+        };
+        let patch_names = ["LEFTHALF".to_string(), "RIGHTHAL".to_string()];
+
+        // One 4x4 texture: LEFTHALF at its top left and RIGHTHAL two columns over, one down
+        let mut texture1 = Vec::new();
+        texture1.extend_from_slice(&1u32.to_le_bytes());
+        texture1.extend_from_slice(&8u32.to_le_bytes());
+        texture1.extend_from_slice(b"TWOPATCH");
+        texture1.extend_from_slice(&0u32.to_le_bytes());
+        for field in [4u16, 4] {
+            texture1.extend_from_slice(&field.to_le_bytes());
+        }
+        texture1.extend_from_slice(&0u32.to_le_bytes());
+        texture1.extend_from_slice(&2u16.to_le_bytes());
+        for (x, y, patch) in [(0i16, 0i16, 0u16), (2, 1, 1)] {
+            texture1.extend_from_slice(&x.to_le_bytes());
+            texture1.extend_from_slice(&y.to_le_bytes());
+            texture1.extend_from_slice(&patch.to_le_bytes());
+            texture1.extend_from_slice(&[0; 4]);
+        }
+
+        let textures = TextureManager::parse_textures(&texture1, &patch_names, &wad).unwrap();
+        let (name, texture) = &textures[0];
+        assert_eq!((name.as_str(), texture.width, texture.height), ("TWOPATCH", 4, 4));
+
+        let texel = |x: usize, y: usize| texture.is_opaque(x, y).then(|| texture.pixels[y * 4 + x]);
+        assert_eq!(texel(1, 3), Some(23));
+        assert_eq!(texel(2, 1), Some(30));
+        assert_eq!(texel(3, 2), Some(41));
+        assert_eq!(texel(2, 0), None);
+        assert_eq!(texel(3, 3), None);
+    }
+
+    #[test]
+    fn picture_posts_leave_gaps_transparent() {
+        // Column 0 has a post at rows 1-2 and one at row 4, column 1 nothing, and column
+        // 2 fills from the top
+        let lump = picture(5, 1, 5, &[&[(1, &[7, 8]), (4, &[9])], &[], &[(0, &[1, 2, 3, 4, 5])]]);
+        let picture = Picture::from_lump(&lump).unwrap();
+        assert_eq!((picture.left_offset, picture.top_offset), (1, 5));
+        let texture = &picture.texture;
+        assert_eq!((texture.width, texture.height), (3, 5));
+
+        let opaque: Vec<Vec<bool>> = (0..5).map(|y| (0..3).map(|x| texture.is_opaque(x, y)).collect()).collect();
+        assert_eq!(opaque, [[false, false, true], [true, false, true], [true, false, true], [false, false, true], [true, false, true]]);
+        assert_eq!(texture.pixels[2 * 3], 8);
+        assert_eq!(texture.pixels[4 * 3], 9);
+        assert_eq!(texture.pixels[3 * 3 + 2], 4);
+    }
+
+    #[test]
+    fn tall_picture_posts_continue_from_the_previous_one() {
+        // A top delta no greater than the last one counts on from it: 200, then 200 + 100
+        let lump = picture(320, 0, 0, &[&[(200, &[5]), (100, &[6])]]);
+        let texture = Picture::from_lump(&lump).unwrap().texture;
+        assert_eq!(texture.pixels[200], 5);
+        assert_eq!(texture.pixels[300], 6);
+        assert!(!texture.is_opaque(0, 100));
+    }
+
+    #[test]
+    fn sprite_dead_ahead_is_centred() {
+        let config = RenderConfig { fov: FRAC_PI_2, ..RenderConfig::default() };
+        let player = Player::new(100.0, 50.0, 1.0);
+        let ahead = (100.0 + 300.0 * 1.0f64.cos(), 50.0 + 300.0 * 1.0f64.sin());
+
+        let (screen_x, depth) = project_sprite(&player, ahead, config.width, config.projection_distance()).unwrap();
+        assert!((screen_x - config.width as f64 / 2.0).abs() < 1e-9, "at column {}", screen_x);
+        assert!((depth - 300.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sprite_at_the_edge_of_the_fov_lands_at_the_screen_edge() {
+        let config = RenderConfig { fov: FRAC_PI_2, ..RenderConfig::default() };
+        let player = Player::new(100.0, 50.0, 1.0);
+        let at = |angle: f64| (100.0 + 300.0 * angle.cos(), 50.0 + 300.0 * angle.sin());
+
+        // Columns run towards increasing angle, so the counterclockwise edge is the last
+        let (last, _) = project_sprite(&player, at(1.0 + FRAC_PI_4), config.width, config.projection_distance()).unwrap();
+        assert!((last - config.width as f64).abs() < 1.0, "at column {}", last);
+        let (first, _) = project_sprite(&player, at(1.0 - FRAC_PI_4), config.width, config.projection_distance()).unwrap();
+        assert!(first.abs() < 1.0, "at column {}", first);
+    }
+
+    #[test]
+    fn sprite_behind_the_player_is_culled() {
+        let config = RenderConfig::default();
+        let player = Player::new(100.0, 50.0, 0.0);
+        assert!(project_sprite(&player, (-200.0, 50.0), config.width, config.projection_distance()).is_none());
+        assert!(project_sprite(&player, (100.0, 300.0), config.width, config.projection_distance()).is_none());
+    }
+
+    #[test]
+    fn headless_wall_fills_the_centre_column() {
+        let map = room("STARTAN3");
+        let mut renderer = Renderer::new_headless(RenderConfig { width: 320, height: 200, ..RenderConfig::default() }).unwrap();
+        renderer.load_map(&map);
+        renderer.load_textures(&doom1()).unwrap();
+        assert_eq!(renderer.frame_buffer().len(), 320 * 200 * 4);
+
+        let mut player = Player::new(256.0, 256.0, 0.0);
+        player.snap_to_floor(&map);
+        renderer.render_frame(&view(&map, &player)).unwrap();
+
+        for y in 90..110 {
+            assert_ne!(pixel(&renderer, 160, y), [0, 0, 0], "row {} of the wall is black", y);
+        }
+    }
+
+    #[test]
+    fn screenshot_reloads_at_the_frame_size_with_its_pixels() {
+        let map = room("STARTAN3");
+        let mut renderer = Renderer::new_headless(RenderConfig { width: 320, height: 200, ..RenderConfig::default() }).unwrap();
+        renderer.load_map(&map);
+        renderer.load_textures(&doom1()).unwrap();
+        let mut player = Player::new(256.0, 256.0, 0.0);
+        player.snap_to_floor(&map);
+        renderer.render_frame(&view(&map, &player)).unwrap();
+
+        let path = std::env::temp_dir().join(format!("room-screenshot-{}.png", std::process::id()));
+        renderer.save_screenshot(&path).unwrap();
+        let decoder = png::Decoder::new(std::fs::File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut rgb = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut rgb).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((info.width, info.height, info.color_type), (320, 200, png::ColorType::Rgb));
+        for (x, y) in [(160, 100), (10, 20), (300, 190)] {
+            let index = (y * 320 + x) as usize * 3;
+            assert_eq!(rgb[index..index + 3], pixel(&renderer, x, y), "pixel ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn walls_blend_towards_fog_only_near_the_far_plane() {
+        let map = room("STARTAN3");
+        let mut player = Player::new(256.0, 256.0, 0.0);
+        player.snap_to_floor(&map);
+        let centre = |config: RenderConfig| {
+            let mut renderer = Renderer::new_headless(RenderConfig { use_bsp: false, ..config }).unwrap();
+            renderer.load_map(&map);
+            renderer.load_textures(&doom1()).unwrap();
+            renderer.render_frame(&view(&map, &player)).unwrap();
+            pixel(&renderer, renderer.screen_width / 2, renderer.screen_height / 2)
+        };
+
+        // The east wall is 256 units away
+        let clear = centre(RenderConfig { max_view_distance: 260.0, ..RenderConfig::default() });
+        let fogged = centre(RenderConfig { max_view_distance: 260.0, fog_color: Some([255, 0, 0]), ..RenderConfig::default() });
+        assert!(fogged[0] > 240 && fogged[1] < 15 && fogged[2] < 15, "{:?} isn't fog", fogged);
+        assert_ne!(fogged, clear);
+
+        let near = centre(RenderConfig { max_view_distance: 2048.0, fog_color: Some([255, 0, 0]), ..RenderConfig::default() });
+        let near_clear = centre(RenderConfig { max_view_distance: 2048.0, ..RenderConfig::default() });
+        assert_eq!(near, near_clear);
+
+        assert_eq!(fog_blend([10, 20, 30], [200, 200, 200], 100.0, 1000.0), [10, 20, 30]);
+        assert_eq!(fog_blend([10, 20, 30], [200, 200, 200], 1000.0, 1000.0), [200, 200, 200]);
+        assert_eq!(fog_blend([0, 0, 0], [200, 100, 0], 750.0, 1000.0), [100, 50, 0]);
+    }
+
+    #[test]
+    fn full_playpal_has_every_tint_palette() {
+        let textures = TextureManager::load_from_wad(&doom1()).unwrap();
+        assert_eq!(textures.palette_count(), NUM_PALETTES);
+
+        // The strongest pain palette pushes every colour towards red
+        let (base, pain) = (textures.palette(0).unwrap(), textures.palette(START_RED_PALETTES + NUM_RED_PALETTES - 1).unwrap());
+        assert_ne!(base, pain);
+        assert!(pain.iter().zip(base).all(|(pain, base)| pain[0] >= base[0]));
+        assert!(textures.palette(NUM_PALETTES).is_none());
+
+        let partial = vec![7; 256 * 3 * 2 + 100];
+        assert_eq!(TextureManager::parse_playpal(&partial).unwrap().len(), 2);
+        assert!(TextureManager::parse_playpal(&[0; 767]).is_err());
+    }
+
+    #[test]
+    fn x_offset_shifts_the_texel_column() {
+        assert_eq!(texture_column(10.5, 0, 64), 10);
+        assert_eq!(texture_column(10.5, 16, 64), 26);
+        // Offsets wrap round the texture either way
+        assert_eq!(texture_column(60.0, 8, 64), 4);
+        assert_eq!(texture_column(2.0, -8, 64), 58);
+    }
+
+    #[test]
+    fn unpegging_moves_the_texture_anchor() {
+        // A 128 high texture on a 72 unit section, 24 below the front ceiling
+        for (section, flags, expected) in [
+            (WallSection::Upper, 0, 56.0),
+            (WallSection::Upper, ML_DONTPEGTOP, 0.0),
+            (WallSection::Lower, 0, 0.0),
+            (WallSection::Lower, ML_DONTPEGBOTTOM, 24.0),
+            (WallSection::Middle, 0, 0.0),
+            (WallSection::Middle, ML_DONTPEGBOTTOM, 56.0),
+        ] {
+            assert_eq!(texture_row_offset(section, flags, 128.0, 72.0, 24.0), expected, "{:?} with flags {}", section, flags);
+        }
+    }
+
+    #[test]
+    fn step_between_sectors_shows_a_lower_span_from_below() {
+        // The back sector's floor is 24 higher, with the same ceiling
+        let spans = wall_spans((0.0, 128.0), Some((24.0, 128.0)));
+        assert_eq!(spans, [WallSpan { section: WallSection::Lower, top: 24.0, bottom: 0.0 }]);
+
+        // A lower ceiling too adds an upper span, leaving 24 to 96 open
+        let spans = wall_spans((0.0, 128.0), Some((24.0, 96.0)));
+        assert_eq!(spans, [WallSpan { section: WallSection::Upper, top: 128.0, bottom: 96.0 }, WallSpan { section: WallSection::Lower, top: 24.0, bottom: 0.0 }]);
+
+        // Seen from the higher side it's a step down, and there's no wall at all
+        assert!(wall_spans((24.0, 128.0), Some((0.0, 128.0))).is_empty());
+        assert_eq!(wall_spans((0.0, 128.0), None), [WallSpan { section: WallSection::Middle, top: 128.0, bottom: 0.0 }]);
+    }
+
+    #[test]
+    fn buffered_points_land_where_a_canvas_would_draw_them() {
+        let mut target = RenderTarget::new(None, 4, 3);
+        target.set_draw_color(Color::RGB(1, 2, 3));
+        target.clear();
+        target.set_draw_color(Color::RGB(200, 100, 50));
+        target.draw_point((2, 1)).unwrap();
+        target.draw_point((-1, 0)).unwrap();
+        target.draw_point((4, 2)).unwrap();
+        target.draw_line((0, 2), (3, 2)).unwrap();
+
+        // What drawing the same points straight onto a 4x3 RGBA canvas leaves
+        let mut expected = [[1, 2, 3, 255]; 12];
+        for (x, y) in [(2, 1), (0, 2), (1, 2), (2, 2), (3, 2)] {
+            expected[y * 4 + x] = [200, 100, 50, 255];
+        }
+        assert_eq!(target.pixels, expected.concat());
+    }
+
+    #[test]
+    fn stepped_plane_rows_match_per_pixel_trig() {
+        let config = RenderConfig::default();
+        let projection_distance = config.projection_distance();
+        let half_width = config.width as f64 / 2.0;
+
+        for (angle, distance) in [(0.0, 40.0), (1.0, 200.0), (4.0, 900.0)] {
+            let player = Player::new(1056.0, -3616.0, angle);
+            let ((mut world_x, mut world_y), (step_x, step_y)) = plane_row_step(&player, distance, config.width, projection_distance);
+
+            // Added up column by column, as the plane loop does
+            for x in 0..config.width {
+                let column_angle = ((x as f64 + 0.5 - half_width) / projection_distance).atan();
+                let (trig_x, trig_y) = plane_world_position(&player, column_angle, distance);
+                assert!((world_x - trig_x).abs() < 1e-6 && (world_y - trig_y).abs() < 1e-6, "column {} facing {}", x, angle);
+                world_x += step_x;
+                world_y += step_y;
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_columns_match_a_single_thread() {
+        let wad = doom1();
+        let map = Map::load_from_wad(&wad, "E1M1").unwrap();
+        let start = map.player_start(1).unwrap();
+        let mut player = Player::new(start.x as f64, start.y as f64, start.angle_radians() as f64);
+        player.snap_to_floor(&map);
+        let mut renderer = raycaster(&map);
+        renderer.load_textures(&wad).unwrap();
+
+        renderer.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        renderer.canvas.clear();
+        renderer.render_3d_view(&map, &player).unwrap();
+        let parallel = renderer.frame_buffer().to_vec();
+        assert!(parallel.chunks_exact(4).any(|pixel| pixel[..3] != [0, 0, 0]), "no walls drawn");
+
+        // The same columns cast and shaded one after another on this thread
+        renderer.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        renderer.canvas.clear();
+        let walls = renderer.wall_columns();
+        let eye_z = player.z + player.height;
+        let columns: Vec<_> = (0..renderer.screen_width)
+            .map(|x| {
+                let hit = walls.cast_ray(&player, normalize_angle_f64(player.angle + renderer.column_angle(x)))?;
+                let mut pixels = Vec::new();
+                walls.shade(&hit, &map, eye_z, (0, renderer.screen_height as i32), &mut pixels);
+                Some(pixels)
+            })
+            .collect();
+        for (x, pixels) in columns.iter().enumerate() {
+            if let Some(pixels) = pixels {
+                renderer.draw_column_pixels(x as u32, pixels).unwrap();
+            }
+        }
+        assert!(renderer.frame_buffer() == parallel.as_slice(), "parallel columns differ");
+    }
+
+    #[test]
+    fn strobe_sectors_alternate_between_their_light_and_the_darkest_neighbour() {
+        // Special 13 strobes in step: bright for STROBE_BRIGHT tics, then dark for FAST_DARK
+        let levels: Vec<i16> = (0..2 * (STROBE_BRIGHT + FAST_DARK)).map(|tic| sector_light(13, 200, 80, 0, tic)).collect();
+        for (tic, &level) in levels.iter().enumerate() {
+            let bright = (tic as u64) % (STROBE_BRIGHT + FAST_DARK) < STROBE_BRIGHT;
+            assert_eq!(level, if bright { 200 } else { 80 }, "tic {}", tic);
+        }
+
+        // Out of step strobes still only use the two levels, and use both
+        let levels: HashSet<i16> = (0..STROBE_BRIGHT + SLOW_DARK).map(|tic| sector_light(3, 200, 80, 5, tic)).collect();
+        assert_eq!(levels, HashSet::from([200, 80]));
+        // The base light level is left alone when nothing animates it
+        assert_eq!(sector_light(0, 200, 80, 0, 7), 200);
+    }
+
+    // The room cut down the middle at x = 256 into a west and an east subsector, with
+    // the north and south walls split at the partition
+    fn halved_room() -> BspTree {
+        let seg = |start_vertex, end_vertex, linedef| Seg { start_vertex, end_vertex, angle: 0, linedef, direction: 0, offset: 0 };
+        BspTree {
+            nodes: vec![BspNode {
+                x: 256, y: 0, dx: 0, dy: 512,
+                bbox_right: [512, 0, 256, 512],
+                bbox_left: [512, 0, 0, 256],
+                right_child: NF_SUBSECTOR | 1,
+                left_child: NF_SUBSECTOR,
+            }],
+            subsectors: vec![Subsector { seg_count: 3, first_seg: 0 }, Subsector { seg_count: 3, first_seg: 3 }],
+            segs: vec![seg(0, 1, 0), seg(1, 4, 1), seg(5, 0, 3), seg(4, 2, 1), seg(2, 3, 2), seg(3, 5, 3)],
+            original_vertex_count: 4,
+            extra_vertices: vec![(256.0, 512.0), (256.0, 0.0)],
+        }
+    }
+
+    #[test]
+    fn bsp_render_counts_the_subsectors_it_visits() {
+        let map = room("STARTAN3");
+        let mut renderer = Renderer::new_headless(RenderConfig::default()).unwrap();
+        renderer.load_map(&map);
+        renderer.load_bsp(halved_room());
+        renderer.load_textures(&doom1()).unwrap();
+
+        // From the west half, looking east sees through into the east half, while
+        // looking west leaves the east half behind the view
+        for (angle, expected) in [(0.0, 2), (std::f64::consts::PI, 1), (0.0, 2)] {
+            let mut player = Player::new(128.0, 256.0, angle);
+            player.snap_to_floor(&map);
+            renderer.render_frame(&view(&map, &player)).unwrap();
+            let stats = renderer.stats();
+            assert_eq!(stats.subsectors_visited, expected, "looking at {}", angle);
+            assert_eq!(stats.rays_cast, 0);
+        }
+    }
+
+    #[test]
+    fn seg_behind_a_near_wall_is_clipped() {
+        let mut clip = ClipRange::new(320);
+        clip.add(100, 201);
+
+        // Columns 100 to 200 are covered, so a seg in them is hidden, one reaching past
+        // them is cut down to the open columns and one elsewhere is untouched
+        assert!(clip.is_occluded(120, 180));
+        assert!(clip.is_occluded(100, 201));
+        assert!(!clip.is_occluded(90, 150));
+        assert_eq!(clip.visible_spans(90, 230), [(90, 100), (201, 230)]);
+        assert_eq!(clip.visible_spans(250, 300), [(250, 300)]);
+
+        // Walls filling the gaps either side merge into one span over the screen
+        clip.add(0, 100);
+        clip.add(201, 320);
+        assert!(clip.is_full());
+    }
+
+    #[test]
+    fn picture_sets_only_its_own_pixels() {
+        let mut renderer = Renderer::new_headless(RenderConfig::default()).unwrap();
+        renderer.load_textures(&doom1()).unwrap();
+        let red = renderer.textures.as_ref().unwrap().current_palette()[176];
+
+        // A 3x2 solid red patch, then the same one with offsets moving it up and left
+        let column: &[(u8, &[u8])] = &[(0, &[176, 176])];
+        let solid = picture(2, 0, 0, &[column; 3]);
+        renderer.draw_picture(&Picture::from_lump(&solid).unwrap(), 10, 10).unwrap();
+        let offset = picture(2, 2, 1, &[column; 3]);
+        renderer.draw_picture(&Picture::from_lump(&offset).unwrap(), 40, 40).unwrap();
+
+        for (left, top) in [(10, 10), (38, 39)] {
+            for y in top - 1..=top + 2 {
+                for x in left - 1..=left + 3 {
+                    let inside = (left..left + 3).contains(&x) && (top..top + 2).contains(&y);
+                    assert_eq!(pixel(&renderer, x, y), if inside { red } else { [0, 0, 0] }, "pixel at ({}, {})", x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn status_bar_changes_with_health() {
+        let map = room("STARTAN3");
+        let mut renderer = raycaster(&map);
+        renderer.load_textures(&doom1()).unwrap();
+        let mut player = Player::new(256.0, 256.0, 0.0);
+        player.snap_to_floor(&map);
+
+        let mut frame = |health: i32| {
+            let hud = HudStatus { health, armor: 0, ammo: Some(50), ammo_max: 200, ..HudStatus::default() };
+            renderer.render_frame(&FrameView { hud: Some(hud), ..view(&map, &player) }).unwrap();
+            renderer.frame_buffer().to_vec()
+        };
+        let (full, half) = (frame(100), frame(50));
+
+        // Only the status bar along the bottom differs
+        let status_bar_top = STATUS_BAR_Y as usize * renderer.screen_height as usize / DOOM_SCREEN_HEIGHT as usize;
+        let split = status_bar_top * renderer.screen_width as usize * 4;
+        assert!(full[..split] == half[..split]);
+        assert!(full[split..] != half[split..]);
+    }
+
+    #[test]
+    fn monster_shows_the_side_facing_the_viewer() {
+        let mut renderer = Renderer::new_headless(RenderConfig::default()).unwrap();
+        renderer.load_textures(&doom1()).unwrap();
+        let textures = renderer.textures.as_ref().unwrap();
+        let rotation = |rotation| textures.sprite_frame("TROO", 'A', rotation).unwrap().0 as *const Picture;
+        let seen_from = |viewer_y: f64| {
+            // An imp at the origin facing north, seen from due north or south of it
+            let to_viewer = viewer_y.atan2(0.0);
+            textures.sprite_view("TROO", 'A', FRAC_PI_2, to_viewer).unwrap().0 as *const Picture
+        };
+
+        // Looking at its face from the north is its front rotation, and from the south
+        // its back
+        assert_eq!(seen_from(100.0), rotation(0));
+        assert_eq!(seen_from(-100.0), rotation(4));
+        assert_ne!(rotation(0), rotation(4));
+    }
+
+    #[test]
+    fn weapon_is_drawn_low_in_the_middle_and_sways_with_movement() {
+        let map = room("STARTAN3");
+        let mut renderer = raycaster(&map);
+        renderer.load_textures(&doom1()).unwrap();
+        let mut player = Player::new(256.0, 256.0, 0.0);
+        player.snap_to_floor(&map);
+
+        let weapon = WeaponOverlay { sprite: "PISG", frame: 'A', ready: true, time: 0.0, fuzz: false };
+        renderer.render_frame(&view(&map, &player)).unwrap();
+        let bare = renderer.frame_buffer().to_vec();
+        let (width, height) = (renderer.screen_width as usize, renderer.screen_height as usize);
+        // The columns and rows the weapon changes, as (left, right, top, bottom)
+        let mut weapon_extent = |player: &Player| {
+            renderer.render_frame(&FrameView { weapon: Some(weapon), ..view(&map, player) }).unwrap();
+            let frame = renderer.frame_buffer();
+            let changed: Vec<(usize, usize)> = (0..frame.len() / 4)
+                .filter(|&index| frame[index * 4..index * 4 + 3] != bare[index * 4..index * 4 + 3])
+                .map(|index| (index % width, index / width))
+                .collect();
+            let xs = changed.iter().map(|&(x, _)| x);
+            let ys = changed.iter().map(|&(_, y)| y);
+            (xs.clone().min().unwrap(), xs.max().unwrap(), ys.clone().min().unwrap(), ys.max().unwrap())
+        };
+
+        let (left, right, top, bottom) = weapon_extent(&player);
+        assert!(top > height / 2 && bottom < height, "rows {}..{}", top, bottom);
+        assert!(left < width / 2 && right > width / 2, "columns {}..{}", left, right);
+
+        // At full speed the bob starts at its furthest right
+        player.velocity = (1000.0, 0.0);
+        let scale = width as f64 / DOOM_SCREEN_WIDTH as f64;
+        let (moved_left, ..) = weapon_extent(&player);
+        assert_eq!(moved_left, left + (MAX_WEAPON_BOB * scale) as usize);
+    }
+}
@@ -1,7 +1,23 @@
-use byteorder::{LittleEndian, ReadBytesExt};
-use std::io::{Read, Seek, SeekFrom};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 use thiserror::Error;
 
+// Lumps that may follow a map marker, in any order. BEHAVIOR holds a Hexen-format
+// map's compiled scripts, and UDMF maps run from TEXTMAP to ENDMAP.
+pub const MAP_LUMP_NAMES: [&str; 15] = [
+    "THINGS", "LINEDEFS", "SIDEDEFS", "VERTEXES", "SEGS", "SSECTORS", "NODES", "SECTORS", "REJECT",
+    "BLOCKMAP", "BEHAVIOR", "TEXTMAP", "ZNODES", "DIALOGUE", "ENDMAP",
+];
+
+// Namespace markers such as S_START, F1_END or PP_START
+pub fn is_marker_lump(name: &str) -> bool {
+    name.ends_with("_START") || name.ends_with("_END")
+}
+
+pub fn is_map_lump(name: &str) -> bool {
+    MAP_LUMP_NAMES.contains(&name)
+}
+
 #[derive(Error, Debug)]
 pub enum WadError {
     #[error("Invalid WAD signature")]
@@ -10,6 +26,34 @@ pub enum WadError {
     Io(#[from] std::io::Error),
     #[error("Invalid lump name")]
     InvalidLumpName,
+    #[error("WAD directory of {count} lumps at offset {offset} runs past the end of the file")]
+    DirectoryOutOfBounds { offset: u32, count: u32 },
+    #[error("Lump {name} at offset {offset} with size {size} runs past the end of the file")]
+    LumpOutOfBounds {
+        name: String,
+        offset: u32,
+        size: u32,
+    },
+    #[error("Too many lumps for a WAD directory")]
+    TooManyLumps,
+    #[error("Lump data too large for a WAD")]
+    LumpTooLarge,
+}
+
+// An IWAD is a complete game, a PWAD a patch loaded over one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WadType {
+    Iwad,
+    Pwad,
+}
+
+impl WadType {
+    fn signature(self) -> &'static [u8; 4] {
+        match self {
+            WadType::Iwad => b"IWAD",
+            WadType::Pwad => b"PWAD",
+        }
+    }
 }
 
 pub struct WadFile {
@@ -23,6 +67,10 @@ pub struct WadLump {
 
 impl WadFile {
     pub fn load<R: Read + Seek>(mut reader: R) -> Result<Self, WadError> {
+        // Everything the directory points at has to fit inside the file
+        let file_length = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+
         // Read the 4-byte signature ("IWAD" or "PWAD")
         let mut signature = [0u8; 4];
         reader.read_exact(&mut signature)?;
@@ -35,6 +83,13 @@ impl WadFile {
         let num_lumps = reader.read_u32::<LittleEndian>()?;
         let dir_offset = reader.read_u32::<LittleEndian>()?;
 
+        if dir_offset as u64 + num_lumps as u64 * 16 > file_length {
+            return Err(WadError::DirectoryOutOfBounds {
+                offset: dir_offset,
+                count: num_lumps,
+            });
+        }
+
         // Seek to directory and read lump entries
         reader.seek(SeekFrom::Start(dir_offset as u64))?;
 
@@ -52,6 +107,22 @@ impl WadFile {
                 .trim_end_matches('\0')
                 .to_string();
 
+            // Markers have no data, so their offset doesn't matter
+            if lump_size == 0 {
+                lumps.push(WadLump {
+                    name,
+                    data: Vec::new(),
+                });
+                continue;
+            }
+            if lump_offset as u64 + lump_size as u64 > file_length {
+                return Err(WadError::LumpOutOfBounds {
+                    name,
+                    offset: lump_offset,
+                    size: lump_size,
+                });
+            }
+
             // Read lump data
             let current_pos = reader.stream_position()?;
             reader.seek(SeekFrom::Start(lump_offset as u64))?;
@@ -68,7 +139,434 @@ impl WadFile {
         Ok(WadFile { lumps })
     }
 
+    // For WADs already in memory, such as ones embedded in a binary
+    pub fn from_bytes(data: &[u8]) -> Result<Self, WadError> {
+        Self::load(Cursor::new(data))
+    }
+
+    // Writes the header, then every lump's data in order, then the directory. Lump
+    // names must fit the directory's 8 bytes.
+    pub fn write<W: Write + Seek>(&self, mut writer: W, wad_type: WadType) -> Result<(), WadError> {
+        let start = writer.stream_position()?;
+        let lump_count = u32::try_from(self.lumps.len()).map_err(|_| WadError::TooManyLumps)?;
+
+        // The directory offset isn't known until the data is written, so come back for it
+        writer.write_all(wad_type.signature())?;
+        writer.write_u32::<LittleEndian>(lump_count)?;
+        writer.write_u32::<LittleEndian>(0)?;
+
+        let mut directory = Vec::with_capacity(self.lumps.len());
+        for lump in &self.lumps {
+            let name = lump.name.as_bytes();
+            if name.len() > 8 {
+                return Err(WadError::InvalidLumpName);
+            }
+            let mut name_bytes = [0u8; 8];
+            name_bytes[..name.len()].copy_from_slice(name);
+
+            // Zero-length markers just point at wherever the next data would go
+            let offset = Self::offset_from(start, writer.stream_position()?)?;
+            writer.write_all(&lump.data)?;
+            let size = u32::try_from(lump.data.len()).map_err(|_| WadError::LumpTooLarge)?;
+            directory.push((offset, size, name_bytes));
+        }
+
+        let directory_offset = Self::offset_from(start, writer.stream_position()?)?;
+        for (offset, size, name) in directory {
+            writer.write_u32::<LittleEndian>(offset)?;
+            writer.write_u32::<LittleEndian>(size)?;
+            writer.write_all(&name)?;
+        }
+        let end = writer.stream_position()?;
+
+        writer.seek(SeekFrom::Start(start + 8))?;
+        writer.write_u32::<LittleEndian>(directory_offset)?;
+        writer.seek(SeekFrom::Start(end))?;
+
+        Ok(())
+    }
+
+    // WAD offsets are 32-bit and relative to the start of the file
+    fn offset_from(start: u64, position: u64) -> Result<u32, WadError> {
+        u32::try_from(position - start).map_err(|_| WadError::LumpTooLarge)
+    }
+
     pub fn find_lump(&self, name: &str) -> Option<&WadLump> {
         self.lumps.iter().find(|lump| lump.name == name)
     }
+
+    // First lump with the name at or after `start_index`, for names like THINGS that
+    // every map repeats
+    pub fn find_lump_after(&self, name: &str, start_index: usize) -> Option<&WadLump> {
+        self.lumps
+            .get(start_index..)?
+            .iter()
+            .find(|lump| lump.name == name)
+    }
+
+    // A lump between a `start_marker` and the `end_marker` after it, such as a sprite
+    // between S_START and S_END. None if the namespace doesn't exist.
+    pub fn find_lump_in_namespace(
+        &self,
+        name: &str,
+        start_marker: &str,
+        end_marker: &str,
+    ) -> Option<&WadLump> {
+        self.namespace_lumps(start_marker, end_marker)
+            .find(|lump| lump.name == name)
+    }
+
+    // The lumps between each `start` marker and the `end` marker after it, in WAD order.
+    // Markers nested inside, like F1_START within F_START, aren't yielded.
+    pub fn namespace_lumps<'a>(
+        &'a self,
+        start: &str,
+        end: &str,
+    ) -> impl Iterator<Item = &'a WadLump> {
+        let mut inside = false;
+        self.lumps.iter().filter(move |lump| {
+            if lump.name == start {
+                inside = true;
+                return false;
+            }
+            if lump.name == end {
+                inside = false;
+                return false;
+            }
+            inside && !is_marker_lump(&lump.name)
+        })
+    }
+
+    // Names of map marker lumps, i.e. those directly followed by map data, in WAD order
+    pub fn map_names(&self) -> Vec<&str> {
+        self.lumps
+            .windows(2)
+            .filter(|pair| !is_map_lump(&pair[0].name) && is_map_lump(&pair[1].name))
+            .map(|pair| pair[0].name.as_str())
+            .collect()
+    }
+
+    // Layers a PWAD over this one. Later lumps replace earlier ones with the same name,
+    // whole maps are swapped out as a group, and sprites/flats are merged into the
+    // existing S_START/S_END and F_START/F_END namespaces.
+    pub fn merge(&mut self, other: WadFile) {
+        let mut lumps = other.lumps.into_iter().peekable();
+
+        while let Some(lump) = lumps.next() {
+            let namespace = match lump.name.as_str() {
+                "S_START" | "SS_START" => Some(("S_START", "S_END", ["S_END", "SS_END"])),
+                "F_START" | "FF_START" => Some(("F_START", "F_END", ["F_END", "FF_END"])),
+                _ => None,
+            };
+
+            if let Some((start, end, end_markers)) = namespace {
+                let mut contents = Vec::new();
+                for lump in lumps.by_ref() {
+                    if end_markers.contains(&lump.name.as_str()) {
+                        break;
+                    }
+                    contents.push(lump);
+                }
+                self.merge_namespace(start, end, contents);
+                continue;
+            }
+
+            if lumps.peek().is_some_and(|next| is_map_lump(&next.name)) {
+                let mut group = vec![lump];
+                while let Some(data) = lumps.next_if(|next| is_map_lump(&next.name)) {
+                    group.push(data);
+                }
+                self.replace_map(group);
+                continue;
+            }
+
+            match self
+                .lumps
+                .iter_mut()
+                .find(|existing| existing.name == lump.name)
+            {
+                Some(existing) => *existing = lump,
+                None => self.lumps.push(lump),
+            }
+        }
+    }
+
+    fn merge_namespace(&mut self, start: &str, end: &str, contents: Vec<WadLump>) {
+        let start_index = self.lumps.iter().position(|lump| lump.name == start);
+        let end_index = start_index.and_then(|start_index| {
+            self.lumps[start_index..]
+                .iter()
+                .position(|lump| lump.name == end)
+                .map(|offset| start_index + offset)
+        });
+
+        let (Some(start_index), Some(mut end_index)) = (start_index, end_index) else {
+            // No namespace to merge into yet, so bring the PWAD's along
+            self.lumps.push(WadLump {
+                name: start.to_string(),
+                data: Vec::new(),
+            });
+            self.lumps.extend(contents);
+            self.lumps.push(WadLump {
+                name: end.to_string(),
+                data: Vec::new(),
+            });
+            return;
+        };
+
+        for lump in contents {
+            let existing = self.lumps[start_index + 1..end_index]
+                .iter()
+                .position(|existing| existing.name == lump.name);
+
+            match existing {
+                Some(offset) => self.lumps[start_index + 1 + offset] = lump,
+                None => {
+                    self.lumps.insert(end_index, lump);
+                    end_index += 1;
+                }
+            }
+        }
+    }
+
+    fn replace_map(&mut self, group: Vec<WadLump>) {
+        let marker = self.lumps.iter().enumerate().position(|(index, lump)| {
+            lump.name == group[0].name
+                && self
+                    .lumps
+                    .get(index + 1)
+                    .is_some_and(|next| is_map_lump(&next.name))
+        });
+
+        match marker {
+            Some(marker) => {
+                let data_count = self.lumps[marker + 1..]
+                    .iter()
+                    .take_while(|lump| is_map_lump(&lump.name))
+                    .count();
+                self.lumps.splice(marker..=marker + data_count, group);
+            }
+            None => self.lumps.extend(group),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wad(lumps: &[(&str, &[u8])]) -> WadFile {
+        WadFile {
+            lumps: lumps
+                .iter()
+                .map(|(name, data)| WadLump {
+                    name: name.to_string(),
+                    data: data.to_vec(),
+                })
+                .collect(),
+        }
+    }
+
+    fn names(wad: &WadFile) -> Vec<&str> {
+        wad.lumps.iter().map(|lump| lump.name.as_str()).collect()
+    }
+
+    #[test]
+    fn pwad_lump_overrides_the_base() {
+        let mut base = wad(&[("PLAYPAL", b"base"), ("COLORMAP", b"base")]);
+        base.merge(wad(&[("PLAYPAL", b"patch")]));
+
+        assert_eq!(base.find_lump("PLAYPAL").unwrap().data, b"patch");
+        assert_eq!(base.find_lump("COLORMAP").unwrap().data, b"base");
+        assert_eq!(names(&base), ["PLAYPAL", "COLORMAP"]);
+    }
+
+    #[test]
+    fn last_pwad_wins() {
+        let mut base = wad(&[("PLAYPAL", b"base")]);
+        base.merge(wad(&[("PLAYPAL", b"first")]));
+        base.merge(wad(&[("PLAYPAL", b"second")]));
+
+        assert_eq!(base.find_lump("PLAYPAL").unwrap().data, b"second");
+    }
+
+    #[test]
+    fn sprites_merge_into_the_base_namespace() {
+        let mut base = wad(&[
+            ("S_START", b""),
+            ("TROOA1", b"base"),
+            ("S_END", b""),
+            ("F_START", b""),
+            ("FLOOR4_8", b"base"),
+            ("F_END", b""),
+        ]);
+        base.merge(wad(&[
+            ("SS_START", b""),
+            ("TROOA1", b"patch"),
+            ("POSSA1", b"patch"),
+            ("SS_END", b""),
+        ]));
+
+        assert_eq!(
+            names(&base),
+            [
+                "S_START", "TROOA1", "POSSA1", "S_END", "F_START", "FLOOR4_8", "F_END"
+            ]
+        );
+        let sprite = base.find_lump_in_namespace("TROOA1", "S_START", "S_END");
+        assert_eq!(sprite.unwrap().data, b"patch");
+    }
+
+    #[test]
+    fn maps_are_replaced_as_a_group() {
+        let mut base = wad(&[
+            ("E1M1", b""),
+            ("THINGS", b"base"),
+            ("LINEDEFS", b"base"),
+            ("SIDEDEFS", b"base"),
+            ("E1M2", b""),
+            ("THINGS", b"base 2"),
+        ]);
+        base.merge(wad(&[
+            ("E1M1", b""),
+            ("TEXTMAP", b"patch"),
+            ("ENDMAP", b""),
+        ]));
+
+        assert_eq!(
+            names(&base),
+            ["E1M1", "TEXTMAP", "ENDMAP", "E1M2", "THINGS"]
+        );
+        assert_eq!(base.find_lump("THINGS").unwrap().data, b"base 2");
+    }
+
+    #[test]
+    fn written_wad_reloads_with_the_same_lumps() {
+        let original = wad(&[
+            ("PLAYPAL", &[1, 2, 3, 4]),
+            ("S_START", &[]),
+            ("TROOA1", &[5; 37]),
+            ("S_END", &[]),
+            ("DEMO1", &[6, 7]),
+        ]);
+
+        let mut buffer = Cursor::new(Vec::new());
+        original.write(&mut buffer, WadType::Pwad).unwrap();
+        let bytes = buffer.into_inner();
+        assert_eq!(&bytes[0..4], b"PWAD");
+
+        let reloaded = WadFile::from_bytes(&bytes).unwrap();
+        assert_eq!(names(&reloaded), names(&original));
+        for (lump, expected) in reloaded.lumps.iter().zip(&original.lumps) {
+            assert_eq!(lump.data, expected.data, "{}", lump.name);
+        }
+    }
+
+    #[test]
+    fn overlong_lump_names_cannot_be_written() {
+        let mut buffer = Cursor::new(Vec::new());
+        let result = wad(&[("TOOLONGNAME", &[1])]).write(&mut buffer, WadType::Iwad);
+        assert!(matches!(result, Err(WadError::InvalidLumpName)));
+    }
+
+    #[test]
+    fn lookups_stay_within_their_map_or_namespace() {
+        let wad = wad(&[
+            ("E1M1", &[]),
+            ("THINGS", &[1]),
+            ("E1M2", &[]),
+            ("THINGS", &[2]),
+            ("TROOA1", &[3]),
+            ("S_START", &[]),
+            ("TROOA1", &[4]),
+            ("S_END", &[]),
+        ]);
+
+        assert_eq!(wad.find_lump_after("THINGS", 0).unwrap().data, [1]);
+        assert_eq!(wad.find_lump_after("THINGS", 2).unwrap().data, [2]);
+        assert!(wad.find_lump_after("THINGS", 4).is_none());
+
+        let sprite = wad.find_lump_in_namespace("TROOA1", "S_START", "S_END");
+        assert_eq!(sprite.unwrap().data, [4]);
+        assert!(
+            wad.find_lump_in_namespace("TROOA1", "F_START", "F_END")
+                .is_none()
+        );
+    }
+
+    fn written(wad: &WadFile) -> Vec<u8> {
+        let mut buffer = Cursor::new(Vec::new());
+        wad.write(&mut buffer, WadType::Iwad).unwrap();
+        buffer.into_inner()
+    }
+
+    #[test]
+    fn truncated_directory_is_an_error() {
+        let mut bytes = written(&wad(&[("PLAYPAL", &[1, 2, 3]), ("COLORMAP", &[4])]));
+        bytes.truncate(bytes.len() - 4);
+        assert!(matches!(
+            WadFile::from_bytes(&bytes),
+            Err(WadError::DirectoryOutOfBounds { count: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn lump_past_the_end_of_the_file_is_an_error() {
+        let mut bytes = written(&wad(&[("PLAYPAL", &[1, 2, 3])]));
+        let directory = u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize;
+        bytes[directory..directory + 4].copy_from_slice(&0x7FFF_FFF0u32.to_le_bytes());
+
+        match WadFile::from_bytes(&bytes) {
+            Err(WadError::LumpOutOfBounds { name, offset, size }) => {
+                assert_eq!((name.as_str(), offset, size), ("PLAYPAL", 0x7FFF_FFF0, 3));
+            }
+            other => panic!("expected LumpOutOfBounds, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn wad_loads_from_bytes_in_memory() {
+        // A PWAD with the lumps "AB" and "CDE" after the header, then its directory
+        #[rustfmt::skip]
+        const DATA: &[u8] = &[
+            b'P', b'W', b'A', b'D', 2, 0, 0, 0, 17, 0, 0, 0,
+            b'a', b'b', b'c', b'd', b'e',
+            12, 0, 0, 0, 2, 0, 0, 0, b'A', b'B', 0, 0, 0, 0, 0, 0,
+            14, 0, 0, 0, 3, 0, 0, 0, b'C', b'D', b'E', 0, 0, 0, 0, 0,
+        ];
+        let wad = WadFile::from_bytes(DATA).unwrap();
+
+        assert_eq!(names(&wad), ["AB", "CDE"]);
+        let data: Vec<&[u8]> = wad.lumps.iter().map(|lump| lump.data.as_slice()).collect();
+        assert_eq!(data, [b"ab".as_slice(), b"cde"]);
+    }
+
+    #[test]
+    fn namespace_yields_only_the_lumps_between_its_markers() {
+        let wad = wad(&[
+            ("PLAYPAL", b""),
+            ("S_START", b""),
+            ("TROOA1", b""),
+            ("TROOB1", b""),
+            ("S_END", b""),
+            ("F_START", b""),
+            ("F1_START", b""),
+            ("FLOOR4_8", b""),
+            ("F1_END", b""),
+            ("F_END", b""),
+            ("ENDOOM", b""),
+        ]);
+
+        let sprites: Vec<&str> = wad
+            .namespace_lumps("S_START", "S_END")
+            .map(|lump| lump.name.as_str())
+            .collect();
+        assert_eq!(sprites, ["TROOA1", "TROOB1"]);
+        // Nested markers are skipped but their contents still count
+        let flats: Vec<&str> = wad
+            .namespace_lumps("F_START", "F_END")
+            .map(|lump| lump.name.as_str())
+            .collect();
+        assert_eq!(flats, ["FLOOR4_8"]);
+    }
 }